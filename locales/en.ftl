@@ -0,0 +1,171 @@
+# Messages of the TUI. Each line is `key = text`; `{ $name }` is replaced by a
+# value when the message is shown. Key hints keep the key names untranslated.
+
+## Tabs
+
+tab-tunnels = Tunnels
+tab-hosts = Hosts
+tab-logs = Logs
+tab-profiles = Profiles
+tab-stats = Stats
+
+## Command palette
+
+action-new-tunnel = New tunnel
+action-close-selected = Close selected tunnel
+action-drain-selected = Drain selected tunnel: refuse new connections, close when idle
+action-retarget-selected = Change the destination of the selected tunnel
+action-toggle-lazy = Connect the selected tunnel on demand, or always
+action-restart-selected = Restart selected tunnel
+action-restart-all = Restart all tunnels
+action-show-qr-code = Show QR code of selected tunnel
+action-filter-by-tag = Filter by next tag
+action-cycle-sort = Change sort order
+action-select-connection = Select next connection of selected tunnel
+action-close-connection = Close selected connection
+action-toggle-capture = Start or stop capturing selected tunnel
+action-export-report = Export a session report
+action-diagnose = Diagnose the selected tunnel
+action-launch-client = Connect to the database of the selected tunnel
+action-show-tab = Go to { $tab }
+action-open-profile = Open profile { $profile }
+action-quit = Quit
+
+## Titles
+
+title-app = Rtun - SSH Tunnel Manager
+title-sorted-by = [sorted by { $sort }]
+title-tag = [tag: { $tag }]
+title-strict = [strict]
+title-read-only = Rtun - read-only (changes are made in the running instance)
+title-tunnel = Tunnel (up/down to select, x to close, r to restart, q for QR code)
+title-history = History
+title-hosts = Hosts from ~/.ssh/config
+title-hosts-forward = Hosts from ~/.ssh/config (enter to forward a port)
+title-recent-and-hosts = Recent tunnels (alt+1-9 to open, ctrl+t to pin), then hosts
+title-host-options = { $host } as ssh resolves it for rtun
+title-profiles = Profiles from ~/.rtun/config.toml (enter to open)
+title-profile-tunnels = { $profile }  ({ $count } tunnels)
+title-stats = Usage over all sessions, longest up first
+title-throughput = Throughput, last { $span } (peak { $peak } per { $interval })
+title-looking-up-ports = Looking up listening ports on { $host }...
+title-listening-ports = Listening on { $host }
+title-orphans = ssh processes left by a previous run
+title-security-key = Security key
+title-confirm-production = Open a production tunnel?
+title-another-instance = Another instance
+title-diagnosis = Diagnosis of { $tunnel }
+
+## New tunnel wizard
+
+wizard-host = New tunnel 1/3: host (type to search, up/down to pick, alt+enter for one tunnel per line)
+wizard-host-placeholder = host, or all at once: Host_name 1234:45321 # optional label, or @file
+wizard-remote-port = New tunnel 2/3: remote port on { $host }
+wizard-remote-port-placeholder = port, or pick a listening one with up/down
+wizard-local-port = New tunnel 3/3: local port for { $host }:{ $port }
+wizard-local-port-placeholder = port
+
+## Key hints
+
+keys-footer = esc: quit  n: new tunnel  tab/1-5: switch tabs  ctrl+p: all commands
+keys-footer-narrow = esc: quit  ctrl+p: all commands
+keys-prompt = enter: send  esc: later
+keys-orphans = a: adopt  k: kill  i: ignore
+keys-confirm = y: open  n: skip
+keys-qr-code = any key to close
+keys-palette = enter: run  esc: close
+keys-take-over = t: take over its tunnels  q: quit
+keys-quit = q: quit
+
+## Messages
+
+error = Error: { $error }
+not-connected = Not connected: { $error }
+terminal-too-small = Terminal too small ({ $width }x{ $height }), rtun needs { $min_width }x{ $min_height }
+touch-security-key = Touch your security key for { $host }
+production-tags = tags: { $tags }
+diagnosing = Running checks...
+resolving-host-options = Resolving...
+already-running = rtun is already running.
+already-running-pid = rtun is already running (pid { $pid }).
+taking-over = Waiting for rtun { $pid } to hand over its tunnels...
+could-not-list-ports = Could not list ports on { $host }: { $error }
+could-not-read-history = Could not read history: { $error }
+could-not-save-capture = Could not save the capture: { $error }
+could-not-save-sort = Could not save the sort order: { $error }
+capture-needs-relay = Only tunnels rtun listens on itself can be captured, such as with log_requests = true
+retarget-prompt = New destination of { $tunnel }, as host:port
+cannot-retarget = SOCKS and load balanced tunnels have no single destination to change
+drain-needs-relay = Only tunnels that are up and that rtun listens on itself can drain, such as with max_connections set
+lazy-needs-open = Only open tunnels can switch to connecting on demand
+client-needs-database = Only tunnels to Postgres, MySQL or Redis have a client; set client.kind for others
+client-failed = The client did not run: { $error }
+status-parked = idle (on-demand)
+status-on-demand-up = up (on-demand, ssh connected)
+startup-progress = Connecting: { $connected } connected, { $pending } pending, { $failed } failed
+no-clients = No clients are connected through this tunnel
+select-connection-first = Select a connection with c first
+not-shared = Only reachable from this machine, set lan = true to share it
+no-network-address = No network address to share
+batch-opened = Opened { $count } tunnels
+batch-line = line { $line }: { $error }
+report-saved = Report saved to { $path }
+report-copied = Report saved to { $path } and copied to the clipboard
+could-not-save-report = Could not save the report: { $error }
+project-profile = profile { $profile } for { $name }
+
+## Tunnel detail
+
+detail-hostnames = in /etc/hosts: { $names }
+detail-last-error = last error: { $error }
+detail-repeats = (×{ $count } in last { $minutes }m)
+detail-ssh-session = ssh session: { $session } (o to keep it up)
+detail-session-parked = parked until a client connects
+detail-session-connected = connected
+detail-session-down = down
+detail-host-key = host key: { $change } (rtun forget-host-key { $host })
+detail-host-keys-off = host keys: not checked
+detail-connections = connections:
+detail-clients = clients (c to select, k to close):
+detail-requests = requests:
+detail-destinations = through the proxy:
+detail-nothing-yet = nothing yet
+badge-host-key = KEY
+badge-remote-port = PORT
+host-key-changed = host key changed
+remote-not-listening = remote { $port } is not listening
+remote-not-listening-near = remote { $port } is not listening, did you mean { $near }?
+repeats-summary = { $error } ×{ $count } in last { $minutes }m
+repeats-host-unreachable = host unreachable
+repeats-auth-failed = auth failed
+repeats-port-in-use = port in use
+
+## Plain mode
+
+plain-list = List tunnels
+plain-close = Close a tunnel
+plain-restart = Restart a tunnel
+plain-open-profile = Open a profile
+plain-menu-hint = Enter a number, or ? to show this menu again.
+plain-ask-tunnel = Tunnel, such as: host 8080:80 # label
+plain-ask-number = Number of the tunnel:
+plain-ask-profile = Number of the profile:
+plain-no-tunnels = No tunnels.
+plain-no-profiles = No profiles in ~/.rtun/config.toml.
+plain-no-such-number = There is no number { $number } in the list.
+plain-unknown-choice = Unknown choice { $choice }, enter ? for the menu.
+plain-port-in-use = Local port { $port } is already in use.
+plain-confirm = Open a production tunnel? { $tunnel } (y/n)
+session-restored = Restored { $tunnel } from the previous session
+session-not-restored = Could not restore a tunnel of the previous session: { $error }
+event-opened = opened
+event-connected = connected
+event-unhealthy = unhealthy
+event-reconnecting = reconnecting
+event-failed = failed
+event-closed = closed
+event-draining = draining
+event-parked = parked
+event-scheduled = scheduled
+event-queued = queued
+event-waiting_for_vpn = waiting for VPN
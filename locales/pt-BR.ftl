@@ -0,0 +1,170 @@
+# Mensagens da TUI em português do Brasil. Veja en.ftl.
+
+## Abas
+
+tab-tunnels = Túneis
+tab-hosts = Hosts
+tab-logs = Logs
+tab-profiles = Perfis
+tab-stats = Estatísticas
+
+## Paleta de comandos
+
+action-new-tunnel = Novo túnel
+action-close-selected = Fechar o túnel selecionado
+action-drain-selected = Drenar o túnel selecionado: recusar novas conexões e fechar quando ocioso
+action-retarget-selected = Mudar o destino do túnel selecionado
+action-toggle-lazy = Conectar o túnel selecionado sob demanda, ou sempre
+action-restart-selected = Reiniciar o túnel selecionado
+action-restart-all = Reiniciar todos os túneis
+action-show-qr-code = Mostrar o QR code do túnel selecionado
+action-filter-by-tag = Filtrar pela próxima tag
+action-cycle-sort = Mudar a ordenação
+action-select-connection = Selecionar a próxima conexão do túnel selecionado
+action-close-connection = Fechar a conexão selecionada
+action-toggle-capture = Iniciar ou parar a captura do túnel selecionado
+action-export-report = Exportar um relatório da sessão
+action-diagnose = Diagnosticar o túnel selecionado
+action-launch-client = Conectar ao banco de dados do túnel selecionado
+action-show-tab = Ir para { $tab }
+action-open-profile = Abrir o perfil { $profile }
+action-quit = Sair
+
+## Títulos
+
+title-app = Rtun - Gerenciador de túneis SSH
+title-sorted-by = [ordenado por { $sort }]
+title-tag = [tag: { $tag }]
+title-strict = [estrito]
+title-read-only = Rtun - somente leitura (as mudanças são feitas na instância em execução)
+title-tunnel = Túnel (cima/baixo para selecionar, x para fechar, r para reiniciar, q para QR code)
+title-history = Histórico
+title-hosts = Hosts do ~/.ssh/config
+title-hosts-forward = Hosts do ~/.ssh/config (enter para encaminhar uma porta)
+title-recent-and-hosts = Túneis recentes (alt+1-9 para abrir, ctrl+t para fixar), depois hosts
+title-host-options = { $host } como o ssh o resolve para o rtun
+title-profiles = Perfis do ~/.rtun/config.toml (enter para abrir)
+title-profile-tunnels = { $profile }  ({ $count } túneis)
+title-stats = Uso em todas as sessões, o mais tempo aberto primeiro
+title-throughput = Tráfego, últimos { $span } (pico de { $peak } a cada { $interval })
+title-looking-up-ports = Procurando portas em escuta em { $host }...
+title-listening-ports = Em escuta em { $host }
+title-orphans = Processos ssh deixados por uma execução anterior
+title-security-key = Chave de segurança
+title-confirm-production = Abrir um túnel de produção?
+title-another-instance = Outra instância
+title-diagnosis = Diagnóstico de { $tunnel }
+
+## Assistente de novo túnel
+
+wizard-host = Novo túnel 1/3: host (digite para buscar, cima/baixo para escolher, alt+enter para um túnel por linha)
+wizard-host-placeholder = host, ou tudo de uma vez: Nome_do_host 1234:45321 # rótulo opcional, ou @arquivo
+wizard-remote-port = Novo túnel 2/3: porta remota em { $host }
+wizard-remote-port-placeholder = porta, ou escolha uma em escuta com cima/baixo
+wizard-local-port = Novo túnel 3/3: porta local para { $host }:{ $port }
+wizard-local-port-placeholder = porta
+
+## Dicas de teclas
+
+keys-footer = esc: sair  n: novo túnel  tab/1-5: trocar de aba  ctrl+p: todos os comandos
+keys-footer-narrow = esc: sair  ctrl+p: todos os comandos
+keys-prompt = enter: enviar  esc: depois
+keys-orphans = a: adotar  k: matar  i: ignorar
+keys-confirm = y: abrir  n: pular
+keys-qr-code = qualquer tecla para fechar
+keys-palette = enter: executar  esc: fechar
+keys-take-over = t: assumir os túneis  q: sair
+keys-quit = q: sair
+
+## Mensagens
+
+error = Erro: { $error }
+not-connected = Sem conexão: { $error }
+terminal-too-small = Terminal pequeno demais ({ $width }x{ $height }), o rtun precisa de { $min_width }x{ $min_height }
+touch-security-key = Toque na sua chave de segurança para { $host }
+production-tags = tags: { $tags }
+diagnosing = Executando verificações...
+resolving-host-options = Resolvendo...
+already-running = O rtun já está em execução.
+already-running-pid = O rtun já está em execução (pid { $pid }).
+taking-over = Aguardando o rtun { $pid } entregar seus túneis...
+could-not-list-ports = Não foi possível listar as portas de { $host }: { $error }
+could-not-read-history = Não foi possível ler o histórico: { $error }
+could-not-save-capture = Não foi possível salvar a captura: { $error }
+could-not-save-sort = Não foi possível salvar a ordenação: { $error }
+capture-needs-relay = Só é possível capturar túneis em que o próprio rtun escuta, como com log_requests = true
+retarget-prompt = Novo destino de { $tunnel }, como host:porta
+cannot-retarget = Túneis SOCKS e com balanceamento de carga não têm um destino único para mudar
+drain-needs-relay = Só é possível drenar túneis ativos em que o próprio rtun escuta, como com max_connections definido
+lazy-needs-open = Só túneis abertos podem passar a conectar sob demanda
+client-needs-database = Só túneis para Postgres, MySQL ou Redis têm um cliente; defina client.kind para outros
+client-failed = O cliente não rodou: { $error }
+status-parked = ocioso (sob demanda)
+status-on-demand-up = ativo (sob demanda, ssh conectado)
+startup-progress = Conectando: { $connected } conectados, { $pending } pendentes, { $failed } com falha
+no-clients = Nenhum cliente está conectado por este túnel
+select-connection-first = Selecione uma conexão com c primeiro
+not-shared = Acessível apenas desta máquina, defina lan = true para compartilhar
+no-network-address = Nenhum endereço de rede para compartilhar
+batch-opened = { $count } túneis abertos
+batch-line = linha { $line }: { $error }
+report-saved = Relatório salvo em { $path }
+report-copied = Relatório salvo em { $path } e copiado para a área de transferência
+could-not-save-report = Não foi possível salvar o relatório: { $error }
+project-profile = perfil { $profile } para { $name }
+
+## Detalhes do túnel
+
+detail-hostnames = em /etc/hosts: { $names }
+detail-last-error = último erro: { $error }
+detail-repeats = (×{ $count } nos últimos { $minutes } min)
+detail-ssh-session = sessão ssh: { $session } (o para mantê-la ativa)
+detail-session-parked = em espera até um cliente conectar
+detail-session-connected = conectada
+detail-session-down = desconectada
+detail-host-key = chave do host: { $change } (rtun forget-host-key { $host })
+detail-host-keys-off = chaves de host: não verificadas
+detail-connections = conexões:
+detail-clients = clientes (c para selecionar, k para fechar):
+detail-requests = requisições:
+detail-destinations = pelo proxy:
+detail-nothing-yet = nada ainda
+badge-host-key = CHAVE
+badge-remote-port = PORTA
+host-key-changed = chave do host mudou
+remote-not-listening = nada escuta na porta remota { $port }
+remote-not-listening-near = nada escuta na porta remota { $port }, você quis dizer { $near }?
+repeats-summary = { $error } ×{ $count } nos últimos { $minutes } min
+repeats-host-unreachable = host inacessível
+repeats-auth-failed = falha de autenticação
+repeats-port-in-use = porta em uso
+
+## Modo texto
+
+plain-list = Listar os túneis
+plain-close = Fechar um túnel
+plain-restart = Reiniciar um túnel
+plain-open-profile = Abrir um perfil
+plain-menu-hint = Digite um número, ou ? para mostrar este menu de novo.
+plain-ask-tunnel = Túnel, por exemplo: host 8080:80 # rótulo
+plain-ask-number = Número do túnel:
+plain-ask-profile = Número do perfil:
+plain-no-tunnels = Nenhum túnel.
+plain-no-profiles = Nenhum perfil no ~/.rtun/config.toml.
+plain-no-such-number = Não há número { $number } na lista.
+plain-unknown-choice = Opção { $choice } desconhecida, digite ? para ver o menu.
+plain-port-in-use = A porta local { $port } já está em uso.
+plain-confirm = Abrir um túnel de produção? { $tunnel } (y/n)
+session-restored = { $tunnel } restaurado da sessão anterior
+session-not-restored = Não foi possível restaurar um túnel da sessão anterior: { $error }
+event-opened = aberto
+event-connected = conectado
+event-unhealthy = com problemas
+event-reconnecting = reconectando
+event-failed = falhou
+event-closed = fechado
+event-draining = drenando
+event-parked = estacionado
+event-scheduled = agendado
+event-queued = na fila
+event-waiting_for_vpn = aguardando a VPN
@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::process;
+use std::sync::Mutex;
+
+/// First descriptor systemd passes, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sockets systemd listens on for rtun, by port, until a tunnel takes its
+/// own. Read from the environment on first use.
+static LISTENERS: Mutex<Option<BTreeMap<u16, TcpListener>>> = Mutex::new(None);
+
+/// The TCP sockets passed by systemd socket activation, as `LISTEN_PID` and
+/// `LISTEN_FDS` describe them. Other sockets are closed.
+fn inherited() -> BTreeMap<u16, TcpListener> {
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse().ok());
+    // Meant for another process, such as the rtun that started this one.
+    if pid != Some(process::id()) {
+        return BTreeMap::new();
+    }
+    let count: RawFd = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .filter_map(|fd| {
+            // Not for ssh and the other children to inherit.
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            Some((listener.local_addr().ok()?.port(), listener))
+        })
+        .collect()
+}
+
+/// Whether systemd listens on `port` for rtun.
+pub fn listens(port: u16) -> bool {
+    let mut listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+    listeners.get_or_insert_with(inherited).contains_key(&port)
+}
+
+/// The socket systemd listens on `port` with, for the tunnel on that port
+/// to accept on instead of binding it. Only the first tunnel gets it.
+pub fn take(port: u16) -> Option<TcpListener> {
+    let mut listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+    listeners.get_or_insert_with(inherited).remove(&port)
+}
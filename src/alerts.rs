@@ -0,0 +1,108 @@
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::{AlertConfig, AlertEvent};
+use crate::platform;
+use crate::tunnel::TunnelSpec;
+
+impl AlertEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertEvent::Down => "down",
+            AlertEvent::Failed => "failed",
+        }
+    }
+}
+
+fn render(
+    template: &str,
+    event: AlertEvent,
+    spec: &TunnelSpec,
+    error: &str,
+    quote: bool,
+) -> String {
+    let value = |v: &str| {
+        if quote {
+            platform::shell_quote(v)
+        } else {
+            v.to_string()
+        }
+    };
+    template
+        .replace("{event}", &value(event.name()))
+        .replace("{host}", &value(&spec.host))
+        .replace("{local_port}", &value(&spec.local_port.to_string()))
+        .replace("{remote_port}", &value(&spec.remote_port.to_string()))
+        .replace("{error}", &value(error))
+}
+
+async fn run_command(command: String, envs: Vec<(&'static str, String)>) {
+    let _ = Command::new(platform::shell())
+        .arg("-c")
+        .arg(command)
+        .envs(envs)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+}
+
+async fn post_webhook(url: String, body: String) {
+    let process = Command::new("curl")
+        .args(["-fsS", "-m", "10", "-X", "POST"])
+        .args([
+            "-H",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut process) = process else {
+        return;
+    };
+    if let Some(mut stdin) = process.stdin.take() {
+        let _ = stdin.write_all(body.as_bytes()).await;
+    }
+    let _ = process.wait().await;
+}
+
+/// Runs the configured alert command and webhook for `event` in the
+/// background. Alerts are best effort and never block the tunnel.
+pub fn fire(alerts: &AlertConfig, event: AlertEvent, spec: &TunnelSpec, error: &str) {
+    if !alerts.on.contains(&event) {
+        return;
+    }
+    let message = render(&alerts.message, event, spec, error, false);
+
+    if let Some(command) = &alerts.command {
+        let command = render(command, event, spec, error, true);
+        let envs = vec![
+            ("RTUN_EVENT", event.name().to_string()),
+            ("RTUN_HOST", spec.host.clone()),
+            ("RTUN_LOCAL_PORT", spec.local_port.to_string()),
+            ("RTUN_REMOTE_PORT", spec.remote_port.to_string()),
+            ("RTUN_ERROR", error.to_string()),
+            ("RTUN_MESSAGE", message.clone()),
+        ];
+        tokio::spawn(run_command(command, envs));
+    }
+
+    if let Some(url) = &alerts.webhook {
+        let body = serde_json::json!({
+            "text": message,
+            "event": event.name(),
+            "host": spec.host,
+            "local_port": spec.local_port,
+            "remote_port": spec.remote_port,
+            "error": error,
+        });
+        tokio::spawn(post_webhook(url.clone(), body.to_string()));
+    }
+}
@@ -0,0 +1,187 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::history;
+
+const AUDIT_PATH: &str = ".rtun/audit.jsonl";
+/// How much of the end of the log is read at a time, looking for the last
+/// entry.
+const TAIL_CHUNK: u64 = 4096;
+
+// Set from the `audit` setting at startup.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Why the last entry could not be written, until taken.
+static FAILED: Mutex<Option<String>> = Mutex::new(None);
+
+/// One line of the audit log. Every entry carries the hash of the one
+/// before it, so editing or removing a line breaks the chain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    /// User running rtun.
+    pub user: String,
+    /// Who asked for the action, when not that user, e.g. `uid 1001` over the
+    /// control socket.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_behalf_of: Option<String>,
+    /// What was done, such as `open` or `remote_command`.
+    pub action: String,
+    /// What it was done to: a tunnel, or a host and a command.
+    pub target: String,
+    pub prev: String,
+    pub hash: String,
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<14}  {}",
+            self.timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            self.action,
+            self.user,
+        )?;
+        if let Some(caller) = &self.on_behalf_of {
+            write!(f, " for {}", caller)?;
+        }
+        write!(f, "  {}", self.target)
+    }
+}
+
+impl AuditEntry {
+    fn digest(&self) -> String {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed).unwrap_or_default();
+        format!("{:x}", Sha256::digest(json))
+    }
+}
+
+fn audit_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(AUDIT_PATH))
+}
+
+/// Turns recording on for the rest of the run.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Hash of the last entry of `file`, read from its end.
+fn last_hash(file: &mut File) -> Result<String> {
+    let mut start = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+    // Back until the tail holds the whole last line.
+    while start > 0 && !tail.strip_suffix(b"\n").unwrap_or(&tail).contains(&b'\n') {
+        let read = start.min(TAIL_CHUNK);
+        start -= read;
+        let mut chunk = vec![0; read as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+    }
+    let tail = String::from_utf8_lossy(&tail);
+    match tail
+        .trim_end()
+        .rsplit('\n')
+        .next()
+        .filter(|line| !line.is_empty())
+    {
+        Some(line) => Ok(serde_json::from_str::<AuditEntry>(line)
+            .wrap_err("Corrupt audit log")?
+            .hash),
+        None => Ok(String::new()),
+    }
+}
+
+fn append(action: &str, target: &str, on_behalf_of: Option<String>) -> Result<()> {
+    let path = audit_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path)
+        .wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    // Held until the file is closed, so that rtun processes writing at once
+    // each chain onto the entry the other just wrote.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(std::io::Error::last_os_error())
+            .wrap_err_with(|| format!("Could not lock {}", path.display()));
+    }
+    let mut entry = AuditEntry {
+        timestamp: Utc::now(),
+        user: history::current_user(),
+        on_behalf_of,
+        action: action.to_string(),
+        target: target.to_string(),
+        prev: last_hash(&mut file)?,
+        hash: String::new(),
+    };
+    entry.hash = entry.digest();
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Appends `action` on `target` to the audit log, if recording is on.
+/// Like the history, a failing log never stops the action; why it failed
+/// is kept for [`take_error`].
+pub fn record(action: &str, target: &str, on_behalf_of: Option<String>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Err(e) = append(action, target, on_behalf_of) {
+        *FAILED.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some(format!("Could not write the audit log: {:#}", e));
+    }
+}
+
+/// Why the audit log could not be written lately, once.
+pub fn take_error() -> Option<String> {
+    FAILED.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Reads the audit log, checking that no entry was changed, inserted or
+/// removed since it was written.
+pub fn load() -> Result<Vec<AuditEntry>> {
+    let path = audit_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", path.display())),
+    };
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let entry: AuditEntry = serde_json::from_str(&line)
+            .wrap_err_with(|| format!("Unreadable audit entry on line {}", number + 1))?;
+        let prev = entries.last().map(|e| e.hash.as_str()).unwrap_or_default();
+        if entry.prev != prev || entry.digest() != entry.hash {
+            return Err(eyre!(
+                "The audit log was tampered with on line {}",
+                number + 1
+            ));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::process::Stdio;
+use std::sync::{Arc, RwLock};
+
+use tokio::process::{Child, Command};
+
+use crate::config::{Config, HealthCheckConfig, HostKeyPolicy};
+use crate::mux;
+use crate::relay::Relay;
+use crate::secrets;
+use crate::tunnel::{RemoteTarget, TunnelSpec};
+use crate::watchdog;
+
+/// Name of the backend tunnels use unless they pick another one.
+pub const DEFAULT: &str = "ssh";
+
+// Backends registered at runtime, in addition to the built-in ones.
+static REGISTRY: RwLock<BTreeMap<String, Arc<dyn TunnelBackend>>> = RwLock::new(BTreeMap::new());
+
+/// One port a backend forwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Forward {
+    /// Connections to `bind` go to `target`, as seen from the remote end.
+    Local { bind: String, target: String },
+    /// Connections to `bind` on the remote end come back to `target` here.
+    Remote { bind: String, target: String },
+    /// A SOCKS proxy listening on `bind`.
+    Dynamic { bind: String },
+}
+
+/// The forwards a tunnel needs: its local port to `target`, or a SOCKS
+/// proxy, or, behind a relay, each relay backend to its destination. A
+/// watchdog with a monitor port adds the loop its test data goes through.
+pub fn forwards(spec: &TunnelSpec, target: &RemoteTarget, relay: Option<&Relay>) -> Vec<Forward> {
+    let mut forwards = if let Some(relay) = relay.filter(|_| spec.load_balance.is_some()) {
+        relay
+            .backends()
+            .iter()
+            .zip(spec.targets())
+            .map(|(port, target)| Forward::Local {
+                bind: format!("127.0.0.1:{}", port),
+                target: target.to_string(),
+            })
+            .collect()
+    } else {
+        let bind = match (relay, spec.lan) {
+            (Some(relay), _) => format!("127.0.0.1:{}", relay.backends()[0]),
+            (None, true) => format!("{}:{}", spec.bind_address(), spec.local_port),
+            (None, false) => spec.local_port.to_string(),
+        };
+        match spec.socks {
+            true => vec![Forward::Dynamic { bind }],
+            false => vec![Forward::Local {
+                bind,
+                target: target.to_string(),
+            }],
+        }
+    };
+    if let Some(port) = spec.watchdog.and_then(|w| w.monitor_port) {
+        // Out to the remote end on the port, and back here on the next one.
+        forwards.push(Forward::Local {
+            bind: port.to_string(),
+            target: format!("127.0.0.1:{}", port),
+        });
+        forwards.push(Forward::Remote {
+            bind: port.to_string(),
+            target: format!("127.0.0.1:{}", port.wrapping_add(1)),
+        });
+    }
+    forwards
+}
+
+/// A transport carrying the forwards of tunnels, such as ssh. The supervisor
+/// keeps the process it starts running, restarting it when it exits.
+pub trait TunnelBackend: Send + Sync {
+    /// Starts a process carrying `forwards` for `spec`, with its stderr piped
+    /// so the last line can explain why it exited. `config` holds the
+    /// credentials configured for the host, see [`Config::auth`], and
+    /// whether strict mode is on.
+    fn connect(
+        &self,
+        spec: &TunnelSpec,
+        forwards: &[Forward],
+        config: &Config,
+    ) -> std::io::Result<Child>;
+
+    /// How to tell that the forwards stopped working while the process still
+    /// runs, if at all.
+    fn health_check(&self, spec: &TunnelSpec) -> Option<HealthCheckConfig> {
+        spec.health_check()
+    }
+
+    /// Cleans up after the process exited or was killed.
+    fn teardown(&self, _spec: &TunnelSpec) {}
+}
+
+/// Makes `backend` available to tunnels as `backend = "<name>"`, replacing
+/// any backend of the same name.
+pub fn register(name: &str, backend: Arc<dyn TunnelBackend>) {
+    REGISTRY
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_string(), backend);
+}
+
+/// The backend called `name`, if there is one.
+pub fn get(name: &str) -> Option<Arc<dyn TunnelBackend>> {
+    let registered = REGISTRY
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned();
+    registered.or_else(|| match name {
+        DEFAULT => Some(Arc::new(Ssh)),
+        _ => None,
+    })
+}
+
+/// Default arguments of the ssh client, see
+/// [`SshClient::args`](crate::config::SshClient::args).
+const SSH_ARGS: [&str; 4] = ["-N", "{options}", "{forwards}", "{host}"];
+
+/// The arguments of the ssh client carrying `forwards` for `spec`, from the
+/// template of the client configured for its host.
+pub fn ssh_args(spec: &TunnelSpec, forwards: &[Forward], config: &Config) -> Vec<OsString> {
+    let auth = config.auth.get(&spec.host).cloned().unwrap_or_default();
+    let mut options: Vec<OsString> =
+        vec!["-T".into(), "-o".into(), "ExitOnForwardFailure=yes".into()];
+    if config.strict {
+        for option in ["-o", "StrictHostKeyChecking=yes", "-o", "ForwardAgent=no"] {
+            options.push(option.into());
+        }
+    }
+    // After strict mode's, as ssh keeps the first value of an option.
+    let host_key = config.host_key(spec);
+    if let Some(policy) = host_key.policy {
+        let value = match policy {
+            HostKeyPolicy::Strict => "yes",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Off => "no",
+        };
+        options.push("-o".into());
+        options.push(format!("StrictHostKeyChecking={}", value).into());
+    }
+    let known_hosts = match host_key.policy {
+        // Keeps the keys seen meanwhile out of the real file.
+        Some(HostKeyPolicy::Off) => Some("/dev/null".into()),
+        _ => host_key.known_hosts,
+    };
+    if let Some(known_hosts) = known_hosts {
+        options.push("-o".into());
+        options.push(format!("UserKnownHostsFile={}", known_hosts.display()).into());
+    }
+    if let Some(identity) = &auth.identity {
+        options.push("-i".into());
+        options.push(identity.into());
+    }
+    if let Some(certificate) = &auth.certificate {
+        options.push("-o".into());
+        options.push(format!("CertificateFile={}", certificate.display()).into());
+    }
+    if !spec.via.is_empty() {
+        options.push("-J".into());
+        options.push(spec.via.join(",").into());
+    }
+    let watched = watchdog::uses_control_socket(spec);
+    if let Some(path) = watchdog::control_path(spec.local_port).filter(|_| watched) {
+        // The watchdog asks ssh through it with `-O check`.
+        options.push("-o".into());
+        options.push("ControlMaster=yes".into());
+        options.push("-o".into());
+        options.push(format!("ControlPath={}", path.display()).into());
+    } else if multiplexes(spec, config) {
+        options.extend(mux::master_options());
+    }
+    let forwards = forward_args(forwards);
+    let template = config
+        .ssh
+        .client(&spec.host)
+        .args
+        .unwrap_or_else(|| SSH_ARGS.iter().map(|arg| arg.to_string()).collect());
+    let mut args = Vec::new();
+    for arg in template {
+        match arg.as_str() {
+            "{options}" => args.extend(options.iter().cloned()),
+            "{forwards}" => args.extend(forwards.iter().cloned()),
+            _ => args.push(arg.replace("{host}", &spec.host).into()),
+        }
+    }
+    args
+}
+
+/// `forwards` as the `-L`, `-R` and `-D` arguments of ssh.
+fn forward_args(forwards: &[Forward]) -> Vec<OsString> {
+    forwards
+        .iter()
+        .flat_map(|forward| match forward {
+            Forward::Local { bind, target } => ["-L".to_string(), format!("{}:{}", bind, target)],
+            Forward::Remote { bind, target } => ["-R".to_string(), format!("{}:{}", bind, target)],
+            Forward::Dynamic { bind } => ["-D".to_string(), bind.clone()],
+        })
+        .map(OsString::from)
+        .collect()
+}
+
+/// Whether the forwards of `spec` go through a master connection, see
+/// [`SshClient::multiplex`](crate::config::SshClient::multiplex). Tunnels through jump hosts, or watched through
+/// a control socket of their own, get an ssh of their own.
+fn multiplexes(spec: &TunnelSpec, config: &Config) -> bool {
+    config.ssh.client(&spec.host).multiplexes()
+        && spec.via.is_empty()
+        && !watchdog::uses_control_socket(spec)
+}
+
+/// The OpenSSH client, or the one configured in `[ssh]`.
+pub struct Ssh;
+
+impl TunnelBackend for Ssh {
+    fn connect(
+        &self,
+        spec: &TunnelSpec,
+        forwards: &[Forward],
+        config: &Config,
+    ) -> std::io::Result<Child> {
+        let auth = config.auth.get(&spec.host).cloned().unwrap_or_default();
+        let watched = watchdog::uses_control_socket(spec);
+        if let Some(path) = watchdog::control_path(spec.local_port).filter(|_| watched) {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Left by an ssh that was killed, it would keep the new one from
+            // listening there.
+            let _ = std::fs::remove_file(&path);
+        }
+        let client = config.ssh.client(&spec.host);
+        let askpass = secrets::askpass_env(auth.password().as_ref())
+            .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+        if multiplexes(spec, config) {
+            // The master, if rtun starts it, logs in as the tunnel's ssh
+            // would, without its forwards.
+            return mux::attach(
+                &client,
+                &spec.host,
+                spec.local_port,
+                forward_args(forwards),
+                ssh_args(spec, &[], config),
+                askpass,
+            );
+        }
+        let mut command = Command::new(client.program());
+        command.envs(askpass);
+        command
+            .args(ssh_args(spec, forwards, config))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+    }
+
+    fn teardown(&self, spec: &TunnelSpec) {
+        mux::cancel(spec.local_port);
+    }
+}
@@ -0,0 +1,241 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::Local;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::tunnel::TunnelSpec;
+
+const CAPTURES_PATH: &str = ".rtun/captures";
+/// Packets hold raw IPv4 or IPv6, without a link layer.
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+// Room for the IPv6 and TCP headers within the snapshot length.
+const MAX_PAYLOAD: usize = SNAPLEN as usize - 60;
+const TCP_PSH_ACK: u8 = 0x18;
+
+/// Where captures go and when they stop on their own.
+///
+/// ```toml
+/// [capture]
+/// dir = "/var/tmp/captures"
+/// max_size = 100
+/// max_duration = 10
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// `~/.rtun/captures` by default.
+    pub dir: Option<PathBuf>,
+    /// In megabytes.
+    pub max_size: u64,
+    /// In minutes.
+    pub max_duration: u64,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            dir: None,
+            max_size: 100,
+            max_duration: 10,
+        }
+    }
+}
+
+/// Traffic of a relayed tunnel, written to a pcap file as TCP packets
+/// between the clients and the tunnel's local port. The packets are made up
+/// from the bytes relayed, so there are no handshakes, retransmissions or
+/// real checksums, but Wireshark follows the streams fine.
+///
+/// A thread of its own writes the file, so that relaying never waits on the
+/// disk.
+#[derive(Debug)]
+pub struct Capture {
+    pub path: PathBuf,
+    records: mpsc::Sender<Vec<u8>>,
+    writer: thread::JoinHandle<io::Result<()>>,
+    pub started: Instant,
+    /// Bytes handed to the writer so far.
+    pub size: u64,
+    max_size: u64,
+    pub max_duration: Duration,
+}
+
+/// Writes the records of a capture to `file` until the capture ends.
+fn write_records(mut file: BufWriter<File>, records: mpsc::Receiver<Vec<u8>>) -> io::Result<()> {
+    for record in records {
+        file.write_all(&record)?;
+    }
+    file.flush()
+}
+
+impl Capture {
+    /// Starts a capture file for `spec` in the configured directory. Only
+    /// the user may read it, as it holds whatever went through the tunnel.
+    pub fn create(config: &CaptureConfig, spec: &TunnelSpec) -> Result<Self> {
+        let dir = match &config.dir {
+            Some(dir) => dir.clone(),
+            None => env::home_dir()
+                .ok_or_else(|| eyre!("Could not determine the home directory"))?
+                .join(CAPTURES_PATH),
+        };
+        fs::create_dir_all(&dir).wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+        let name = format!(
+            "{}-{}-{}.pcap",
+            spec.host,
+            spec.local_port,
+            Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let path = dir.join(name);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .wrap_err_with(|| format!("Could not create {}", path.display()))?;
+        let (records, received) = mpsc::channel();
+        let writer = thread::spawn(move || write_records(BufWriter::new(file), received));
+        let mut capture = Capture {
+            path,
+            records,
+            writer,
+            started: Instant::now(),
+            size: 0,
+            max_size: config.max_size * 1_000_000,
+            max_duration: Duration::from_secs(config.max_duration * 60),
+        };
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        capture.write(header)?;
+        Ok(capture)
+    }
+
+    /// Queues `record` for the writer, failing once it gave up.
+    fn write(&mut self, record: Vec<u8>) -> io::Result<()> {
+        let len = record.len() as u64;
+        self.records
+            .send(record)
+            .map_err(|_| io::Error::other("the capture file could not be written"))?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Whether the size or time limit was reached.
+    pub fn is_over(&self) -> bool {
+        self.size >= self.max_size || self.started.elapsed() >= self.max_duration
+    }
+
+    /// Records `payload` sent from `from` to `to`, starting at sequence
+    /// number `seq` and acknowledging `ack`.
+    pub fn packet(
+        &mut self,
+        from: SocketAddr,
+        to: SocketAddr,
+        seq: u64,
+        ack: u64,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut seq = seq;
+        for chunk in payload.chunks(MAX_PAYLOAD) {
+            let packet = ip_packet(from, to, seq as u32, ack as u32, chunk);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let mut record = Vec::with_capacity(16 + packet.len());
+            record.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+            record.extend_from_slice(&now.subsec_micros().to_le_bytes());
+            record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            record.extend_from_slice(&packet);
+            self.write(record)?;
+            seq += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Ends the capture without waiting for the writer to catch up, which
+    /// it still does.
+    pub fn close(self) -> PathBuf {
+        self.path
+    }
+
+    /// Ends the capture once everything queued is written out.
+    pub fn finish(self) -> io::Result<PathBuf> {
+        drop(self.records);
+        self.writer
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the capture writer panicked")))?;
+        Ok(self.path)
+    }
+}
+
+fn checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !(((sum & 0xffff) + (sum >> 16)) as u16)
+}
+
+fn tcp_segment(from: u16, to: u16, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&from.to_be_bytes());
+    segment.extend_from_slice(&to.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, TCP_PSH_ACK]);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes()); // window
+    segment.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+    segment.extend_from_slice(payload);
+    segment
+}
+
+fn ip_packet(from: SocketAddr, to: SocketAddr, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+    let segment = tcp_segment(from.port(), to.port(), seq, ack, payload);
+    match (from.ip().to_canonical(), to.ip().to_canonical()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let mut packet = Vec::with_capacity(20 + segment.len());
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+            packet.extend_from_slice(&segment);
+            packet
+        }
+        (source, destination) => {
+            let v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (source, destination): (Ipv6Addr, Ipv6Addr) = (v6(source), v6(destination));
+            let mut packet = Vec::with_capacity(40 + segment.len());
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[6, 64]);
+            packet.extend_from_slice(&source.octets());
+            packet.extend_from_slice(&destination.octets());
+            packet.extend_from_slice(&segment);
+            packet
+        }
+    }
+}
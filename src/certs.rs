@@ -0,0 +1,106 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{Duration, NaiveDateTime};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+// As printed by `ssh-keygen -L`, in local time.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// When an ssh certificate can be used. `None` bounds are open, as in
+/// `Valid: forever`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validity {
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+}
+
+fn parse_time(time: &str) -> Option<Option<NaiveDateTime>> {
+    match time {
+        "forever" => Some(None),
+        _ => NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+            .ok()
+            .map(Some),
+    }
+}
+
+/// Reads the `Valid:` line of `ssh-keygen -L` output: `forever`,
+/// `after <from>`, `before <to>` or `from <from> to <to>`.
+pub fn parse(output: &str) -> Option<Validity> {
+    let valid = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Valid:"))?
+        .trim();
+    let (from, to) = if valid == "forever" {
+        ("forever", "forever")
+    } else if let Some(from) = valid.strip_prefix("after ") {
+        (from, "forever")
+    } else if let Some(to) = valid.strip_prefix("before ") {
+        ("forever", to)
+    } else {
+        valid.strip_prefix("from ")?.split_once(" to ")?
+    };
+    Some(Validity {
+        from: parse_time(from)?,
+        to: parse_time(to)?,
+    })
+}
+
+// ssh expands `~` in key paths itself, ssh-keygen does not.
+pub(crate) fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Validity of the certificate at `path`, as reported by `ssh-keygen`.
+pub fn validity(path: &Path) -> Result<Validity> {
+    let output = Command::new("ssh-keygen")
+        .arg("-L")
+        .arg("-f")
+        .arg(expand_home(path))
+        .output()
+        .wrap_err("Could not run ssh-keygen")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!(
+            "Could not read the certificate {}: {}",
+            path.display(),
+            stderr.trim()
+        ));
+    }
+    parse(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| eyre!("No validity in the certificate {}", path.display()))
+}
+
+fn describe_remaining(remaining: Duration) -> String {
+    match (
+        remaining.num_days(),
+        remaining.num_hours(),
+        remaining.num_minutes(),
+    ) {
+        (days, _, _) if days > 1 => format!("{} days", days),
+        (_, hours, _) if hours > 1 => format!("{} hours", hours),
+        (_, _, minutes) => format!("{} minutes", minutes.max(1)),
+    }
+}
+
+impl Validity {
+    /// Whether the certificate is expired at `now`, or will be within
+    /// `window`.
+    pub fn expires_within(&self, now: NaiveDateTime, window: Duration) -> bool {
+        self.to.is_some_and(|to| to - now <= window)
+    }
+
+    /// Human readable state at `now`, e.g. `expires in 3 hours`.
+    pub fn describe(&self, now: NaiveDateTime) -> String {
+        match (self.from, self.to) {
+            (Some(from), _) if now < from => format!("not valid before {}", from),
+            (_, Some(to)) if now >= to => format!("expired {}", to),
+            (_, Some(to)) => format!("expires in {} ({})", describe_remaining(to - now), to),
+            (_, None) => "valid forever".to_string(),
+        }
+    }
+}
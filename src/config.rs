@@ -0,0 +1,987 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::capture::CaptureConfig;
+use crate::control::ControlConfig;
+use crate::dbclient::{self, Database};
+use crate::errors::{ErrorCode, Failure};
+use crate::exports;
+use crate::hostsfile;
+use crate::i18n;
+use crate::knock::Knock;
+use crate::platform;
+use crate::schedule::Schedule;
+use crate::secrets::Secret;
+use crate::tunnel::TunnelSpec;
+
+const RTUN_CONFIG_PATH: &str = ".rtun/config.toml";
+/// Idle timeout of tunnels in strict mode that set none, in minutes.
+const STRICT_IDLE_TIMEOUT: u64 = 60;
+/// Below the 10 unauthenticated connections sshd takes by default.
+const DEFAULT_PARALLEL_CONNECTS: usize = 8;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub alerts: Option<AlertConfig>,
+    /// Restart all tunnels when the network changes or the machine wakes up.
+    pub restart_on_network_change: bool,
+    /// Check that the ssh server answers before spawning ssh.
+    pub reachability_check: bool,
+    /// Ask the ssh host whether anything listens on the forwarded port, and
+    /// warn when nothing does.
+    pub remote_port_check: bool,
+    /// Ring the terminal bell when a tunnel drops while rtun is not focused.
+    pub bell: bool,
+    /// The narrow layout at any width, for phone screens. On by default in
+    /// Termux.
+    pub compact: Option<bool>,
+    /// Default reconnect settings, overridden per profile and per tunnel.
+    pub reconnect: ReconnectConfig,
+    pub profiles: BTreeMap<String, Profile>,
+    /// Port knocking sequences, by ssh host.
+    pub knock: BTreeMap<String, KnockConfig>,
+    /// VPNs tunnels can depend on, by name.
+    pub vpn: BTreeMap<String, VpnConfig>,
+    pub http_proxy: Option<HttpProxyConfig>,
+    pub pac: Option<PacConfig>,
+    pub dns: Option<DnsConfig>,
+    /// Order of the tunnel list, changed from the TUI with `s`.
+    pub sort: SortOrder,
+    pub control: ControlConfig,
+    /// Accent color by ssh host, such as `red` or `#ff8800`. Other hosts get
+    /// one picked from their name.
+    pub host_colors: BTreeMap<String, String>,
+    /// How to answer ssh prompts, by ssh host.
+    pub auth: BTreeMap<String, AuthConfig>,
+    /// How ssh checks host keys, by ssh host.
+    pub host_keys: BTreeMap<String, HostKeyConfig>,
+    /// The ssh client to run instead of `ssh` from PATH.
+    pub ssh: SshConfig,
+    /// Rhai script reacting to tunnel events, `~/.rtun/hooks.rhai` by
+    /// default.
+    pub hooks: Option<PathBuf>,
+    /// Known hosts only, no agent forwarding, localhost-only binds,
+    /// mandatory idle timeouts and confirmation for `prod` tunnels. Also
+    /// turned on with `--strict`.
+    pub strict: bool,
+    /// Minutes without connections after which tunnels close, unless they
+    /// set their own.
+    pub idle_timeout: Option<u64>,
+    /// Record tunnel actions and remote commands in `~/.rtun/audit.jsonl`.
+    pub audit: bool,
+    /// Where traffic captures, started with `p`, are written.
+    pub capture: CaptureConfig,
+    pub intervals: IntervalsConfig,
+    /// Language of the TUI, such as `pt-BR`. Taken from `LANG` when unset.
+    pub locale: Option<String>,
+    /// Tunnels connecting at once, the others waiting their turn, so that
+    /// opening a large profile does not trip the `MaxStartups` of sshd.
+    pub parallel_connects: usize,
+    /// Fill the one-time code prompt with a code found on the clipboard.
+    pub paste_codes: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            alerts: None,
+            restart_on_network_change: true,
+            reachability_check: true,
+            remote_port_check: false,
+            bell: true,
+            compact: None,
+            reconnect: ReconnectConfig::default(),
+            profiles: BTreeMap::new(),
+            knock: BTreeMap::new(),
+            vpn: BTreeMap::new(),
+            http_proxy: None,
+            pac: None,
+            dns: None,
+            sort: SortOrder::default(),
+            host_colors: BTreeMap::new(),
+            control: ControlConfig::default(),
+            hooks: None,
+            auth: BTreeMap::new(),
+            host_keys: BTreeMap::new(),
+            ssh: SshConfig::default(),
+            strict: false,
+            idle_timeout: None,
+            audit: false,
+            capture: CaptureConfig::default(),
+            intervals: IntervalsConfig::default(),
+            locale: None,
+            parallel_connects: DEFAULT_PARALLEL_CONNECTS,
+            paste_codes: true,
+        }
+    }
+}
+
+impl Config {
+    pub fn compact(&self) -> bool {
+        self.compact.unwrap_or_else(platform::is_termux)
+    }
+
+    /// How long `spec` may go without connections before it is closed.
+    pub fn idle_timeout(&self, spec: &TunnelSpec) -> Option<Duration> {
+        let minutes = spec
+            .idle_timeout
+            .or(self.idle_timeout)
+            .or(self.strict.then_some(STRICT_IDLE_TIMEOUT))?;
+        Some(Duration::from_secs(minutes * 60))
+    }
+
+    /// Why strict mode refuses to open `spec`, if it does.
+    pub fn strict_violation(&self, spec: &TunnelSpec) -> Option<String> {
+        if !self.strict {
+            return None;
+        }
+        if spec.lan {
+            return Some("Strict mode only allows tunnels listening on localhost".to_string());
+        }
+        let args = self.ssh.client(&spec.host).args;
+        if args.is_some_and(|args| !args.iter().any(|arg| arg == "{options}")) {
+            // It would go without the host key checks strict mode adds.
+            return Some(format!(
+                "Strict mode needs {{options}} in the ssh client args of {}",
+                spec.host
+            ));
+        }
+        if spec.tls_backend.as_ref().is_some_and(|tls| tls.insecure) {
+            return Some(format!(
+                "Strict mode does not allow skipping the certificate checks of {}",
+                spec
+            ));
+        }
+        match self.host_key(spec).policy {
+            Some(HostKeyPolicy::Off) => Some(format!(
+                "Strict mode does not allow turning off host key checks for {}",
+                spec.host
+            )),
+            _ => None,
+        }
+    }
+
+    /// How ssh checks the key of the host of `spec`: the tunnel's own
+    /// setting, from its profile, or the global `[host_keys]` entry.
+    pub fn host_key(&self, spec: &TunnelSpec) -> HostKeyConfig {
+        spec.host_key
+            .clone()
+            .or_else(|| self.host_keys.get(&spec.host).cloned())
+            .unwrap_or_default()
+    }
+
+    /// Whether the user has to confirm opening `spec`: in strict mode, for
+    /// tunnels tagged `prod` or `<key>:prod`, such as `env:prod`.
+    pub fn needs_confirmation(&self, spec: &TunnelSpec) -> bool {
+        self.strict
+            && spec
+                .tags
+                .iter()
+                .any(|tag| tag == "prod" || tag.ends_with(":prod"))
+    }
+
+    /// The profile `name` with what it extends folded in: its settings
+    /// override those of the parent, and its tunnels replace the parent's
+    /// tunnels on the same local port.
+    pub fn profile(&self, name: &str) -> Result<Profile> {
+        self.resolve_profile(name, &mut Vec::new())
+    }
+
+    fn resolve_profile(&self, name: &str, chain: &mut Vec<String>) -> Result<Profile> {
+        let profile = self.profiles.get(name).ok_or_else(|| {
+            Failure::new(ErrorCode::Config, format!("Unknown profile '{}'", name))
+        })?;
+        chain.push(name.to_string());
+        let Some(parent) = &profile.extends else {
+            return Ok(profile.clone());
+        };
+        if chain.contains(parent) {
+            let message = format!(
+                "Profile '{}' extends itself: {} -> {}",
+                parent,
+                chain.join(" -> "),
+                parent
+            );
+            return Err(Failure::new(ErrorCode::Config, message).into());
+        }
+        let parent = self.resolve_profile(parent, chain)?;
+        let mut knock = parent.knock;
+        knock.extend(profile.knock.clone());
+        let mut host_keys = parent.host_keys;
+        host_keys.extend(profile.host_keys.clone());
+        let mut env = parent.env;
+        env.extend(profile.env.clone());
+        let mut tags = parent.tags;
+        for tag in profile.tags.iter() {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        Ok(Profile {
+            extends: None,
+            reconnect: profile.reconnect.or(parent.reconnect),
+            schedule: profile.schedule.clone().or(parent.schedule),
+            tags,
+            requires_vpn: profile.requires_vpn.clone().or(parent.requires_vpn),
+            knock,
+            host_keys,
+            env,
+            tunnels: merge_tunnels(parent.tunnels, profile.tunnels.clone()),
+        })
+    }
+
+    /// Tunnels of the profiles `names` opened together. A tunnel of a later
+    /// profile replaces one of an earlier profile on the same local port.
+    pub fn compose_profiles(&self, names: &[String]) -> Result<Vec<TunnelSpec>> {
+        let mut tunnels = Vec::new();
+        for name in names {
+            tunnels = merge_tunnels(tunnels, self.profile_tunnels(name)?);
+        }
+        Ok(tunnels)
+    }
+
+    /// Tunnels of the profile `name`, with the profile's settings applied.
+    pub fn profile_tunnels(&self, name: &str) -> Result<Vec<TunnelSpec>> {
+        let profile = self.profile(name)?;
+        Ok(profile
+            .tunnels
+            .iter()
+            .cloned()
+            .map(|mut spec| {
+                spec.reconnect = spec.reconnect.or(profile.reconnect);
+                if spec.schedule.is_none() {
+                    spec.schedule = profile.schedule.clone();
+                }
+                for tag in profile.tags.iter() {
+                    if !spec.tags.contains(tag) {
+                        spec.tags.push(tag.clone());
+                    }
+                }
+                if spec.requires_vpn.is_none() {
+                    spec.requires_vpn = profile.requires_vpn.clone();
+                }
+                if spec.knock.is_none() {
+                    spec.knock = profile.knock.get(spec.first_hop()).cloned();
+                }
+                if spec.host_key.is_none() {
+                    spec.host_key = profile.host_keys.get(&spec.host).cloned();
+                }
+                spec
+            })
+            .collect())
+    }
+}
+
+/// The tunnels listed in `path` for `--from-file`, one
+/// `HOST LOCAL:REMOTE[:DEST] # label` per line, as kept in runbooks. Lines
+/// that do not parse are all reported at once.
+pub fn tunnels_from_file(path: &Path) -> Result<Vec<TunnelSpec>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    let mut tunnels = Vec::new();
+    let mut mistakes = Vec::new();
+    for (line, spec) in TunnelSpec::parse_list(&contents) {
+        match spec {
+            Ok(spec) => tunnels.push(spec),
+            Err(e) => mistakes.push(format!("{} line {}: {}", path.display(), line, e)),
+        }
+    }
+    if !mistakes.is_empty() {
+        return Err(Failure::new(ErrorCode::Config, mistakes.join("\n")).into());
+    }
+    Ok(tunnels)
+}
+
+/// Keeps `base`, except for tunnels on a local port `overrides` uses too,
+/// then adds `overrides`.
+pub fn merge_tunnels(base: Vec<TunnelSpec>, overrides: Vec<TunnelSpec>) -> Vec<TunnelSpec> {
+    let mut tunnels: Vec<TunnelSpec> = base
+        .into_iter()
+        .filter(|spec| !overrides.iter().any(|o| o.local_port == spec.local_port))
+        .collect();
+    tunnels.extend(overrides);
+    tunnels
+}
+
+/// A named set of tunnels opened together with `rtun up <profile>`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Profile whose settings and tunnels this one starts from.
+    pub extends: Option<String>,
+    pub reconnect: ReconnectConfig,
+    /// Schedule for tunnels of the profile that do not set their own.
+    pub schedule: Option<Schedule>,
+    /// Tags added to every tunnel of the profile.
+    pub tags: Vec<String>,
+    /// VPN the tunnels of the profile wait for, unless they name their own.
+    pub requires_vpn: Option<String>,
+    /// Port knocking sequences, by ssh host, overriding the global ones.
+    pub knock: BTreeMap<String, KnockConfig>,
+    /// Host key checking, by ssh host, overriding the global settings.
+    pub host_keys: BTreeMap<String, HostKeyConfig>,
+    /// Environment variables printed by `rtun env`, as templates where
+    /// `{NAME.port}` stands for the local port of the tunnel named NAME.
+    pub env: BTreeMap<String, String>,
+    pub tunnels: Vec<TunnelSpec>,
+}
+
+/// Reconnect settings as written in the config; unset fields fall back to
+/// the enclosing profile, then to the global `[reconnect]` table.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// Attempts before the tunnel is marked failed.
+    pub max_attempts: Option<u32>,
+    /// Never give up, ignoring `max_attempts`.
+    pub forever: Option<bool>,
+    /// Delay before the first retry, in seconds. Doubles on every attempt.
+    pub backoff_base: Option<u64>,
+    /// Upper bound for the delay between retries, in seconds.
+    pub backoff_cap: Option<u64>,
+}
+
+impl ReconnectConfig {
+    /// Fills the fields unset in `self` from `fallback`.
+    pub fn or(self, fallback: ReconnectConfig) -> Self {
+        ReconnectConfig {
+            max_attempts: self.max_attempts.or(fallback.max_attempts),
+            forever: self.forever.or(fallback.forever),
+            backoff_base: self.backoff_base.or(fallback.backoff_base),
+            backoff_cap: self.backoff_cap.or(fallback.backoff_cap),
+        }
+    }
+
+    pub fn policy(self) -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: self.max_attempts.unwrap_or(5),
+            forever: self.forever.unwrap_or(false),
+            backoff_base: Duration::from_secs(self.backoff_base.unwrap_or(2)),
+            backoff_cap: Duration::from_secs(self.backoff_cap.unwrap_or(60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub forever: bool,
+    pub backoff_base: Duration,
+    pub backoff_cap: Duration,
+}
+
+impl ReconnectPolicy {
+    pub fn gives_up_after(&self, attempt: u32) -> bool {
+        !self.forever && attempt > self.max_attempts
+    }
+
+    /// Delay before reconnect `attempt`, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.backoff_base
+            .saturating_mul(factor)
+            .min(self.backoff_cap)
+    }
+}
+
+/// Periodic probes of a forwarded port, see [`crate::health`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    /// Seconds between probes.
+    pub interval: u64,
+    /// Failed probes in a row before the forward is restarted on the next target.
+    pub failures: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        HealthCheckConfig {
+            interval: 10,
+            failures: 3,
+        }
+    }
+}
+
+/// Checks that the ssh session still carries data, in the manner of
+/// autossh, restarting ssh once it is wedged; see [`crate::watchdog`].
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Seconds between checks.
+    pub interval: u64,
+    /// Failed checks in a row before ssh is restarted.
+    pub failures: u32,
+    /// Sends test data through a loop of forwards over this port and the
+    /// next one, as `autossh -M`. Without it, asks ssh with `ssh -O check`
+    /// through a control socket.
+    pub monitor_port: Option<u16>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            interval: 30,
+            failures: 2,
+            monitor_port: None,
+        }
+    }
+}
+
+/// How often rtun does its periodic work. Longer intervals save battery,
+/// shorter ones notice changes sooner.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IntervalsConfig {
+    /// Milliseconds between redraws of the TUI.
+    pub ui_tick: u64,
+    /// Seconds between health probes of tunnels not setting their own.
+    pub health_check: u64,
+    /// Seconds between throughput samples of relayed tunnels.
+    pub stats_sample: u64,
+    /// Seconds between looks for clients of tunnels with an idle timeout.
+    pub idle_check: u64,
+    /// Seconds between reads of ssh certificates, which get renewed behind
+    /// rtun's back.
+    pub certificate_check: u64,
+}
+
+impl Default for IntervalsConfig {
+    fn default() -> Self {
+        IntervalsConfig {
+            ui_tick: 16,
+            health_check: HealthCheckConfig::default().interval,
+            stats_sample: 1,
+            idle_check: 30,
+            certificate_check: 60,
+        }
+    }
+}
+
+impl IntervalsConfig {
+    pub fn ui_tick(&self) -> Duration {
+        Duration::from_millis(self.ui_tick.max(1))
+    }
+
+    pub fn stats_sample(&self) -> Duration {
+        Duration::from_secs(self.stats_sample.max(1))
+    }
+
+    pub fn idle_check(&self) -> Duration {
+        Duration::from_secs(self.idle_check.max(1))
+    }
+
+    pub fn certificate_check(&self) -> Duration {
+        Duration::from_secs(self.certificate_check.max(1))
+    }
+}
+
+/// Ports to knock on before connecting to a host protected by knockd.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct KnockConfig {
+    /// Address to knock on, when the ssh host is an alias.
+    #[serde(default)]
+    pub address: Option<String>,
+    pub sequence: Vec<Knock>,
+    /// Milliseconds between knocks.
+    #[serde(default = "default_knock_delay")]
+    pub delay: u64,
+}
+
+fn default_knock_delay() -> u64 {
+    200
+}
+
+/// How ssh checks the key of a host.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostKeyConfig {
+    /// `StrictHostKeyChecking` of ssh; its own setting when unset.
+    pub policy: Option<HostKeyPolicy>,
+    /// Known hosts file to use instead of `~/.ssh/known_hosts`, such as one
+    /// checked into a project. Relative paths start at the directory rtun
+    /// runs in.
+    pub known_hosts: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// Only keys already in the known hosts file.
+    Strict,
+    /// Adds the keys of new hosts, refuses changed ones.
+    AcceptNew,
+    /// No checks at all, with a warning in the TUI.
+    Off,
+}
+
+impl std::fmt::Display for HostKeyPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HostKeyPolicy::Strict => "strict",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Off => "off",
+        })
+    }
+}
+
+/// Reverse proxy routing `<name>.localhost` to the tunnel called `name`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpProxyConfig {
+    pub port: u16,
+}
+
+/// Proxy auto-config file sending the `pac_domains` of SOCKS tunnels
+/// through them.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PacConfig {
+    pub port: u16,
+    /// Point the system proxy settings at the PAC file while rtun runs.
+    #[serde(default)]
+    pub set_system_proxy: bool,
+    /// macOS network service whose proxy settings are changed.
+    #[serde(default = "default_network_service")]
+    pub network_service: String,
+}
+
+/// How `tls_port` reaches a forward that speaks TLS itself, such as a web
+/// app with a certificate from an internal CA: by encrypting again, towards
+/// it.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsBackendConfig {
+    /// PEM file of the CA that must have issued the certificate of the
+    /// forward; the CAs the system trusts without one.
+    pub ca: Option<PathBuf>,
+    /// Name the certificate must be for, the destination's by default.
+    pub server_name: Option<String>,
+    /// Takes any certificate, for services whose CA is not at hand. Strict
+    /// mode refuses it.
+    pub insecure: bool,
+}
+
+/// The database client the quick-connect action launches for a forward.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Guessed from the label, the name and the remote port when unset.
+    pub kind: Option<Database>,
+    pub user: Option<String>,
+    /// Database name, or number for Redis.
+    pub database: Option<String>,
+    /// Whole command line instead of the usual one, with `{local_port}`,
+    /// `{user}` and `{database}` replaced with shell-quoted values.
+    pub command: Option<String>,
+}
+
+/// DNS stub answering for the `pac_domains` of SOCKS tunnels, by asking
+/// `server` through them, and refusing other names.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DnsConfig {
+    pub port: u16,
+    /// Resolver as the ssh hosts reach it, such as `10.0.0.2` or
+    /// `10.0.0.2:53`.
+    pub server: String,
+}
+
+fn default_network_service() -> String {
+    "Wi-Fi".to_string()
+}
+
+/// How to tell that a VPN is connected. Every condition given must hold.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VpnConfig {
+    /// Network interface that exists while connected, e.g. `utun3` or `tun0`.
+    pub interface: Option<String>,
+    /// `host:port` only reachable through the VPN.
+    pub probe: Option<String>,
+    /// Shell command exiting successfully while connected.
+    pub command: Option<String>,
+}
+
+/// How rtun answers the password and passphrase prompts of ssh for a host.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub password: Option<Secret>,
+    /// Shorthand for `password = { command = "..." }`.
+    pub password_cmd: Option<String>,
+    /// Private key to log in with, passed to ssh as `-i`.
+    pub identity: Option<PathBuf>,
+    /// Certificate signed for `identity`, e.g. by Vault or step-ca.
+    pub certificate: Option<PathBuf>,
+    /// Minutes before the certificate expires to start warning, 60 by
+    /// default.
+    pub warn_before: Option<u64>,
+}
+
+impl AuthConfig {
+    pub fn password(&self) -> Option<Secret> {
+        self.password
+            .clone()
+            .or_else(|| self.password_cmd.clone().map(Secret::Command))
+    }
+
+    pub fn certificate_warning(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.warn_before.unwrap_or(60) as i64)
+    }
+}
+
+/// The ssh client, for when the `ssh` on PATH is not the one with access,
+/// overridden by host:
+///
+/// ```toml
+/// [ssh]
+/// program = "/opt/homebrew/bin/ssh"
+///
+/// [ssh.hosts.prod-db]
+/// program = "tsh"
+/// args = ["ssh", "-N", "{options}", "{forwards}", "{host}"]
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    /// `ssh` by default.
+    pub program: Option<PathBuf>,
+    /// Arguments of the program, see [`SshClient::args`].
+    pub args: Option<Vec<String>>,
+    /// See [`SshClient::multiplex`].
+    pub multiplex: Option<bool>,
+    pub hosts: BTreeMap<String, SshClient>,
+}
+
+impl SshConfig {
+    /// The client of hosts without settings of their own.
+    pub fn default_client(&self) -> SshClient {
+        SshClient {
+            program: self.program.clone(),
+            args: self.args.clone(),
+            multiplex: self.multiplex,
+        }
+    }
+
+    /// The client for `host`, its own settings over the global ones.
+    pub fn client(&self, host: &str) -> SshClient {
+        let own = self.hosts.get(host).cloned().unwrap_or_default();
+        let default = self.default_client();
+        SshClient {
+            program: own.program.or(default.program),
+            args: own.args.or(default.args),
+            multiplex: own.multiplex.or(default.multiplex),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshClient {
+    pub program: Option<PathBuf>,
+    /// Arguments of the program, in which `{host}` is replaced by the host,
+    /// and the lone arguments `{forwards}` and `{options}` by the `-L` and
+    /// `-D` forwards and by the OpenSSH options rtun passes. Without it,
+    /// the program is run like OpenSSH, as
+    /// `-N {options} {forwards} {host}`. A template without `{options}`
+    /// drops host key policies, jump hosts and identities, and strict mode
+    /// refuses its hosts.
+    pub args: Option<Vec<String>>,
+    /// Adds the forwards of tunnels to an ssh already connected to the host,
+    /// with `ssh -O forward`, instead of starting another one. rtun starts
+    /// that master unless the user's ssh config has one running. OpenSSH
+    /// only.
+    pub multiplex: Option<bool>,
+}
+
+impl SshClient {
+    pub fn program(&self) -> PathBuf {
+        self.program.clone().unwrap_or_else(|| PathBuf::from("ssh"))
+    }
+
+    /// Whether the program takes the arguments of OpenSSH, so rtun can ask
+    /// it for the host's settings with `-G`.
+    pub fn is_openssh(&self) -> bool {
+        self.args.is_none()
+    }
+
+    pub fn multiplexes(&self) -> bool {
+        self.multiplex.unwrap_or(false) && self.is_openssh()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// The order tunnels were opened in.
+    #[default]
+    Opened,
+    Host,
+    LocalPort,
+    Status,
+    /// Longest running first.
+    Uptime,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Opened => SortOrder::Host,
+            SortOrder::Host => SortOrder::LocalPort,
+            SortOrder::LocalPort => SortOrder::Status,
+            SortOrder::Status => SortOrder::Uptime,
+            SortOrder::Uptime => SortOrder::Opened,
+        }
+    }
+
+    /// Name as written in the config.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortOrder::Opened => "opened",
+            SortOrder::Host => "host",
+            SortOrder::LocalPort => "local_port",
+            SortOrder::Status => "status",
+            SortOrder::Uptime => "uptime",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertEvent {
+    /// The ssh process of a tunnel exited unexpectedly.
+    Down,
+    /// A tunnel ran out of reconnect attempts and was given up on.
+    Failed,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Shell command run on alerts. Placeholders are replaced with shell-quoted values.
+    pub command: Option<String>,
+    /// URL that receives a JSON POST on alerts.
+    pub webhook: Option<String>,
+    /// Template for the human readable alert text.
+    pub message: String,
+    /// Which events trigger the alert.
+    pub on: Vec<AlertEvent>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        AlertConfig {
+            command: None,
+            webhook: None,
+            message: "rtun: tunnel {host} {local_port}:{remote_port} {event}: {error}".to_string(),
+            on: vec![AlertEvent::Down, AlertEvent::Failed],
+        }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(RTUN_CONFIG_PATH))
+}
+
+/// Loads `~/.rtun/config.toml`, falling back to defaults when it does not exist.
+pub fn load() -> Result<Config> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => parse(&contents, &path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).wrap_err_with(|| {
+            Failure::new(
+                ErrorCode::Config,
+                format!("Could not read {}", path.display()),
+            )
+        }),
+    }
+}
+
+/// Parses and validates the contents of the config file at `path`. Mistakes
+/// are reported with their line and column, see [`diagnose`].
+pub fn parse(contents: &str, path: &Path) -> Result<Config> {
+    let config: Config = toml::from_str(contents)
+        .map_err(|e| Failure::new(ErrorCode::Config, diagnose(path, contents, &e)))?;
+    validate(&config).map_err(|e| Failure::new(ErrorCode::Config, format!("{:#}", e)))?;
+    Ok(config)
+}
+
+fn validate(config: &Config) -> Result<()> {
+    if let Some(locale) = config.locale.as_deref() {
+        if i18n::find(locale).is_none() {
+            let known: Vec<_> = i18n::locales().collect();
+            return Err(eyre!(
+                "Unknown locale '{}', expected one of {}",
+                locale,
+                known.join(", ")
+            ));
+        }
+    }
+    for (name, profile) in config.profiles.iter() {
+        let resolved = config.profile(name)?;
+        exports::render(&resolved.env, &exports::endpoints(&resolved.tunnels))
+            .map_err(|e| eyre!("Invalid env in profile '{}': {}", name, e))?;
+        let schedules = profile.schedule.iter().chain(
+            profile
+                .tunnels
+                .iter()
+                .filter_map(|tunnel| tunnel.schedule.as_ref()),
+        );
+        for schedule in schedules {
+            schedule
+                .validate()
+                .wrap_err_with(|| format!("Invalid schedule in profile '{}'", name))?;
+        }
+        for hostname in profile.tunnels.iter().flat_map(|t| t.hostnames.iter()) {
+            if !hostsfile::is_valid(hostname) {
+                return Err(eyre!(
+                    "Invalid hostname '{}' in profile '{}'",
+                    hostname,
+                    name
+                ));
+            }
+        }
+        let insecure_with_ca = profile.tunnels.iter().find(|t| {
+            t.tls_backend
+                .as_ref()
+                .is_some_and(|tls| tls.insecure && tls.ca.is_some())
+        });
+        if let Some(tunnel) = insecure_with_ca {
+            return Err(eyre!(
+                "Tunnel '{}' in profile '{}' sets both a ca and insecure in tls_backend",
+                tunnel,
+                name
+            ));
+        }
+        for tunnel in profile.tunnels.iter().filter(|t| t.client.is_some()) {
+            match dbclient::command(tunnel) {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    return Err(eyre!(
+                        "Tunnel '{}' in profile '{}' has a client but no database; set its kind to postgres, mysql or redis",
+                        tunnel,
+                        name
+                    ))
+                }
+                Err(e) => return Err(eyre!("{} in profile '{}'", e, name)),
+            }
+        }
+        let vpns = profile.requires_vpn.iter().chain(
+            profile
+                .tunnels
+                .iter()
+                .filter_map(|tunnel| tunnel.requires_vpn.as_ref()),
+        );
+        for vpn in vpns {
+            if !config.vpn.contains_key(vpn) {
+                return Err(eyre!("Profile '{}' requires unknown VPN '{}'", name, vpn));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Describes a parse error as `path:line:column: message`, followed by the
+/// offending line, and suggests the closest key or value when the error is
+/// about an unknown one.
+pub fn diagnose(path: &Path, contents: &str, error: &toml::de::Error) -> String {
+    let message = error.message().trim();
+    let message = match suggestion(message) {
+        Some(known) => format!(
+            "{}; did you mean `{}`?",
+            message.split_once(", expected").map_or(message, |(m, _)| m),
+            known
+        ),
+        None => message.to_string(),
+    };
+    let Some(span) = error.span() else {
+        return format!("{}: {}", path.display(), message);
+    };
+    let start = span.start.min(contents.len());
+    let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_number = contents[..start].matches('\n').count() + 1;
+    let column = contents[line_start..start].chars().count() + 1;
+    let line = contents[line_start..].lines().next().unwrap_or_default();
+    let width = contents[start..span.end.clamp(start, line_start + line.len())]
+        .chars()
+        .count()
+        .max(1);
+    let gutter = " ".repeat(line_number.to_string().len());
+    format!(
+        "{}:{}:{}: {}\n{} |\n{} | {}\n{} | {}{}",
+        path.display(),
+        line_number,
+        column,
+        message,
+        gutter,
+        line_number,
+        line,
+        gutter,
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    )
+}
+
+/// For serde's "unknown field `x`, expected one of `a`, `b`" errors, the
+/// expected name closest to the unknown one, if it is close enough to be a
+/// typo.
+fn suggestion(message: &str) -> Option<&str> {
+    if !message.starts_with("unknown field") && !message.starts_with("unknown variant") {
+        return None;
+    }
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+    let limit = (unknown.chars().count() / 3).max(1);
+    quoted
+        .map(|known| (edit_distance(unknown, known), known))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+/// Levenshtein distance, ignoring case.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Writes `sort` to the config file, keeping the rest of it as it is.
+pub fn save_sort(sort: SortOrder) -> Result<()> {
+    let path = config_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", path.display())),
+    };
+    let mut document: toml_edit::DocumentMut = contents
+        .parse()
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+    document["sort"] = toml_edit::value(sort.as_str());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, document.to_string())
+        .wrap_err_with(|| format!("Could not write {}", path.display()))
+}
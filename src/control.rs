@@ -0,0 +1,630 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::errors::{ErrorCode, Failure};
+use crate::events::Event;
+use crate::status::TunnelInfo;
+use crate::tunnel::TunnelSpec;
+
+const SOCKET_PATH: &str = ".rtun/rtun.sock";
+
+/// Version of the protocol below, raised on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Methods of the JSON-RPC 2.0 API on the control socket. Requests and
+/// responses are one JSON object per line, several per connection.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    /// The protocol version and the version of rtun.
+    Version,
+    /// The tunnels and their status.
+    List,
+    /// Opens a tunnel, owned by the caller.
+    Add(NewTunnel),
+    /// Opens the tunnels of a profile whose local ports are free, owned by
+    /// the caller.
+    Up { profile: String },
+    /// Turns the connection into a stream of `event` notifications, one
+    /// per lifecycle event of a tunnel.
+    Subscribe,
+    /// Closes the tunnels matching all the given criteria.
+    Close {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        local_port: Option<u16>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    },
+    /// Points the tunnel on `local_port` at `target`, as `host:port`. Its
+    /// open connections stay on the old destination until they are over.
+    Retarget { local_port: u16, target: String },
+    /// Stops the tunnels matching all the given criteria from taking new
+    /// connections, closing each once its open ones are over.
+    Drain {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        local_port: Option<u16>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    },
+    /// Closes the tunnels on `local_ports` once the process `pid` exits.
+    Tie { pid: u32, local_ports: Vec<u16> },
+}
+
+impl Request {
+    pub const METHODS: [&'static str; 9] = [
+        "version",
+        "list",
+        "add",
+        "up",
+        "subscribe",
+        "close",
+        "drain",
+        "retarget",
+        "tie",
+    ];
+
+    pub fn required_permission(&self) -> Permission {
+        match self {
+            Request::Version | Request::List | Request::Subscribe => Permission::Observe,
+            Request::Add(_)
+            | Request::Up { .. }
+            | Request::Close { .. }
+            | Request::Drain { .. }
+            | Request::Retarget { .. }
+            | Request::Tie { .. } => Permission::ManageOwn,
+        }
+    }
+
+    /// Name of the method, as in `METHODS`.
+    pub fn method(&self) -> &'static str {
+        match self {
+            Request::Version => "version",
+            Request::List => "list",
+            Request::Add(_) => "add",
+            Request::Up { .. } => "up",
+            Request::Subscribe => "subscribe",
+            Request::Close { .. } => "close",
+            Request::Drain { .. } => "drain",
+            Request::Retarget { .. } => "retarget",
+            Request::Tie { .. } => "tie",
+        }
+    }
+}
+
+/// Parameters of `add`, the fields of a tunnel in the config file that make
+/// sense for a tunnel opened on the fly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct NewTunnel {
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// Where `remote_port` lives, as seen from `host`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl From<NewTunnel> for TunnelSpec {
+    fn from(tunnel: NewTunnel) -> Self {
+        TunnelSpec {
+            remote_host: tunnel.remote_host,
+            name: tunnel.name,
+            label: tunnel.label,
+            tags: tunnel.tags,
+            ..TunnelSpec::new(&tunnel.host, tunnel.local_port, tunnel.remote_port)
+        }
+    }
+}
+
+/// Result of `version`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    pub protocol: u32,
+    pub rtun: String,
+    /// The methods the instance answers, empty from instances that predate
+    /// this field.
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        VersionInfo {
+            protocol: PROTOCOL_VERSION,
+            rtun: env!("CARGO_PKG_VERSION").to_string(),
+            methods: Request::METHODS.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    /// Why the instance answering with this version cannot take `request`
+    /// from this rtun, if it cannot.
+    pub fn incompatibility(&self, request: &Request) -> Option<Failure> {
+        let ours = env!("CARGO_PKG_VERSION");
+        if self.protocol > PROTOCOL_VERSION {
+            return Some(Failure::new(
+                ErrorCode::Incompatible,
+                format!(
+                    "The running rtun {} is newer than this one ({}); upgrade this rtun, or restart the instance with it",
+                    self.rtun, ours
+                ),
+            ));
+        }
+        let method = request.method();
+        let lacks_method = !self.methods.is_empty() && !self.methods.iter().any(|m| m == method);
+        (self.protocol < PROTOCOL_VERSION || lacks_method).then(|| self.too_old(method))
+    }
+
+    /// Whether `error` from this instance means it did not know the method,
+    /// which instances without a list of methods only tell that way. Other
+    /// errors, such as invalid parameters, are answers to the request.
+    fn rejects(&self, error: &RpcError) -> bool {
+        self.methods.is_empty() && error.code == RpcError::METHOD_NOT_FOUND
+    }
+
+    /// Failure telling the instance is too old for `method`.
+    pub fn too_old(&self, method: &str) -> Failure {
+        Failure::new(
+            ErrorCode::Incompatible,
+            format!(
+                "The running rtun {} is too old for `{}` (this is rtun {}); restart it",
+                self.rtun,
+                method,
+                env!("CARGO_PKG_VERSION")
+            ),
+        )
+    }
+}
+
+/// Result of `list`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TunnelList {
+    pub tunnels: Vec<TunnelInfo>,
+}
+
+/// Result of `up`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Opened {
+    pub opened: usize,
+    /// Their local ports; missing from instances before `tie`.
+    #[serde(default)]
+    pub local_ports: Vec<u16>,
+}
+
+/// Result of `close`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Closed {
+    pub closed: usize,
+}
+
+/// Result of `drain`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Draining {
+    pub draining: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const PERMISSION_DENIED: i64 = -32000;
+    pub const SHUTTING_DOWN: i64 = -32001;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// What the instance answers to a request.
+pub type Reply = std::result::Result<Value, RpcError>;
+
+/// Turns a method result into a reply.
+pub fn reply(result: &impl Serialize) -> Reply {
+    serde_json::to_value(result).map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e.to_string()))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    fn new(id: Value, reply: Reply) -> Self {
+        let (result, error) = match reply {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// A message from the instance that answers no request.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Notification<T> {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: T,
+}
+
+/// Splits a request line into its id and request. Requests without an id
+/// are notifications, which get no response.
+fn parse(line: &str) -> (Option<Value>, std::result::Result<Request, RpcError>) {
+    let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(line) else {
+        return (
+            Some(Value::Null),
+            Err(RpcError::new(RpcError::PARSE_ERROR, "Invalid JSON")),
+        );
+    };
+    let id = object.remove("id");
+    if object.remove("jsonrpc") != Some(Value::from("2.0")) {
+        let error = RpcError::new(RpcError::INVALID_REQUEST, "Expected \"jsonrpc\": \"2.0\"");
+        return (id, Err(error));
+    }
+    let method = object
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    if !Request::METHODS.contains(&method) {
+        let error = RpcError::new(
+            RpcError::METHOD_NOT_FOUND,
+            format!("Unknown method '{}'", method),
+        );
+        return (id, Err(error));
+    }
+    if object.get("params") == Some(&Value::Null) {
+        object.remove("params");
+    }
+    let request = serde_json::from_value(Value::Object(object))
+        .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e.to_string()));
+    (id, request)
+}
+
+/// What a user connecting to the control socket may do, from least to most.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    /// See tunnels and their status.
+    Observe,
+    /// Also change the tunnels the user opened.
+    ManageOwn,
+    /// Change any tunnel.
+    ManageAll,
+}
+
+/// The control socket, shared with other users on a common jump host:
+///
+/// ```toml
+/// [control]
+/// path = "/srv/rtun/rtun.sock"
+/// users = { alice = "manage-all", bob = "manage-own", "*" = "observe" }
+/// ```
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Socket location, `~/.rtun/rtun.sock` by default.
+    pub path: Option<PathBuf>,
+    /// Permissions of other users by user name, `*` for everyone else. The
+    /// user running rtun and root may always do everything.
+    pub users: BTreeMap<String, Permission>,
+}
+
+impl ControlConfig {
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        self.path
+            .clone()
+            .or_else(|| env::home_dir().map(|home| home.join(SOCKET_PATH)))
+    }
+
+    /// What the user `uid` may do, if anything.
+    pub fn permission(&self, uid: u32) -> Option<Permission> {
+        if uid == 0 || uid == unsafe { libc::geteuid() } {
+            return Some(Permission::ManageAll);
+        }
+        user_name(uid)
+            .and_then(|name| self.users.get(&name))
+            .or_else(|| self.users.get("*"))
+            .copied()
+    }
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Who sent a request, from the peer credentials of the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caller {
+    pub uid: u32,
+    pub permission: Permission,
+}
+
+/// A request waiting for the instance to handle it.
+pub type Pending = (Request, Caller, oneshot::Sender<Reply>);
+
+/// Listens on the control socket and hands the requests callers are allowed
+/// to make to `requests`. Removes the socket when dropped.
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Only call this while holding the instance lock: a leftover socket is
+    /// assumed to be stale and replaced. Subscribers get the events sent to
+    /// `changes`.
+    pub fn start(
+        config: &ControlConfig,
+        requests: mpsc::UnboundedSender<Pending>,
+        changes: broadcast::Sender<Event>,
+    ) -> Result<Self> {
+        let path = config
+            .socket_path()
+            .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .wrap_err_with(|| format!("Could not listen on {}", path.display()))?;
+        if !config.users.is_empty() {
+            // Other users need write access to connect; the permissions
+            // are enforced per request instead.
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o666))?;
+        }
+        let config = config.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle(
+                    stream,
+                    config.clone(),
+                    requests.clone(),
+                    changes.clone(),
+                ));
+            }
+        });
+        Ok(ControlServer { path })
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+async fn handle(
+    stream: tokio::net::UnixStream,
+    config: ControlConfig,
+    requests: mpsc::UnboundedSender<Pending>,
+    changes: broadcast::Sender<Event>,
+) {
+    let Ok(uid) = stream.peer_cred().map(|cred| cred.uid()) else {
+        return;
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    let mut subscription: Option<broadcast::Receiver<Event>> = None;
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    return;
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (id, request) = parse(&line);
+                let subscribe = request == Ok(Request::Subscribe);
+                let reply = match request {
+                    Ok(request) => answer(request, uid, &config, &requests).await,
+                    Err(error) => Err(error),
+                };
+                if subscribe && reply.is_ok() {
+                    subscription = Some(changes.subscribe());
+                }
+                let Some(id) = id else {
+                    continue;
+                };
+                serde_json::to_value(Response::new(id, reply))
+            }
+            event = next_event(&mut subscription) => serde_json::to_value(Notification {
+                jsonrpc: "2.0".to_string(),
+                method: "event".to_string(),
+                params: event,
+            }),
+        };
+        let Ok(mut message) = message.map(|m| m.to_string()) else {
+            continue;
+        };
+        message.push('\n');
+        if writer.write_all(message.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The next event of a subscribed connection; never without a subscription.
+async fn next_event(subscription: &mut Option<broadcast::Receiver<Event>>) -> Event {
+    let Some(events) = subscription else {
+        return std::future::pending().await;
+    };
+    loop {
+        match events.recv().await {
+            Ok(event) => return event,
+            // A slow subscriber misses events rather than holding up rtun.
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+async fn answer(
+    request: Request,
+    uid: u32,
+    config: &ControlConfig,
+    requests: &mpsc::UnboundedSender<Pending>,
+) -> Reply {
+    let permission = config
+        .permission(uid)
+        .filter(|&p| p >= request.required_permission())
+        .ok_or_else(|| RpcError::new(RpcError::PERMISSION_DENIED, "Permission denied"))?;
+    match request {
+        Request::Version => return reply(&VersionInfo::current()),
+        Request::Subscribe => return Ok(Value::Bool(true)),
+        _ => {}
+    }
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let _ = requests.send((request, Caller { uid, permission }, reply_tx));
+    reply_rx.await.unwrap_or_else(|_| {
+        Err(RpcError::new(
+            RpcError::SHUTTING_DOWN,
+            "rtun is shutting down",
+        ))
+    })
+}
+
+type Lines = std::io::Lines<BufReader<UnixStream>>;
+
+/// Sends `request` on a connection and reads its reply.
+fn exchange(stream: &mut UnixStream, lines: &mut Lines, request: &Request) -> Result<Reply> {
+    let mut message = serde_json::to_value(request)?;
+    message["jsonrpc"] = Value::from("2.0");
+    message["id"] = Value::from(1);
+    let mut line = message.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let response = lines
+        .next()
+        .ok_or_else(|| eyre!("rtun closed the connection"))??;
+    let response: Response =
+        serde_json::from_str(&response).wrap_err("Invalid response from rtun")?;
+    Ok(match response.error {
+        Some(error) => Err(error),
+        None => Ok(response.result.unwrap_or_default()),
+    })
+}
+
+/// Connects to the running instance and sends `request`, returning its reply
+/// and the rest of the connection. The versions are compared first, so that
+/// an instance from another release fails with a clear error instead of
+/// misreading the request.
+fn connect(config: &ControlConfig, request: &Request) -> Result<(Reply, Lines)> {
+    let path = config
+        .socket_path()
+        .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    let mut stream = UnixStream::connect(&path)
+        .wrap_err_with(|| Failure::new(ErrorCode::NotRunning, "rtun is not running"))?;
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    if *request == Request::Version {
+        let reply = exchange(&mut stream, &mut lines, request)?;
+        return Ok((reply, lines));
+    }
+    let version = exchange(&mut stream, &mut lines, &Request::Version)?
+        .ok()
+        .and_then(|version| serde_json::from_value::<VersionInfo>(version).ok());
+    if let Some(failure) = version.as_ref().and_then(|v| v.incompatibility(request)) {
+        return Err(failure.into());
+    }
+    let reply = exchange(&mut stream, &mut lines, request)?;
+    if let (Err(error), Some(version)) = (&reply, &version) {
+        // Instances without a list of methods only tell by failing.
+        if version.rejects(error) {
+            return Err(version.too_old(request.method()).into());
+        }
+    }
+    Ok((reply, lines))
+}
+
+/// Sends `request` to the running instance and waits for its reply. Fails
+/// when rtun is not running; errors of the request itself are in the reply.
+pub fn send(config: &ControlConfig, request: &Request) -> Result<Reply> {
+    connect(config, request).map(|(reply, _)| reply)
+}
+
+/// Sends `request` and decodes its result, turning errors into reports.
+pub fn call<T: DeserializeOwned>(config: &ControlConfig, request: &Request) -> Result<T> {
+    let result = send(config, request)?.map_err(|e| eyre!(e.message))?;
+    serde_json::from_value(result).wrap_err("Invalid response from rtun")
+}
+
+/// The events of the running instance, as they happen, until it exits.
+pub struct Subscription {
+    lines: Lines,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(
+            serde_json::from_str::<Notification<Event>>(&line)
+                .map(|notification| notification.params)
+                .wrap_err("Invalid event from rtun"),
+        )
+    }
+}
+
+/// Subscribes to the events of the running instance.
+pub fn subscribe(config: &ControlConfig) -> Result<Subscription> {
+    let (reply, lines) = connect(config, &Request::Subscribe)?;
+    reply.map_err(|e| eyre!(e.message))?;
+    Ok(Subscription { lines })
+}
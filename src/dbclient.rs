@@ -0,0 +1,132 @@
+use serde::Deserialize;
+
+use crate::platform;
+use crate::tunnel::TunnelSpec;
+
+/// Databases whose command-line client the quick-connect action launches.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Database {
+    Postgres,
+    Mysql,
+    Redis,
+}
+
+impl Database {
+    pub fn program(&self) -> &'static str {
+        match self {
+            Database::Postgres => "psql",
+            Database::Mysql => "mysql",
+            Database::Redis => "redis-cli",
+        }
+    }
+
+    fn from_text(text: &str) -> Option<Database> {
+        let text = text.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| text.contains(word));
+        if has(&["postgres", "psql"]) {
+            Some(Database::Postgres)
+        } else if has(&["mysql", "mariadb"]) {
+            Some(Database::Mysql)
+        } else if has(&["redis", "valkey"]) {
+            Some(Database::Redis)
+        } else {
+            None
+        }
+    }
+
+    fn from_port(port: u16) -> Option<Database> {
+        match port {
+            5432 => Some(Database::Postgres),
+            3306 => Some(Database::Mysql),
+            6379 => Some(Database::Redis),
+            _ => None,
+        }
+    }
+}
+
+/// The database behind `spec`: the `kind` of its `client` section, or else
+/// what its label or name mention, or else the usual one of its remote
+/// port.
+pub fn database(spec: &TunnelSpec) -> Option<Database> {
+    if spec.socks {
+        return None;
+    }
+    spec.client
+        .as_ref()
+        .and_then(|client| client.kind)
+        .or_else(|| spec.label.as_deref().and_then(Database::from_text))
+        .or_else(|| spec.name.as_deref().and_then(Database::from_text))
+        .or_else(|| Database::from_port(spec.remote_port))
+}
+
+/// The default command line for `database` on `port`, logging in as `user`
+/// to `name` when given.
+fn default_command(
+    database: Database,
+    port: u16,
+    user: Option<&str>,
+    name: Option<&str>,
+) -> String {
+    let quote = platform::shell_quote;
+    // 127.0.0.1 rather than localhost, which mysql takes for its socket.
+    let mut command = match database {
+        Database::Mysql => format!("mysql -h 127.0.0.1 -P {}", port),
+        _ => format!("{} -h 127.0.0.1 -p {}", database.program(), port),
+    };
+    if let Some(user) = user {
+        let flag = match database {
+            Database::Postgres => "-U",
+            Database::Mysql => "-u",
+            Database::Redis => "--user",
+        };
+        command.push_str(&format!(" {} {}", flag, quote(user)));
+    }
+    if let Some(name) = name {
+        let flag = match database {
+            Database::Postgres => " -d ",
+            Database::Mysql => " ",
+            Database::Redis => " -n ",
+        };
+        command.push_str(&format!("{}{}", flag, quote(name)));
+    }
+    command
+}
+
+/// The shell command launching the client of the database behind `spec`,
+/// pointed at its local port. `None` when it is not a database; an error
+/// when its `command` uses a placeholder it sets no value for.
+pub fn command(spec: &TunnelSpec) -> Result<Option<String>, String> {
+    let Some(database) = database(spec) else {
+        return Ok(None);
+    };
+    let client = spec.client.clone().unwrap_or_default();
+    let Some(template) = client.command else {
+        return Ok(Some(default_command(
+            database,
+            spec.local_port,
+            client.user.as_deref(),
+            client.database.as_deref(),
+        )));
+    };
+    let values = [
+        ("local_port", Some(spec.local_port.to_string())),
+        ("user", client.user),
+        ("database", client.database),
+    ];
+    let mut command = template.clone();
+    for (key, value) in values {
+        let placeholder = format!("{{{}}}", key);
+        if !command.contains(&placeholder) {
+            continue;
+        }
+        let value = value.ok_or_else(|| {
+            format!(
+                "'{}' uses {} but the client sets no {}",
+                template, placeholder, key
+            )
+        })?;
+        command = command.replace(&placeholder, &platform::shell_quote(&value));
+    }
+    Ok(Some(command))
+}
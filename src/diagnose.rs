@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+
+use crate::config::Config;
+use crate::doctor::{Check, Status};
+use crate::platform;
+use crate::remoteport;
+use crate::tunnel::{RemoteTarget, TunnelSpec};
+use crate::wizard;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long the ssh host waits for the destination before calling it dropped.
+const PROBE_SECONDS: u32 = 5;
+/// Exit status of `timeout` when the command ran out of time.
+const TIMED_OUT: i32 = 124;
+/// Exit status of a shell that could not find the command.
+const NOT_FOUND: i32 = 127;
+/// Exit status of ssh when it could not run the command at all.
+const SSH_FAILED: i32 = 255;
+
+/// Whether `host` can go into a remote shell command unquoted.
+fn is_plain_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':'))
+}
+
+/// The check of a probe from the ssh host to `target`, from the exit status
+/// of `timeout` running bash's `/dev/tcp`.
+pub fn probe_check(target: &RemoteTarget, host: &str, status: Option<i32>) -> Check {
+    let name = "remote port";
+    match status {
+        Some(0) => Check::pass(
+            name,
+            format!("{} accepts connections from {}", target, host),
+        ),
+        Some(TIMED_OUT) => Check::fail(
+            name,
+            format!("{} did not answer {} at all", target, host),
+            "A firewall between them probably drops the packets",
+        ),
+        Some(NOT_FOUND) => Check::warn(
+            name,
+            format!("could not check {} from {}", target, host),
+            "The host needs bash and timeout for this check",
+        ),
+        _ => Check::fail(
+            name,
+            format!("{} refused connections from {}", target, host),
+            "Nothing listens on that port, or a firewall rejects connections to it",
+        ),
+    }
+}
+
+/// The exit status of `command` on `host`, or why ssh could not run it.
+async fn remote(config: &Config, host: &str, command: &str) -> Result<Option<i32>, String> {
+    let output = wizard::remote_command(&config.ssh.client(host), host, command).await?;
+    match output.status.code() {
+        Some(SSH_FAILED) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(stderr.lines().last().unwrap_or("ssh failed").to_string())
+        }
+        code => Ok(code),
+    }
+}
+
+fn unchecked(name: &str, host: &str, error: &str) -> Check {
+    Check::warn(
+        name,
+        format!("could not run commands on {}: {}", host, error),
+        "Checks need key or agent authentication",
+    )
+}
+
+fn local_port(spec: &TunnelSpec, accepts: bool) -> Check {
+    let name = "local port";
+    if accepts {
+        return Check::pass(
+            name,
+            format!("127.0.0.1:{} accepts connections", spec.local_port),
+        );
+    }
+    Check::fail(
+        name,
+        format!(
+            "nothing accepts connections on 127.0.0.1:{}",
+            spec.local_port
+        ),
+        format!(
+            "Another program may hold the port (lsof -iTCP:{} -sTCP:LISTEN)",
+            spec.local_port
+        ),
+    )
+}
+
+fn session(spec: &TunnelSpec, session: &Result<(), String>) -> Check {
+    let name = "ssh session";
+    match session {
+        Ok(()) => Check::pass(name, format!("connected to {}", spec.host)),
+        Err(e) => Check::fail(
+            name,
+            format!("not connected to {}: {}", spec.host, e),
+            "Restart the tunnel with r, or see rtun doctor",
+        ),
+    }
+}
+
+/// Why connections through the tunnel `spec`, forwarding to `target`, fail:
+/// a short decision tree whose steps on the ssh host stop at the first that
+/// fails. `session_state` is whether its ssh session is up, or the last error.
+pub async fn run(
+    config: &Config,
+    spec: &TunnelSpec,
+    target: &RemoteTarget,
+    session_state: Result<(), String>,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let accepts = tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        TcpStream::connect(("127.0.0.1", spec.local_port)),
+    )
+    .await
+    .is_ok_and(|connected| connected.is_ok());
+    checks.push(local_port(spec, accepts));
+    checks.push(session(spec, &session_state));
+    // A SOCKS proxy has no single destination to look at.
+    if checks.iter().any(|c| c.status == Status::Fail) || spec.socks {
+        return checks;
+    }
+    if !spec.via.is_empty() || !is_plain_host(&target.host) {
+        checks.push(Check::warn(
+            "destination",
+            format!("not checked from {}", spec.host),
+            "rtun only runs checks on hosts it reaches directly",
+        ));
+        return checks;
+    }
+    if !remoteport::on_host(target) {
+        let command = format!("getent hosts {}", target.host);
+        let check = match remote(config, &spec.host, &command).await {
+            Err(e) => unchecked("destination", &spec.host, &e),
+            Ok(Some(0)) => Check::pass(
+                "destination",
+                format!("{} resolves on {}", target.host, spec.host),
+            ),
+            Ok(_) => Check::fail(
+                "destination",
+                format!("{} does not resolve on {}", target.host, spec.host),
+                "Check the name of the destination, or use its address",
+            ),
+        };
+        let failed = check.status != Status::Pass;
+        checks.push(check);
+        if failed {
+            return checks;
+        }
+    }
+    let script = format!("exec 3<>/dev/tcp/{}/{}", target.host, target.port);
+    let command = format!(
+        "timeout {} bash -c {}",
+        PROBE_SECONDS,
+        platform::shell_quote(&script)
+    );
+    let status = remote(config, &spec.host, &command).await;
+    let refused = matches!(status, Ok(code) if !matches!(code, Some(0 | TIMED_OUT | NOT_FOUND)));
+    checks.push(match status {
+        Ok(code) => probe_check(target, &spec.host, code),
+        Err(e) => unchecked("remote port", &spec.host, &e),
+    });
+    if refused && remoteport::on_host(target) {
+        // Nothing listens there for sure, and a nearby port may.
+        let client = config.ssh.client(&spec.host);
+        if let Ok(listening) = wizard::listening_ports(&client, &spec.host).await {
+            if let Some(warning) = remoteport::warning(target.port, &listening) {
+                if let Some(check) = checks.last_mut() {
+                    check.detail = warning;
+                }
+            }
+        }
+    }
+    checks
+}
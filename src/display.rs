@@ -0,0 +1,34 @@
+use std::env;
+
+/// Whether the user asked programs not to use colors, by setting `NO_COLOR`
+/// to anything but an empty string (<https://no-color.org>).
+pub fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// What `--ascii` draws instead of `symbol`, a single cell of the TUI: box
+/// drawing, bullets, arrows and the bars of charts. `None` for symbols to
+/// keep, which includes the accented letters of translations.
+pub fn ascii(symbol: &str) -> Option<&'static str> {
+    let mut chars = symbol.chars();
+    let (Some(c), None) = (chars.next(), chars.next()) else {
+        return None;
+    };
+    let replacement = match c {
+        '─' | '━' | '═' | '┄' | '┈' | '╌' => "-",
+        '│' | '┃' | '║' | '┆' | '┊' | '╎' => "|",
+        '●' | '•' | '◆' | '■' => "*",
+        '↑' => "^",
+        '↓' => "v",
+        '→' => ">",
+        '←' => "<",
+        '…' => ".",
+        '▁' | '▂' | '▃' => "_",
+        '▄' | '▅' | '▆' => "=",
+        '▀' => "\"",
+        '\u{2500}'..='\u{257f}' => "+",
+        '\u{2580}'..='\u{259f}' => "#",
+        _ => return None,
+    };
+    Some(replacement)
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+
+use crate::config::DnsConfig;
+use crate::pac::PacRules;
+use crate::socks;
+
+const DNS_PORT: u16 = 53;
+/// Longest query taken, as large as EDNS lets clients ask for.
+const MAX_MESSAGE: usize = 4096;
+const HEADER: usize = 12;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const SERVFAIL: u8 = 2;
+const REFUSED: u8 = 5;
+
+/// The name asked for in `query` and where its question ends.
+fn question(query: &[u8]) -> Option<(String, usize)> {
+    // Exactly one question, as every resolver sends.
+    if query.get(4..6)? != [0, 1] {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut at = HEADER;
+    loop {
+        let len = *query.get(at)? as usize;
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression has no place in a question.
+        if len > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(query.get(at..at + len)?).to_lowercase());
+        at += len;
+    }
+    // Type and class.
+    let end = at + 4;
+    (query.len() >= end).then(|| (labels.join("."), end))
+}
+
+/// The name asked for in `query`, lowercase, if it is a query.
+pub fn name(query: &[u8]) -> Option<String> {
+    question(query).map(|(name, _)| name)
+}
+
+/// The answer to `query` that it failed with `rcode`, without records.
+pub fn failure(query: &[u8], rcode: u8) -> Option<Vec<u8>> {
+    let (_, end) = question(query)?;
+    let mut answer = query[..end].to_vec();
+    // A response, with the opcode and recursion desired of the query.
+    answer[2] = 0x80 | (query[2] & 0x79);
+    // Recursion available.
+    answer[3] = 0x80 | rcode;
+    answer[6..HEADER].fill(0);
+    Some(answer)
+}
+
+/// `server` as a host and port, port 53 unless given.
+fn server(server: &str) -> io::Result<(String, u16)> {
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok((address.ip().to_string(), address.port()));
+    }
+    if let Ok(address) = server.parse::<IpAddr>() {
+        return Ok((address.to_string(), DNS_PORT));
+    }
+    match server.rsplit_once(':') {
+        Some((host, port)) => port
+            .parse()
+            .map(|port| (host.to_string(), port))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a DNS server", server),
+                )
+            }),
+        None => Ok((server.to_string(), DNS_PORT)),
+    }
+}
+
+/// Sends `query` to `server` over TCP through the SOCKS forward on
+/// `proxy_port`, for the answer of the far end.
+async fn ask(proxy_port: u16, server: &(String, u16), query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = socks::connect(proxy_port, &server.0, server.1).await?;
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(query);
+    stream.write_all(&message).await?;
+    let len = stream.read_u16().await?;
+    let mut answer = vec![0; len as usize];
+    stream.read_exact(&mut answer).await?;
+    Ok(answer)
+}
+
+/// The answer to `query`, from `server` when the name goes through a SOCKS
+/// forward, refused otherwise.
+async fn answer(query: &[u8], rules: &PacRules, server: &(String, u16)) -> Option<Vec<u8>> {
+    let Some(proxy_port) = rules.route(&name(query)?) else {
+        return failure(query, REFUSED);
+    };
+    match tokio::time::timeout(QUERY_TIMEOUT, ask(proxy_port, server, query)).await {
+        Ok(Ok(answer)) => Some(answer),
+        _ => failure(query, SERVFAIL),
+    }
+}
+
+/// Answers DNS queries on `127.0.0.1:<port>` for the names `rules` send
+/// through SOCKS tunnels, by asking the configured server through them.
+pub async fn serve(config: &DnsConfig, rules: Arc<PacRules>) -> io::Result<()> {
+    let server = server(&config.server)?;
+    let socket = Arc::new(UdpSocket::bind(("127.0.0.1", config.port)).await?);
+    tokio::spawn(async move {
+        let mut buf = vec![0; MAX_MESSAGE];
+        loop {
+            let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let query = buf[..len].to_vec();
+            let (socket, rules, server) = (socket.clone(), rules.clone(), server.clone());
+            tokio::spawn(async move {
+                if let Some(answer) = answer(&query, &rules, &server).await {
+                    let _ = socket.send_to(&answer, peer).await;
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::backend;
+use crate::config::{Config, SshClient};
+use crate::control::{self, Request, VersionInfo};
+use crate::hosts;
+
+const KNOWN_HOSTS_PATH: &str = ".ssh/known_hosts";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Works, but something may go wrong later.
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check of `rtun doctor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    /// How to fix it, for warnings and failures.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.to_string(),
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub(crate) fn warn(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: Status::Warn,
+            hint: Some(hint.into()),
+            ..Check::pass(name, detail)
+        }
+    }
+
+    pub(crate) fn fail(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            status: Status::Fail,
+            hint: Some(hint.into()),
+            ..Check::pass(name, detail)
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = match self.status {
+            Status::Pass => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{:<4}] {:<14} {}", mark, self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       {:<14} hint: {}", "", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the environment rtun relies on, for `rtun doctor`.
+pub fn run(config: &Config) -> Vec<Check> {
+    let mut checks = vec![
+        ssh_binary(&config.ssh.default_client()),
+        ssh_config(),
+        agent(),
+        known_hosts(config.strict),
+        control_socket(config),
+    ];
+    checks.extend(backends(config));
+    checks.extend(tools(config));
+    checks
+}
+
+/// Where `program` is on `PATH`, if anywhere.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+fn ssh_binary(client: &SshClient) -> Check {
+    let program = client.program();
+    if !client.is_openssh() {
+        let found = program.is_file() || find_program(&program.to_string_lossy()).is_some();
+        return match found {
+            true => Check::pass("ssh", format!("{}, not OpenSSH", program.display())),
+            false => Check::fail(
+                "ssh",
+                format!("{} not found", program.display()),
+                "fix `program` under [ssh]",
+            ),
+        };
+    }
+    let output = match Command::new(&program).arg("-V").output() {
+        Ok(output) => output,
+        Err(e) => {
+            return Check::fail(
+                "ssh",
+                format!("could not run {}: {}", program.display(), e),
+                "install the OpenSSH client, e.g. `apt install openssh-client`, or fix \
+                 `program` under [ssh]",
+            )
+        }
+    };
+    // ssh prints its version on stderr.
+    let version = String::from_utf8_lossy(&output.stderr);
+    let version = version.lines().next().unwrap_or_default().trim();
+    match output.status.success() {
+        true => Check::pass("ssh", version),
+        false => Check::fail(
+            "ssh",
+            format!("`ssh -V` failed: {}", version),
+            "reinstall the OpenSSH client",
+        ),
+    }
+}
+
+fn ssh_config() -> Check {
+    match hosts::load_ssh_config() {
+        Ok(Some(config)) => Check::pass(
+            "ssh config",
+            format!("{} host entries", config.get_hosts().len()),
+        ),
+        Ok(None) => Check::pass("ssh config", "~/.ssh/config does not exist"),
+        Err(e) => Check::fail(
+            "ssh config",
+            format!("{:#}", e),
+            "fix the entry, `ssh -G <host>` shows how ssh reads it",
+        ),
+    }
+}
+
+fn agent() -> Check {
+    let Some(socket) = env::var_os("SSH_AUTH_SOCK") else {
+        return Check::warn(
+            "ssh agent",
+            "SSH_AUTH_SOCK is not set",
+            "start one with `eval \"$(ssh-agent)\"` and add keys with `ssh-add`, unless \
+             your keys have no passphrase or it is stored with `rtun secret set`",
+        );
+    };
+    let socket = PathBuf::from(socket);
+    // ssh-add exits with 1 when the agent holds no keys, 2 when it cannot
+    // reach it.
+    match Command::new("ssh-add").arg("-l").output() {
+        Ok(output) if output.status.success() => {
+            let keys = String::from_utf8_lossy(&output.stdout).lines().count();
+            Check::pass("ssh agent", format!("{} keys loaded", keys))
+        }
+        Ok(output) if output.status.code() == Some(1) => Check::warn(
+            "ssh agent",
+            "the agent holds no keys",
+            "add your keys with `ssh-add`",
+        ),
+        Ok(_) => Check::fail(
+            "ssh agent",
+            format!("not reachable at {}", socket.display()),
+            "start a new agent with `eval \"$(ssh-agent)\"`",
+        ),
+        Err(_) if socket.exists() => {
+            Check::pass("ssh agent", format!("listening at {}", socket.display()))
+        }
+        Err(_) => Check::fail(
+            "ssh agent",
+            format!("{} does not exist", socket.display()),
+            "start a new agent with `eval \"$(ssh-agent)\"`",
+        ),
+    }
+}
+
+fn known_hosts(strict: bool) -> Check {
+    let Some(path) = env::home_dir().map(|home| home.join(KNOWN_HOSTS_PATH)) else {
+        return Check::fail(
+            "known_hosts",
+            "could not determine the home directory",
+            "set HOME",
+        );
+    };
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let entries = contents
+                .lines()
+                .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+                .count();
+            Check::pass("known_hosts", format!("{} entries", entries))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let detail = format!("{} does not exist", path.display());
+            let hint = "connect once with `ssh <host>` to accept the host key";
+            // Strict mode has ssh refuse unknown host keys.
+            match strict {
+                true => Check::fail("known_hosts", detail, hint),
+                false => Check::warn("known_hosts", detail, hint),
+            }
+        }
+        Err(e) => Check::fail(
+            "known_hosts",
+            format!("could not read {}: {}", path.display(), e),
+            format!("fix the permissions, e.g. `chmod 644 {}`", path.display()),
+        ),
+    }
+}
+
+fn control_socket(config: &Config) -> Check {
+    let Some(path) = config.control.socket_path() else {
+        return Check::fail(
+            "control socket",
+            "could not determine the home directory",
+            "set HOME or `path` under [control]",
+        );
+    };
+    if !path.exists() {
+        return Check::pass("control socket", "no instance running");
+    }
+    match control::call::<VersionInfo>(&config.control, &Request::Version) {
+        Ok(version) if version.rtun != env!("CARGO_PKG_VERSION") => Check::warn(
+            "control socket",
+            format!(
+                "rtun {} answering at {}, but this is rtun {}",
+                version.rtun,
+                path.display(),
+                env!("CARGO_PKG_VERSION")
+            ),
+            "restart it so that it runs this version; commands it is too old for fail",
+        ),
+        Ok(version) => Check::pass(
+            "control socket",
+            format!(
+                "rtun {} answering at {}, protocol {}",
+                version.rtun,
+                path.display(),
+                version.protocol
+            ),
+        ),
+        Err(e) => Check::fail(
+            "control socket",
+            format!("{} does not answer: {:#}", path.display(), e),
+            format!(
+                "if no rtun is running, remove {}; starting rtun replaces it too",
+                path.display()
+            ),
+        ),
+    }
+}
+
+/// Backends the tunnels of the profiles ask for that do not exist.
+fn backends(config: &Config) -> Vec<Check> {
+    let mut names = BTreeSet::new();
+    for name in config.profiles.keys() {
+        for tunnel in config.profile_tunnels(name).unwrap_or_default() {
+            names.insert(
+                tunnel
+                    .backend
+                    .unwrap_or_else(|| backend::DEFAULT.to_string()),
+            );
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| name != backend::DEFAULT)
+        .map(|name| match backend::get(&name) {
+            Some(_) => Check::pass("backend", format!("{} is available", name)),
+            None => Check::fail(
+                "backend",
+                format!("unknown backend '{}'", name),
+                "register it before starting rtun, or remove `backend` from the tunnel",
+            ),
+        })
+        .collect()
+}
+
+/// Programs rtun runs for the features the config turns on.
+fn tools(config: &Config) -> Vec<Check> {
+    let tunnels: Vec<_> = config
+        .profiles
+        .keys()
+        .flat_map(|name| config.profile_tunnels(name).unwrap_or_default())
+        .collect();
+    let mut needed = Vec::new();
+    if config.strict || tunnels.iter().any(|t| t.idle_timeout.is_some()) {
+        needed.push(("lsof", "idle timeouts"));
+    }
+    if config.alerts.as_ref().is_some_and(|a| a.webhook.is_some()) {
+        needed.push(("curl", "webhook alerts"));
+    }
+    if config.auth.values().any(|auth| auth.certificate.is_some()) {
+        needed.push(("ssh-keygen", "certificate checks"));
+    }
+    needed
+        .into_iter()
+        .map(|(program, feature)| match find_program(program) {
+            Some(path) => Check::pass(program, format!("{}, for {}", path.display(), feature)),
+            None => Check::fail(
+                program,
+                format!("not found on PATH, needed for {}", feature),
+                format!("install {}", program),
+            ),
+        })
+        .collect()
+}
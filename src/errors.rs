@@ -0,0 +1,149 @@
+use std::fmt;
+
+use color_eyre::eyre::Report;
+use serde::{Deserialize, Serialize};
+
+/// Kinds of failure scripts can tell apart, by exit status or by the
+/// `error.code` field of JSON output. Both are stable across versions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Anything without a code of its own.
+    Other,
+    /// The config file cannot be read, parsed or validated, or names an
+    /// unknown profile.
+    Config,
+    /// The ssh server did not resolve or accept connections.
+    HostUnreachable,
+    /// The ssh server turned down the keys or password.
+    AuthFailure,
+    /// A local port of a tunnel is taken.
+    PortConflict,
+    /// No rtun instance answered on the control socket.
+    NotRunning,
+    /// The running instance is from a release that cannot take the request,
+    /// and needs restarting.
+    Incompatible,
+}
+
+impl ErrorCode {
+    /// Exit status of rtun when a command fails this way. 2 is left to the
+    /// usage errors reported by the argument parser.
+    pub fn exit_status(self) -> i32 {
+        match self {
+            ErrorCode::Other => 1,
+            ErrorCode::Config => 3,
+            ErrorCode::HostUnreachable => 4,
+            ErrorCode::AuthFailure => 5,
+            ErrorCode::PortConflict => 6,
+            ErrorCode::NotRunning => 7,
+            ErrorCode::Incompatible => 8,
+        }
+    }
+
+    /// The kind of a tunnel error, from the last line ssh printed or the
+    /// reachability check.
+    pub fn of_tunnel_error(error: &str) -> ErrorCode {
+        const UNREACHABLE: [&str; 8] = [
+            "Connection refused",
+            "Connection timed out",
+            "Operation timed out",
+            "No route to host",
+            "Network is unreachable",
+            "Could not resolve hostname",
+            "Host unreachable",
+            "DNS lookup",
+        ];
+        const AUTH: [&str; 3] = [
+            "Permission denied",
+            "Too many authentication failures",
+            "Host key verification failed",
+        ];
+        const PORT: [&str; 3] = [
+            "Address already in use",
+            "cannot listen to port",
+            "Could not request local forwarding",
+        ];
+        let matches = |patterns: &[&str]| patterns.iter().any(|p| error.contains(p));
+        if matches(&UNREACHABLE) {
+            ErrorCode::HostUnreachable
+        } else if matches(&AUTH) {
+            ErrorCode::AuthFailure
+        } else if matches(&PORT) {
+            ErrorCode::PortConflict
+        } else {
+            ErrorCode::Other
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCode::Other => "other",
+            ErrorCode::Config => "config",
+            ErrorCode::HostUnreachable => "host_unreachable",
+            ErrorCode::AuthFailure => "auth_failure",
+            ErrorCode::PortConflict => "port_conflict",
+            ErrorCode::NotRunning => "not_running",
+            ErrorCode::Incompatible => "incompatible",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An error carrying its code, as the source of a report or as context
+/// added to one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// The code of the outermost [`Failure`] in `report`, `Other` without one.
+pub fn code(report: &Report) -> ErrorCode {
+    report
+        .downcast_ref::<Failure>()
+        .or_else(|| report.chain().find_map(|e| e.downcast_ref::<Failure>()))
+        .map_or(ErrorCode::Other, |failure| failure.code)
+}
+
+/// An error in JSON output, e.g. `{"code": "port_conflict", "message": ...}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorInfo {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorInfo {
+    pub fn of_report(report: &Report) -> Self {
+        ErrorInfo {
+            code: code(report),
+            message: format!("{:#}", report),
+        }
+    }
+
+    pub fn of_tunnel_error(error: &str) -> Self {
+        ErrorInfo {
+            code: ErrorCode::of_tunnel_error(error),
+            message: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::fmt;
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::status::TunnelInfo;
+use crate::tunnel::TunnelStatus;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Opened,
+    Connected,
+    /// Went down while it was up; a `reconnecting` or `failed` follows.
+    Unhealthy,
+    Reconnecting,
+    /// Out of reconnect attempts.
+    Failed,
+    Closed,
+    /// Stopped taking connections; a `closed` follows once the open ones
+    /// are over.
+    Draining,
+    /// Has no ssh session until a client connects.
+    Parked,
+    Scheduled,
+    /// Waits for other tunnels to connect first; an `opened` follows.
+    Queued,
+    WaitingForVpn,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 11] = [
+        Kind::Opened,
+        Kind::Connected,
+        Kind::Unhealthy,
+        Kind::Reconnecting,
+        Kind::Failed,
+        Kind::Closed,
+        Kind::Draining,
+        Kind::Parked,
+        Kind::Scheduled,
+        Kind::Queued,
+        Kind::WaitingForVpn,
+    ];
+
+    /// The event of a tunnel entering `status`.
+    pub fn of(status: &TunnelStatus) -> Self {
+        match status {
+            TunnelStatus::Up => Kind::Connected,
+            TunnelStatus::Reconnecting(_) => Kind::Reconnecting,
+            TunnelStatus::Failed => Kind::Failed,
+            TunnelStatus::Closed => Kind::Closed,
+            TunnelStatus::Draining => Kind::Draining,
+            TunnelStatus::Parked => Kind::Parked,
+            TunnelStatus::Scheduled => Kind::Scheduled,
+            TunnelStatus::Queued => Kind::Queued,
+            TunnelStatus::WaitingForVpn => Kind::WaitingForVpn,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Kind::Opened => "opened",
+            Kind::Connected => "connected",
+            Kind::Unhealthy => "unhealthy",
+            Kind::Reconnecting => "reconnecting",
+            Kind::Failed => "failed",
+            Kind::Closed => "closed",
+            Kind::Draining => "draining",
+            Kind::Parked => "parked",
+            Kind::Scheduled => "scheduled",
+            Kind::Queued => "queued",
+            Kind::WaitingForVpn => "waiting_for_vpn",
+        };
+        f.pad(name)
+    }
+}
+
+/// A change in the lifecycle of a tunnel, streamed to subscribers of the
+/// control socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub timestamp: DateTime<Utc>,
+    pub event: Kind,
+    pub tunnel: TunnelInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Event {
+    pub fn new(event: Kind, tunnel: TunnelInfo, detail: Option<String>) -> Self {
+        Event {
+            timestamp: Utc::now(),
+            event,
+            tunnel,
+            detail,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<15}  {} {}:{}",
+            self.timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            self.event,
+            self.tunnel.host,
+            self.tunnel.local_port,
+            self.tunnel.remote_port
+        )?;
+        if let Some(detail) = &self.detail {
+            write!(f, "  ({})", detail)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use crate::platform;
+use crate::tunnel::TunnelSpec;
+
+/// Where a named tunnel of a profile listens, for the `{NAME.port}`
+/// placeholders of its `env` templates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub name: String,
+    pub port: u16,
+}
+
+/// The named ones of `tunnels`.
+pub fn endpoints(tunnels: &[TunnelSpec]) -> Vec<Endpoint> {
+    tunnels
+        .iter()
+        .filter_map(|spec| {
+            Some(Endpoint {
+                name: spec.name.clone()?,
+                port: spec.local_port,
+            })
+        })
+        .collect()
+}
+
+fn is_variable(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replaces every `{NAME.port}` in `template` with the local port of the
+/// tunnel called NAME.
+pub fn expand(template: &str, endpoints: &[Endpoint]) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("Unclosed '{{' in '{}'", template))?;
+        let placeholder = &rest[start + 1..end];
+        let name = placeholder.strip_suffix(".port").ok_or_else(|| {
+            format!(
+                "Unknown placeholder '{{{}}}' in '{}', expected {{NAME.port}}",
+                placeholder, template
+            )
+        })?;
+        let endpoint = endpoints
+            .iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| format!("No tunnel named '{}' for '{}'", name, template))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&endpoint.port.to_string());
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// `export KEY='value'` lines for the `env` templates of a profile, for
+/// `eval "$(rtun env PROFILE)"`.
+pub fn render(env: &BTreeMap<String, String>, endpoints: &[Endpoint]) -> Result<String, String> {
+    let mut lines = String::new();
+    for (key, template) in env {
+        if !is_variable(key) {
+            return Err(format!(
+                "'{}' is not a valid environment variable name",
+                key
+            ));
+        }
+        let value = expand(template, endpoints)?;
+        lines.push_str(&format!(
+            "export {}={}\n",
+            key,
+            platform::shell_quote(&value)
+        ));
+    }
+    Ok(lines)
+}
+
+/// An `.envrc` snippet that opens the tunnels of `profile` when direnv loads
+/// the directory and exports its `env`. direnv runs nothing on leaving a
+/// directory, so an instance started for it is tied to the shell instead:
+/// the parent of direnv, which is the parent of the `.envrc` shell.
+pub fn envrc(profile: &str) -> String {
+    let profile = platform::shell_quote(profile);
+    format!(
+        "# Tunnels of the rtun profile {profile}, see `rtun direnv`.\n\
+         if has rtun; then\n  \
+           watch_file \"$HOME/.rtun/config.toml\"\n  \
+           shell_pid=$(ps -o ppid= -p \"$PPID\" | tr -d ' ')\n  \
+           rtun up {profile} --wait --timeout 30s --tie-to-pid \"$shell_pid\" >/dev/null \\\n    \
+             || log_error \"rtun: the tunnels of {profile} are not ready\"\n  \
+           eval \"$(rtun env {profile})\"\n\
+         fi\n"
+    )
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+use crate::config::HealthCheckConfig;
+
+// How long a probe waits for ssh to drop the connection. ssh accepts on the
+// local port right away and only closes the socket once opening the channel
+// to the destination failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether a connection through the forward on `local_port` reaches its
+/// destination.
+pub async fn probe(local_port: u16) -> bool {
+    let Ok(mut stream) = TcpStream::connect(("127.0.0.1", local_port)).await else {
+        return false;
+    };
+    let mut buf = [0u8; 1];
+    match tokio::time::timeout(PROBE_TIMEOUT, stream.read(&mut buf)).await {
+        // The destination spoke first.
+        Ok(Ok(n)) => n > 0,
+        Ok(Err(_)) => false,
+        // Still open: the destination is waiting for us to talk.
+        Err(_) => true,
+    }
+}
+
+/// Probes `local_port` every `check.interval` seconds and returns once
+/// `check.failures` probes in a row have failed.
+pub async fn wait_unhealthy(local_port: u16, check: HealthCheckConfig) {
+    let mut failures = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(check.interval)).await;
+        if probe(local_port).await {
+            failures = 0;
+        } else {
+            failures += 1;
+            if failures >= check.failures.max(1) {
+                return;
+            }
+        }
+    }
+}
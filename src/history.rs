@@ -0,0 +1,125 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::tunnel::TunnelSpec;
+
+const HISTORY_PATH: &str = ".rtun/history.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Open,
+    Close,
+    Failure,
+    Reconnect,
+    /// A client outside the tunnel's allow list was turned away.
+    Rejected,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EventKind::Open => "open",
+            EventKind::Close => "close",
+            EventKind::Failure => "failure",
+            EventKind::Reconnect => "reconnect",
+            EventKind::Rejected => "rejected",
+        };
+        f.pad(name)
+    }
+}
+
+/// One line of the history log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    pub event: EventKind,
+    pub user: String,
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<9}  {}  {} {}:{}",
+            self.timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S"),
+            self.event,
+            self.user,
+            self.host,
+            self.local_port,
+            self.remote_port
+        )?;
+        if let Some(detail) = &self.detail {
+            write!(f, "  ({})", detail)?;
+        }
+        Ok(())
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(HISTORY_PATH))
+}
+
+pub(crate) fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn append(entry: &HistoryEntry) -> std::io::Result<()> {
+    let path = history_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(entry)?;
+    writeln!(file, "{}", line)
+}
+
+/// Appends an event for `spec` to the history log. Failing to write the log
+/// must never take a tunnel down, so errors are ignored.
+pub fn record(event: EventKind, spec: &TunnelSpec, detail: Option<String>) {
+    let entry = HistoryEntry {
+        timestamp: Utc::now(),
+        event,
+        user: current_user(),
+        host: spec.host.clone(),
+        local_port: spec.local_port,
+        remote_port: spec.remote_port,
+        detail,
+    };
+    let _ = append(&entry);
+}
+
+/// Returns the last `limit` entries of the history log, oldest first,
+/// optionally restricted to a single host.
+pub fn load(limit: usize, host: Option<&str>) -> std::io::Result<Vec<HistoryEntry>> {
+    let path = history_path().ok_or(std::io::ErrorKind::NotFound)?;
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries: Vec<HistoryEntry> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .filter(|entry: &HistoryEntry| host.is_none_or(|h| entry.host == h))
+        .collect();
+    let skip = entries.len().saturating_sub(limit);
+    entries.drain(..skip);
+    Ok(entries)
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use crate::events::Event;
+use crate::tunnel::TunnelSpec;
+
+const HOOKS_PATH: &str = ".rtun/hooks.rhai";
+
+// Keeps a runaway loop in a hook from freezing the TUI.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Something a hook asked rtun to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Open(Box<TunnelSpec>),
+    OpenProfile(String),
+    CloseTag(String),
+}
+
+/// A rhai script whose `on_<event>` functions run when tunnels change, such
+/// as `on_failed(event)`, and `on_vpn_up(name)` / `on_vpn_down(name)` when a
+/// configured VPN connects or disconnects. Hooks act through `open(host,
+/// local_port, remote_port)`, `open_profile(name)` and `close_tag(tag)`.
+pub struct Hooks {
+    engine: Engine,
+    ast: AST,
+    commands: Rc<RefCell<Vec<Command>>>,
+}
+
+/// `~/.rtun/hooks.rhai`, used unless the config names another script.
+pub fn default_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(HOOKS_PATH))
+}
+
+fn port(value: i64) -> Result<u16, Box<EvalAltResult>> {
+    u16::try_from(value).map_err(|_| format!("{} is not a port", value).into())
+}
+
+impl Hooks {
+    /// Loads the script at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let script = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        Hooks::compile(&script).wrap_err_with(|| format!("Invalid hooks in {}", path.display()))
+    }
+
+    pub fn compile(script: &str) -> Result<Self> {
+        let commands: Rc<RefCell<Vec<Command>>> = Rc::default();
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let issued = commands.clone();
+        engine.register_fn(
+            "open",
+            move |host: &str,
+                  local_port: i64,
+                  remote_port: i64|
+                  -> Result<(), Box<EvalAltResult>> {
+                let spec = TunnelSpec::new(host, port(local_port)?, port(remote_port)?);
+                issued.borrow_mut().push(Command::Open(Box::new(spec)));
+                Ok(())
+            },
+        );
+        let issued = commands.clone();
+        engine.register_fn("open_profile", move |name: &str| {
+            issued
+                .borrow_mut()
+                .push(Command::OpenProfile(name.to_string()));
+        });
+        let issued = commands.clone();
+        engine.register_fn("close_tag", move |tag: &str| {
+            issued.borrow_mut().push(Command::CloseTag(tag.to_string()));
+        });
+        let ast = engine.compile(script).map_err(|e| eyre!("{}", e))?;
+        Ok(Hooks {
+            engine,
+            ast,
+            commands,
+        })
+    }
+
+    /// Whether the script defines the hook `name`.
+    pub fn handles(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    fn call(&self, name: &str, argument: Dynamic) -> Result<Vec<Command>> {
+        if !self.handles(name) {
+            return Ok(Vec::new());
+        }
+        let result =
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, (argument,));
+        // Taken either way, so a failed hook's commands don't leak into the
+        // next one.
+        let commands = self.commands.take();
+        match result {
+            Ok(_) => Ok(commands),
+            Err(e) => Err(eyre!("Hook {} failed: {}", name, e)),
+        }
+    }
+
+    /// Runs the hook for `event`, e.g. `on_connected`.
+    pub fn on_event(&self, event: &Event) -> Result<Vec<Command>> {
+        let argument = rhai::serde::to_dynamic(event).map_err(|e| eyre!("{}", e))?;
+        self.call(&format!("on_{}", event.event), argument)
+    }
+
+    /// Runs `on_vpn_up` or `on_vpn_down` for the VPN `name`.
+    pub fn on_vpn(&self, name: &str, up: bool) -> Result<Vec<Command>> {
+        let hook = if up { "on_vpn_up" } else { "on_vpn_down" };
+        self.call(hook, name.into())
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::SshClient;
+use crate::reachability;
+
+const HOST_KEYS_PATH: &str = ".rtun/host_keys.json";
+/// Seconds ssh-keyscan waits for a server.
+const SCAN_TIMEOUT: &str = "5";
+
+// Tunnel tasks record keys concurrently.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+// Keys that differ from the first ones seen, by ssh host.
+static CHANGED: Mutex<BTreeMap<String, Change>> = Mutex::new(BTreeMap::new());
+
+/// The fingerprint of one key of an ssh server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostKey {
+    /// Key type, such as `ED25519`.
+    pub kind: String,
+    /// As printed by `ssh-keygen -l`, such as `SHA256:...`.
+    pub fingerprint: String,
+}
+
+/// The keys of a host as rtun first saw them, in `~/.rtun/host_keys.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KnownKeys {
+    pub first_seen: DateTime<Utc>,
+    pub keys: Vec<HostKey>,
+}
+
+/// A host key that is not the one first seen for its type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: String,
+    pub was: String,
+    pub now: String,
+    pub first_seen: DateTime<Utc>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} key changed from {} to {}, first seen {}",
+            self.kind,
+            self.was,
+            self.now,
+            self.first_seen.format("%Y-%m-%d")
+        )
+    }
+}
+
+/// Reads the fingerprints printed by `ssh-keygen -l`, one key per line like
+/// `256 SHA256:... host (ED25519)`.
+pub fn parse(output: &str) -> Vec<HostKey> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let fingerprint = fields.nth(1)?;
+            let kind = fields.last()?.strip_prefix('(')?.strip_suffix(')')?;
+            Some(HostKey {
+                kind: kind.to_string(),
+                fingerprint: fingerprint.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The first key in `seen` whose fingerprint differs from the known key of
+/// its type. Key types the host did not have before are not changes.
+pub fn compare(known: &KnownKeys, seen: &[HostKey]) -> Option<Change> {
+    seen.iter().find_map(|key| {
+        let old = known.keys.iter().find(|k| k.kind == key.kind)?;
+        (old.fingerprint != key.fingerprint).then(|| Change {
+            kind: key.kind.clone(),
+            was: old.fingerprint.clone(),
+            now: key.fingerprint.clone(),
+            first_seen: known.first_seen,
+        })
+    })
+}
+
+/// The change last found in the keys of `host`, if any.
+pub fn changed(host: &str) -> Option<Change> {
+    CHANGED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(host)
+        .cloned()
+}
+
+fn host_keys_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(HOST_KEYS_PATH))
+}
+
+fn read() -> BTreeMap<String, KnownKeys> {
+    host_keys_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write(known: &BTreeMap<String, KnownKeys>) -> std::io::Result<()> {
+    let path = host_keys_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(known)?)?;
+    fs::rename(tmp, path)
+}
+
+/// The keys the ssh server of `host` offers, from `ssh-keyscan`. `None` when
+/// it cannot be reached directly or either program is missing.
+async fn scan(client: &SshClient, host: &str) -> Option<Vec<HostKey>> {
+    let (hostname, port) = reachability::resolve(client, host).await?;
+    let scanned = Command::new("ssh-keyscan")
+        .args(["-T", SCAN_TIMEOUT, "-p", &port.to_string(), &hostname])
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    let mut keygen = Command::new("ssh-keygen")
+        .args(["-l", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdin = keygen.stdin.take()?;
+    stdin.write_all(&scanned.stdout).await.ok()?;
+    drop(stdin);
+    let output = keygen.wait_with_output().await.ok()?;
+    let keys = parse(&String::from_utf8_lossy(&output.stdout));
+    (!keys.is_empty()).then_some(keys)
+}
+
+/// Compares the keys `host` offers with the ones rtun first saw, recording
+/// them on the first visit and key types that are new since. A change is
+/// kept for [`changed`] until the keys match again or are forgotten with
+/// [`forget`].
+pub async fn check(client: SshClient, host: String) {
+    let Some(seen) = scan(&client, &host).await else {
+        return;
+    };
+    let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut known = read();
+    let entry = known.entry(host.clone()).or_insert_with(|| KnownKeys {
+        first_seen: Utc::now(),
+        keys: Vec::new(),
+    });
+    let change = compare(entry, &seen);
+    let mut changed = CHANGED.lock().unwrap_or_else(|e| e.into_inner());
+    match change {
+        Some(change) => {
+            changed.insert(host, change);
+        }
+        None => {
+            changed.remove(&host);
+            let new: Vec<HostKey> = seen
+                .into_iter()
+                .filter(|key| !entry.keys.iter().any(|k| k.kind == key.kind))
+                .collect();
+            if !new.is_empty() {
+                entry.keys.extend(new);
+                let _ = write(&known);
+            }
+        }
+    }
+}
+
+/// Drops the recorded keys of `host`, after its server got new ones on
+/// purpose. Returns false when there were none.
+pub fn forget(host: &str) -> std::io::Result<bool> {
+    let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut known = read();
+    let forgotten = known.remove(host).is_some();
+    if forgotten {
+        write(&known)?;
+    }
+    CHANGED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(host);
+    Ok(forgotten)
+}
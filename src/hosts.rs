@@ -0,0 +1,131 @@
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::Command;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use ssh2_config::{ParseRule, SshConfig};
+
+use crate::backend;
+use crate::config::Config;
+use crate::tunnel::TunnelSpec;
+
+const SSH_CONFIG_PATH: &str = ".ssh/config";
+
+/// Options of `ssh -G` shown in the host detail, as ssh prints them and as
+/// they are written in ssh configs.
+const RESOLVED_OPTIONS: [(&str, &str); 15] = [
+    ("hostname", "HostName"),
+    ("user", "User"),
+    ("port", "Port"),
+    ("identityfile", "IdentityFile"),
+    ("certificatefile", "CertificateFile"),
+    ("proxyjump", "ProxyJump"),
+    ("proxycommand", "ProxyCommand"),
+    ("localforward", "LocalForward"),
+    ("remoteforward", "RemoteForward"),
+    ("dynamicforward", "DynamicForward"),
+    ("forwardagent", "ForwardAgent"),
+    ("stricthostkeychecking", "StrictHostKeyChecking"),
+    ("userknownhostsfile", "UserKnownHostsFile"),
+    ("controlmaster", "ControlMaster"),
+    ("controlpath", "ControlPath"),
+];
+
+pub fn ssh_config_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(SSH_CONFIG_PATH))
+}
+
+/// Parses `~/.ssh/config`. A missing file is not an error and yields `None`.
+pub fn load_ssh_config() -> Result<Option<SshConfig>> {
+    let Some(path) = ssh_config_path() else {
+        return Ok(None);
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).wrap_err_with(|| format!("Could not open {}", path.display())),
+    };
+    let config = SshConfig::default()
+        .parse(&mut BufReader::new(file), ParseRule::STRICT)
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(config))
+}
+
+/// Host aliases declared in `~/.ssh/config`, without the `*` catch-all.
+pub fn get_hosts() -> Result<Vec<String>> {
+    let Some(config) = load_ssh_config()? else {
+        return Ok(Vec::new());
+    };
+    let hosts = config
+        .get_hosts()
+        .iter()
+        .filter_map(|h| match h.pattern.first() {
+            Some(host_clause) => {
+                if host_clause.pattern == "*" {
+                    None
+                } else {
+                    Some(host_clause.pattern.clone())
+                }
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(hosts)
+}
+
+/// The options of [`RESOLVED_OPTIONS`] in `ssh -G` output, in that order,
+/// with one entry per value of options given more than once.
+pub fn parse_resolved(output: &str) -> Vec<(&'static str, String)> {
+    let lines: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, value)| *value != "none")
+        .collect();
+    RESOLVED_OPTIONS
+        .iter()
+        .flat_map(|(key, name)| {
+            lines
+                .iter()
+                .filter(move |(k, _)| k == key)
+                .map(move |(_, value)| (*name, value.to_string()))
+        })
+        .collect()
+}
+
+/// What ssh makes of the tunnels to `host`, from `ssh -G` given the
+/// arguments rtun passes: `~/.ssh/config` together with `[auth]`,
+/// `[host_keys]`, strict mode and the forwards of `tunnels`. Only OpenSSH
+/// can tell.
+pub fn resolve(
+    config: &Config,
+    host: &str,
+    tunnels: &[TunnelSpec],
+) -> Result<Vec<(&'static str, String)>> {
+    let client = config.ssh.client(host);
+    if !client.is_openssh() {
+        return Err(eyre!(
+            "{} is not OpenSSH and cannot show the options it resolves",
+            client.program().display()
+        ));
+    }
+    let spec = tunnels
+        .first()
+        .cloned()
+        .unwrap_or_else(|| TunnelSpec::new(host, 0, 0));
+    let forwards: Vec<_> = tunnels
+        .iter()
+        .flat_map(|tunnel| backend::forwards(tunnel, &tunnel.targets()[0], None))
+        .collect();
+    let output = Command::new(client.program())
+        .arg("-G")
+        .args(backend::ssh_args(&spec, &forwards, config))
+        .output()
+        .wrap_err_with(|| format!("Could not run {}", client.program().display()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("ssh -G {} failed: {}", host, stderr.trim()));
+    }
+    Ok(parse_resolved(&String::from_utf8_lossy(&output.stdout)))
+}
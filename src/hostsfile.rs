@@ -0,0 +1,99 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+const HOSTS_PATH: &str = "/etc/hosts";
+/// Ends the lines rtun added, so that it finds them again, even those an
+/// instance that crashed left behind.
+const MARKER: &str = "# rtun";
+
+/// Whether `name` can go in the hosts file as is.
+pub fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// `contents` of a hosts file with the lines of rtun replaced by one
+/// pointing each of `names` at the loopback address.
+pub fn with_names(contents: &str, names: &BTreeSet<String>) -> String {
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| !line.trim_end().ends_with(MARKER))
+        .map(str::to_string)
+        .collect();
+    lines.extend(
+        names
+            .iter()
+            .map(|name| format!("127.0.0.1 {} {}", name, MARKER)),
+    );
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Where the new hosts file is written before it replaces the old one, so
+/// that nothing reading it meanwhile sees it half written.
+const STAGING_PATH: &str = "/etc/.hosts.rtun";
+
+/// Replaces the hosts file with `contents` as rtun itself, keeping its
+/// permissions.
+fn replace(contents: &str) -> io::Result<()> {
+    let permissions = fs::metadata(HOSTS_PATH)?.permissions();
+    let mut staged = fs::File::create(STAGING_PATH)?;
+    let written = staged
+        .write_all(contents.as_bytes())
+        .and_then(|_| staged.sync_all())
+        .and_then(|_| fs::set_permissions(STAGING_PATH, permissions))
+        .and_then(|_| fs::rename(STAGING_PATH, HOSTS_PATH));
+    if written.is_err() {
+        let _ = fs::remove_file(STAGING_PATH);
+    }
+    written
+}
+
+/// Replaces the hosts file with `contents` through `program`, such as
+/// `sudo`, the same way as [`replace`].
+fn replace_as(program: &str, args: &[&str], contents: &str) -> io::Result<()> {
+    let script = r#"cat > "$1" && chmod 644 "$1" && mv "$1" "$2" || { rm -f "$1"; exit 1; }"#;
+    let mut child = Command::new(program)
+        .args(args)
+        .args(["sh", "-c", script, "sh", STAGING_PATH, HOSTS_PATH])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(contents.as_bytes())?;
+    }
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("{} did not write it", program))),
+    }
+}
+
+/// Points `names` at the loopback address in `/etc/hosts`, and no others
+/// rtun added. Unless rtun may write the file itself, `sudo` does, if it
+/// needs no password by then, or else `pkexec`, which asks for one in a
+/// window of the desktop. The file is replaced whole rather than rewritten,
+/// so that lookups never find it empty.
+pub fn sync(names: &BTreeSet<String>) -> io::Result<()> {
+    let contents = fs::read_to_string(HOSTS_PATH)?;
+    let wanted = with_names(&contents, names);
+    if wanted == contents {
+        return Ok(());
+    }
+    if replace(&wanted).is_ok() {
+        return Ok(());
+    }
+    replace_as("sudo", &["-n"], &wanted)
+        .or_else(|_| replace_as("pkexec", &[], &wanted))
+        .map_err(|_| {
+            io::Error::other(format!(
+                "could not write {}; run sudo -v first, or set up a polkit agent",
+                HOSTS_PATH
+            ))
+        })
+}
@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local};
+
+use crate::sniff;
+
+/// Requests kept per tunnel, oldest dropped first.
+const LOG_LIMIT: usize = 100;
+/// Longer lines are bodies, not request or status lines.
+const MAX_LINE: usize = 8 * 1024;
+
+/// An HTTP request seen passing through a relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub at: DateTime<Local>,
+    pub peer: IpAddr,
+    pub method: String,
+    pub path: String,
+    /// Status of the response, once it came back.
+    pub status: Option<u16>,
+    id: u64,
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.at.format("%H:%M:%S"),
+            self.method,
+            self.path,
+            match self.status {
+                Some(status) => status.to_string(),
+                None => "...".to_string(),
+            }
+        )
+    }
+}
+
+/// The requests of a tunnel, shared by its connections.
+#[derive(Debug, Default)]
+pub struct AccessLog {
+    requests: Mutex<VecDeque<Request>>,
+    next_id: AtomicU64,
+}
+
+impl AccessLog {
+    /// The logged requests, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        requests.iter().cloned().collect()
+    }
+
+    fn push(&self, peer: IpAddr, method: &str, path: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if requests.len() == LOG_LIMIT {
+            requests.pop_front();
+        }
+        requests.push_back(Request {
+            at: Local::now(),
+            peer,
+            method: method.to_string(),
+            path: path.to_string(),
+            status: None,
+            id,
+        });
+        id
+    }
+
+    fn answer(&self, id: u64, status: u16) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(request) = requests.iter_mut().rev().find(|r| r.id == id) {
+            request.status = Some(status);
+        }
+    }
+}
+
+/// Watches the bytes of one connection for request and status lines. Bodies
+/// are not parsed, so a body holding such lines can fool it.
+pub struct Exchange {
+    peer: IpAddr,
+    log: Arc<AccessLog>,
+    sent: Vec<u8>,
+    received: Vec<u8>,
+    /// Requests still waiting for a response, in order.
+    waiting: VecDeque<u64>,
+}
+
+/// Feeds `bytes` to `partial`, calling `line` for each line completed.
+fn lines(partial: &mut Vec<u8>, bytes: &[u8], mut line: impl FnMut(&str)) {
+    for chunk in bytes.split_inclusive(|b| *b == b'\n') {
+        if partial.len() + chunk.len() > MAX_LINE {
+            partial.clear();
+            continue;
+        }
+        partial.extend_from_slice(chunk);
+        if partial.ends_with(b"\n") {
+            if let Ok(text) = std::str::from_utf8(partial) {
+                line(text.trim_end());
+            }
+            partial.clear();
+        }
+    }
+}
+
+impl Exchange {
+    pub fn new(peer: IpAddr, log: Arc<AccessLog>) -> Self {
+        Exchange {
+            peer,
+            log,
+            sent: Vec::new(),
+            received: Vec::new(),
+            waiting: VecDeque::new(),
+        }
+    }
+
+    /// Bytes the client sent.
+    pub fn sent(&mut self, bytes: &[u8]) {
+        let (log, peer, waiting) = (&self.log, self.peer, &mut self.waiting);
+        lines(&mut self.sent, bytes, |line| {
+            let mut parts = line.split(' ');
+            if let (Some(method), Some(path), Some(version)) =
+                (parts.next(), parts.next(), parts.next())
+            {
+                if sniff::is_http_method(method) && version.starts_with("HTTP/1.") {
+                    waiting.push_back(log.push(peer, method, path));
+                }
+            }
+        });
+    }
+
+    /// Bytes the server sent back.
+    pub fn received(&mut self, bytes: &[u8]) {
+        let (log, waiting) = (&self.log, &mut self.waiting);
+        lines(&mut self.received, bytes, |line| {
+            let mut parts = line.split(' ');
+            let is_status_line = parts.next().is_some_and(|v| v.starts_with("HTTP/1."));
+            let status = parts.next().and_then(|s| s.parse::<u16>().ok());
+            if let (true, Some(status)) = (is_status_line, status) {
+                // Interim responses such as 100 Continue come before the
+                // final one.
+                if status >= 200 {
+                    if let Some(id) = waiting.pop_front() {
+                        log.answer(id, status);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// Locale used when no other one is asked for or known.
+pub const DEFAULT: &str = "en";
+
+/// Message catalogs of the TUI, in the subset of the Fluent syntax that is
+/// `key = text` lines with `{ $name }` placeables. English comes first and
+/// fills in the messages other catalogs lack.
+const CATALOGS: [(&str, &str); 2] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("pt-BR", include_str!("../locales/pt-BR.ftl")),
+];
+
+// Index into CATALOGS of the locale in use.
+static LOCALE: AtomicUsize = AtomicUsize::new(0);
+
+fn catalogs() -> &'static [HashMap<&'static str, &'static str>] {
+    static PARSED: OnceLock<Vec<HashMap<&'static str, &'static str>>> = OnceLock::new();
+    PARSED.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .map(|(_, source)| {
+                source
+                    .lines()
+                    .filter(|line| !line.starts_with('#'))
+                    .filter_map(|line| line.split_once('='))
+                    .map(|(key, text)| (key.trim(), text.trim()))
+                    .collect()
+            })
+            .collect()
+    })
+}
+
+/// Locales there is a catalog for.
+pub fn locales() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(locale, _)| *locale)
+}
+
+/// The catalog locale for `locale`, written like `pt-BR`, or as in `LANG`
+/// like `pt_BR.UTF-8`. A bare language such as `pt` picks its first
+/// catalog.
+pub fn find(locale: &str) -> Option<&'static str> {
+    let locale = locale
+        .split(['.', '@'])
+        .next()
+        .unwrap_or_default()
+        .replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+    locales()
+        .find(|known| known.eq_ignore_ascii_case(&locale))
+        .or_else(|| {
+            locales().find(|known| {
+                known
+                    .split('-')
+                    .next()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(language))
+            })
+        })
+}
+
+/// The locale asked for by `LC_ALL`, `LC_MESSAGES` or `LANG`, in that order.
+pub fn from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Switches the TUI to `locale`, see [`find`]. Returns false, keeping the
+/// current locale, when there is no catalog for it.
+pub fn set_locale(locale: &str) -> bool {
+    match find(locale).and_then(|found| locales().position(|l| l == found)) {
+        Some(index) => {
+            LOCALE.store(index, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The locale in use.
+pub fn locale() -> &'static str {
+    CATALOGS[LOCALE.load(Ordering::Relaxed)].0
+}
+
+/// The message `key` in the current locale.
+pub fn text(key: &str) -> String {
+    format(key, &[])
+}
+
+/// The message `key` in the current locale, with its `{ $name }`
+/// placeables replaced by the values in `args`. Falls back to English, then
+/// to the key itself.
+pub fn format(key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let catalogs = catalogs();
+    let Some(message) = catalogs[LOCALE.load(Ordering::Relaxed)]
+        .get(key)
+        .or_else(|| catalogs[0].get(key))
+    else {
+        return key.to_string();
+    };
+    let mut text = message.to_string();
+    for (name, value) in args {
+        let value = value.to_string();
+        text = text
+            .replace(&format!("{{ ${} }}", name), &value)
+            .replace(&format!("{{${}}}", name), &value);
+    }
+    text
+}
+
+/// Whether the English catalog, which all others fall back to, has `key`.
+pub fn has(key: &str) -> bool {
+    catalogs()[0].contains_key(key)
+}
+
+/// Keys of the English catalog that the catalog of `locale` lacks.
+pub fn missing(locale: &str) -> Vec<&'static str> {
+    let Some(index) = find(locale).and_then(|found| locales().position(|l| l == found)) else {
+        return Vec::new();
+    };
+    let catalogs = catalogs();
+    let mut missing: Vec<_> = catalogs[0]
+        .keys()
+        .filter(|key| !catalogs[index].contains_key(*key))
+        .copied()
+        .collect();
+    missing.sort();
+    missing
+}
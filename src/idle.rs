@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+use tokio::process::Command;
+
+/// Whether a client is connected to `local_port`. Assumed when `lsof` cannot
+/// tell, so tunnels are not closed under someone.
+pub async fn has_clients(local_port: u16) -> bool {
+    let output = Command::new("lsof")
+        .arg("-nP")
+        .arg(format!("-iTCP:{}", local_port))
+        .args(["-sTCP:ESTABLISHED", "-t"])
+        .output()
+        .await;
+    match output {
+        // lsof exits with 1 when it found nothing.
+        Ok(output) => !output.stdout.is_empty() || output.status.code() != Some(1),
+        Err(_) => true,
+    }
+}
+
+/// Returns once nothing was connected to `local_port` for `timeout`, looking
+/// every `interval`.
+pub async fn wait_idle(local_port: u16, timeout: Duration, interval: Duration) {
+    let mut last_active = Instant::now();
+    loop {
+        tokio::time::sleep(interval.min(timeout)).await;
+        if has_clients(local_port).await {
+            last_active = Instant::now();
+        } else if last_active.elapsed() >= timeout {
+            return;
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use tokio::sync::mpsc;
+
+use crate::state;
+use crate::tunnel::Stop;
+
+const LOCK_PATH: &str = ".rtun/rtun.lock";
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a process rtun is tied to is checked on.
+const TIE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Held for as long as this process is the running rtun instance.
+pub struct InstanceLock {
+    _file: File,
+}
+
+pub enum Acquire {
+    Acquired(InstanceLock),
+    /// Another instance holds the lock; its pid, when it could be read.
+    Running(Option<u32>),
+}
+
+fn lock_path() -> Result<PathBuf> {
+    env::home_dir()
+        .map(|home| home.join(LOCK_PATH))
+        .ok_or_else(|| eyre!("Could not determine the home directory"))
+}
+
+/// Tries to become the running rtun instance.
+pub fn acquire() -> Result<Acquire> {
+    let path = lock_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    // The lock is released by the kernel when the holder exits, even on a crash.
+    let locked = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0;
+    if !locked {
+        let mut contents = String::new();
+        let _ = file.read_to_string(&mut contents);
+        return Ok(Acquire::Running(contents.trim().parse().ok()));
+    }
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "{}", process::id())?;
+    file.flush()?;
+    Ok(Acquire::Acquired(InstanceLock { _file: file }))
+}
+
+/// The pid of this process written to a file for service managers, such as
+/// `brew services`, removed again when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the pid to `path`, replacing what a crashed run left there.
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+        }
+        fs::write(path, format!("{}\n", process::id()))
+            .wrap_err_with(|| format!("Could not write {}", path.display()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Redirects stdout and stderr to the end of the file at `path`, so the
+/// output of a background run is kept.
+pub fn log_to(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error())
+                .wrap_err_with(|| format!("Could not log to {}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Asks the instance `pid` to exit while leaving its ssh processes running,
+/// then waits for it to release the lock. Its tunnels become orphans that the
+/// caller can adopt.
+pub async fn take_over(pid: u32) -> Result<InstanceLock> {
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGUSR1);
+    }
+    let deadline = tokio::time::Instant::now() + TAKEOVER_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if let Acquire::Acquired(lock) = acquire()? {
+            return Ok(lock);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(eyre!("rtun instance {} did not hand over its tunnels", pid))
+}
+
+// A process that exited but was not reaped yet still takes signals. Linux
+// tells from /proc; elsewhere zombies count as running.
+fn is_zombie(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+        stat.rsplit_once(')')
+            .is_some_and(|(_, rest)| rest.trim_start().starts_with('Z'))
+    })
+}
+
+pub fn is_running(pid: u32) -> bool {
+    state::is_alive(pid) && !is_zombie(pid)
+}
+
+/// Returns once the process `pid` exits, for tunnels tied to it through the
+/// control socket.
+pub async fn exited(pid: u32) {
+    while is_running(pid) {
+        tokio::time::sleep(TIE_INTERVAL).await;
+    }
+}
+
+/// Sends [`Stop::Close`] on `quit` once the process `pid` exits, for
+/// `--tie-to-pid` and `--tie-to-parent`. When `pid` is our parent, being
+/// handed to another parent counts as it exiting too, as it may linger as a
+/// zombie until reaped.
+pub fn tie_to(pid: u32, quit: mpsc::Sender<Stop>) -> Result<()> {
+    if !is_running(pid) {
+        return Err(eyre!("No process with pid {}", pid));
+    }
+    let parent = os::unix::process::parent_id() == pid;
+    tokio::spawn(async move {
+        while is_running(pid) && (!parent || os::unix::process::parent_id() == pid) {
+            tokio::time::sleep(TIE_INTERVAL).await;
+        }
+        let _ = quit.send(Stop::Close).await;
+    });
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::config::KnockConfig;
+
+// knockd only needs to see the SYN; nobody is expected to answer.
+const TCP_KNOCK_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// One knock, written as `7000`, `7000/tcp` or `8000/udp`.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Knock {
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+impl TryFrom<String> for Knock {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (port, protocol) = match value.split_once('/') {
+            Some((port, "tcp")) => (port, Protocol::Tcp),
+            Some((port, "udp")) => (port, Protocol::Udp),
+            Some(_) => return Err(format!("Unknown protocol in knock '{}'", value)),
+            None => (value.as_str(), Protocol::Tcp),
+        };
+        let port = port
+            .parse()
+            .map_err(|_| format!("Invalid port in knock '{}'", value))?;
+        Ok(Knock { port, protocol })
+    }
+}
+
+/// Knocks the sequence on `host`, or on the configured address. Best effort:
+/// a knock that goes wrong shows up as ssh failing to connect.
+pub async fn knock(host: &str, config: &KnockConfig) {
+    let address = config.address.as_deref().unwrap_or(host);
+    for (i, knock) in config.sequence.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(Duration::from_millis(config.delay)).await;
+        }
+        match knock.protocol {
+            Protocol::Tcp => {
+                let _ = tokio::time::timeout(
+                    TCP_KNOCK_TIMEOUT,
+                    TcpStream::connect((address, knock.port)),
+                )
+                .await;
+            }
+            Protocol::Udp => {
+                if let Ok(socket) = UdpSocket::bind(("0.0.0.0", 0)).await {
+                    let _ = socket.send_to(&[], (address, knock.port)).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Seconds launchd waits after SIGTERM before killing rtun, enough for the
+/// tunnels to close and the hooks to run.
+const EXIT_TIMEOUT: u32 = 30;
+
+/// Label of the LaunchAgent of `profile`, and the name of its plist file
+/// without the extension.
+pub fn label(profile: &str) -> String {
+    format!("com.github.andycancado.rtun.{}", profile)
+}
+
+/// Where the agent of `profile` writes its output, `~/.rtun/<profile>.log`.
+pub fn log_path(profile: &str) -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".rtun").join(format!("{}.log", profile)))
+}
+
+/// A LaunchAgent starting rtun at login with the tunnels of `profile`, in
+/// plain mode with stdin on /dev/null, so it runs without a terminal until
+/// launchd stops it with SIGTERM. Failures are restarted but a clean exit is
+/// not, so `launchctl bootout` sticks. `path` becomes the `PATH` of rtun, as
+/// agents otherwise get only the system directories and miss ssh from
+/// Homebrew or MacPorts.
+pub fn plist(profile: &str, program: &Path, log: &Path, path: &str) -> String {
+    let arguments = [&program.to_string_lossy(), "--plain", "up", profile]
+        .iter()
+        .map(|argument| format!("        <string>{}</string>\n", escape(argument)))
+        .collect::<String>();
+    let log = escape(&log.to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>PATH</key>
+        <string>{path}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>{timeout}</integer>
+    <key>StandardInPath</key>
+    <string>/dev/null</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = escape(&label(profile)),
+        path = escape(path),
+        timeout = EXIT_TIMEOUT,
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
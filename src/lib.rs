@@ -0,0 +1,64 @@
+//! Core of rtun: tunnel supervision, configuration and the event history.
+//! The TUI lives in the `rtun` binary on top of these modules.
+
+pub mod activation;
+pub mod alerts;
+pub mod audit;
+pub mod backend;
+pub mod capture;
+pub mod certs;
+pub mod config;
+pub mod control;
+pub mod dbclient;
+pub mod diagnose;
+pub mod display;
+pub mod dns;
+pub mod doctor;
+pub mod errors;
+pub mod events;
+pub mod exports;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod hostkeys;
+pub mod hosts;
+pub mod hostsfile;
+pub mod httplog;
+pub mod i18n;
+pub mod idle;
+pub mod instance;
+pub mod knock;
+pub mod launchd;
+pub mod manager;
+pub mod mux;
+pub mod network;
+pub mod otp;
+pub mod pac;
+pub mod palette;
+pub mod plan;
+pub mod platform;
+pub mod project;
+pub mod proxy;
+pub mod reachability;
+pub mod ready;
+pub mod recent;
+pub mod relay;
+pub mod remoteport;
+pub mod repeats;
+pub mod report;
+pub mod schedule;
+pub mod script;
+pub mod secrets;
+pub mod session;
+pub mod sniff;
+pub mod socks;
+pub mod state;
+pub mod status;
+pub mod tls;
+pub mod tunnel;
+#[cfg(feature = "self-update")]
+pub mod upgrade;
+pub mod usage;
+pub mod vpn;
+pub mod watchdog;
+pub mod wizard;
@@ -1,21 +1,58 @@
-use std::sync::Arc;
-
-use clap::Parser;
-use color_eyre::eyre::Result;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use crossterm::{
-    event::{self, KeyCode, KeyEventKind},
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    cursor::Show,
+    event::{
+        self, DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange,
+        KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    },
+    style::Print,
+    terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, SetTitle,
+    },
     ExecutableCommand,
 };
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
 use ratatui::{prelude::*, widgets::*};
-use ssh2_config::{ParseRule, SshConfig};
-use std::io::stdout;
-use std::io::BufReader;
-use std::{env, fs::File};
-use tokio::process::Command;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{stdout, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::sync::{mpsc, Mutex};
-use tui_textarea::TextArea;
+use tokio::sync::{mpsc, oneshot};
+use tui_textarea::{CursorMove, TextArea};
+
+use rtun::capture::Capture;
+use rtun::certs::Validity;
+use rtun::config::{HostKeyConfig, HostKeyPolicy, SortOrder};
+use rtun::control::{
+    self, Caller, Closed, ControlConfig, ControlServer, Draining, NewTunnel, Opened, Permission,
+    Reply, Request, RpcError, TunnelList, VersionInfo,
+};
+use rtun::errors::{self, ErrorCode, ErrorInfo, Failure};
+use rtun::events::Event;
+use rtun::exports::{self, Endpoint};
+use rtun::history::{self, HistoryEntry};
+use rtun::hooks::{self, Hooks};
+use rtun::instance::{self, Acquire};
+use rtun::manager::{ManagedTunnel, Manager, Progress};
+use rtun::palette::{Action, Palette, Tab};
+use rtun::report::{self, ReportFormat};
+use rtun::secrets::{self, Secret};
+use rtun::session::{self, SavedTunnel};
+use rtun::sniff::{self, Protocol};
+use rtun::state;
+use rtun::status::{Format, Health, Summary, TunnelInfo};
+use rtun::tunnel::{Prompt, RemoteTarget, Stop, TunnelSpec, TunnelStatus};
+use rtun::usage::Usage;
+use rtun::wizard::{Outcome, Step, Wizard};
+use rtun::{
+    audit, certs, config, dbclient, diagnose, display, dns, doctor, hostkeys, hosts, i18n, launchd,
+    network, otp, pac, plan, platform, project, proxy, ready, recent, remoteport, repeats, script,
+    tls, usage, vpn,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -24,252 +61,4161 @@ use tui_textarea::TextArea;
     about = "A simple CLI for creating SSH tunnels."
 )]
 struct Args {
-    #[arg(required = true, num_args=1.., help = "List of ports to tunnel")]
-    ports: Vec<u16>,
-    #[arg(required = true, long, help = "Host")]
-    host: String,
+    #[arg(
+        long,
+        global = true,
+        help = "Strict security mode, as with strict = true in the config"
+    )]
+    strict: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Line-oriented text with a numbered menu instead of the TUI, for screen readers and dumb terminals"
+    )]
+    plain: bool,
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "plain",
+        help = "Take commands such as `add bastion 8080:80`, `close 8080` and `quit` from stdin, one per line, and acknowledge each on stdout"
+    )]
+    stdin: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Write the pid to this file while running, for service managers"
+    )]
+    pid_file: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Open the tunnels listed in this file, one HOST LOCAL:REMOTE[:DEST] per line"
+    )]
+    from_file: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PID",
+        conflicts_with = "tie_to_parent",
+        help = "Close the tunnels and exit when this process exits"
+    )]
+    tie_to_pid: Option<u32>,
+    #[arg(
+        long,
+        global = true,
+        help = "Close the tunnels and exit when the process that started rtun exits"
+    )]
+    tie_to_parent: bool,
+    #[arg(
+        long,
+        global = true,
+        requires = "plain",
+        help = "Append the output to this file instead of the terminal"
+    )]
+    log_file: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Draw the TUI with ASCII characters only, without box drawing or other symbols"
+    )]
+    ascii: bool,
+    #[arg(
+        long,
+        help = "Do not open the profile .rtun.toml maps the branch or worktree of the current git repository to"
+    )]
+    no_project: bool,
+    #[command(subcommand)]
+    command: Option<Commands>,
 }
 
-const CONFIG_PATH: &str = ".ssh/config";
+impl Args {
+    /// The process whose exit closes the tunnels, if any.
+    fn tied_to(&self) -> Option<u32> {
+        self.tie_to_pid
+            .or(self.tie_to_parent.then(std::os::unix::process::parent_id))
+    }
+}
 
-fn get_hosts() -> Vec<String> {
-    let home_dir = env::home_dir().unwrap();
-    let mut reader = BufReader::new(
-        File::open(home_dir.join(CONFIG_PATH)).expect("Could not open configuration file"),
-    );
-    let config = SshConfig::default()
-        .parse(&mut reader, ParseRule::STRICT)
-        .expect("Failed to parse configuration");
-    let hosts = config.get_hosts();
-    let hosts: Vec<_> = hosts
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show the log of tunnel open/close/failure/reconnect events
+    History {
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 50,
+            help = "Number of entries to show"
+        )]
+        limit: usize,
+        #[arg(long, help = "Only show events for this host")]
+        host: Option<String>,
+        #[arg(long, help = "Print raw JSON lines")]
+        json: bool,
+    },
+    /// Start the TUI with the tunnels of one or more profiles already open
+    Up {
+        #[arg(
+            required = true,
+            help = "Profile names from ~/.rtun/config.toml; later ones override earlier ones on the same local port"
+        )]
+        profiles: Vec<String>,
+        #[arg(
+            long,
+            help = "Open them in the running instance, or one started in the background, and exit once they are up"
+        )]
+        wait: bool,
+        #[arg(
+            long,
+            requires = "wait",
+            default_value = "60s",
+            value_parser = ready::parse_timeout,
+            help = "How long to wait, such as 30s or 2m"
+        )]
+        timeout: std::time::Duration,
+        #[arg(
+            long,
+            conflicts_with = "wait",
+            help = "Print which tunnels up would open, without opening them"
+        )]
+        diff: bool,
+    },
+    /// Wait until tunnels of the running instance are up and take connections
+    Wait {
+        #[arg(
+            long = "port",
+            help = "Local port of a tunnel to wait for, repeatable; all tunnels without it"
+        )]
+        ports: Vec<u16>,
+        #[arg(
+            long,
+            default_value = "60s",
+            value_parser = ready::parse_timeout,
+            help = "How long to wait, such as 30s or 2m"
+        )]
+        timeout: std::time::Duration,
+    },
+    /// Open a tunnel in the running instance
+    Add {
+        #[arg(
+            required_unless_present = "host",
+            help = "Tunnel as HOST LOCAL:REMOTE, the form of the new-tunnel prompt"
+        )]
+        tunnel: Vec<String>,
+        #[arg(
+            long,
+            conflicts_with = "tunnel",
+            requires_all = ["local_port", "remote_port"],
+            help = "ssh host, instead of the HOST LOCAL:REMOTE form where its quoting is awkward"
+        )]
+        host: Option<String>,
+        #[arg(
+            long,
+            value_name = "PORT",
+            requires = "host",
+            help = "Local port, with --host"
+        )]
+        local_port: Option<u16>,
+        #[arg(
+            long = "remote",
+            value_name = "PORT",
+            requires = "host",
+            help = "Remote port, with --host"
+        )]
+        remote_port: Option<u16>,
+        #[arg(
+            long,
+            value_name = "HOST",
+            requires = "host",
+            help = "Host the remote port lives on as seen from the ssh host, with --host"
+        )]
+        dest: Option<String>,
+        #[arg(long, help = "Service name, for the HTTP proxy")]
+        name: Option<String>,
+        #[arg(long, help = "Free-text description")]
+        label: Option<String>,
+        #[arg(long = "tag", help = "Tag such as env:staging, repeatable")]
+        tags: Vec<String>,
+    },
+    /// Point a tunnel of the running instance at another destination, keeping its local port
+    Retarget {
+        #[arg(help = "Local port of the tunnel")]
+        local_port: u16,
+        #[arg(
+            required_unless_present = "dest",
+            help = "New destination as seen from the ssh host, such as replica-2:5432"
+        )]
+        target: Option<String>,
+        #[arg(
+            long,
+            value_name = "HOST",
+            conflicts_with = "target",
+            requires = "remote_port",
+            help = "Host of the new destination, instead of the HOST:PORT form"
+        )]
+        dest: Option<String>,
+        #[arg(
+            long = "remote",
+            value_name = "PORT",
+            requires = "dest",
+            help = "Port of the new destination, with --dest"
+        )]
+        remote_port: Option<u16>,
+    },
+    /// Close the tunnels of the running instance that carry all given tags
+    Close {
+        #[arg(
+            long = "tag",
+            required = true,
+            help = "Tag such as env:staging, repeatable"
+        )]
+        tags: Vec<String>,
+        #[arg(
+            long,
+            help = "Stop taking connections and close each tunnel once its open ones are over"
+        )]
+        drain: bool,
+    },
+    /// Watch the tunnels and logs of the running instance
+    Attach {
+        #[arg(long, help = "Only observe, without opening or closing anything")]
+        read_only: bool,
+    },
+    /// Print a one-line summary of the tunnels for shell prompts and status bars
+    Status {
+        #[arg(long, value_enum, default_value_t = Format::Plain)]
+        format: Format,
+    },
+    /// Print tunnel lifecycle events of the running instance as they happen
+    Events {
+        #[arg(
+            long,
+            help = "Keep waiting while rtun is not running, and across restarts"
+        )]
+        follow: bool,
+        #[arg(long, help = "Print raw JSON lines")]
+        json: bool,
+    },
+    /// Manage the encrypted store of ssh passwords and passphrases
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommand,
+    },
+    /// Print the path of the local CA certificate used for tls_port, creating it if needed
+    Ca,
+    /// Forget the host keys first seen for a host, after its server got new ones
+    ForgetHostKey {
+        #[arg(help = "ssh host, as in the tunnels")]
+        host: String,
+    },
+    /// Check the audit log for tampering and print it for review
+    Audit {
+        #[arg(long, help = "Print raw JSON lines")]
+        json: bool,
+    },
+    /// Show how much each tunnel was used, and the profile tunnels never opened
+    Stats {
+        #[arg(long, help = "Print raw JSON lines")]
+        json: bool,
+    },
+    /// Print a report of the open tunnels and recent events, for handover notes and incident timelines
+    Report {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Markdown)]
+        format: ReportFormat,
+        #[arg(
+            short,
+            long,
+            help = "Write the report to this file instead of printing it"
+        )]
+        output: Option<PathBuf>,
+        #[arg(long, help = "Copy the report to the clipboard too")]
+        copy: bool,
+    },
+    /// Check the ssh setup and the programs rtun needs, with hints to fix problems
+    Doctor,
+    /// Validate the config file, exiting with status 3 when it has mistakes
+    Check {
+        #[arg(help = "Config file to check instead of ~/.rtun/config.toml")]
+        file: Option<PathBuf>,
+    },
+    /// Print shell exports from the env templates of a profile, for eval "$(rtun env dev)"
+    Env {
+        #[arg(help = "Profile name from ~/.rtun/config.toml")]
+        profile: String,
+    },
+    /// Print an .envrc snippet that opens a profile and exports its env when direnv loads the directory
+    Direnv {
+        #[arg(help = "Profile name from ~/.rtun/config.toml")]
+        profile: String,
+    },
+    /// Print a macOS LaunchAgent plist that opens a profile at login
+    Launchd {
+        #[arg(help = "Profile name from ~/.rtun/config.toml")]
+        profile: String,
+    },
+    /// Replace this binary with the latest release from GitHub, after showing what changed
+    #[cfg(feature = "self-update")]
+    Upgrade {
+        #[arg(long, help = "Only say whether a newer release is out")]
+        check: bool,
+        #[arg(long, short, help = "Upgrade without asking for confirmation")]
+        yes: bool,
+    },
+}
+
+impl Commands {
+    /// Whether the command prints JSON, and so its errors too.
+    fn prints_json(&self) -> bool {
+        match self {
+            Commands::History { json, .. }
+            | Commands::Events { json, .. }
+            | Commands::Audit { json }
+            | Commands::Stats { json } => *json,
+            Commands::Status { format } => matches!(format, Format::Json | Format::Waybar),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum SecretCommand {
+    /// Store a password or passphrase, prompting for its value
+    Set {
+        name: String,
+        #[arg(long, help = "Save to the OS keychain instead of the encrypted store")]
+        keychain: bool,
+    },
+    /// List the names of the secrets in the encrypted store
+    List,
+    /// Forget a stored secret
+    Remove {
+        name: String,
+        #[arg(
+            long,
+            help = "Delete from the OS keychain instead of the encrypted store"
+        )]
+        keychain: bool,
+    },
+}
+
+/// Opens the secret store, asking for its passphrase up to three times, and
+/// for a new one twice when there is no store yet.
+fn open_store() -> Result<secrets::Store> {
+    let new = !secrets::store_exists();
+    for _ in 0..3 {
+        let passphrase = secrets::read_hidden("Passphrase for ~/.rtun/secrets.enc: ")?;
+        if new && secrets::read_hidden("Repeat the passphrase: ")? != passphrase {
+            eprintln!("The passphrases differ");
+            continue;
+        }
+        match secrets::Store::open(&passphrase) {
+            Ok(store) => return Ok(store),
+            Err(e) => eprintln!("{:#}", e),
+        }
+    }
+    Err(eyre!("Could not unlock the secret store"))
+}
+
+fn manage_secrets(command: &SecretCommand) -> Result<()> {
+    match command {
+        SecretCommand::Set {
+            name,
+            keychain: true,
+        } => {
+            let value = secrets::read_hidden(&format!("Value of {}: ", name))?;
+            return secrets::set_in_keychain(name, &value);
+        }
+        SecretCommand::Remove {
+            name,
+            keychain: true,
+        } => return secrets::remove_from_keychain(name),
+        _ => {}
+    }
+    let mut store = open_store()?;
+    match command {
+        SecretCommand::Set { name, .. } => {
+            let value = secrets::read_hidden(&format!("Value of {}: ", name))?;
+            store.set(name, &value);
+            store.save()?;
+        }
+        SecretCommand::List => {
+            for name in store.names() {
+                println!("{}", name);
+            }
+        }
+        SecretCommand::Remove { name, .. } => {
+            if !store.remove(name) {
+                return Err(eyre!("No secret named '{}'", name));
+            }
+            store.save()?;
+        }
+    }
+    Ok(())
+}
+
+/// A QR code for `url` drawn with unicode half blocks, light on dark so it
+/// scans on dark terminal themes too.
+fn qr_code(url: &str) -> Result<String> {
+    let code = QrCode::new(url.as_bytes()).wrap_err("Could not encode the URL as a QR code")?;
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+/// Everything known about the selected tunnel.
+/// Validity of the certificates configured in `[auth]`, by ssh host.
+type Certificates = BTreeMap<String, Result<Validity, String>>;
+
+fn check_certificates(config: &config::Config) -> Certificates {
+    config
+        .auth
+        .iter()
+        .filter_map(|(host, auth)| {
+            let validity = certs::validity(auth.certificate.as_ref()?);
+            Some((host.clone(), validity.map_err(|e| format!("{:#}", e))))
+        })
+        .collect()
+}
+
+fn certificate_state(host: &str, certificates: &Certificates) -> Option<String> {
+    match certificates.get(host)? {
+        Ok(validity) => Some(validity.describe(chrono::Local::now().naive_local())),
+        Err(e) => Some(e.clone()),
+    }
+}
+
+/// A warning when `protocol` is not the one usually on `port`, which often
+/// means a forward to the wrong port.
+fn protocol_mismatch(protocol: &Protocol, port: u16) -> String {
+    match sniff::usual(port) {
+        Some(usual) if usual != protocol.name() => {
+            format!(", but {} is usual on port {}", usual, port)
+        }
+        _ => String::new(),
+    }
+}
+
+/// The last `width` of `samples` as a line of bars, scaled to their peak.
+fn sparkline(samples: &[u64], width: usize) -> String {
+    const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let samples = &samples[samples.len().saturating_sub(width)..];
+    let peak = samples.iter().copied().max().unwrap_or_default().max(1);
+    samples
         .iter()
-        .filter_map(|h| match h.pattern.first() {
-            Some(host_clause) => {
-                if host_clause.pattern == "*" {
-                    None
+        .map(|sample| BARS[(sample * 8).div_ceil(peak) as usize])
+        .collect()
+}
+
+fn bytes(count: u64) -> String {
+    match count {
+        0..1_000 => format!("{} B", count),
+        1_000..1_000_000 => format!("{:.1} kB", count as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", count as f64 / 1e6),
+        _ => format!("{:.1} GB", count as f64 / 1e9),
+    }
+}
+
+/// One row of `rtun stats` and the Stats tab.
+fn usage_line(usage: &Usage) -> String {
+    format!(
+        "{:<32} {:>5} opens {:>9} up {:>10}  last {}",
+        format!("{} {}:{}", usage.host, usage.local_port, usage.remote_port),
+        usage.opens,
+        elapsed(std::time::Duration::from_secs(usage.uptime)),
+        bytes(usage.bytes),
+        usage
+            .last_used
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d")
+    )
+}
+
+fn print_stats(config: &config::Config, json: bool) -> Result<()> {
+    let entries = usage::load();
+    for entry in entries.iter() {
+        if json {
+            println!("{}", serde_json::to_string(entry)?);
+        } else {
+            println!("{}", usage_line(entry));
+        }
+    }
+    if json {
+        return Ok(());
+    }
+    for name in config.profiles.keys() {
+        let tunnels = config.profile_tunnels(name)?;
+        for spec in usage::unused(&entries, &tunnels) {
+            println!("never opened: {} in profile {}", spec, name);
+        }
+    }
+    Ok(())
+}
+
+fn elapsed(duration: std::time::Duration) -> String {
+    match duration.as_secs() {
+        seconds @ 0..60 => format!("{}s", seconds),
+        seconds @ 60..3600 => format!("{}m", seconds / 60),
+        seconds => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// `Connecting: 12 connected, 6 pending, 2 failed`.
+fn progress_text(progress: &Progress) -> String {
+    i18n::format(
+        "startup-progress",
+        &[
+            ("connected", &progress.connected),
+            ("pending", &progress.pending),
+            ("failed", &progress.failed),
+        ],
+    )
+}
+
+/// The status of `tunnel` as the TUI shows it, telling whether the ssh
+/// session of a lazy tunnel is connected or parked.
+fn status_text(tunnel: &ManagedTunnel) -> String {
+    match tunnel.status {
+        TunnelStatus::Parked => i18n::text("status-parked"),
+        TunnelStatus::Up if tunnel.spec.lazy => i18n::text("status-on-demand-up"),
+        status => status.to_string(),
+    }
+}
+
+fn tunnel_detail(
+    tunnel: &ManagedTunnel,
+    certificates: &Certificates,
+    host_key: HostKeyConfig,
+    selected_client: Option<u64>,
+) -> Paragraph<'static> {
+    let spec = &tunnel.spec;
+    let mut lines = vec![Line::from(format!("{} [{}]", spec, status_text(tunnel)))];
+    if let Some(label) = &spec.label {
+        lines.push(Line::from(label.clone()));
+    }
+    if let Some(name) = &spec.name {
+        lines.push(Line::from(format!("name: {}", name)));
+    }
+    if !spec.tags.is_empty() {
+        lines.push(Line::from(format!("tags: {}", spec.tags.join(", "))));
+    }
+    if !spec.hostnames.is_empty() {
+        lines.push(Line::from(i18n::format(
+            "detail-hostnames",
+            &[("names", &spec.hostnames.join(", "))],
+        )));
+    }
+    if let Some(target) = &tunnel.target {
+        lines.push(Line::from(format!("target: {}", target)));
+    }
+    if let Some(error) = &tunnel.last_error {
+        let mut line = i18n::format("detail-last-error", &[("error", error)]);
+        let repeats = tunnel.repeats.count(std::time::Instant::now());
+        if repeats > 1 {
+            line.push(' ');
+            line.push_str(&i18n::format(
+                "detail-repeats",
+                &[
+                    ("count", &repeats),
+                    ("minutes", &(repeats::WINDOW.as_secs() / 60)),
+                ],
+            ));
+        }
+        lines.push(Line::from(line));
+    }
+    if spec.lazy {
+        let session = match tunnel.status {
+            TunnelStatus::Parked => "detail-session-parked",
+            TunnelStatus::Up => "detail-session-connected",
+            _ => "detail-session-down",
+        };
+        lines.push(Line::from(i18n::format(
+            "detail-ssh-session",
+            &[("session", &i18n::text(session))],
+        )));
+    }
+    if let Some(warning) = remoteport::not_listening(spec.local_port) {
+        lines.push(Line::styled(warning, Style::default().fg(Color::Yellow)));
+    }
+    if let Some(state) = certificate_state(&spec.host, certificates) {
+        lines.push(Line::from(format!("certificate: {}", state)));
+    }
+    if let Some(change) = hostkeys::changed(&spec.host) {
+        lines.push(Line::styled(
+            i18n::format(
+                "detail-host-key",
+                &[("change", &change), ("host", &spec.host)],
+            ),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    match (host_key.policy, &host_key.known_hosts) {
+        (Some(HostKeyPolicy::Off), _) => lines.push(Line::styled(
+            i18n::text("detail-host-keys-off"),
+            Style::default().fg(Color::Yellow),
+        )),
+        (None, None) => {}
+        (policy, known_hosts) => {
+            let text = [
+                policy.map(|policy| policy.to_string()),
+                known_hosts
+                    .as_ref()
+                    .map(|path| format!("known in {}", path.display())),
+            ];
+            let text: Vec<String> = text.into_iter().flatten().collect();
+            lines.push(Line::from(format!("host keys: {}", text.join(", "))));
+        }
+    }
+    if let Some(protocol) = tunnel.connections.as_ref().and_then(|c| c.protocol()) {
+        lines.push(Line::from(format!(
+            "protocol: {}{}",
+            protocol,
+            protocol_mismatch(&protocol, spec.remote_port)
+        )));
+    }
+    if let Some(connections) = &tunnel.connections {
+        if let Some(capture) = connections.capturing() {
+            lines.push(Line::styled(
+                format!(
+                    "capturing to {}: {}, {} of {} (p to stop)",
+                    capture.path.display(),
+                    bytes(capture.size),
+                    elapsed(capture.elapsed),
+                    elapsed(capture.limit)
+                ),
+                Style::default().fg(Color::Red),
+            ));
+        } else if let Some(path) = connections.captured() {
+            lines.push(Line::from(format!("captured to {}", path.display())));
+        }
+        lines.push(Line::from(i18n::text("detail-connections")));
+        for target in connections.targets.iter() {
+            lines.push(Line::from(format!(
+                "  {}  {} open, {} total",
+                target.target,
+                target.active(),
+                target.total()
+            )));
+        }
+        let clients = connections.clients();
+        if !clients.is_empty() {
+            lines.push(Line::from(i18n::text("detail-clients")));
+        }
+        for client in clients {
+            let line = Line::from(format!(
+                "{} {}  {} sent, {} received, {}",
+                if selected_client == Some(client.id) {
+                    ">"
                 } else {
-                    Some(host_clause.pattern.clone())
-                }
+                    " "
+                },
+                client.peer,
+                bytes(client.sent()),
+                bytes(client.received()),
+                elapsed(client.since.elapsed())
+            ));
+            lines.push(match selected_client == Some(client.id) {
+                true => line.style(Style::default().add_modifier(Modifier::REVERSED)),
+                false => line,
+            });
+        }
+        if let Some(requests) = connections.requests() {
+            lines.push(Line::from(i18n::text("detail-requests")));
+            let skip = requests.len().saturating_sub(REQUESTS_SHOWN);
+            for request in &requests[skip..] {
+                let style = match request.status {
+                    Some(400..) => Style::default().fg(Color::Red),
+                    _ => Style::default(),
+                };
+                lines.push(Line::styled(format!("  {}", request), style));
             }
-            _ => None,
-        })
-        .collect();
-    hosts
+        }
+        if let Some(destinations) = connections.destinations() {
+            lines.push(Line::from(i18n::text("detail-destinations")));
+            if destinations.is_empty() {
+                lines.push(Line::from(format!(
+                    "  {}",
+                    i18n::text("detail-nothing-yet")
+                )));
+            }
+            for destination in destinations.iter().take(DESTINATIONS_SHOWN) {
+                lines.push(Line::from(format!("  {}", destination)));
+            }
+        }
+    }
+    Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::bordered().title(i18n::text("title-tunnel")))
 }
 
-async fn create_ssh_tunnel(
-    local_port: u16,
-    remote_port: u16,
-    host: &str,
-    shutdown: Arc<Mutex<mpsc::Receiver<()>>>,
-) {
-    let ssh_command = format!(
-        "ssh -N -T -L {}:127.0.0.1:{} {}",
-        local_port, remote_port, host
-    );
-    let mut process = Command::new("sh")
-        .arg("-c")
-        .arg(&ssh_command)
-        .spawn()
-        .expect("Failed to spawn process");
-    let mut rx = shutdown.lock().await;
-    tokio::select! {
-        _ = rx.recv() => {
-            println!("Terminating SSH tunnel on port {}", local_port);
-            let _ = process.kill().await;
+/// The tunnels to `host` the host detail resolves: open ones, then those of
+/// the profiles on other local ports.
+fn host_tunnels(manager: &Manager, config: &config::Config, host: &str) -> Vec<TunnelSpec> {
+    let mut tunnels: Vec<TunnelSpec> = manager
+        .tunnels
+        .iter()
+        .map(|t| t.spec.clone())
+        .filter(|spec| spec.host == host)
+        .collect();
+    for name in config.profiles.keys() {
+        for spec in config.profile_tunnels(name).unwrap_or_default() {
+            if spec.host == host && !tunnels.iter().any(|t| t.local_port == spec.local_port) {
+                tunnels.push(spec);
+            }
         }
     }
+    tunnels
+}
+
+/// Options ssh resolved for a host, see [`hosts::resolve`].
+type HostOptions = Result<Vec<(&'static str, String)>, String>;
+
+/// The options of a host, resolved in the background.
+struct HostDetail {
+    host: String,
+    /// Local ports of the tunnels they are resolved for.
+    ports: Vec<u16>,
+    done: oneshot::Receiver<HostOptions>,
+    options: Option<HostOptions>,
+}
+
+/// The options of `host`, or that they are being resolved while `None`.
+fn host_detail(host: &str, resolved: Option<&HostOptions>) -> Paragraph<'static> {
+    let lines = match resolved {
+        None => vec![Line::from(i18n::text("resolving-host-options"))],
+        Some(Ok(options)) => options
+            .iter()
+            .map(|(name, value)| Line::from(format!("{:<22} {}", name, value)))
+            .collect(),
+        Some(Err(e)) => vec![Line::styled(e.clone(), Style::default().fg(Color::Red))],
+    };
+    Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::bordered().title(i18n::format("title-host-options", &[("host", &host)])))
 }
 
-async fn handle_signals(tx: Arc<Mutex<mpsc::Sender<()>>>) {
+const HISTORY_VIEW_LIMIT: usize = 200;
+/// Samples of throughput drawn next to relayed tunnels in the list.
+const SPARKLINE_WIDTH: usize = 12;
+/// Latest HTTP requests listed in the tunnel detail.
+const REQUESTS_SHOWN: usize = 10;
+/// Hosts reached through a SOCKS tunnel listed in the tunnel detail.
+const DESTINATIONS_SHOWN: usize = 10;
+/// Below this size only a notice is drawn.
+const MIN_WIDTH: u16 = 30;
+const MIN_HEIGHT: u16 = 8;
+/// Below this width tab titles and key hints are shortened.
+const NARROW_WIDTH: u16 = 70;
+/// From this width the tunnel detail is shown beside the list.
+const WIDE_WIDTH: u16 = 110;
+const SCHEDULE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether SIGHUP is set to be ignored, by nohup or the parent.
+fn hangup_ignored() -> bool {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    let queried = unsafe { libc::sigaction(libc::SIGHUP, std::ptr::null(), &mut action) } == 0;
+    queried && action.sa_sigaction == libc::SIG_IGN
+}
+
+fn handle_signals(quit: mpsc::Sender<Stop>) -> Result<()> {
     let mut sigint =
-        signal(SignalKind::interrupt()).expect("Failed to create SIGINT signal handler");
+        signal(SignalKind::interrupt()).wrap_err("Failed to create SIGINT signal handler")?;
     let mut sigterm =
-        signal(SignalKind::terminate()).expect("Failed to create SIGTERM signal handler");
+        signal(SignalKind::terminate()).wrap_err("Failed to create SIGTERM signal handler")?;
+    // Sent when the terminal goes away, such as a Termux session that is
+    // swiped away. Left alone when ignored, as under nohup.
+    let mut sighup = match hangup_ignored() {
+        true => None,
+        false => {
+            Some(signal(SignalKind::hangup()).wrap_err("Failed to create SIGHUP signal handler")?)
+        }
+    };
+    // Sent by a newer instance taking over our tunnels. Without it, as where
+    // sandboxes refuse the handler, rtun simply cannot be taken over.
+    let mut sigusr1 = signal(SignalKind::user_defined1()).ok();
 
     tokio::spawn(async move {
-        tokio::select! {
-            _ = sigint.recv() => {
-                println!("Received SIGINT");
-                let _ = tx.lock().await.send(()).await;
-            },
+        let hung_up = async {
+            match sighup.as_mut() {
+                Some(sighup) => sighup.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let taken_over = async {
+            match sigusr1.as_mut() {
+                Some(sigusr1) => sigusr1.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let stop = tokio::select! {
+            _ = sigint.recv() => Stop::Close,
             _ = sigterm.recv() => {
-                println!("Received SIGTERM");
-                let _ = tx.lock().await.send(()).await;
+                // How service managers stop rtun to restart or upgrade it.
+                session::keep();
+                Stop::Close
+            }
+            _ = hung_up => Stop::Close,
+            _ = taken_over => Stop::Detach,
+        };
+        let _ = quit.send(stop).await;
+    });
+    Ok(())
+}
+
+/// xterm sequences pushing the terminal title on a stack and popping it
+/// back, so the title rtun sets does not outlive it.
+const SAVE_TITLE: &str = "\x1b[22;0t";
+const RESTORE_TITLE: &str = "\x1b[23;0t";
+
+/// Undoes [`resume_terminal`]. Every step runs even when one before it
+/// failed, so the shell gets back as much of the terminal as possible; the
+/// first error is returned.
+fn restore_terminal() -> Result<()> {
+    let mut out = stdout();
+    let results = [
+        disable_raw_mode(),
+        out.execute(DisableBracketedPaste).map(drop),
+        out.execute(DisableFocusChange).map(drop),
+        out.execute(Print(RESTORE_TITLE)).map(drop),
+        out.execute(LeaveAlternateScreen).map(drop),
+        out.execute(Show).map(drop),
+    ];
+    results.into_iter().collect::<std::io::Result<()>>()?;
+    Ok(())
+}
+
+/// Switches to the alternate screen in raw mode, as the TUI draws in.
+fn resume_terminal() -> Result<()> {
+    stdout()
+        .execute(EnterAlternateScreen)?
+        .execute(EnableBracketedPaste)?
+        .execute(EnableFocusChange)?
+        .execute(Print(SAVE_TITLE))?;
+    enable_raw_mode()?;
+    Ok(())
+}
+
+/// Hands the terminal to `command`, such as psql, until it exits. The
+/// client runs in a process group of its own, in the foreground of the
+/// terminal, so Ctrl+C reaches it alone rather than rtun and its ssh
+/// processes. The tunnels are tended to and control requests answered
+/// meanwhile.
+async fn launch_client(
+    command: &str,
+    manager: &mut Manager,
+    requests: &mut mpsc::UnboundedReceiver<control::Pending>,
+) -> Result<()> {
+    restore_terminal()?;
+    let terminal = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    let mut client = tokio::process::Command::new(platform::shell());
+    client.arg("-c").arg(command);
+    unsafe {
+        client.pre_exec(move || {
+            libc::setpgid(0, 0);
+            if terminal {
+                // Taking the terminal from the background would stop it.
+                libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                if libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid()) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            }
+            // rtun waits for it to exit, so being stopped would leave both
+            // stuck.
+            libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+            Ok(())
+        });
+    }
+    let status = match client.spawn() {
+        Ok(mut child) => {
+            let mut tick = tokio::time::interval(manager.config().intervals.ui_tick());
+            loop {
+                tokio::select! {
+                    status = child.wait() => break status,
+                    Some((request, caller, reply)) = requests.recv() => {
+                        let _ = reply.send(handle_request(manager, request, caller));
+                    }
+                    _ = tick.tick() => {
+                        manager.poll_events();
+                    }
+                }
+            }
+        }
+        Err(e) => Err(e),
+    };
+    if terminal {
+        // The client's group kept the foreground; the TUI draws from it.
+        unsafe {
+            let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            libc::signal(libc::SIGTTOU, previous);
+        }
+    }
+    resume_terminal()?;
+    let status = status.wrap_err_with(|| format!("Could not run {}", command))?;
+    // 127 is the shell not finding the client.
+    match status.code() {
+        Some(127) => Err(eyre!(
+            "{} was not found",
+            command.split_whitespace().next().unwrap_or(command)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Holds the terminal in raw mode on the alternate screen. Dropping the guard
+/// restores it, so early returns and unwinding never leave the shell unusable.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        // Created first so a failure halfway through setup is still undone.
+        let guard = TerminalGuard;
+        resume_terminal()?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = restore_terminal();
+    }
+}
+
+/// Installs color-eyre with a panic hook that leaves the alternate screen and
+/// raw mode before the panic report is printed.
+fn install_hooks() -> Result<()> {
+    let mut hooks = color_eyre::config::HookBuilder::default();
+    if display::no_color() {
+        hooks = hooks.theme(color_eyre::config::Theme::new());
+    }
+    let (panic_hook, eyre_hook) = hooks.into_hooks();
+    eyre_hook.install()?;
+    let panic_hook = panic_hook.into_panic_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = restore_terminal();
+        panic_hook(info);
+    }));
+    Ok(())
+}
+
+/// Draws through `inner` without colors under `NO_COLOR`, and with ASCII
+/// stand-ins for unicode symbols under `--ascii`, for CI captures, logs and
+/// old consoles.
+struct PlainBackend<B> {
+    inner: B,
+    color: bool,
+    ascii: bool,
+}
+
+impl<B: Backend> PlainBackend<B> {
+    fn new(inner: B, ascii: bool) -> Self {
+        PlainBackend {
+            inner,
+            color: !display::no_color(),
+            ascii,
+        }
+    }
+
+    fn restyle(&self, cell: &buffer::Cell) -> buffer::Cell {
+        let mut cell = cell.clone();
+        if !self.color {
+            // Keeps selections visible without their background.
+            if cell.bg != Color::Reset {
+                cell.modifier |= Modifier::REVERSED;
+            }
+            cell.fg = Color::Reset;
+            cell.bg = Color::Reset;
+            cell.underline_color = Color::Reset;
+        }
+        if self.ascii {
+            if let Some(symbol) = display::ascii(cell.symbol()) {
+                cell.set_symbol(symbol);
             }
         }
+        cell
+    }
+}
+
+impl<B: Backend> Backend for PlainBackend<B> {
+    fn draw<'a, I>(&mut self, content: I) -> std::io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a buffer::Cell)>,
+    {
+        if self.color && !self.ascii {
+            return self.inner.draw(content);
+        }
+        let cells: Vec<(u16, u16, buffer::Cell)> = content
+            .map(|(x, y, cell)| (x, y, self.restyle(cell)))
+            .collect();
+        self.inner
+            .draw(cells.iter().map(|(x, y, cell)| (*x, *y, cell)))
+    }
+
+    fn append_lines(&mut self, n: u16) -> std::io::Result<()> {
+        self.inner.append_lines(n)
+    }
+
+    fn hide_cursor(&mut self) -> std::io::Result<()> {
+        self.inner.hide_cursor()
+    }
+
+    fn show_cursor(&mut self) -> std::io::Result<()> {
+        self.inner.show_cursor()
+    }
+
+    fn get_cursor(&mut self) -> std::io::Result<(u16, u16)> {
+        self.inner.get_cursor()
+    }
+
+    fn set_cursor(&mut self, x: u16, y: u16) -> std::io::Result<()> {
+        self.inner.set_cursor(x, y)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.inner.clear()
+    }
+
+    fn size(&self) -> std::io::Result<Rect> {
+        self.inner.size()
+    }
+
+    fn window_size(&mut self) -> std::io::Result<backend::WindowSize> {
+        self.inner.window_size()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A popup centered in `r`, covering the given percentages of it but never
+/// less than `min_width` x `min_height` unless `r` itself is smaller.
+fn centered_rect(r: Rect, percent_x: u16, percent_y: u16, min_width: u16, min_height: u16) -> Rect {
+    let width = (r.width * percent_x / 100).max(min_width).min(r.width);
+    let height = (r.height * percent_y / 100).max(min_height).min(r.height);
+    Rect::new(
+        r.x + (r.width - width) / 2,
+        r.y + (r.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Terminal title summing up the tunnels, like `rtun: 5 up, 1 down`.
+fn status_title(tunnels: &[ManagedTunnel], unseen_drops: usize) -> String {
+    let up = tunnels
+        .iter()
+        .filter(|t| t.status == TunnelStatus::Up)
+        .count();
+    let down = tunnels
+        .iter()
+        .filter(|t| {
+            matches!(
+                t.status,
+                TunnelStatus::Reconnecting(_) | TunnelStatus::Failed
+            )
+        })
+        .count();
+    let mut title = format!("rtun: {} up", up);
+    if down > 0 {
+        title.push_str(&format!(", {} down", down));
+    }
+    if unseen_drops > 0 {
+        title.push_str(&format!(" ({} new)", unseen_drops));
+    }
+    title
+}
+
+/// Accents told apart on both dark and light terminal themes.
+const HOST_COLORS: [Color; 8] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightMagenta,
+];
+
+/// The configured accent of `host`, or one derived from its name so that it
+/// stays the same across runs.
+fn host_color(host: &str, configured: &BTreeMap<String, String>) -> Color {
+    if let Some(color) = configured.get(host).and_then(|c| c.parse().ok()) {
+        return color;
+    }
+    // FNV-1a, as the std hashers are not stable across releases.
+    let hash = host.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
     });
+    HOST_COLORS[hash as usize % HOST_COLORS.len()]
+}
+
+/// Moves the highlight of a list with `len` items by one.
+fn move_selection(state: &mut ListState, len: usize, down: bool) {
+    let next = match (state.selected(), down) {
+        (None, _) => 0,
+        (Some(i), true) => (i + 1).min(len.saturating_sub(1)),
+        (Some(i), false) => i.saturating_sub(1),
+    };
+    state.select(Some(next));
+}
+
+/// Readline-style editing of the single line new-tunnel input. Returns
+/// whether the key was used.
+fn edit_input(textarea: &mut TextArea, key: KeyEvent) -> bool {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    match key.code {
+        KeyCode::Char('a') if ctrl => textarea.move_cursor(CursorMove::Head),
+        KeyCode::Char('e') if ctrl => textarea.move_cursor(CursorMove::End),
+        KeyCode::Char('b') if ctrl => textarea.move_cursor(CursorMove::Back),
+        KeyCode::Char('f') if ctrl => textarea.move_cursor(CursorMove::Forward),
+        KeyCode::Char('b') if alt => textarea.move_cursor(CursorMove::WordBack),
+        KeyCode::Char('f') if alt => textarea.move_cursor(CursorMove::WordForward),
+        KeyCode::Char('w') if ctrl => {
+            textarea.delete_word();
+        }
+        KeyCode::Char('u') if ctrl => {
+            textarea.delete_line_by_head();
+        }
+        KeyCode::Char('k') if ctrl => {
+            textarea.delete_line_by_end();
+        }
+        KeyCode::Char('d') if ctrl => {
+            textarea.delete_next_char();
+        }
+        KeyCode::Char(_) if ctrl || alt => return false,
+        KeyCode::Char(c) => textarea.insert_char(c),
+        KeyCode::Left if ctrl || alt => textarea.move_cursor(CursorMove::WordBack),
+        KeyCode::Right if ctrl || alt => textarea.move_cursor(CursorMove::WordForward),
+        KeyCode::Left => textarea.move_cursor(CursorMove::Back),
+        KeyCode::Right => textarea.move_cursor(CursorMove::Forward),
+        KeyCode::Home => textarea.move_cursor(CursorMove::Head),
+        KeyCode::End => textarea.move_cursor(CursorMove::End),
+        KeyCode::Backspace if ctrl || alt => {
+            textarea.delete_word();
+        }
+        KeyCode::Backspace => {
+            textarea.delete_char();
+        }
+        KeyCode::Delete => {
+            textarea.delete_next_char();
+        }
+        _ => return false,
+    }
+    true
+}
+
+type Batch = Vec<(usize, Result<TunnelSpec, String>)>;
+
+/// Opens the tunnels of a batch that parsed. Returns how many, and the lines
+/// that did not parse with why.
+fn open_batch(manager: &mut Manager, specs: Batch) -> (usize, Vec<(usize, String)>) {
+    let mut opened = 0;
+    let mut failed = Vec::new();
+    for (line, spec) in specs {
+        match spec {
+            Ok(spec) => {
+                manager.open(spec);
+                opened += 1;
+            }
+            Err(e) => failed.push((line, e)),
+        }
+    }
+    (opened, failed)
+}
+
+fn batch_errors(failed: &[(usize, String)]) -> String {
+    failed
+        .iter()
+        .map(|(line, e)| i18n::format("batch-line", &[("line", line), ("error", e)]))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
-fn centered_rect(r: Rect, percent_x: u16, percent_y: u16) -> Rect {
-    let popup_layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Percentage((100 - percent_y) / 2),
-            Constraint::Percentage(percent_y),
-            Constraint::Percentage((100 - percent_y) / 2),
-        ])
-        .split(r);
+/// The new-tunnel wizard, offering the recent and pinned tunnels first.
+fn new_wizard(hosts: &[String], config: &config::Config) -> Wizard {
+    Wizard::new(hosts.to_vec())
+        .with_ssh(&config.ssh)
+        .with_recent(recent::quick(&recent::load()))
+}
 
-    Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Percentage((100 - percent_x) / 2),
-            Constraint::Percentage(percent_x),
-            Constraint::Percentage((100 - percent_x) / 2),
-        ])
-        .split(popup_layout[1])[1]
+/// Input for the current step of the new-tunnel wizard, or its first step.
+fn code_input<'a>(prompt: &str) -> TextArea<'a> {
+    let mut textarea = TextArea::default();
+    textarea.set_block(
+        Block::bordered()
+            .border_style(Style::default().fg(Color::Yellow))
+            .title(prompt.trim().to_string())
+            .title_bottom(i18n::text("keys-prompt")),
+    );
+    textarea
 }
 
-fn get_text_area<'a>() -> TextArea<'a> {
+fn get_text_area<'a>(wizard: Option<&Wizard>) -> TextArea<'a> {
+    let (title, placeholder) = match wizard.map(|w| (w.step, w)) {
+        None | Some((Step::Host, _)) => (
+            i18n::text("wizard-host"),
+            i18n::text("wizard-host-placeholder"),
+        ),
+        Some((Step::RemotePort, w)) => (
+            i18n::format("wizard-remote-port", &[("host", &w.host)]),
+            i18n::text("wizard-remote-port-placeholder"),
+        ),
+        Some((Step::LocalPort, w)) => (
+            i18n::format(
+                "wizard-local-port",
+                &[("host", &w.host), ("port", &w.remote_port)],
+            ),
+            i18n::text("wizard-local-port-placeholder"),
+        ),
+    };
     let mut textarea = TextArea::default();
     textarea.set_block(
         Block::default()
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::LightBlue))
-            .title("Hit enter to set port"),
+            .title(title),
     );
     textarea.set_style(Style::default().fg(Color::Yellow));
     textarea.set_placeholder_style(Style::default());
-    textarea.set_placeholder_text("Host_name 1234:45321");
+    textarea.set_placeholder_text(placeholder);
     textarea
 }
 
-fn get_config_from_str(input: &str) -> Result<(String, u16, u16), &'static str> {
-    let parts: Vec<&str> = input.split(' ').collect();
-    if parts.len() != 2 {
-        return Err("Input does not match expected format 'HOST_NAME 12234:45321'");
+fn print_history(limit: usize, host: Option<&str>, json: bool) -> Result<()> {
+    for entry in history::load(limit, host)? {
+        if json {
+            println!("{}", serde_json::to_string(&entry)?);
+        } else {
+            println!("{}", entry);
+        }
     }
+    Ok(())
+}
 
-    let host_name = parts[0].to_string();
+/// Prints what is wrong with the config file at `file`, or the default one,
+/// and exits with the status of config errors if anything is.
+fn check_config(file: Option<PathBuf>) -> Result<()> {
+    let path = file
+        .or_else(config::config_path)
+        .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    let contents =
+        fs::read_to_string(&path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    match config::parse(&contents, &path) {
+        Ok(config) => {
+            println!("{}: ok, {} profiles", path.display(), config.profiles.len());
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(errors::code(&e).exit_status());
+        }
+    }
+}
 
-    let ports: Vec<&str> = parts[1].split(':').collect();
-    if ports.len() != 2 {
-        return Err("Ports part does not match expected format '12234:45321'");
+fn print_audit(json: bool) -> Result<()> {
+    let entries = audit::load()?;
+    for entry in entries.iter() {
+        if json {
+            println!("{}", serde_json::to_string(entry)?);
+        } else {
+            println!("{}", entry);
+        }
+    }
+    if !json {
+        // Compared with an earlier export, this shows that nothing was cut
+        // off the end either.
+        let head = entries.last().map(|e| e.hash.as_str()).unwrap_or("none");
+        eprintln!(
+            "{} entries, chain intact, last hash {}",
+            entries.len(),
+            head
+        );
     }
+    Ok(())
+}
 
-    let host_port = ports[0]
-        .parse::<u16>()
-        .map_err(|_| "Failed to parse host_port")?;
-    let remote_port = ports[1]
-        .parse::<u16>()
-        .map_err(|_| "Failed to parse remote_port")?;
+/// Asks the running instance for the status of its tunnels, falling back to
+/// the ssh processes recorded in the state file.
+fn print_status(config: &ControlConfig, format: Format) -> Result<()> {
+    let summary = match control::send(config, &Request::List) {
+        Ok(reply) => {
+            let result = reply.map_err(|e| eyre!(e.message))?;
+            let list: TunnelList = serde_json::from_value(result)?;
+            Summary::new(true, list.tunnels)
+        }
+        Err(e) if errors::code(&e) == ErrorCode::Incompatible => return Err(e),
+        Err(_) => Summary::new(
+            false,
+            state::running().iter().map(TunnelInfo::from).collect(),
+        ),
+    };
+    println!("{}", summary.render(format));
+    Ok(())
+}
 
-    Ok((host_name, host_port, remote_port))
+/// Prints which of the tunnels `wanted` `rtun up` would open in the running
+/// instance, for `--diff`, without changing anything.
+fn print_plan(config: &ControlConfig, wanted: &[TunnelSpec]) -> Result<()> {
+    let running = match control::call::<TunnelList>(config, &Request::List) {
+        Ok(list) => list.tunnels,
+        Err(e) if errors::code(&e) == ErrorCode::NotRunning => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let plan = plan::plan(wanted, &running);
+    for change in &plan.changes {
+        println!("{}", change);
+    }
+    println!("{}", plan.summary());
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    color_eyre::install()?;
-    stdout().execute(EnterAlternateScreen)?;
-    enable_raw_mode()?;
-    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
-    terminal.clear()?;
-    let (tx, rx) = mpsc::channel(1);
-    let sender = Arc::new(Mutex::new(tx));
-    let shutdown_receiver = Arc::new(Mutex::new(rx));
-    handle_signals(sender.clone()).await;
-
-    let mut ports: Vec<String> = Vec::new();
-    let mut textarea = get_text_area();
-    let mut new_port: Option<String> = None;
-    loop {
-        let _ = terminal.draw(|frame| {
-            let area = frame.size();
-            let items = &ports;
-            let items: Vec<String> = items.iter().map(|t| format!("{}:{}", t, t)).collect();
+fn print_env(config: &config::Config, name: &str) -> Result<()> {
+    let profile = config.profile(name)?;
+    // Ports of the running instance first, where tunnels may have been
+    // opened on other ones than the config says.
+    let mut endpoints: Vec<Endpoint> = control::call::<TunnelList>(&config.control, &Request::List)
+        .map(|list| list.tunnels)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|tunnel| {
+            Some(Endpoint {
+                name: tunnel.name?,
+                port: tunnel.local_port,
+            })
+        })
+        .collect();
+    endpoints.extend(exports::endpoints(&config.profile_tunnels(name)?));
+    let exports = exports::render(&profile.env, &endpoints)
+        .map_err(|e| Failure::new(ErrorCode::Config, e))?;
+    print!("{}", exports);
+    Ok(())
+}
 
-            let list = List::new(items)
-                .block(
-                    Block::bordered()
-                        .title("Rtun - SSH Tunnel Manager (hit esc to quit, n to new tunnel)"),
-                )
-                .style(Style::default().fg(Color::White))
-                .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
-                .highlight_symbol(">>")
-                .repeat_highlight_symbol(true)
-                .direction(ListDirection::BottomToTop);
-            let center = centered_rect(area, 50, 50);
-            frame.render_widget(list, center);
-
-            let list_hosts = List::new(get_hosts())
-                .style(Style::default().fg(Color::White))
-                .direction(ListDirection::TopToBottom);
+/// The profile `.rtun.toml` selected, for the tab bar and plain mode.
+fn project_text(selection: &project::Selection) -> String {
+    i18n::format(
+        "project-profile",
+        &[("profile", &selection.profile), ("name", &selection.name)],
+    )
+}
 
+fn print_report(
+    config: &ControlConfig,
+    format: ReportFormat,
+    output: Option<&std::path::Path>,
+    copy: bool,
+) -> Result<()> {
+    let list: TunnelList = control::call(config, &Request::List)?;
+    let events = history::load(report::REPORT_EVENTS, None).wrap_err("Could not read history")?;
+    let text = report::render(format, &list.tunnels, &events, chrono::Utc::now());
+    match output {
+        Some(path) => fs::write(path, &text)
+            .wrap_err_with(|| format!("Could not write {}", path.display()))?,
+        None => print!("{}", text),
+    }
+    if copy && !platform::copy_to_clipboard(&text) {
+        return Err(eyre!(
+            "Could not copy to the clipboard, none of pbcopy, wl-copy, xclip, xsel or termux-clipboard-set worked"
+        ));
+    }
+    Ok(())
+}
+
+const BACKGROUND_LOG_PATH: &str = ".rtun/background.log";
+const WAIT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Opens the tunnels of `rtun up --wait` in the running instance, or starts
+/// one in the background with them, logging to `~/.rtun/background.log`.
+fn up_in_background(
+    config: &config::Config,
+    args: &Args,
+    profiles: &[String],
+    initial: &[TunnelSpec],
+) -> Result<()> {
+    if let Ok(version) = control::call::<VersionInfo>(&config.control, &Request::Version) {
+        let tie = args.tied_to().map(|pid| Request::Tie {
+            pid,
+            local_ports: Vec::new(),
+        });
+        let untied = match &tie {
+            // Instances listing no methods predate `tie` as well.
+            Some(_) if version.methods.is_empty() => Some(version.too_old("tie")),
+            Some(tie) => version.incompatibility(tie),
+            None => None,
+        };
+        // Before opening anything, which would otherwise outlive the process.
+        if let Some(failure) = untied {
+            return Err(failure.into());
+        }
+        let mut local_ports = Vec::new();
+        for profile in profiles {
+            let request = Request::Up {
+                profile: profile.clone(),
+            };
+            let opened = control::call::<Opened>(&config.control, &request)?;
+            local_ports.extend(opened.local_ports);
+        }
+        // Those of --from-file, which the instance has no profile for.
+        let from_profiles = config.compose_profiles(profiles)?;
+        for spec in initial.iter().filter(|spec| !from_profiles.contains(spec)) {
+            let request = Request::Add(NewTunnel {
+                host: spec.host.clone(),
+                local_port: spec.local_port,
+                remote_port: spec.remote_port,
+                remote_host: spec.remote_host.clone(),
+                name: spec.name.clone(),
+                label: spec.label.clone(),
+                tags: spec.tags.clone(),
+            });
+            let added = control::call::<Option<TunnelInfo>>(&config.control, &request)?;
+            local_ports.extend(added.map(|tunnel| tunnel.local_port));
+        }
+        if let Some(pid) = args.tied_to() {
+            let request = Request::Tie { pid, local_ports };
+            control::call::<bool>(&config.control, &request)?;
+        }
+        return Ok(());
+    }
+    let log = std::env::home_dir()
+        .map(|home| home.join(BACKGROUND_LOG_PATH))
+        .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    let mut command = std::process::Command::new(
+        std::env::current_exe().wrap_err("Could not locate the rtun binary")?,
+    );
+    command.arg("--plain").arg("--log-file").arg(&log);
+    if args.strict {
+        command.arg("--strict");
+    }
+    if let Some(path) = &args.from_file {
+        command.arg("--from-file").arg(path);
+    }
+    if let Some(path) = &args.pid_file {
+        command.arg("--pid-file").arg(path);
+    }
+    if let Some(pid) = args.tied_to() {
+        command.arg("--tie-to-pid").arg(pid.to_string());
+    }
+    // Its own process group, so Ctrl+C in the shell that started it does
+    // not reach it.
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command
+        .arg("up")
+        .args(profiles)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .wrap_err("Could not start rtun in the background")?;
+    Ok(())
+}
+
+/// Waits until the tunnels of the running instance on `ports`, or all of
+/// them when empty, are up and take connections on their local port.
+fn wait_until_ready(
+    config: &ControlConfig,
+    ports: &[u16],
+    timeout: std::time::Duration,
+) -> Result<()> {
+    let deadline = std::time::Instant::now() + timeout;
+    // Progress goes to a terminal only, on one line rewritten as it changes.
+    let terminal = std::io::stderr().is_terminal();
+    let mut shown = String::new();
+    loop {
+        // Not answering yet is fine while an instance starts.
+        let tunnels = control::call::<TunnelList>(config, &Request::List)
+            .map(|list| list.tunnels)
+            .unwrap_or_default();
+        let waiting = ready::waiting_for(&tunnels, ports);
+        let total = if ports.is_empty() {
+            tunnels.len()
+        } else {
+            ports.len()
+        };
+        let failing = tunnels
+            .iter()
+            .filter(|t| waiting.contains(&t.local_port) && t.health == Health::Down)
+            .count();
+        let progress = format!(
+            "{} of {} ready, {} pending, {} failing",
+            total - waiting.len(),
+            total,
+            waiting.len() - failing,
+            failing
+        );
+        if terminal && progress != shown {
+            eprint!("\r\x1b[K{}", progress);
+            shown = progress;
+        }
+        if waiting.is_empty() && !(ports.is_empty() && tunnels.is_empty()) {
+            if terminal {
+                eprintln!();
+            }
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            if terminal {
+                eprintln!();
+            }
+            let mut code = ErrorCode::Other;
+            let mut reasons = Vec::new();
+            for port in waiting.iter().copied() {
+                match tunnels.iter().find(|t| t.local_port == port) {
+                    Some(tunnel) => {
+                        let mut reason = format!(
+                            "{} {}:{} {}",
+                            tunnel.host, tunnel.local_port, tunnel.remote_port, tunnel.status
+                        );
+                        if let Some(error) = &tunnel.error {
+                            reason.push_str(&format!(": {}", error.message));
+                            code = error.code;
+                        }
+                        reasons.push(reason);
+                    }
+                    None => reasons.push(format!("no tunnel on port {}", port)),
+                }
+            }
+            if reasons.is_empty() {
+                reasons.push("no tunnels".to_string());
+            }
+            let message = format!(
+                "Not ready after {}s: {}",
+                timeout.as_secs(),
+                reasons.join(", ")
+            );
+            return Err(Failure::new(code, message).into());
+        }
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Carries out what a hook asked for. Tunnels whose port is taken are
+/// skipped, so hooks may open a profile every time a VPN comes up.
+fn run_hook_commands(
+    manager: &mut Manager,
+    config: &config::Config,
+    commands: Vec<hooks::Command>,
+) -> Result<()> {
+    for command in commands {
+        let specs = match command {
+            hooks::Command::Open(spec) => vec![*spec],
+            hooks::Command::OpenProfile(name) => config.profile_tunnels(&name)?,
+            hooks::Command::CloseTag(tag) => {
+                manager.close_tagged(&[tag], None);
+                continue;
+            }
+        };
+        for spec in specs {
+            if !manager.port_in_use(spec.local_port) {
+                manager.open(spec);
+            }
+        }
+    }
+    Ok(())
+}
+
+const EVENTS_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Prints the events of the running instance until it exits or, with
+/// `follow`, forever.
+fn print_events(config: &ControlConfig, follow: bool, json: bool) -> Result<()> {
+    loop {
+        match control::subscribe(config) {
+            Ok(events) => {
+                for event in events {
+                    let event = event?;
+                    if json {
+                        println!("{}", serde_json::to_string(&event)?);
+                    } else {
+                        println!("{}", event);
+                    }
+                }
+            }
+            Err(e) if !follow || errors::code(&e) == ErrorCode::Incompatible => return Err(e),
+            Err(_) => {}
+        }
+        if !follow {
+            return Ok(());
+        }
+        std::thread::sleep(EVENTS_RETRY_INTERVAL);
+    }
+}
+
+fn handle_request(manager: &mut Manager, request: Request, caller: Caller) -> Reply {
+    // Users allowed to manage their own tunnels only are limited to those.
+    let owner = Some(caller.uid).filter(|_| caller.permission < Permission::ManageAll);
+    match request {
+        Request::Version | Request::Subscribe => Err(RpcError::new(
+            RpcError::INVALID_REQUEST,
+            "Answered by the control server",
+        )),
+        Request::List => control::reply(&TunnelList {
+            tunnels: manager.tunnels.iter().map(TunnelInfo::from).collect(),
+        }),
+        Request::Add(tunnel) => {
+            let spec = TunnelSpec::from(tunnel);
+            if manager.port_in_use(spec.local_port) {
+                return Err(RpcError::new(
+                    RpcError::INVALID_PARAMS,
+                    format!("Local port {} is already in use", spec.local_port),
+                ));
+            }
+            manager.open_for(spec, caller.uid);
+            control::reply(&manager.tunnels.last().map(TunnelInfo::from))
+        }
+        Request::Up { profile } => {
+            let specs = manager
+                .config()
+                .profile_tunnels(&profile)
+                .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, format!("{:#}", e)))?;
+            let mut local_ports = Vec::new();
+            for spec in specs {
+                if !manager.port_in_use(spec.local_port) {
+                    local_ports.push(spec.local_port);
+                    manager.open_for(spec, caller.uid);
+                }
+            }
+            control::reply(&Opened {
+                opened: local_ports.len(),
+                local_ports,
+            })
+        }
+        Request::Tie { pid, local_ports } => {
+            if !instance::is_running(pid) {
+                return Err(RpcError::new(
+                    RpcError::INVALID_PARAMS,
+                    format!("No process with pid {}", pid),
+                ));
+            }
+            manager.tie(pid, local_ports, owner);
+            control::reply(&true)
+        }
+        Request::Close { local_port, tags } => {
+            let closed = manager.close_matching(
+                |spec| local_port.is_none_or(|p| p == spec.local_port) && spec.has_tags(&tags),
+                owner,
+            );
+            control::reply(&Closed { closed })
+        }
+        Request::Retarget { local_port, target } => {
+            let target = RemoteTarget::try_from(target)
+                .map_err(|e| RpcError::new(RpcError::INVALID_PARAMS, e))?;
+            if !manager.retarget(local_port, target, owner) {
+                return Err(RpcError::new(
+                    RpcError::INVALID_PARAMS,
+                    format!(
+                        "No open tunnel on port {} can change its destination",
+                        local_port
+                    ),
+                ));
+            }
+            let tunnel = manager
+                .tunnels
+                .iter()
+                .rev()
+                .find(|t| t.spec.local_port == local_port);
+            control::reply(&tunnel.map(TunnelInfo::from))
+        }
+        Request::Drain { local_port, tags } => {
+            let draining = manager.drain_matching(
+                |spec| local_port.is_none_or(|p| p == spec.local_port) && spec.has_tags(&tags),
+                owner,
+            );
+            control::reply(&Draining { draining })
+        }
+    }
+}
+
+const ATTACH_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Shows the tunnels and history of the running instance, refreshed every
+/// second, without any way to change them.
+async fn attach(config: &config::Config, ascii: bool) -> Result<()> {
+    let (quit_tx, mut quit_rx) = mpsc::channel(1);
+    handle_signals(quit_tx)?;
+    let _terminal_guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(PlainBackend::new(CrosstermBackend::new(stdout()), ascii))?;
+    terminal.clear()?;
+
+    let mut status: Result<Vec<TunnelInfo>, String> = Ok(Vec::new());
+    let mut history: Vec<HistoryEntry> = Vec::new();
+    let mut refreshed: Option<std::time::Instant> = None;
+    loop {
+        if quit_rx.try_recv().is_ok() {
+            break;
+        }
+        if refreshed.is_none_or(|r| r.elapsed() >= ATTACH_REFRESH_INTERVAL) {
+            status = control::call::<TunnelList>(&config.control, &Request::List)
+                .map(|list| list.tunnels)
+                .map_err(|e| format!("{:#}", e));
+            history = history::load(HISTORY_VIEW_LIMIT, None).unwrap_or_default();
+            refreshed = Some(std::time::Instant::now());
+        }
+
+        terminal.draw(|frame| {
+            let rows = Layout::vertical([
+                Constraint::Percentage(50),
+                Constraint::Percentage(50),
+                Constraint::Length(1),
+            ])
+            .split(frame.size());
+            let title = i18n::text("title-read-only");
+            match &status {
+                Ok(tunnels) => {
+                    let items: Vec<ListItem> = tunnels
+                        .iter()
+                        .map(|t| {
+                            let color = host_color(&t.host, &config.host_colors);
+                            let mut item = format!(
+                                "{} {}:{} [{}]",
+                                t.host, t.local_port, t.remote_port, t.status
+                            );
+                            if let Some(label) = &t.label {
+                                item = format!("{}  {}", label, item);
+                            }
+                            ListItem::new(Line::from(vec![
+                                Span::styled("● ", Style::default().fg(color)),
+                                Span::raw(item),
+                            ]))
+                        })
+                        .collect();
+                    frame.render_widget(
+                        List::new(items).block(Block::bordered().title(title.as_str())),
+                        rows[0],
+                    );
+                }
+                Err(e) => frame.render_widget(
+                    Paragraph::new(i18n::format("not-connected", &[("error", e)]))
+                        .style(Style::default().fg(Color::Red))
+                        .wrap(Wrap { trim: true })
+                        .block(Block::bordered().title(title.as_str())),
+                    rows[0],
+                ),
+            }
+            let items: Vec<String> = history.iter().rev().map(|e| e.to_string()).collect();
+            frame.render_widget(
+                List::new(items).block(Block::bordered().title(i18n::text("title-history"))),
+                rows[1],
+            );
+            frame.render_widget(
+                Paragraph::new("esc: quit").style(Style::default().fg(Color::DarkGray)),
+                rows[2],
+            );
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(100))? {
+            if let event::Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && matches!(key.code, KeyCode::Esc | KeyCode::Char('q'))
+                {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Numbers of the menu of `--plain` and their messages.
+const PLAIN_MENU: [(&str, &str); 6] = [
+    ("1", "plain-list"),
+    ("2", "action-new-tunnel"),
+    ("3", "plain-close"),
+    ("4", "plain-restart"),
+    ("5", "plain-open-profile"),
+    ("0", "action-quit"),
+];
+
+/// What `--plain` takes the next line of input for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlainInput {
+    Menu,
+    NewTunnel,
+    Close,
+    Restart,
+    Profile,
+    /// A one-time code for the tunnel on the port.
+    Code(u16),
+    /// Whether to open the first pending production tunnel.
+    Confirm,
+}
+
+fn print_plain_menu() {
+    for (number, key) in PLAIN_MENU {
+        println!("{}) {}", number, i18n::text(key));
+    }
+    println!("{}", i18n::text("plain-menu-hint"));
+}
+
+/// Opens the tunnels of the session an earlier instance left behind that
+/// are not open yet, telling `frontend` about each.
+fn restore_session(
+    manager: &mut Manager,
+    config: &config::Config,
+    session: session::Session,
+    frontend: &mut impl Frontend,
+) {
+    for saved in session.tunnels {
+        if manager.port_in_use(saved.local_port()) {
+            continue;
+        }
+        match saved.restore(config) {
+            Ok(spec) => {
+                frontend.notice(&i18n::format(
+                    "session-restored",
+                    &[("tunnel", &spec.to_string())],
+                ));
+                manager.open(spec);
+            }
+            Err(e) => frontend.warn(&i18n::format("session-not-restored", &[("error", &e)])),
+        }
+    }
+}
+
+/// Prints the tunnels numbered in `order`, or says there are none.
+fn print_plain_tunnels(manager: &Manager, order: &[usize]) {
+    if order.is_empty() {
+        println!("{}", i18n::text("plain-no-tunnels"));
+    }
+    for (number, &i) in order.iter().enumerate() {
+        let tunnel = &manager.tunnels[i];
+        let mut line = format!("{}) {} {}", number + 1, tunnel.spec, tunnel.status);
+        if let Some(label) = &tunnel.spec.label {
+            line.push_str(&format!(", {}", label));
+        }
+        if let Some(error) = tunnel.error_summary() {
+            line.push_str(&format!(", {}", error));
+        }
+        if let Some(warning) = remoteport::not_listening(tunnel.spec.local_port) {
+            line.push_str(&format!(", {}", warning));
+        }
+        println!("{}", line);
+    }
+}
+
+/// Item `choice` of a list numbered from 1.
+fn plain_choice<T: Copy>(items: &[T], choice: &str) -> Option<T> {
+    let item = choice
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_sub(1))
+        .and_then(|i| items.get(i).copied());
+    if item.is_none() {
+        println!(
+            "{}",
+            i18n::format("plain-no-such-number", &[("number", &choice)])
+        );
+    }
+    item
+}
+
+/// Carries out `line`, given in answer to what `input` asked for.
+fn plain_answer(
+    manager: &mut Manager,
+    config: &config::Config,
+    input: PlainInput,
+    line: &str,
+    listed: &[usize],
+) {
+    match input {
+        PlainInput::Menu => {}
+        PlainInput::Code(port) => manager.answer(port, line.to_string()),
+        PlainInput::Confirm => manager.confirm(line.eq_ignore_ascii_case("y")),
+        PlainInput::NewTunnel => match TunnelSpec::parse(line) {
+            Ok(spec) if manager.port_in_use(spec.local_port) => println!(
+                "{}",
+                i18n::format("plain-port-in-use", &[("port", &spec.local_port)])
+            ),
+            Ok(spec) => manager.open(spec),
+            Err(e) => println!("{}", i18n::format("error", &[("error", &e)])),
+        },
+        PlainInput::Close => {
+            if let Some(i) = plain_choice(listed, line) {
+                manager.close(i);
+            }
+        }
+        PlainInput::Restart => {
+            if let Some(i) = plain_choice(listed, line) {
+                manager.restart(i, "restarted by user");
+            }
+        }
+        PlainInput::Profile => {
+            let profiles: Vec<&String> = config.profiles.keys().collect();
+            let Some(name) = plain_choice(&profiles, line) else {
+                return;
+            };
+            match config.profile_tunnels(name) {
+                Ok(specs) => {
+                    for spec in specs {
+                        if !manager.port_in_use(spec.local_port) {
+                            manager.open(spec);
+                        }
+                    }
+                }
+                Err(e) => println!(
+                    "{}",
+                    i18n::format("error", &[("error", &format!("{:#}", e))])
+                ),
+            }
+        }
+    }
+}
+
+/// What tells `--plain` and `--stdin` apart: how they read lines and print.
+/// [`headless`] runs the tunnels for both.
+trait Frontend {
+    /// Once the initial tunnels are open.
+    fn started(&mut self) {}
+    fn event(&mut self, event: &Event);
+    /// Carries out a line of stdin, returning how to stop if it quits.
+    fn line(&mut self, manager: &mut Manager, config: &config::Config, line: &str) -> Option<Stop>;
+    /// At the end of stdin, returning how to stop if that quits.
+    fn closed(&mut self) -> Option<Stop> {
+        None
+    }
+    /// Something the user should know about, not in answer to a line.
+    fn notice(&mut self, text: &str);
+    /// Something that went wrong, not in answer to a line.
+    fn warn(&mut self, text: &str);
+    fn progress(&mut self, progress: &Progress);
+    /// Announces what the tunnels wait for: a confirmation, a one-time code
+    /// or a security key touch.
+    fn prompts(&mut self, manager: &Manager);
+}
+
+/// Runs the tunnels without the TUI, taking lines of stdin for `frontend`
+/// and answering the control socket.
+async fn headless(
+    config: Arc<config::Config>,
+    initial: Vec<TunnelSpec>,
+    pid_file: Option<&std::path::Path>,
+    tied_to: Option<u32>,
+    mut frontend: impl Frontend,
+) -> Result<()> {
+    let instance_lock = match instance::acquire()? {
+        Acquire::Acquired(lock) => lock,
+        Acquire::Running(Some(pid)) => {
+            return Err(eyre!(i18n::format("already-running-pid", &[("pid", &pid)])))
+        }
+        Acquire::Running(None) => return Err(eyre!(i18n::text("already-running"))),
+    };
+    let pid_file = pid_file.map(instance::PidFile::create).transpose()?;
+    let (quit_tx, mut quit_rx) = mpsc::channel(1);
+    handle_signals(quit_tx.clone())?;
+    if let Some(pid) = tied_to {
+        instance::tie_to(pid, quit_tx)?;
+    }
+    let mut manager = Manager::new(config.clone());
+    let mut changes = manager.changes().subscribe();
+    let (requests_tx, mut requests_rx) = mpsc::unbounded_channel();
+    let _control_server =
+        match ControlServer::start(&config.control, requests_tx, manager.changes()) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                frontend.warn(&format!("{:#}", e));
+                None
+            }
+        };
+    let (lines_tx, mut lines_rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    for spec in initial {
+        manager.open(spec);
+    }
+    if let Some(session) = session::left_behind() {
+        restore_session(&mut manager, &config, session, &mut frontend);
+    }
+    frontend.started();
+
+    let mut reading = true;
+    let mut tick = tokio::time::interval(config.intervals.ui_tick());
+    let mut schedules_checked = std::time::Instant::now();
+    // Tunnels last saved as the session.
+    let mut saved: Vec<TunnelSpec> = Vec::new();
+    let mut shown_progress: Option<Progress> = None;
+    let stop = loop {
+        tokio::select! {
+            Some(stop) = quit_rx.recv() => break stop,
+            Ok(event) = changes.recv() => frontend.event(&event),
+            line = lines_rx.recv(), if reading => {
+                let stop = match line {
+                    Some(line) => frontend.line(&mut manager, &config, &line),
+                    None => {
+                        reading = false;
+                        frontend.closed()
+                    }
+                };
+                if let Some(stop) = stop {
+                    break stop;
+                }
+            }
+            _ = tick.tick() => {}
+        }
+        while let Ok((request, caller, reply)) = requests_rx.try_recv() {
+            let _ = reply.send(handle_request(&mut manager, request, caller));
+        }
+        manager.poll_events();
+        for e in [manager.take_hostnames_error(), audit::take_error()]
+            .into_iter()
+            .flatten()
+        {
+            frontend.warn(&e);
+        }
+        let progress = manager.progress();
+        if progress != shown_progress {
+            if let Some(progress) = &progress {
+                frontend.progress(progress);
+            }
+            shown_progress = progress;
+        }
+        if schedules_checked.elapsed() >= SCHEDULE_CHECK_INTERVAL {
+            manager.apply_schedules(chrono::Local::now());
+            schedules_checked = std::time::Instant::now();
+        }
+        frontend.prompts(&manager);
+        let open: Vec<TunnelSpec> = manager
+            .tunnels
+            .iter()
+            .filter(|t| t.status != TunnelStatus::Closed)
+            .map(|t| t.spec.clone())
+            .collect();
+        if open != saved {
+            let tunnels = open
+                .iter()
+                .map(|spec| SavedTunnel::of(&config, spec))
+                .collect();
+            let _ = session::save(tunnels);
+            saved = open;
+        }
+    };
+    // Ended on purpose, or handed over to the instance that took over.
+    if !session::kept() || stop == Stop::Detach {
+        session::clear();
+    }
+    manager.shutdown(stop).await;
+    drop(pid_file);
+    drop(instance_lock);
+    Ok(())
+}
+
+/// `--plain`, for screen readers, dumb terminals and serial consoles:
+/// changes are printed as lines of text and a numbered menu read from stdin
+/// replaces the key bindings.
+struct Plain {
+    input: PlainInput,
+    /// Tunnels, in the order last listed, the numbers refer to.
+    listed: Vec<usize>,
+    /// Ports whose security key prompt was already read out.
+    touch_announced: BTreeSet<u16>,
+}
+
+impl Frontend for Plain {
+    fn started(&mut self) {
+        print_plain_menu();
+    }
+
+    fn event(&mut self, event: &Event) {
+        let mut line = format!(
+            "{} {}:{} {}",
+            event.tunnel.host,
+            event.tunnel.local_port,
+            event.tunnel.remote_port,
+            i18n::text(&format!("event-{}", event.event))
+        );
+        if let Some(detail) = &event.detail {
+            line.push_str(&format!(", {}", detail));
+        }
+        println!("{}", line);
+    }
+
+    fn line(&mut self, manager: &mut Manager, config: &config::Config, line: &str) -> Option<Stop> {
+        let line = line.trim();
+        let (choice, argument) = match line.split_once(' ') {
+            Some((choice, argument)) => (choice, Some(argument.trim())),
+            None => (line, None),
+        };
+        let profiles: Vec<&String> = config.profiles.keys().collect();
+        let mut next = PlainInput::Menu;
+        match (self.input, choice) {
+            (PlainInput::Menu, "" | "?") => print_plain_menu(),
+            (PlainInput::Menu, "0" | "q") => return Some(Stop::Close),
+            (PlainInput::Menu, "1") => {
+                self.listed = manager.sorted(config.sort);
+                print_plain_tunnels(manager, &self.listed);
+            }
+            (PlainInput::Menu, "2") => {
+                println!("{}", i18n::text("plain-ask-tunnel"));
+                next = PlainInput::NewTunnel;
+            }
+            (PlainInput::Menu, "3" | "4" | "5") => {
+                let asked = match choice {
+                    "3" => PlainInput::Close,
+                    "4" => PlainInput::Restart,
+                    _ => PlainInput::Profile,
+                };
+                if asked != PlainInput::Profile {
+                    self.listed = manager.sorted(config.sort);
+                }
+                match argument {
+                    // `3 2` closes the second tunnel without asking.
+                    Some(argument) => plain_answer(manager, config, asked, argument, &self.listed),
+                    None if asked == PlainInput::Profile && profiles.is_empty() => {
+                        println!("{}", i18n::text("plain-no-profiles"))
+                    }
+                    None if asked == PlainInput::Profile => {
+                        for (number, name) in profiles.iter().enumerate() {
+                            println!("{}) {}", number + 1, name);
+                        }
+                        println!("{}", i18n::text("plain-ask-profile"));
+                        next = asked;
+                    }
+                    None => {
+                        print_plain_tunnels(manager, &self.listed);
+                        if !self.listed.is_empty() {
+                            println!("{}", i18n::text("plain-ask-number"));
+                            next = asked;
+                        }
+                    }
+                }
+            }
+            (PlainInput::Menu, _) => println!(
+                "{}",
+                i18n::format("plain-unknown-choice", &[("choice", &choice)])
+            ),
+            (input, _) => plain_answer(manager, config, input, line, &self.listed),
+        }
+        self.input = next;
+        None
+    }
+
+    fn notice(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn warn(&mut self, text: &str) {
+        println!("{}", i18n::format("error", &[("error", &text)]));
+    }
+
+    fn progress(&mut self, progress: &Progress) {
+        println!("{}", progress_text(progress));
+    }
+
+    fn prompts(&mut self, manager: &Manager) {
+        for tunnel in manager.tunnels.iter() {
+            let port = tunnel.spec.local_port;
+            match &tunnel.prompt {
+                Some(Prompt::Touch(text)) if self.touch_announced.insert(port) => {
+                    println!(
+                        "{}",
+                        i18n::format("touch-security-key", &[("host", &tunnel.spec.host)])
+                    );
+                    println!("{}", text);
+                }
+                Some(Prompt::Touch(_)) => {}
+                Some(Prompt::Code(text)) if self.input == PlainInput::Menu => {
+                    println!("{}", text);
+                    self.input = PlainInput::Code(port);
+                }
+                _ => {
+                    self.touch_announced.remove(&port);
+                }
+            }
+        }
+        if let PlainInput::Code(port) = self.input {
+            let asking = manager
+                .tunnels
+                .iter()
+                .any(|t| t.spec.local_port == port && matches!(t.prompt, Some(Prompt::Code(_))));
+            if !asking {
+                self.input = PlainInput::Menu;
+            }
+        }
+        if let (PlainInput::Menu, Some((spec, _))) = (self.input, manager.pending.first()) {
+            println!(
+                "{}",
+                i18n::format("plain-confirm", &[("tunnel", &spec.to_string())])
+            );
+            self.input = PlainInput::Confirm;
+        }
+    }
+}
+
+async fn plain(
+    config: Arc<config::Config>,
+    initial: Vec<TunnelSpec>,
+    pid_file: Option<&std::path::Path>,
+    tied_to: Option<u32>,
+) -> Result<()> {
+    let frontend = Plain {
+        input: PlainInput::Menu,
+        listed: Vec::new(),
+        touch_announced: BTreeSet::new(),
+    };
+    headless(config, initial, pid_file, tied_to, frontend).await
+}
+
+/// `--stdin`, taking one [`script::Command`] per line. Each is answered with
+/// a line starting with `ok` or `error`; lines starting with `event`,
+/// `progress`, `notice`, `warning`, `confirm`, `code` or `touch` may come in
+/// between. The end of stdin quits like `quit`.
+#[derive(Default)]
+struct Scripted {
+    /// The pending confirmation already announced, so it is announced once.
+    confirm_announced: Option<TunnelSpec>,
+    /// Ports whose code or touch prompt was already announced.
+    prompts_announced: BTreeSet<u16>,
+}
+
+impl Frontend for Scripted {
+    fn event(&mut self, event: &Event) {
+        let mut line = format!(
+            "event {} {} {}:{}",
+            event.event, event.tunnel.host, event.tunnel.local_port, event.tunnel.remote_port
+        );
+        if let Some(detail) = &event.detail {
+            line.push_str(&format!(": {}", detail));
+        }
+        println!("{}", line);
+    }
+
+    fn line(&mut self, manager: &mut Manager, config: &config::Config, line: &str) -> Option<Stop> {
+        let reply = match script::Command::parse(line)? {
+            Err(e) => Err(e),
+            Ok(script::Command::Quit) => {
+                println!("ok quitting");
+                return Some(Stop::Close);
+            }
+            Ok(command) => run_script_command(manager, config, command),
+        };
+        match reply {
+            Ok(reply) => println!("ok {}", reply),
+            Err(e) => println!("error {}", e),
+        }
+        None
+    }
+
+    fn closed(&mut self) -> Option<Stop> {
+        Some(Stop::Close)
+    }
+
+    fn notice(&mut self, text: &str) {
+        println!("notice {}", text);
+    }
+
+    fn warn(&mut self, text: &str) {
+        println!("warning {}", text);
+    }
+
+    fn progress(&mut self, progress: &Progress) {
+        println!(
+            "progress {} {} {}",
+            progress.connected, progress.pending, progress.failed
+        );
+    }
+
+    fn prompts(&mut self, manager: &Manager) {
+        let pending = manager.pending.first().map(|(spec, _)| spec.clone());
+        if let Some(spec) = pending
+            .as_ref()
+            .filter(|_| pending != self.confirm_announced)
+        {
+            println!("confirm {}", spec);
+        }
+        self.confirm_announced = pending;
+        for tunnel in manager.tunnels.iter() {
+            let port = tunnel.spec.local_port;
+            let (kind, text) = match &tunnel.prompt {
+                Some(Prompt::Code(text)) => ("code", text),
+                Some(Prompt::Touch(text)) => ("touch", text),
+                None => {
+                    self.prompts_announced.remove(&port);
+                    continue;
+                }
+            };
+            if self.prompts_announced.insert(port) {
+                let text = text.lines().collect::<Vec<_>>().join(" ");
+                println!("{} {} {}", kind, port, text.trim());
+            }
+        }
+    }
+}
+
+/// Carries out a command of `--stdin`, returning what to acknowledge it with.
+fn run_script_command(
+    manager: &mut Manager,
+    config: &config::Config,
+    command: script::Command,
+) -> Result<String, String> {
+    let index = |port: u16| {
+        manager
+            .tunnels
+            .iter()
+            .position(|t| t.spec.local_port == port && t.status != TunnelStatus::Closed)
+            .ok_or_else(|| format!("No tunnel on port {}", port))
+    };
+    match command {
+        script::Command::Add(spec) if manager.port_in_use(spec.local_port) => {
+            Err(format!("Local port {} is already in use", spec.local_port))
+        }
+        script::Command::Add(spec) => {
+            let reply = format!("added {}", spec);
+            manager.open(*spec);
+            Ok(reply)
+        }
+        script::Command::Close(port) => {
+            manager.close(index(port)?);
+            Ok(format!("closed {}", port))
+        }
+        script::Command::Restart(port) => {
+            manager.restart(index(port)?, "restarted by script");
+            Ok(format!("restarted {}", port))
+        }
+        script::Command::Up(name) => {
+            let specs = config
+                .profile_tunnels(&name)
+                .map_err(|e| format!("{:#}", e))?;
+            let mut opened = 0;
+            for spec in specs {
+                if !manager.port_in_use(spec.local_port) {
+                    manager.open(spec);
+                    opened += 1;
+                }
+            }
+            Ok(format!("opened {} tunnels of {}", opened, name))
+        }
+        script::Command::List => {
+            for tunnel in manager.tunnels.iter() {
+                println!("tunnel {} {}", tunnel.spec, tunnel.status);
+            }
+            Ok(format!("{} tunnels", manager.tunnels.len()))
+        }
+        script::Command::Confirm(_) if manager.pending.is_empty() => {
+            Err("No tunnel is waiting for confirmation".to_string())
+        }
+        script::Command::Confirm(open) => {
+            manager.confirm(open);
+            Ok(match open {
+                true => "confirmed".to_string(),
+                false => "declined".to_string(),
+            })
+        }
+        script::Command::Code(port, code) => {
+            index(port)?;
+            manager.answer(port, code);
+            Ok(format!("answered {}", port))
+        }
+        script::Command::Quit => Ok("quitting".to_string()),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Run by ssh as its askpass program.
+    if let Some(answer) = secrets::askpass() {
+        println!("{}", answer?);
+        return Ok(());
+    }
+    install_hooks()?;
+    let args = Args::parse();
+    let json = args.command.as_ref().is_some_and(Commands::prints_json);
+    if let Err(e) = run(args).await {
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({ "error": ErrorInfo::of_report(&e) })
+            );
+        } else {
+            eprintln!("Error: {:?}", e);
+        }
+        std::process::exit(errors::code(&e).exit_status());
+    }
+    Ok(())
+}
+
+/// Checks for a newer release and, once confirmed, puts it in place of the
+/// running binary.
+#[cfg(feature = "self-update")]
+fn self_update(check: bool, yes: bool) -> Result<()> {
+    use rtun::upgrade;
+
+    let current = env!("CARGO_PKG_VERSION");
+    let release = upgrade::latest()?;
+    if !upgrade::is_newer(release.version(), current) {
+        println!("rtun {} is the latest release", current);
+        return Ok(());
+    }
+    println!("rtun {} is out, this is {}", release.version(), current);
+    let changelog = upgrade::changelog(&release.body, upgrade::CHANGELOG_LINES);
+    if !changelog.is_empty() {
+        println!();
+        for line in &changelog {
+            println!("  {}", line);
+        }
+        println!();
+    }
+    if check {
+        return Ok(());
+    }
+    let path = std::env::current_exe().wrap_err("Could not locate the rtun binary")?;
+    if !yes {
+        print!(
+            "Replace {} with {}? [y/N] ",
+            path.display(),
+            release.version()
+        );
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Not upgraded");
+            return Ok(());
+        }
+    }
+    let bytes = upgrade::download(&release)?;
+    upgrade::replace(&path, &bytes)?;
+    println!("Upgraded to rtun {}", release.version());
+    Ok(())
+}
+
+/// Runs the command given on the command line, or the TUI without one.
+async fn run(args: Args) -> Result<()> {
+    #[cfg(feature = "self-update")]
+    if let Some(Commands::Upgrade { check, yes }) = &args.command {
+        return self_update(*check, *yes);
+    }
+    if let Some(Commands::History { limit, host, json }) = &args.command {
+        return print_history(*limit, host.as_deref(), *json);
+    }
+    if let Some(Commands::Secret { command }) = &args.command {
+        return manage_secrets(command);
+    }
+    if let Some(Commands::Audit { json }) = &args.command {
+        return print_audit(*json);
+    }
+    if let Some(Commands::Check { file }) = &args.command {
+        return check_config(file.clone());
+    }
+    if let Some(Commands::Ca) = &args.command {
+        tls::load_or_create_ca()?;
+        if let Some(path) = tls::ca_cert_path() {
+            println!("{}", path.display());
+        }
+        return Ok(());
+    }
+    if let Some(Commands::ForgetHostKey { host }) = &args.command {
+        match hostkeys::forget(host).wrap_err("Could not update ~/.rtun/host_keys.json")? {
+            true => println!("Forgot the host keys of {}", host),
+            false => println!("No host keys recorded for {}", host),
+        }
+        return Ok(());
+    }
+    let mut config = config::load()?;
+    config.strict |= args.strict;
+    if let Some(locale) = config.locale.clone().or_else(i18n::from_env) {
+        i18n::set_locale(&locale);
+    }
+    let config = Arc::new(config);
+    if config.audit {
+        audit::enable();
+    }
+    if let Some(Commands::Doctor) = &args.command {
+        let checks = doctor::run(&config);
+        for check in checks.iter() {
+            println!("{}", check);
+        }
+        if checks.iter().any(|c| c.status == doctor::Status::Fail) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Stats { json }) = &args.command {
+        return print_stats(&config, *json);
+    }
+    if let Some(Commands::Report {
+        format,
+        output,
+        copy,
+    }) = &args.command
+    {
+        return print_report(&config.control, *format, output.as_deref(), *copy);
+    }
+    if let Some(Commands::Env { profile }) = &args.command {
+        return print_env(&config, profile);
+    }
+    if let Some(Commands::Direnv { profile }) = &args.command {
+        config.profile(profile)?;
+        print!("{}", exports::envrc(profile));
+        return Ok(());
+    }
+    if let Some(Commands::Launchd { profile }) = &args.command {
+        config.profile_tunnels(profile)?;
+        let program = std::env::current_exe().wrap_err("Could not locate the rtun binary")?;
+        let log = launchd::log_path(profile)
+            .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+        let path = std::env::var("PATH").unwrap_or_default();
+        print!("{}", launchd::plist(profile, &program, &log, &path));
+        return Ok(());
+    }
+    if let Some(Commands::Status { format }) = &args.command {
+        return print_status(&config.control, *format);
+    }
+    if let Some(Commands::Events { follow, json }) = &args.command {
+        return print_events(&config.control, *follow, *json);
+    }
+    if let Some(Commands::Close { tags, drain: true }) = &args.command {
+        let request = Request::Drain {
+            local_port: None,
+            tags: tags.clone(),
+        };
+        let result: Draining = control::call(&config.control, &request)?;
+        println!("Draining {} tunnel(s)", result.draining);
+        return Ok(());
+    }
+    if let Some(Commands::Close { tags, .. }) = &args.command {
+        let request = Request::Close {
+            local_port: None,
+            tags: tags.clone(),
+        };
+        let result: Closed = control::call(&config.control, &request)?;
+        println!("Closed {} tunnel(s)", result.closed);
+        return Ok(());
+    }
+    if let Some(Commands::Add {
+        tunnel,
+        host,
+        local_port,
+        remote_port,
+        dest,
+        name,
+        label,
+        tags,
+    }) = &args.command
+    {
+        let spec = match (host, local_port, remote_port) {
+            (Some(host), Some(local_port), Some(remote_port)) => TunnelSpec {
+                remote_host: dest.clone(),
+                ..TunnelSpec::new(host, *local_port, *remote_port)
+            },
+            _ => TunnelSpec::parse(&tunnel.join(" ")).map_err(|e| eyre!(e))?,
+        };
+        let request = Request::Add(NewTunnel {
+            host: spec.host,
+            local_port: spec.local_port,
+            remote_port: spec.remote_port,
+            remote_host: spec.remote_host,
+            name: name.clone(),
+            label: label.clone().or(spec.label),
+            tags: tags.clone(),
+        });
+        let added: Option<TunnelInfo> = control::call(&config.control, &request)?;
+        if let Some(tunnel) = added {
+            println!(
+                "Opened {} {}:{}",
+                tunnel.host, tunnel.local_port, tunnel.remote_port
+            );
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Retarget {
+        local_port,
+        target,
+        dest,
+        remote_port,
+    }) = &args.command
+    {
+        let target = match (dest, remote_port) {
+            (Some(dest), Some(port)) => format!("{}:{}", dest, port),
+            _ => target.clone().unwrap_or_default(),
+        };
+        let request = Request::Retarget {
+            local_port: *local_port,
+            target,
+        };
+        let tunnel: Option<TunnelInfo> = control::call(&config.control, &request)?;
+        if let Some(tunnel) = tunnel {
+            println!(
+                "Port {} now forwards to {}",
+                tunnel.local_port,
+                RemoteTarget {
+                    host: tunnel
+                        .remote_host
+                        .unwrap_or_else(|| "127.0.0.1".to_string()),
+                    port: tunnel.remote_port,
+                }
+            );
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Attach { read_only }) = &args.command {
+        if !read_only {
+            return Err(eyre!(
+                "Only read-only attaching is supported, pass --read-only"
+            ));
+        }
+        return attach(&config, args.ascii).await;
+    }
+    // The profile of the git checkout rtun is started in, without a command.
+    // A mistake in the file is warned about, and rtun starts without it.
+    let mut project_warning = None;
+    let mut project = match &args.command {
+        None if !args.no_project => {
+            project::detect(&std::env::current_dir()?).unwrap_or_else(|e| {
+                project_warning = Some(format!("{:#}", e));
+                None
+            })
+        }
+        _ => None,
+    };
+    let project_tunnels = project.as_ref().map(|selection| {
+        config
+            .profile_tunnels(&selection.profile)
+            .wrap_err_with(|| {
+                format!(
+                    "Profile of '{}' in {}",
+                    selection.pattern,
+                    project::PROJECT_FILE
+                )
+            })
+    });
+    let project_tunnels = match project_tunnels {
+        Some(Ok(tunnels)) => tunnels,
+        Some(Err(e)) => {
+            project_warning = Some(format!("{:#}", e));
+            project = None;
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    let mut initial = match &args.command {
+        Some(Commands::Up { profiles, .. }) => config.compose_profiles(profiles)?,
+        None => project_tunnels,
+        _ => Vec::new(),
+    };
+    if let Some(path) = &args.from_file {
+        initial = config::merge_tunnels(initial, config::tunnels_from_file(path)?);
+    }
+    if let Some(Commands::Up { diff: true, .. }) = &args.command {
+        return print_plan(&config.control, &initial);
+    }
+    if let Some(Commands::Up {
+        profiles,
+        wait: true,
+        timeout,
+        ..
+    }) = &args.command
+    {
+        up_in_background(&config, &args, profiles, &initial)?;
+        let ports: Vec<u16> = initial.iter().map(|spec| spec.local_port).collect();
+        return wait_until_ready(&config.control, &ports, *timeout);
+    }
+    if let Some(Commands::Wait { ports, timeout }) = &args.command {
+        return wait_until_ready(&config.control, ports, *timeout);
+    }
+    let uses_store = config
+        .auth
+        .values()
+        .any(|auth| matches!(auth.password(), Some(Secret::Store(_))));
+    if uses_store && secrets::store_exists() {
+        open_store()?.unlock();
+    }
+    if let Some(warning) = &project_warning {
+        if args.stdin || args.plain {
+            eprintln!("{}", warning);
+        }
+    }
+    if args.stdin {
+        let frontend = Scripted::default();
+        return headless(
+            config,
+            initial,
+            args.pid_file.as_deref(),
+            args.tied_to(),
+            frontend,
+        )
+        .await;
+    }
+    if args.plain {
+        if let Some(path) = &args.log_file {
+            instance::log_to(path)?;
+        }
+        if let Some(selection) = &project {
+            println!("{}", project_text(selection));
+        }
+        return plain(config, initial, args.pid_file.as_deref(), args.tied_to()).await;
+    }
+    let (mut instance_lock, mut running_instance) = match instance::acquire()? {
+        Acquire::Acquired(lock) => (Some(lock), None),
+        Acquire::Running(pid) => (None, Some(pid)),
+    };
+    // Set while the instance asked to hand over its tunnels is exiting,
+    // which can take seconds.
+    let mut takeover: Option<oneshot::Receiver<Result<instance::InstanceLock>>> = None;
+    let pid_file = args
+        .pid_file
+        .as_deref()
+        .map(instance::PidFile::create)
+        .transpose()?;
+    let (quit_tx, mut quit_rx) = mpsc::channel(1);
+    handle_signals(quit_tx.clone())?;
+    if let Some(pid) = args.tied_to() {
+        instance::tie_to(pid, quit_tx)?;
+    }
+
+    let terminal_guard = TerminalGuard::enter()?;
+    let mut terminal = Terminal::new(PlainBackend::new(
+        CrosstermBackend::new(stdout()),
+        args.ascii,
+    ))?;
+    terminal.clear()?;
+    let (network_tx, mut network_rx) = mpsc::unbounded_channel();
+    if config.restart_on_network_change {
+        tokio::spawn(network::watch(network_tx));
+    }
+
+    let mut manager = Manager::new(config.clone());
+    if running_instance.is_none() {
+        for spec in initial {
+            manager.open(spec);
+        }
+    }
+    let mut schedules_checked = std::time::Instant::now();
+    let mut certificates = Certificates::new();
+    let mut certificates_checked: Option<std::time::Instant> = None;
+    // Hosts whose certificate was already warned about.
+    let mut cert_warned: BTreeSet<String> = BTreeSet::new();
+    let mut textarea = get_text_area(None);
+    let mut wizard: Option<Wizard> = None;
+    let mut tab = Tab::Tunnels;
+    let mut host_selected = ListState::default();
+    // Options of the selected host.
+    let mut host_options: Option<HostDetail> = None;
+    let mut profile_selected = ListState::default();
+    let mut log_selected = ListState::default();
+    let mut stats_selected = ListState::default();
+    let mut selected = ListState::default();
+    // Client chosen with `c`, by local port of its tunnel and id.
+    let mut selected_client: Option<(u16, u64)> = None;
+    let mut qr: Option<(String, String)> = None;
+    // Tunnel being diagnosed with `d`, and the checks once they ran.
+    let mut diagnosis: Option<(
+        String,
+        oneshot::Receiver<Vec<doctor::Check>>,
+        Vec<doctor::Check>,
+    )> = None;
+    // Code asked for by the tunnel on the port, and the one whose prompt was
+    // put aside with Esc.
+    let mut code_entry: Option<(u16, TextArea)> = None;
+    let mut code_dismissed: Option<u16> = None;
+    // The clipboard read for the code prompt, which may stall on a slow
+    // clipboard tool.
+    let mut code_paste: Option<oneshot::Receiver<Option<String>>> = None;
+    // The new destination of the tunnel on a local port, being typed.
+    let mut retarget_entry: Option<(u16, TextArea)> = None;
+    let mut palette: Option<Palette> = None;
+    // Set while another window or pane has focus, to alert about tunnels
+    // dropping meanwhile.
+    let mut focus_lost: Option<std::time::Instant> = None;
+    let mut alerted = 0;
+    let mut shown_title = String::new();
+    let mut history: Vec<HistoryEntry> = Vec::new();
+    let mut stats: Vec<Usage> = Vec::new();
+    let mut error: Option<String> = project_warning;
+    // Outcome of the last action worth confirming, cleared like errors.
+    let mut notice: Option<String> = None;
+    let hosts = match hosts::get_hosts() {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            error = Some(format!("{:#}", e));
+            Vec::new()
+        }
+    };
+    if let Some(http_proxy) = config.http_proxy {
+        if let Err(e) = proxy::serve(http_proxy.port, manager.routes()).await {
+            error = Some(format!(
+                "Could not start the HTTP proxy on port {}: {}",
+                http_proxy.port, e
+            ));
+        }
+    }
+    if let Some((host, color)) = config
+        .host_colors
+        .iter()
+        .find(|(_, color)| color.parse::<Color>().is_err())
+    {
+        error = Some(format!("Unknown color '{}' for host {}", color, host));
+    }
+    let mut system_proxy = None;
+    if let Some(pac) = &config.pac {
+        match pac::serve(pac.port, manager.pac_rules()).await {
+            Ok(()) if pac.set_system_proxy => {
+                match pac::set_system_proxy(&pac::url(pac.port), &pac.network_service) {
+                    Ok(previous) => system_proxy = Some(previous),
+                    Err(e) => error = Some(format!("Could not set the system proxy: {}", e)),
+                }
+            }
+            Ok(()) => {}
+            Err(e) => {
+                error = Some(format!(
+                    "Could not serve the PAC file on port {}: {}",
+                    pac.port, e
+                ))
+            }
+        }
+    }
+    if let Some(dns_config) = &config.dns {
+        if let Err(e) = dns::serve(dns_config, manager.pac_rules()).await {
+            error = Some(format!(
+                "Could not answer DNS queries on port {}: {}",
+                dns_config.port, e
+            ));
+        }
+    }
+    let (requests_tx, mut requests_rx) = mpsc::unbounded_channel();
+    let mut control_server = None;
+    if instance_lock.is_some() {
+        match ControlServer::start(&config.control, requests_tx.clone(), manager.changes()) {
+            Ok(server) => control_server = Some(server),
+            Err(e) => error = Some(format!("{:#}", e)),
+        }
+    }
+    let hooks = match config.hooks.clone().or_else(hooks::default_path) {
+        Some(path) if config.hooks.is_some() || path.exists() => match Hooks::load(&path) {
+            Ok(hooks) => Some(hooks),
+            Err(e) => {
+                error = Some(format!("{:#}", e));
+                None
+            }
+        },
+        _ => None,
+    };
+    let mut hook_events = hooks.as_ref().map(|_| manager.changes().subscribe());
+    let (vpn_tx, mut vpn_rx) = mpsc::unbounded_channel();
+    if hooks
+        .as_ref()
+        .is_some_and(|h| h.handles("on_vpn_up") || h.handles("on_vpn_down"))
+    {
+        tokio::spawn(vpn::watch(config.vpn.clone(), vpn_tx));
+    }
+    let mut tag_filter: Option<String> = None;
+    let mut sort = config.sort;
+    let mut orphans = state::orphans();
+    let mut stop = Stop::Close;
+    loop {
+        if let Ok(quit) = quit_rx.try_recv() {
+            stop = quit;
+            break;
+        }
+        while let Ok((request, caller, reply)) = requests_rx.try_recv() {
+            let _ = reply.send(handle_request(&mut manager, request, caller));
+        }
+        while let Ok(event) = network_rx.try_recv() {
+            manager.restart_all(event.reason());
+        }
+        if schedules_checked.elapsed() >= SCHEDULE_CHECK_INTERVAL {
+            manager.apply_schedules(chrono::Local::now());
+            schedules_checked = std::time::Instant::now();
+        }
+        if certificates_checked.is_none_or(|t| t.elapsed() >= config.intervals.certificate_check())
+        {
+            certificates = check_certificates(&config);
+            certificates_checked = Some(std::time::Instant::now());
+            let now = chrono::Local::now().naive_local();
+            for (host, validity) in certificates.iter() {
+                let warning = match validity {
+                    Ok(v) if v.expires_within(now, config.auth[host].certificate_warning()) => {
+                        Some(format!("Certificate for {} {}", host, v.describe(now)))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(e.clone()),
+                };
+                match warning {
+                    Some(warning) if cert_warned.insert(host.clone()) => error = Some(warning),
+                    Some(_) => {}
+                    // Renewed.
+                    None => {
+                        cert_warned.remove(host);
+                    }
+                }
+            }
+        }
+        let history_changed = manager.poll_events();
+        if let Some(e) = manager.take_hostnames_error().or_else(audit::take_error) {
+            error = Some(e);
+        }
+        if let (Some(hooks), Some(events)) = (&hooks, hook_events.as_mut()) {
+            let mut results = Vec::new();
+            while let Ok(event) = events.try_recv() {
+                results.push(hooks.on_event(&event));
+            }
+            while let Ok((name, up)) = vpn_rx.try_recv() {
+                results.push(hooks.on_vpn(&name, up));
+            }
+            for result in results {
+                if let Err(e) = result.and_then(|c| run_hook_commands(&mut manager, &config, c)) {
+                    error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        if let Some(w) = wizard.as_mut() {
+            w.poll();
+        }
+        if let Some(detail) = host_options.as_mut() {
+            if let Ok(options) = detail.done.try_recv() {
+                detail.options = Some(options);
+            }
+        }
+        if let Some((_, done, checks)) = diagnosis.as_mut() {
+            if let Ok(result) = done.try_recv() {
+                *checks = result;
+            }
+        }
+        let asking = |port: u16| {
+            manager.tunnels.iter().find_map(|t| match &t.prompt {
+                Some(Prompt::Code(text)) if t.spec.local_port == port => Some(text),
+                _ => None,
+            })
+        };
+        if code_entry
+            .as_ref()
+            .is_some_and(|(port, _)| asking(*port).is_none())
+        {
+            code_entry = None;
+        }
+        if code_dismissed.is_some_and(|port| asking(port).is_none()) {
+            code_dismissed = None;
+        }
+        if code_entry.is_none() {
+            let next = manager.tunnels.iter().find_map(|t| match &t.prompt {
+                Some(Prompt::Code(text)) if code_dismissed != Some(t.spec.local_port) => {
+                    Some((t.spec.local_port, text))
+                }
+                _ => None,
+            });
+            if let Some((port, text)) = next {
+                code_entry = Some((port, code_input(text)));
+                code_paste = config.paste_codes.then(|| {
+                    let (done_tx, done_rx) = oneshot::channel();
+                    tokio::task::spawn_blocking(move || {
+                        let _ = done_tx.send(otp::from_clipboard());
+                    });
+                    done_rx
+                });
+            }
+        }
+        if let (Some(done), Some(Some(pid))) = (takeover.as_mut(), running_instance) {
+            let result = match done.try_recv() {
+                Ok(result) => Some(result),
+                Err(oneshot::error::TryRecvError::Empty) => None,
+                Err(oneshot::error::TryRecvError::Closed) => Some(Err(eyre!(
+                    "rtun instance {} did not hand over its tunnels",
+                    pid
+                ))),
+            };
+            if let Some(result) = result {
+                match result {
+                    Ok(lock) => {
+                        instance_lock = Some(lock);
+                        match ControlServer::start(
+                            &config.control,
+                            requests_tx.clone(),
+                            manager.changes(),
+                        ) {
+                            Ok(server) => control_server = Some(server),
+                            Err(e) => error = Some(format!("{:#}", e)),
+                        }
+                        let (taken, others): (Vec<_>, Vec<_>) =
+                            state::orphans().into_iter().partition(|r| r.owner == pid);
+                        for record in taken {
+                            manager.adopt(record);
+                        }
+                        orphans = others;
+                    }
+                    Err(e) => error = Some(e.to_string()),
+                }
+                takeover = None;
+                running_instance = None;
+            }
+        }
+        if let Some(Ok(code)) = code_paste.as_mut().map(|done| done.try_recv()) {
+            code_paste = None;
+            // Not over what was typed meanwhile.
+            if let (Some(code), Some((_, input))) = (code, code_entry.as_mut()) {
+                if input.is_empty() {
+                    input.insert_str(code);
+                }
+            }
+        }
+        // Tunnels that went down since the terminal lost focus.
+        let unseen_drops = focus_lost.map_or(0, |since| {
+            manager
+                .tunnels
+                .iter()
+                .filter(|t| t.dropped_at.is_some_and(|d| d > since) && t.status != TunnelStatus::Up)
+                .count()
+        });
+        if unseen_drops > alerted {
+            if config.bell {
+                let _ = stdout().write_all(b"\x07");
+            }
+            alerted = unseen_drops;
+        }
+        let title = status_title(&manager.tunnels, unseen_drops);
+        if title != shown_title {
+            let _ = stdout().execute(SetTitle(&title));
+            shown_title = title;
+        }
+        if history_changed && tab == Tab::Logs {
+            match history::load(HISTORY_VIEW_LIMIT, None) {
+                Ok(entries) => history = entries,
+                Err(e) => error = Some(i18n::format("could-not-read-history", &[("error", &e)])),
+            }
+        }
+        if history_changed && tab == Tab::Stats {
+            stats = usage::load();
+        }
+
+        if tab == Tab::Hosts {
+            if let Some(host) = host_selected.selected().and_then(|i| hosts.get(i)) {
+                let tunnels = host_tunnels(&manager, &config, host);
+                let ports: Vec<u16> = tunnels.iter().map(|t| t.local_port).collect();
+                if host_options
+                    .as_ref()
+                    .is_none_or(|detail| detail.host != *host || detail.ports != ports)
+                {
+                    // `ssh -G` takes a moment, too long to wait for in a frame.
+                    let (done_tx, done_rx) = oneshot::channel();
+                    let (config, host_name) = (config.clone(), host.clone());
+                    tokio::task::spawn_blocking(move || {
+                        let resolved = hosts::resolve(&config, &host_name, &tunnels)
+                            .map_err(|e| format!("{:#}", e));
+                        let _ = done_tx.send(resolved);
+                    });
+                    host_options = Some(HostDetail {
+                        host: host.clone(),
+                        ports,
+                        done: done_rx,
+                        options: None,
+                    });
+                }
+            }
+        }
+        let progress = manager.progress();
+        // Indices into manager.tunnels of the tunnels listed, in the order
+        // shown, which the selection refers to.
+        let visible: Vec<usize> = manager
+            .sorted(sort)
+            .into_iter()
+            .filter(|&i| {
+                tag_filter
+                    .as_ref()
+                    .is_none_or(|tag| manager.tunnels[i].spec.tags.contains(tag))
+            })
+            .collect();
+        let _ = terminal.draw(|frame| {
+            let area = frame.size();
+            if area.width < MIN_WIDTH || area.height < MIN_HEIGHT {
+                frame.render_widget(
+                    Paragraph::new(i18n::format(
+                        "terminal-too-small",
+                        &[
+                            ("width", &area.width),
+                            ("height", &area.height),
+                            ("min_width", &MIN_WIDTH),
+                            ("min_height", &MIN_HEIGHT),
+                        ],
+                    ))
+                    .wrap(Wrap { trim: true }),
+                    area,
+                );
+                return;
+            }
+            let narrow = area.width < NARROW_WIDTH || config.compact();
+            let rows = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ])
+            .split(area);
+            let titles = Tab::ALL.iter().map(|t| {
+                if narrow {
+                    t.key().to_string()
+                } else {
+                    format!("{} {}", t.key(), t.title())
+                }
+            });
+            let project = project.as_ref().filter(|_| !narrow).map(project_text);
+            let header = Layout::horizontal([
+                Constraint::Min(0),
+                Constraint::Length(project.as_ref().map_or(0, |p| p.chars().count() as u16)),
+            ])
+            .split(rows[0]);
             frame.render_widget(
-                list_hosts,
-                Rect::new(
-                    center.x + (center.width / 2),
-                    center.y + 1,
-                    center.width / 2,
-                    center.height,
+                Tabs::new(titles).select(tab.index()).highlight_style(
+                    Style::default()
+                        .fg(Color::LightBlue)
+                        .add_modifier(Modifier::BOLD),
                 ),
+                header[0],
             );
+            if let Some(project) = project {
+                frame.render_widget(
+                    Paragraph::new(project).style(Style::default().fg(Color::DarkGray)),
+                    header[1],
+                );
+            }
+            let footer = match (&error, &notice, &progress) {
+                (Some(error), _, _) => Paragraph::new(i18n::format("error", &[("error", error)]))
+                    .style(Style::default().fg(Color::Red)),
+                (None, Some(notice), _) => {
+                    Paragraph::new(notice.clone()).style(Style::default().fg(Color::Green))
+                }
+                (None, None, Some(progress)) => Paragraph::new(progress_text(progress))
+                    .style(Style::default().fg(Color::Yellow)),
+                (None, None, None) if narrow => Paragraph::new(i18n::text("keys-footer-narrow"))
+                    .style(Style::default().fg(Color::DarkGray)),
+                (None, None, None) => Paragraph::new(i18n::text("keys-footer"))
+                    .style(Style::default().fg(Color::DarkGray)),
+            };
+            frame.render_widget(footer, rows[2]);
+            let body = rows[1];
+
+            match tab {
+                Tab::Tunnels => {
+                    let detail = selected
+                        .selected()
+                        .and_then(|i| visible.get(i))
+                        .filter(|_| wizard.is_none());
+                    let input = textarea.lines().join("\n");
+                    let candidates = wizard
+                        .as_ref()
+                        .map(|w| w.labels(&input))
+                        .unwrap_or_default();
+                    let candidates_height = match &wizard {
+                        Some(w) if w.step == Step::RemotePort => candidates.len().clamp(1, 8) + 2,
+                        Some(_) if !candidates.is_empty() => candidates.len().min(8) + 2,
+                        _ => 0,
+                    };
+                    let parts = Layout::vertical([
+                        Constraint::Min(0),
+                        Constraint::Length(candidates_height as u16),
+                        Constraint::Length(match wizard {
+                            Some(_) => textarea.lines().len().clamp(1, 8) as u16 + 2,
+                            None => 0,
+                        }),
+                    ])
+                    .split(body);
+                    let (list_area, candidates_area, input_area) = (parts[0], parts[1], parts[2]);
+                    // Beside the list on wide terminals, below it when there
+                    // is room, and left out on short ones.
+                    let (list_area, detail_area) = if detail.is_none() {
+                        (list_area, None)
+                    } else if list_area.width >= WIDE_WIDTH {
+                        let columns = Layout::horizontal([
+                            Constraint::Percentage(60),
+                            Constraint::Percentage(40),
+                        ])
+                        .split(list_area);
+                        (columns[0], Some(columns[1]))
+                    } else if list_area.height >= 20 {
+                        let rows = Layout::vertical([Constraint::Min(0), Constraint::Length(10)])
+                            .split(list_area);
+                        (rows[0], Some(rows[1]))
+                    } else {
+                        (list_area, None)
+                    };
+
+                    let items: Vec<ListItem> = visible
+                        .iter()
+                        .map(|&i| &manager.tunnels[i])
+                        .map(|t| {
+                            let mut item = format!("{} [{}]", t.spec, status_text(t));
+                            if let Some(label) = &t.spec.label {
+                                item = format!("{}  {}", label, item);
+                            }
+                            // Only tunnels with fallbacks can point anywhere but the
+                            // primary, and load balanced ones point everywhere at once.
+                            if let Some(target) = t
+                                .target
+                                .as_ref()
+                                .filter(|_| !t.spec.fallbacks.is_empty() && t.connections.is_none())
+                            {
+                                item.push_str(&format!(" -> {}", target));
+                            }
+                            if let Some(connections) = &t.connections {
+                                let throughput = connections.throughput();
+                                if !throughput.is_empty() && !narrow {
+                                    item.push_str(&format!(
+                                        " {}",
+                                        sparkline(&throughput, SPARKLINE_WIDTH)
+                                    ));
+                                }
+                            }
+                            if let Some(protocol) =
+                                t.connections.as_ref().and_then(|c| c.protocol())
+                            {
+                                item.push_str(&format!(" [{}]", protocol.name()));
+                                if !protocol_mismatch(&protocol, t.spec.remote_port).is_empty() {
+                                    item.push('!');
+                                }
+                            }
+                            if t.status != TunnelStatus::Up {
+                                if let Some(e) = t.error_summary() {
+                                    item.push_str(&format!(" {}", e));
+                                }
+                            }
+                            let color = host_color(&t.spec.host, &config.host_colors);
+                            let mut spans = vec![Span::styled("● ", Style::default().fg(color))];
+                            if t.connections
+                                .as_ref()
+                                .is_some_and(|c| c.capturing().is_some())
+                            {
+                                spans.push(Span::styled(
+                                    "REC ",
+                                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                                ));
+                            }
+                            if hostkeys::changed(&t.spec.host).is_some() {
+                                spans.push(Span::styled(
+                                    format!("{} ", i18n::text("badge-host-key")),
+                                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                                ));
+                            }
+                            if remoteport::not_listening(t.spec.local_port).is_some() {
+                                spans.push(Span::styled(
+                                    format!("{} ", i18n::text("badge-remote-port")),
+                                    Style::default()
+                                        .fg(Color::Yellow)
+                                        .add_modifier(Modifier::BOLD),
+                                ));
+                            }
+                            spans.push(Span::raw(item));
+                            let row = ListItem::new(Line::from(spans));
+                            let unseen_drop = focus_lost.is_some_and(|since| {
+                                t.dropped_at.is_some_and(|d| d > since)
+                                    && t.status != TunnelStatus::Up
+                            });
+                            if unseen_drop {
+                                row.style(
+                                    Style::default()
+                                        .fg(Color::Red)
+                                        .add_modifier(Modifier::SLOW_BLINK),
+                                )
+                            } else if t.status == TunnelStatus::Parked {
+                                row.style(Style::default().add_modifier(Modifier::DIM))
+                            } else {
+                                row
+                            }
+                        })
+                        .collect();
+
+                    let mut title = i18n::text("title-app");
+                    if sort != SortOrder::Opened {
+                        title.push(' ');
+                        title.push_str(&i18n::format(
+                            "title-sorted-by",
+                            &[("sort", &sort.as_str())],
+                        ));
+                    }
+                    if let Some(tag) = &tag_filter {
+                        title.push(' ');
+                        title.push_str(&i18n::format("title-tag", &[("tag", tag)]));
+                    }
+                    if config.strict {
+                        title.push(' ');
+                        title.push_str(&i18n::text("title-strict"));
+                    }
+                    let list = List::new(items)
+                        .block(Block::bordered().title(title))
+                        .style(Style::default().fg(Color::White))
+                        .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
+                        .highlight_symbol(">>")
+                        .repeat_highlight_symbol(true)
+                        .direction(ListDirection::TopToBottom);
+                    frame.render_stateful_widget(list, list_area, &mut selected);
+
+                    if let (Some(&i), Some(detail_area)) = (detail, detail_area) {
+                        let throughput = manager.tunnels[i]
+                            .connections
+                            .as_ref()
+                            .map(|c| c.throughput())
+                            .unwrap_or_default();
+                        let detail_area = if throughput.is_empty() || detail_area.height < 12 {
+                            detail_area
+                        } else {
+                            let rows =
+                                Layout::vertical([Constraint::Min(0), Constraint::Length(5)])
+                                    .split(detail_area);
+                            let width = rows[1].width.saturating_sub(2) as usize;
+                            let shown = &throughput[throughput.len().saturating_sub(width)..];
+                            let peak = shown.iter().copied().max().unwrap_or_default();
+                            frame.render_widget(
+                                Sparkline::default()
+                                    .data(shown)
+                                    .style(Style::default().fg(Color::Cyan))
+                                    .block(Block::bordered().title(i18n::format(
+                                        "title-throughput",
+                                        &[
+                                            (
+                                                "span",
+                                                &elapsed(
+                                                    config.intervals.stats_sample()
+                                                        * shown.len() as u32,
+                                                ),
+                                            ),
+                                            ("peak", &bytes(peak)),
+                                            ("interval", &elapsed(config.intervals.stats_sample())),
+                                        ],
+                                    ))),
+                                rows[1],
+                            );
+                            rows[0]
+                        };
+                        frame.render_widget(
+                            tunnel_detail(
+                                &manager.tunnels[i],
+                                &certificates,
+                                config.host_key(&manager.tunnels[i].spec),
+                                selected_client
+                                    .filter(|(port, _)| *port == manager.tunnels[i].spec.local_port)
+                                    .map(|(_, id)| id),
+                            ),
+                            detail_area,
+                        );
+                    }
+                    if let Some(w) = &wizard {
+                        let title = match (w.step, w.discovery_error()) {
+                            (Step::RemotePort, _) if w.discovering() => {
+                                i18n::format("title-looking-up-ports", &[("host", &w.host)])
+                            }
+                            (Step::RemotePort, Some(e)) => i18n::format(
+                                "could-not-list-ports",
+                                &[("host", &w.host), ("error", &e)],
+                            ),
+                            (Step::RemotePort, None) => {
+                                i18n::format("title-listening-ports", &[("host", &w.host)])
+                            }
+                            (Step::Host, _) if !w.recent(&input).is_empty() => {
+                                i18n::text("title-recent-and-hosts")
+                            }
+                            _ => i18n::text("title-hosts"),
+                        };
+                        let mut state = ListState::default().with_selected(w.selected);
+                        frame.render_stateful_widget(
+                            List::new(candidates)
+                                .block(Block::bordered().title(title))
+                                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+                            candidates_area,
+                            &mut state,
+                        );
+                        frame.render_widget(textarea.widget(), input_area);
+                    }
+                }
+                Tab::Hosts => {
+                    let items: Vec<ListItem> = hosts
+                        .iter()
+                        .map(|host| {
+                            let color = host_color(host, &config.host_colors);
+                            let mut spans = vec![
+                                Span::styled("● ", Style::default().fg(color)),
+                                Span::raw(host.as_str()),
+                            ];
+                            if let Some(state) = certificate_state(host, &certificates) {
+                                let warned = cert_warned.contains(host.as_str());
+                                spans.push(Span::styled(
+                                    format!("  certificate {}", state),
+                                    Style::default().fg(match warned {
+                                        true => Color::Yellow,
+                                        false => Color::DarkGray,
+                                    }),
+                                ));
+                            }
+                            if hostkeys::changed(host).is_some() {
+                                spans.push(Span::styled(
+                                    format!("  {}", i18n::text("host-key-changed")),
+                                    Style::default().fg(Color::Red),
+                                ));
+                            }
+                            ListItem::new(Line::from(spans))
+                        })
+                        .collect();
+                    let list = List::new(items)
+                        .block(Block::bordered().title(i18n::text("title-hosts-forward")))
+                        .style(Style::default().fg(Color::White))
+                        .highlight_symbol(">>")
+                        .direction(ListDirection::TopToBottom);
+                    let selected_host = host_selected.selected().and_then(|i| hosts.get(i));
+                    let options = host_options
+                        .as_ref()
+                        .filter(|detail| Some(&detail.host) == selected_host);
+                    match options {
+                        Some(detail) if body.width >= 100 => {
+                            let columns = Layout::horizontal([
+                                Constraint::Percentage(50),
+                                Constraint::Percentage(50),
+                            ])
+                            .split(body);
+                            frame.render_stateful_widget(list, columns[0], &mut host_selected);
+                            frame.render_widget(
+                                host_detail(&detail.host, detail.options.as_ref()),
+                                columns[1],
+                            );
+                        }
+                        Some(detail) if body.height >= 20 => {
+                            let rows =
+                                Layout::vertical([Constraint::Min(0), Constraint::Length(12)])
+                                    .split(body);
+                            frame.render_stateful_widget(list, rows[0], &mut host_selected);
+                            frame.render_widget(
+                                host_detail(&detail.host, detail.options.as_ref()),
+                                rows[1],
+                            );
+                        }
+                        _ => frame.render_stateful_widget(list, body, &mut host_selected),
+                    }
+                }
+                Tab::Logs => {
+                    let items: Vec<String> = history.iter().rev().map(|e| e.to_string()).collect();
+                    let list = List::new(items)
+                        .block(Block::bordered().title(i18n::text("title-history")))
+                        .style(Style::default().fg(Color::White))
+                        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                        .direction(ListDirection::TopToBottom);
+                    frame.render_stateful_widget(list, body, &mut log_selected);
+                }
+                Tab::Profiles => {
+                    let items: Vec<String> = config
+                        .profiles
+                        .iter()
+                        .map(|(name, profile)| {
+                            i18n::format(
+                                "title-profile-tunnels",
+                                &[("profile", name), ("count", &profile.tunnels.len())],
+                            )
+                        })
+                        .collect();
+                    let list = List::new(items)
+                        .block(Block::bordered().title(i18n::text("title-profiles")))
+                        .style(Style::default().fg(Color::White))
+                        .highlight_symbol(">>")
+                        .direction(ListDirection::TopToBottom);
+                    frame.render_stateful_widget(list, body, &mut profile_selected);
+                }
+                Tab::Stats => {
+                    let items: Vec<String> = stats.iter().map(usage_line).collect();
+                    let list = List::new(items)
+                        .block(Block::bordered().title(i18n::text("title-stats")))
+                        .style(Style::default().fg(Color::White))
+                        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                        .direction(ListDirection::TopToBottom);
+                    frame.render_stateful_widget(list, body, &mut stats_selected);
+                }
+            }
+
+            if !orphans.is_empty() {
+                let items: Vec<String> = orphans
+                    .iter()
+                    .map(|o| {
+                        format!(
+                            "pid {}  {} {}:{}",
+                            o.pid, o.host, o.local_port, o.remote_port
+                        )
+                    })
+                    .collect();
+                let popup = centered_rect(area, 60, 30, 50, items.len() as u16 + 2);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    List::new(items).block(
+                        Block::bordered()
+                            .border_style(Style::default().fg(Color::LightBlue))
+                            .title(i18n::text("title-orphans"))
+                            .title_bottom(i18n::text("keys-orphans")),
+                    ),
+                    popup,
+                );
+            }
+
+            // ssh would otherwise seem to hang while it waits for the touch.
+            let touch = manager.tunnels.iter().find_map(|t| match &t.prompt {
+                Some(Prompt::Touch(text)) => Some((t, text)),
+                _ => None,
+            });
+            if let Some((waiting, text)) = touch {
+                let popup = centered_rect(area, 50, 20, 40, 5);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    Paragraph::new(vec![
+                        Line::from(i18n::format(
+                            "touch-security-key",
+                            &[("host", &waiting.spec.host)],
+                        ))
+                        .bold(),
+                        Line::from(text.clone()),
+                    ])
+                    .wrap(Wrap { trim: false })
+                    .block(
+                        Block::bordered()
+                            .border_style(Style::default().fg(Color::Yellow))
+                            .title(i18n::text("title-security-key")),
+                    ),
+                    popup,
+                );
+            }
+
+            if let Some((spec, _)) = manager.pending.first() {
+                let popup = centered_rect(area, 50, 20, 40, 5);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    Paragraph::new(vec![
+                        Line::from(spec.to_string()).bold(),
+                        Line::from(i18n::format(
+                            "production-tags",
+                            &[("tags", &spec.tags.join(", "))],
+                        )),
+                    ])
+                    .wrap(Wrap { trim: false })
+                    .block(
+                        Block::bordered()
+                            .border_style(Style::default().fg(Color::Red))
+                            .title(i18n::text("title-confirm-production"))
+                            .title_bottom(i18n::text("keys-confirm")),
+                    ),
+                    popup,
+                );
+            }
+
+            if let Some((_, input)) = code_entry.as_ref().or(retarget_entry.as_ref()) {
+                let popup = centered_rect(area, 50, 20, 40, 3);
+                let popup = Rect::new(popup.x, popup.y, popup.width, 3);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(input.widget(), popup);
+            }
+
+            if let Some((tunnel, _, checks)) = &diagnosis {
+                let lines: Vec<Line> = if checks.is_empty() {
+                    vec![Line::from(i18n::text("diagnosing"))]
+                } else {
+                    checks
+                        .iter()
+                        .flat_map(|check| {
+                            let color = match check.status {
+                                doctor::Status::Pass => Color::Green,
+                                doctor::Status::Warn => Color::Yellow,
+                                doctor::Status::Fail => Color::Red,
+                            };
+                            check
+                                .to_string()
+                                .lines()
+                                .map(|line| {
+                                    Line::styled(line.to_string(), Style::default().fg(color))
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                        .collect()
+                };
+                let popup = centered_rect(area, 80, 40, 60, lines.len() as u16 + 2);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                        Block::bordered()
+                            .title(i18n::format("title-diagnosis", &[("tunnel", tunnel)]))
+                            .title_bottom(i18n::text("keys-qr-code")),
+                    ),
+                    popup,
+                );
+            }
+
+            if let Some((url, code)) = &qr {
+                let width = code.lines().map(|l| l.chars().count()).max().unwrap_or(0) as u16;
+                let height = code.lines().count() as u16;
+                let popup = Rect::new(
+                    area.x + area.width.saturating_sub(width + 2) / 2,
+                    area.y + area.height.saturating_sub(height + 2) / 2,
+                    width + 2,
+                    height + 2,
+                )
+                .intersection(area);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    Paragraph::new(code.as_str()).block(
+                        Block::bordered()
+                            .title(url.as_str())
+                            .title_bottom(i18n::text("keys-qr-code")),
+                    ),
+                    popup,
+                );
+            }
+
+            if let Some(palette) = &palette {
+                let items: Vec<ListItem> = palette
+                    .matches()
+                    .iter()
+                    .map(|action| {
+                        let key = action
+                            .key()
+                            .map(|k| format!("  [{}]", k))
+                            .unwrap_or_default();
+                        ListItem::new(Line::from(vec![
+                            Span::raw(action.title()),
+                            Span::styled(key, Style::default().fg(Color::DarkGray)),
+                        ]))
+                    })
+                    .collect();
+                let mut state = ListState::default().with_selected(Some(palette.selected));
+                let popup = centered_rect(area, 50, 40, 40, 10);
+                frame.render_widget(Clear, popup);
+                frame.render_stateful_widget(
+                    List::new(items)
+                        .block(
+                            Block::bordered()
+                                .border_style(Style::default().fg(Color::LightBlue))
+                                .title(format!("> {}", palette.query))
+                                .title_bottom(i18n::text("keys-palette")),
+                        )
+                        .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+                    popup,
+                    &mut state,
+                );
+            }
 
-            if new_port.is_some() {
-                let new_area = Rect::new(center.x, center.y + center.height, center.width, 20);
-                frame.render_widget(textarea.widget(), centered_rect(new_area, 100, 100));
+            if let Some(pid) = running_instance {
+                let (text, keys) = match pid {
+                    Some(pid) if takeover.is_some() => (
+                        i18n::format("taking-over", &[("pid", &pid)]),
+                        i18n::text("keys-quit"),
+                    ),
+                    Some(pid) => (
+                        i18n::format("already-running-pid", &[("pid", &pid)]),
+                        i18n::text("keys-take-over"),
+                    ),
+                    None => (i18n::text("already-running"), i18n::text("keys-quit")),
+                };
+                let popup = centered_rect(area, 50, 20, 40, 5);
+                frame.render_widget(Clear, popup);
+                frame.render_widget(
+                    Paragraph::new(text).wrap(Wrap { trim: true }).block(
+                        Block::bordered()
+                            .border_style(Style::default().fg(Color::LightBlue))
+                            .title(i18n::text("title-another-instance"))
+                            .title_bottom(keys),
+                    ),
+                    popup,
+                );
             }
         });
-        if event::poll(std::time::Duration::from_millis(16))? {
-            if let event::Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
+        let mut action = None;
+        if event::poll(config.intervals.ui_tick())? {
+            let event = event::read()?;
+            if let event::Event::Paste(text) = &event {
+                if let Some((_, input)) = code_entry.as_mut().or(retarget_entry.as_mut()) {
+                    input.insert_str(text.trim());
+                } else if let Some(p) = palette.as_mut() {
+                    text.chars()
+                        .filter(|c| !c.is_control())
+                        .for_each(|c| p.push(c));
+                } else if running_instance.is_none() && qr.is_none() && orphans.is_empty() {
+                    let lines: Vec<&str> = text
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .collect();
+                    if lines.len() > 1 && wizard.as_ref().is_some_and(|w| w.step == Step::Host) {
+                        // Into the popup, to be looked over before Enter.
+                        textarea.insert_str(lines.join("\n"));
+                    } else if lines.len() > 1 {
+                        // A list of specs, one tunnel per line.
+                        let specs = TunnelSpec::parse_list(text)
+                            .into_iter()
+                            .map(|(line, spec)| (line, spec.map_err(str::to_string)))
+                            .collect();
+                        let (_, failed) = open_batch(&mut manager, specs);
+                        if !failed.is_empty() {
+                            error = Some(batch_errors(&failed));
+                        }
+                        wizard = None;
+                        tab = Tab::Tunnels;
+                    } else if let Some(line) = lines.first() {
+                        let w = wizard.get_or_insert_with(|| {
+                            tab = Tab::Tunnels;
+                            textarea = get_text_area(None);
+                            new_wizard(&hosts, &config)
+                        });
+                        w.selected = None;
+                        textarea.insert_str(line);
+                    }
+                }
+            }
+            match event {
+                event::Event::FocusLost => focus_lost = Some(std::time::Instant::now()),
+                event::Event::FocusGained => {
+                    focus_lost = None;
+                    alerted = 0;
+                }
+                _ => {}
+            }
+            if let event::Event::Key(key) = event {
+                if key.kind == KeyEventKind::Press && running_instance.is_some() {
                     match key.code {
-                        KeyCode::Esc => {
-                            if new_port.is_none() {
-                                stdout().execute(LeaveAlternateScreen)?;
-                                disable_raw_mode()?;
-                                break;
-                            } else {
-                                new_port = None;
+                        KeyCode::Char('t') if takeover.is_none() => {
+                            if let Some(Some(pid)) = running_instance {
+                                let (done_tx, done_rx) = oneshot::channel();
+                                tokio::spawn(async move {
+                                    let _ = done_tx.send(instance::take_over(pid).await);
+                                });
+                                takeover = Some(done_rx);
                             }
                         }
-                        KeyCode::Char('n') if new_port.is_none() => {
-                            new_port = Some("".to_string());
-                            textarea = get_text_area();
+                        KeyCode::Char('q') | KeyCode::Esc => break,
+                        _ => {}
+                    }
+                } else if key.kind == KeyEventKind::Press && code_entry.is_some() {
+                    if let Some((port, input)) = code_entry.as_mut() {
+                        match key.code {
+                            KeyCode::Enter => {
+                                let code = input.lines().concat().trim().to_string();
+                                manager.answer(*port, code);
+                                code_entry = None;
+                            }
+                            KeyCode::Esc => {
+                                code_dismissed = Some(*port);
+                                code_entry = None;
+                            }
+                            _ => {
+                                edit_input(input, key);
+                            }
                         }
-                        KeyCode::Backspace => {
-                            textarea.delete_char();
+                    }
+                } else if key.kind == KeyEventKind::Press && retarget_entry.is_some() {
+                    if let Some((port, input)) = retarget_entry.as_mut() {
+                        match key.code {
+                            KeyCode::Enter => {
+                                let text = input.lines().concat().trim().to_string();
+                                match RemoteTarget::try_from(text) {
+                                    Ok(target) => {
+                                        if !manager.retarget(*port, target, None) {
+                                            error = Some(i18n::text("cannot-retarget"));
+                                        }
+                                    }
+                                    Err(e) => error = Some(e),
+                                }
+                                retarget_entry = None;
+                            }
+                            KeyCode::Esc => retarget_entry = None,
+                            _ => {
+                                edit_input(input, key);
+                            }
                         }
-                        KeyCode::Char(_) if new_port.is_some() => {
-                            textarea.input(key);
-                            new_port = match textarea.lines().first() {
-                                Some(l) => Some(l.to_string()),
-                                _ => Some("".to_string()),
-                            };
+                    }
+                } else if key.kind == KeyEventKind::Press && !manager.pending.is_empty() {
+                    match key.code {
+                        KeyCode::Char('y') => manager.confirm(true),
+                        KeyCode::Char('n') | KeyCode::Esc => manager.confirm(false),
+                        _ => {}
+                    }
+                } else if key.kind == KeyEventKind::Press && qr.is_some() {
+                    qr = None;
+                } else if key.kind == KeyEventKind::Press && diagnosis.is_some() {
+                    diagnosis = None;
+                } else if key.kind == KeyEventKind::Press && !orphans.is_empty() {
+                    match key.code {
+                        KeyCode::Char('a') => {
+                            for record in orphans.drain(..) {
+                                manager.adopt(record);
+                            }
                         }
-                        KeyCode::Enter if new_port.is_some() => {
-                            if let Some(ref l) = &new_port {
-                                match get_config_from_str(l) {
-                                    Ok((host_name, host_port, remote_port)) => {
-                                        let shutdown_receiver = shutdown_receiver.clone();
-                                        let _jh = tokio::spawn(async move {
-                                            create_ssh_tunnel(
-                                                host_port,
-                                                remote_port,
-                                                host_name.as_str(),
-                                                shutdown_receiver.clone(),
-                                            )
-                                            .await;
-                                        });
-                                        ports.push(host_port.to_string());
-                                    }
-                                    Err(e) => {
-                                        println!("Error: {}", e);
+                        KeyCode::Char('k') => orphans.drain(..).for_each(|r| state::kill(&r)),
+                        KeyCode::Char('i') | KeyCode::Esc => orphans.clear(),
+                        _ => {}
+                    }
+                } else if key.kind == KeyEventKind::Press && palette.is_some() {
+                    if let Some(p) = palette.as_mut() {
+                        match key.code {
+                            KeyCode::Esc => palette = None,
+                            KeyCode::Enter => {
+                                action = p.chosen();
+                                palette = None;
+                            }
+                            KeyCode::Up => p.previous(),
+                            KeyCode::Down => p.next(),
+                            KeyCode::Backspace => p.pop(),
+                            KeyCode::Char(c) => p.push(c),
+                            _ => {}
+                        }
+                    }
+                } else if key.kind == KeyEventKind::Press && wizard.is_some() {
+                    error = None;
+                    if let Some(w) = wizard.as_mut() {
+                        let input = textarea.lines().join("\n");
+                        match key.code {
+                            KeyCode::Esc => wizard = None,
+                            KeyCode::Enter
+                                if key.modifiers.contains(KeyModifiers::ALT)
+                                    && w.step == Step::Host =>
+                            {
+                                textarea.insert_newline();
+                            }
+                            KeyCode::Up | KeyCode::Down if textarea.lines().len() > 1 => {
+                                textarea.move_cursor(match key.code {
+                                    KeyCode::Up => CursorMove::Up,
+                                    _ => CursorMove::Down,
+                                });
+                            }
+                            KeyCode::Up | KeyCode::Down => {
+                                w.move_selection(&input, key.code == KeyCode::Down)
+                            }
+                            // Alt, as plain digits start hosts such as 10.0.0.5.
+                            KeyCode::Char(c @ '1'..='9')
+                                if key.modifiers.contains(KeyModifiers::ALT)
+                                    && w.quick_pick(&input, c as usize - '0' as usize)
+                                        .is_some() =>
+                            {
+                                if let Some(entry) = w.quick_pick(&input, c as usize - '0' as usize)
+                                {
+                                    let spec = entry.spec();
+                                    recent::record(&spec);
+                                    manager.open(spec);
+                                }
+                                wizard = None;
+                            }
+                            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                if let Some(entry) = w.selected_recent(&input) {
+                                    recent::toggle_pin(entry);
+                                    *w = new_wizard(&hosts, &config);
+                                }
+                            }
+                            KeyCode::Enter => match w.submit(&input) {
+                                Outcome::Next { prefill } => {
+                                    textarea = get_text_area(Some(w));
+                                    textarea.insert_str(prefill);
+                                }
+                                Outcome::Done(spec) => {
+                                    recent::record(&spec);
+                                    manager.open(*spec);
+                                    wizard = None;
+                                }
+                                Outcome::Batch(specs) => {
+                                    let (opened, failed) = open_batch(&mut manager, specs);
+                                    if failed.is_empty() {
+                                        wizard = None;
+                                        notice = Some(i18n::format(
+                                            "batch-opened",
+                                            &[("count", &opened)],
+                                        ));
+                                    } else {
+                                        error = Some(batch_errors(&failed));
+                                        // Leave the lines to fix, unless they came from a file.
+                                        if !input.trim().starts_with('@') {
+                                            let lines: Vec<&str> = input.lines().collect();
+                                            textarea = get_text_area(Some(w));
+                                            textarea.insert_str(
+                                                failed
+                                                    .iter()
+                                                    .map(|(line, _)| lines[line - 1].trim())
+                                                    .collect::<Vec<_>>()
+                                                    .join("\n"),
+                                            );
+                                        }
                                     }
                                 }
+                                Outcome::Invalid(e) => error = Some(e),
+                            },
+                            _ => {
+                                if edit_input(&mut textarea, key) {
+                                    w.selected = None;
+                                }
+                            }
+                        }
+                    }
+                } else if key.kind == KeyEventKind::Press {
+                    error = None;
+                    notice = None;
+                    let in_list = tab == Tab::Tunnels;
+                    match key.code {
+                        KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            palette = Some(Palette::new(Action::all(config.profiles.keys())));
+                        }
+                        KeyCode::Esc => action = Some(Action::Quit),
+                        KeyCode::Up | KeyCode::Down => {
+                            let (state, len) = match tab {
+                                Tab::Tunnels => (&mut selected, visible.len()),
+                                Tab::Hosts => (&mut host_selected, hosts.len()),
+                                Tab::Profiles => (&mut profile_selected, config.profiles.len()),
+                                Tab::Logs => (&mut log_selected, history.len()),
+                                Tab::Stats => (&mut stats_selected, stats.len()),
+                            };
+                            move_selection(state, len, key.code == KeyCode::Down);
+                        }
+                        KeyCode::Tab => action = Some(Action::ShowTab(tab.next())),
+                        KeyCode::BackTab => action = Some(Action::ShowTab(tab.previous())),
+                        KeyCode::Char(c @ '1'..='5') => {
+                            action = Some(Action::ShowTab(Tab::ALL[c as usize - '1' as usize]))
+                        }
+                        KeyCode::Enter if tab == Tab::Hosts => {
+                            if let Some(host) = host_selected.selected().and_then(|i| hosts.get(i))
+                            {
+                                // Skip straight to the remote port.
+                                let mut w = new_wizard(&hosts, &config);
+                                w.submit(host);
+                                textarea = get_text_area(Some(&w));
+                                wizard = Some(w);
+                                tab = Tab::Tunnels;
                             }
-                            new_port = None;
                         }
+                        KeyCode::Enter if tab == Tab::Profiles => {
+                            action = profile_selected
+                                .selected()
+                                .and_then(|i| config.profiles.keys().nth(i))
+                                .map(|name| Action::OpenProfile(name.clone()));
+                        }
+                        KeyCode::Char('q') if in_list => action = Some(Action::ShowQrCode),
+                        KeyCode::Char('f') if in_list => action = Some(Action::FilterByTag),
+                        KeyCode::Char('s') if in_list => action = Some(Action::CycleSort),
+                        KeyCode::Char('x') if in_list => action = Some(Action::CloseSelected),
+                        KeyCode::Char('r') if in_list => action = Some(Action::RestartSelected),
+                        KeyCode::Char('R') if in_list => action = Some(Action::RestartAll),
+                        KeyCode::Char('n') if in_list => action = Some(Action::NewTunnel),
+                        KeyCode::Char('c') if in_list => action = Some(Action::SelectConnection),
+                        KeyCode::Char('k') if in_list => action = Some(Action::CloseConnection),
+                        KeyCode::Char('p') if in_list => action = Some(Action::ToggleCapture),
+                        KeyCode::Char('e') if in_list => action = Some(Action::ExportReport),
+                        KeyCode::Char('d') if in_list => action = Some(Action::Diagnose),
+                        KeyCode::Char('l') if in_list => action = Some(Action::LaunchClient),
+                        KeyCode::Char('D') if in_list => action = Some(Action::DrainSelected),
+                        KeyCode::Char('t') if in_list => action = Some(Action::RetargetSelected),
+                        KeyCode::Char('o') if in_list => action = Some(Action::ToggleLazy),
                         _ => {}
                     }
                 }
             }
         }
+
+        let selected_tunnel = selected.selected().and_then(|i| visible.get(i)).copied();
+        match action {
+            None => {}
+            Some(Action::Quit) => break,
+            Some(Action::NewTunnel) => {
+                tab = Tab::Tunnels;
+                wizard = Some(new_wizard(&hosts, &config));
+                textarea = get_text_area(None);
+            }
+            Some(Action::CloseSelected) => {
+                if let Some(i) = selected_tunnel {
+                    manager.close(i);
+                }
+            }
+            Some(Action::DrainSelected) => {
+                if let Some(i) = selected_tunnel {
+                    if !manager.drain(i) {
+                        error = Some(i18n::text("drain-needs-relay"));
+                    }
+                }
+            }
+            Some(Action::ToggleLazy) => {
+                if let Some(i) = selected_tunnel {
+                    let lazy = !manager.tunnels[i].spec.lazy;
+                    if !manager.set_lazy(i, lazy) {
+                        error = Some(i18n::text("lazy-needs-open"));
+                    }
+                }
+            }
+            Some(Action::RetargetSelected) => {
+                if let Some(i) = selected_tunnel {
+                    let t = &manager.tunnels[i];
+                    let prompt =
+                        i18n::format("retarget-prompt", &[("tunnel", &t.spec.to_string())]);
+                    retarget_entry = Some((t.spec.local_port, code_input(&prompt)));
+                }
+            }
+            Some(Action::RestartSelected) => {
+                if let Some(i) = selected_tunnel {
+                    manager.restart(i, "restarted by user");
+                }
+            }
+            Some(Action::RestartAll) => manager.restart_all("restarted by user"),
+            Some(Action::ToggleCapture) => {
+                let tunnel = selected_tunnel.map(|i| &manager.tunnels[i]);
+                match tunnel.map(|t| (t, t.connections.as_ref())) {
+                    None => {}
+                    Some((_, None)) => error = Some(i18n::text("capture-needs-relay")),
+                    Some((t, Some(connections))) => match connections.stop_capture() {
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => {
+                            error = Some(i18n::format("could-not-save-capture", &[("error", &e)]))
+                        }
+                        None => match Capture::create(&config.capture, &t.spec) {
+                            Ok(capture) => {
+                                audit::record("capture", &t.spec.to_string(), None);
+                                connections.start_capture(capture);
+                            }
+                            Err(e) => error = Some(format!("{:#}", e)),
+                        },
+                    },
+                }
+            }
+            Some(Action::ExportReport) => {
+                let tunnels: Vec<TunnelInfo> =
+                    manager.tunnels.iter().map(TunnelInfo::from).collect();
+                let events = history::load(report::REPORT_EVENTS, None).unwrap_or_default();
+                let now = chrono::Utc::now();
+                let text = report::render(ReportFormat::Markdown, &tunnels, &events, now);
+                match report::save(&text, ReportFormat::Markdown, now) {
+                    Ok(path) => {
+                        let path = path.display().to_string();
+                        let key = match platform::copy_to_clipboard(&text) {
+                            true => "report-copied",
+                            false => "report-saved",
+                        };
+                        notice = Some(i18n::format(key, &[("path", &path)]));
+                    }
+                    Err(e) => error = Some(i18n::format("could-not-save-report", &[("error", &e)])),
+                }
+            }
+            Some(Action::Diagnose) => {
+                if let Some(i) = selected_tunnel {
+                    let t = &manager.tunnels[i];
+                    let target = t
+                        .target
+                        .clone()
+                        .and_then(|target| RemoteTarget::try_from(target).ok())
+                        .unwrap_or_else(|| t.spec.targets().remove(0));
+                    let session = match (&t.status, &t.last_error) {
+                        (TunnelStatus::Up, _) => Ok(()),
+                        (_, Some(e)) => Err(e.clone()),
+                        (status, None) => Err(status.to_string()),
+                    };
+                    let (done_tx, done_rx) = oneshot::channel();
+                    let (config, spec) = (config.clone(), t.spec.clone());
+                    tokio::spawn(async move {
+                        let checks = diagnose::run(&config, &spec, &target, session).await;
+                        let _ = done_tx.send(checks);
+                    });
+                    diagnosis = Some((t.spec.to_string(), done_rx, Vec::new()));
+                }
+            }
+            Some(Action::LaunchClient) => {
+                if let Some(i) = selected_tunnel {
+                    match dbclient::command(&manager.tunnels[i].spec) {
+                        Ok(Some(command)) => {
+                            if let Err(e) =
+                                launch_client(&command, &mut manager, &mut requests_rx).await
+                            {
+                                error = Some(i18n::format(
+                                    "client-failed",
+                                    &[("error", &format!("{:#}", e))],
+                                ));
+                            }
+                            terminal.clear()?;
+                        }
+                        Ok(None) => error = Some(i18n::text("client-needs-database")),
+                        Err(e) => error = Some(e),
+                    }
+                }
+            }
+            Some(Action::SelectConnection) => {
+                let tunnel = selected_tunnel.map(|i| &manager.tunnels[i]);
+                let clients = tunnel
+                    .and_then(|t| t.connections.as_ref())
+                    .map(|connections| connections.clients())
+                    .unwrap_or_default();
+                let port = tunnel.map(|t| t.spec.local_port).unwrap_or_default();
+                let current = selected_client
+                    .filter(|(p, _)| *p == port)
+                    .map(|(_, id)| id);
+                selected_client = clients
+                    .iter()
+                    .map(|client| client.id)
+                    .find(|id| current.is_none_or(|current| *id > current))
+                    .or_else(|| clients.first().map(|client| client.id))
+                    .map(|id| (port, id));
+                if clients.is_empty() {
+                    error = Some(i18n::text("no-clients"));
+                }
+            }
+            Some(Action::CloseConnection) => {
+                let tunnel = selected_tunnel.map(|i| &manager.tunnels[i]);
+                let connections = tunnel.and_then(|t| t.connections.as_ref());
+                match (tunnel, connections, selected_client.take()) {
+                    (Some(t), Some(connections), Some((port, id))) if t.spec.local_port == port => {
+                        connections.kill(id);
+                    }
+                    _ => error = Some(i18n::text("select-connection-first")),
+                }
+            }
+            Some(Action::ShowQrCode) => {
+                if let Some(i) = selected_tunnel {
+                    let t = &manager.tunnels[i];
+                    if !t.spec.lan {
+                        error = Some(i18n::text("not-shared"));
+                    } else if let Some(ip) = network::lan_ip() {
+                        let url = format!("http://{}:{}", ip, t.spec.local_port);
+                        match qr_code(&url) {
+                            Ok(code) => qr = Some((url, code)),
+                            Err(e) => error = Some(e.to_string()),
+                        }
+                    } else {
+                        error = Some(i18n::text("no-network-address"));
+                    }
+                }
+            }
+            Some(Action::FilterByTag) => {
+                let tags = manager.tags();
+                tag_filter = match &tag_filter {
+                    None => tags.first().cloned(),
+                    Some(tag) => tags.iter().skip_while(|t| *t != tag).nth(1).cloned(),
+                };
+                selected.select(None);
+            }
+            Some(Action::CycleSort) => {
+                sort = sort.next();
+                selected.select(None);
+                if let Err(e) = config::save_sort(sort) {
+                    error = Some(i18n::format(
+                        "could-not-save-sort",
+                        &[("error", &format!("{:#}", e))],
+                    ));
+                }
+            }
+            Some(Action::ShowTab(next)) => {
+                if next == Tab::Logs {
+                    match history::load(HISTORY_VIEW_LIMIT, None) {
+                        Ok(entries) => history = entries,
+                        Err(e) => {
+                            error = Some(i18n::format("could-not-read-history", &[("error", &e)]))
+                        }
+                    }
+                }
+                if next == Tab::Stats {
+                    stats = usage::load();
+                }
+                tab = next;
+            }
+            Some(Action::OpenProfile(name)) => match config.profile_tunnels(&name) {
+                Ok(specs) => {
+                    for spec in specs {
+                        manager.open(spec);
+                    }
+                    tab = Tab::Tunnels;
+                }
+                Err(e) => error = Some(e.to_string()),
+            },
+        }
     }
 
-    for _ in ports.iter() {
-        let _ = sender.lock().await.send(()).await;
+    drop(terminal_guard);
+
+    manager.shutdown(stop).await;
+    if let Some(previous) = system_proxy {
+        let _ = previous.restore();
     }
+    drop(control_server);
+    drop(pid_file);
+    drop(instance_lock);
     Ok(())
 }
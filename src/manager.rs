@@ -0,0 +1,778 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::audit;
+use crate::config::{Config, SortOrder};
+use crate::events::{self, Event};
+use crate::hostsfile;
+use crate::instance;
+use crate::pac::PacRules;
+use crate::proxy::Routes;
+use crate::relay::RelayStats;
+use crate::repeats::Repeats;
+use crate::state::ProcessRecord;
+use crate::status::TunnelInfo;
+use crate::tunnel::{
+    self, Control, Prompt, RemoteTarget, Stop, TunnelEvent, TunnelSpec, TunnelStatus,
+};
+use crate::usage;
+
+/// A tunnel owned by the [`Manager`], with the last status its supervisor
+/// reported.
+pub struct ManagedTunnel {
+    pub spec: TunnelSpec,
+    pub status: TunnelStatus,
+    pub last_error: Option<String>,
+    /// How often `last_error` came up lately.
+    pub repeats: Repeats,
+    /// Destination the forward points at, once the supervisor reported it.
+    pub target: Option<String>,
+    pub connections: Option<Arc<RelayStats>>,
+    /// When the tunnel last came up, while it is up.
+    pub up_since: Option<DateTime<Local>>,
+    /// When the tunnel last went down unexpectedly.
+    pub dropped_at: Option<Instant>,
+    /// User who opened the tunnel through the control socket, if not the
+    /// user running rtun.
+    pub owner: Option<u32>,
+    /// What ssh waits on the user for, such as touching a security key.
+    pub prompt: Option<Prompt>,
+    // Bytes of `connections` already added to the usage statistics.
+    accounted: u64,
+    // Both unset while a scheduled tunnel is outside its window.
+    control: Option<mpsc::UnboundedSender<Control>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ManagedTunnel {
+    fn is_running(&self) -> bool {
+        self.handle.as_ref().is_some_and(|h| !h.is_finished())
+    }
+
+    fn start(
+        &mut self,
+        config: &Arc<Config>,
+        events: &mpsc::UnboundedSender<TunnelEvent>,
+        orphan: Option<ProcessRecord>,
+    ) {
+        let (control, control_rx) = mpsc::unbounded_channel();
+        let config = config.clone();
+        let events = events.clone();
+        let handle = match orphan {
+            Some(record) => tokio::spawn(tunnel::adopt(record, config, events, control_rx)),
+            None => tokio::spawn(tunnel::supervise(
+                self.spec.clone(),
+                config,
+                events,
+                control_rx,
+            )),
+        };
+        self.status = TunnelStatus::Up;
+        self.up_since = Some(Local::now());
+        self.last_error = None;
+        self.repeats = Repeats::default();
+        self.accounted = 0;
+        self.control = Some(control);
+        self.handle = Some(handle);
+        usage::opened(&self.spec);
+    }
+
+    /// Adds the time up since `up_since` and the bytes relayed since last
+    /// time to the usage statistics, before `up_since` is reset.
+    fn account(&mut self) {
+        let Some(since) = self.up_since else {
+            return;
+        };
+        let up = (Local::now() - since).to_std().unwrap_or_default();
+        let bytes = self.connections.as_ref().map_or(0, |c| c.transferred());
+        usage::add(&self.spec, up, bytes.saturating_sub(self.accounted));
+        self.accounted = bytes;
+    }
+
+    /// The last error, folded with its repeats, as the UI shows it.
+    pub fn error_summary(&self) -> Option<String> {
+        let error = self.last_error.as_ref()?;
+        Some(self.repeats.summary(error, Instant::now()))
+    }
+
+    fn send(&self, command: Control) {
+        if let Some(control) = &self.control {
+            let _ = control.send(command);
+        }
+    }
+
+    /// Whether the tunnel is up and rtun listens on its port itself, so that
+    /// it can stop taking connections without dropping the open ones.
+    fn can_drain(&self) -> bool {
+        self.spec.relayed() && self.control.is_some() && self.status == TunnelStatus::Up
+    }
+
+    /// Stops the tunnel for good; it stays listed as closed and its schedule
+    /// no longer reopens it.
+    fn close(&mut self) {
+        self.account();
+        self.send(Control::Stop(Stop::Close));
+        self.control = None;
+        self.status = TunnelStatus::Closed;
+        self.up_since = None;
+        self.last_error = None;
+        self.prompt = None;
+    }
+}
+
+/// Takes a closing tunnel out of the proxy routes and the PAC file, which
+/// [`Manager::add`] put it in.
+fn unroute(routes: &Routes, pac: &PacRules, spec: &TunnelSpec) {
+    if spec.socks {
+        pac.remove(spec.local_port);
+    }
+    routes.remove(spec.name.as_deref().unwrap_or(&spec.host), spec.local_port);
+}
+
+// Subscribers further behind than this miss events.
+const EVENT_BACKLOG: usize = 256;
+/// How long a tunnel may take to connect before the next one in the queue
+/// gets its turn anyway.
+const CONNECT_TURN: Duration = Duration::from_secs(15);
+const CONNECT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How the tunnels opened at once are coming along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub connected: usize,
+    /// Waiting for their turn, or connecting.
+    pub pending: usize,
+    /// Given up on, or trying again.
+    pub failed: usize,
+}
+
+/// Whether something listens on `local_port`. Told by failing to bind it
+/// rather than by connecting, which a relay would count as a client and ssh
+/// would forward to the remote service.
+fn listening(local_port: u16) -> bool {
+    std::net::TcpListener::bind(("127.0.0.1", local_port)).is_err()
+}
+
+/// Resolves once `local_port` is listened on, or the turn of its tunnel is
+/// over. Tunnels rtun listens on itself, relayed or parked, are done right
+/// away.
+async fn bound(local_port: u16) {
+    let _ = tokio::time::timeout(CONNECT_TURN, async {
+        while !listening(local_port) {
+            tokio::time::sleep(CONNECT_POLL_INTERVAL).await;
+        }
+    })
+    .await;
+}
+
+/// Tells subscribers about a change of `tunnel`.
+fn emit(
+    changes: &broadcast::Sender<Event>,
+    tunnel: &ManagedTunnel,
+    kind: events::Kind,
+    detail: Option<String>,
+) {
+    // Fails only without subscribers.
+    let _ = changes.send(Event::new(kind, TunnelInfo::from(tunnel), detail));
+}
+
+/// Keeps the hostnames `wanted` has in /etc/hosts, catching up with the
+/// latest once a write, which may wait on a password, is done.
+async fn keep_hostnames(
+    mut wanted: watch::Receiver<BTreeSet<String>>,
+    error: Arc<Mutex<Option<String>>>,
+) {
+    while wanted.changed().await.is_ok() {
+        let names = wanted.borrow_and_update().clone();
+        let synced = tokio::task::spawn_blocking(move || hostsfile::sync(&names)).await;
+        if let Ok(Err(e)) = synced {
+            *error.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(format!("Could not update /etc/hosts: {}", e));
+        }
+    }
+}
+
+/// How the audit log names a user acting through the control socket.
+fn caller(uid: u32) -> String {
+    format!("uid {}", uid)
+}
+
+/// Puts tunnels needing attention first when sorting by status.
+fn status_rank(status: &TunnelStatus) -> u8 {
+    match status {
+        TunnelStatus::Failed => 0,
+        TunnelStatus::Reconnecting(_) => 1,
+        TunnelStatus::WaitingForVpn => 2,
+        TunnelStatus::Up | TunnelStatus::Draining | TunnelStatus::Parked => 3,
+        TunnelStatus::Scheduled | TunnelStatus::Queued => 4,
+        TunnelStatus::Closed => 5,
+    }
+}
+
+/// Owns the set of tunnels of a running rtun and their supervisor tasks.
+pub struct Manager {
+    config: Arc<Config>,
+    /// Tunnels waiting for the user to confirm opening them, with the user
+    /// who asked for them; see [`Config::needs_confirmation`].
+    pub pending: Vec<(TunnelSpec, Option<u32>)>,
+    events_tx: mpsc::UnboundedSender<TunnelEvent>,
+    events_rx: mpsc::UnboundedReceiver<TunnelEvent>,
+    /// Local ports of tunnels tied to a process that exited, with the user
+    /// who tied them; see [`Manager::tie`].
+    exits_tx: mpsc::UnboundedSender<(Vec<u16>, Option<u32>)>,
+    exits_rx: mpsc::UnboundedReceiver<(Vec<u16>, Option<u32>)>,
+    routes: Arc<Routes>,
+    pac: Arc<PacRules>,
+    changes: broadcast::Sender<Event>,
+    /// Tunnels waiting for their turn to connect, by index in `tunnels`.
+    queue: VecDeque<usize>,
+    /// Tunnels connecting, by index in `tunnels`, with the task telling when
+    /// they are done; see [`Config::parallel_connects`].
+    connecting: Vec<(usize, JoinHandle<()>)>,
+    /// The `hostnames` of the open tunnels, for the task keeping them in
+    /// /etc/hosts, once a tunnel had any.
+    hostnames: Option<watch::Sender<BTreeSet<String>>>,
+    /// Why /etc/hosts could not be updated, until taken.
+    hostnames_error: Arc<Mutex<Option<String>>>,
+    pub tunnels: Vec<ManagedTunnel>,
+}
+
+impl Manager {
+    pub fn new(config: Arc<Config>) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (exits_tx, exits_rx) = mpsc::unbounded_channel();
+        Manager {
+            config,
+            pending: Vec::new(),
+            events_tx,
+            events_rx,
+            exits_tx,
+            exits_rx,
+            routes: Arc::default(),
+            pac: Arc::default(),
+            changes: broadcast::channel(EVENT_BACKLOG).0,
+            queue: VecDeque::new(),
+            connecting: Vec::new(),
+            hostnames: None,
+            hostnames_error: Arc::default(),
+            tunnels: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    /// Names of the tunnels for the HTTP proxy, kept up to date as tunnels
+    /// are opened.
+    pub fn routes(&self) -> Arc<Routes> {
+        self.routes.clone()
+    }
+
+    /// Where lifecycle events of the tunnels are sent; subscribe to it to
+    /// receive them.
+    pub fn changes(&self) -> broadcast::Sender<Event> {
+        self.changes.clone()
+    }
+
+    /// Domains routed through SOCKS tunnels, for the PAC file.
+    pub fn pac_rules(&self) -> Arc<PacRules> {
+        self.pac.clone()
+    }
+
+    fn add(&mut self, spec: TunnelSpec, orphan: Option<ProcessRecord>) {
+        if spec.socks {
+            self.pac.insert(&spec.pac_domains, spec.local_port);
+        }
+        self.routes
+            .insert(spec.name.as_deref().unwrap_or(&spec.host), spec.local_port);
+        let mut tunnel = ManagedTunnel {
+            spec,
+            status: TunnelStatus::Scheduled,
+            last_error: None,
+            repeats: Repeats::default(),
+            target: None,
+            connections: None,
+            up_since: None,
+            dropped_at: None,
+            owner: None,
+            prompt: None,
+            accounted: 0,
+            control: None,
+            handle: None,
+        };
+        let scheduled_closed = tunnel
+            .spec
+            .schedule
+            .as_ref()
+            .is_some_and(|s| !s.is_open(Local::now()));
+        self.take_turns();
+        if let Some(orphan) = orphan {
+            // Connected already.
+            tunnel.start(&self.config, &self.events_tx, Some(orphan));
+            emit(&self.changes, &tunnel, events::Kind::Opened, None);
+        } else if scheduled_closed {
+            emit(&self.changes, &tunnel, events::Kind::Scheduled, None);
+        } else if self.connecting.len() >= self.config.parallel_connects.max(1) {
+            tunnel.status = TunnelStatus::Queued;
+            emit(&self.changes, &tunnel, events::Kind::Queued, None);
+            self.queue.push_back(self.tunnels.len());
+        } else {
+            tunnel.start(&self.config, &self.events_tx, None);
+            emit(&self.changes, &tunnel, events::Kind::Opened, None);
+            let turn = tokio::spawn(bound(tunnel.spec.local_port));
+            self.connecting.push((self.tunnels.len(), turn));
+        }
+        self.tunnels.push(tunnel);
+    }
+
+    /// Lets queued tunnels connect as those connecting are done: once their
+    /// port is listened on, they went down, or their turn is over.
+    fn take_turns(&mut self) {
+        let tunnels = &self.tunnels;
+        self.connecting.retain(|(i, turn)| {
+            let done = turn.is_finished() || tunnels[*i].status != TunnelStatus::Up;
+            if done {
+                turn.abort();
+            }
+            !done
+        });
+        while self.connecting.len() < self.config.parallel_connects.max(1) {
+            let Some(i) = self.queue.pop_front() else {
+                break;
+            };
+            let tunnel = &mut self.tunnels[i];
+            // Closed while it waited.
+            if tunnel.status != TunnelStatus::Queued {
+                continue;
+            }
+            tunnel.start(&self.config, &self.events_tx, None);
+            emit(&self.changes, tunnel, events::Kind::Opened, None);
+            let turn = tokio::spawn(bound(tunnel.spec.local_port));
+            self.connecting.push((i, turn));
+        }
+    }
+
+    /// How the tunnels are coming along while some wait for their turn or
+    /// connect; `None` once none do.
+    pub fn progress(&self) -> Option<Progress> {
+        let queued = self
+            .queue
+            .iter()
+            .filter(|&&i| self.tunnels[i].status == TunnelStatus::Queued)
+            .count();
+        let pending = queued + self.connecting.len();
+        if pending == 0 {
+            return None;
+        }
+        let connected = self
+            .tunnels
+            .iter()
+            .enumerate()
+            .filter(|(i, t)| {
+                matches!(
+                    t.status,
+                    TunnelStatus::Up | TunnelStatus::Parked | TunnelStatus::Draining
+                ) && !self.connecting.iter().any(|(c, _)| c == i)
+            })
+            .count();
+        let failed = self
+            .tunnels
+            .iter()
+            .filter(|t| {
+                matches!(
+                    t.status,
+                    TunnelStatus::Failed | TunnelStatus::Reconnecting(_)
+                )
+            })
+            .count();
+        Some(Progress {
+            connected,
+            pending,
+            failed,
+        })
+    }
+
+    /// Opens a tunnel, or parks it until its schedule opens or, in strict
+    /// mode, until the user confirms it.
+    pub fn open(&mut self, spec: TunnelSpec) {
+        if self.config.needs_confirmation(&spec) {
+            self.pending.push((spec, None));
+            return;
+        }
+        audit::record("open", &spec.to_string(), None);
+        self.add(spec, None);
+    }
+
+    /// Opens a tunnel on behalf of the user `owner`.
+    pub fn open_for(&mut self, spec: TunnelSpec, owner: u32) {
+        if self.config.needs_confirmation(&spec) {
+            self.pending.push((spec, Some(owner)));
+            return;
+        }
+        audit::record("open", &spec.to_string(), Some(caller(owner)));
+        self.add(spec, None);
+        if let Some(tunnel) = self.tunnels.last_mut() {
+            tunnel.owner = Some(owner);
+        }
+    }
+
+    /// Opens the first of the `pending` tunnels, or drops it.
+    pub fn confirm(&mut self, open: bool) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let (spec, owner) = self.pending.remove(0);
+        let action = if open { "confirm" } else { "reject" };
+        audit::record(action, &spec.to_string(), owner.map(caller));
+        if open {
+            self.add(spec, None);
+            if let Some(tunnel) = self.tunnels.last_mut() {
+                tunnel.owner = owner;
+            }
+        }
+    }
+
+    /// Whether a tunnel that is not closed listens on `local_port`.
+    pub fn port_in_use(&self, local_port: u16) -> bool {
+        self.tunnels
+            .iter()
+            .any(|t| t.spec.local_port == local_port && t.status != TunnelStatus::Closed)
+    }
+
+    /// Takes over an ssh process left behind by another rtun instance.
+    pub fn adopt(&mut self, record: ProcessRecord) {
+        audit::record("adopt", &record.spec().to_string(), None);
+        self.add(record.spec(), Some(record));
+    }
+
+    /// Closes the tunnel at `index` in `tunnels`.
+    pub fn close(&mut self, index: usize) {
+        if let Some(tunnel) = self.tunnels.get_mut(index) {
+            audit::record("close", &tunnel.spec.to_string(), None);
+            unroute(&self.routes, &self.pac, &tunnel.spec);
+            tunnel.close();
+            emit(&self.changes, tunnel, events::Kind::Closed, None);
+        }
+    }
+
+    /// Drains the tunnel at `index`: it stops taking connections and closes
+    /// once the open ones are over. Only tunnels rtun listens on itself can
+    /// drain; returns whether it does.
+    pub fn drain(&mut self, index: usize) -> bool {
+        match self.tunnels.get(index) {
+            Some(tunnel) if tunnel.can_drain() => {
+                audit::record("drain", &tunnel.spec.to_string(), None);
+                tunnel.send(Control::Drain);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Drains the open tunnels whose spec matches and that can, returning
+    /// how many. With an `owner`, only the tunnels that user opened.
+    pub fn drain_matching(
+        &mut self,
+        matches: impl Fn(&TunnelSpec) -> bool,
+        owner: Option<u32>,
+    ) -> usize {
+        let mut draining = 0;
+        for tunnel in self.tunnels.iter() {
+            if tunnel.can_drain()
+                && matches(&tunnel.spec)
+                && owner.is_none_or(|uid| tunnel.owner == Some(uid))
+            {
+                audit::record("drain", &tunnel.spec.to_string(), owner.map(caller));
+                tunnel.send(Control::Drain);
+                draining += 1;
+            }
+        }
+        draining
+    }
+
+    /// Has the tunnel at `index` connect ssh only once a client connects, or
+    /// right away; see [`TunnelSpec::lazy`]. Returns whether it is open.
+    pub fn set_lazy(&mut self, index: usize, lazy: bool) -> bool {
+        match self.tunnels.get_mut(index) {
+            Some(tunnel) if tunnel.control.is_some() => {
+                let action = if lazy { "lazy" } else { "eager" };
+                audit::record(action, &tunnel.spec.to_string(), None);
+                tunnel.spec.lazy = lazy;
+                tunnel.send(Control::Lazy(lazy));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Points the tunnel on `local_port` at `target` instead, keeping its
+    /// local port. Tunnels rtun listens on itself keep their open
+    /// connections on the old destination until they are over. SOCKS and
+    /// load balanced tunnels have no single destination to change; returns
+    /// whether the tunnel could. With an `owner`, only if that user opened it.
+    pub fn retarget(&mut self, local_port: u16, target: RemoteTarget, owner: Option<u32>) -> bool {
+        let Some(tunnel) = self.tunnels.iter_mut().rev().find(|t| {
+            t.spec.local_port == local_port
+                && t.control.is_some()
+                && owner.is_none_or(|uid| t.owner == Some(uid))
+        }) else {
+            return false;
+        };
+        if tunnel.spec.socks || tunnel.spec.load_balance.is_some() {
+            return false;
+        }
+        let detail = format!("{} to {}", tunnel.spec, target);
+        audit::record("retarget", &detail, owner.map(caller));
+        tunnel.spec.remote_host = Some(target.host.clone());
+        tunnel.spec.remote_port = target.port;
+        // They were fallbacks of the old destination.
+        tunnel.spec.fallbacks.clear();
+        tunnel.send(Control::Retarget(target));
+        true
+    }
+
+    /// Closes the tunnels carrying all of `tags`, returning how many. With
+    /// an `owner`, only the tunnels that user opened.
+    pub fn close_tagged(&mut self, tags: &[String], owner: Option<u32>) -> usize {
+        self.close_matching(|spec| spec.has_tags(tags), owner)
+    }
+
+    /// Closes the open tunnels whose spec matches, returning how many. With
+    /// an `owner`, only the tunnels that user opened.
+    pub fn close_matching(
+        &mut self,
+        matches: impl Fn(&TunnelSpec) -> bool,
+        owner: Option<u32>,
+    ) -> usize {
+        let mut closed = 0;
+        for tunnel in self.tunnels.iter_mut() {
+            if tunnel.control.is_some()
+                && matches(&tunnel.spec)
+                && owner.is_none_or(|uid| tunnel.owner == Some(uid))
+            {
+                audit::record("close", &tunnel.spec.to_string(), owner.map(caller));
+                unroute(&self.routes, &self.pac, &tunnel.spec);
+                tunnel.close();
+                emit(&self.changes, tunnel, events::Kind::Closed, None);
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    /// Closes the tunnels on `local_ports` once the process `pid` exits, as
+    /// `owner` when given, for `--tie-to-pid` when an instance is already
+    /// running.
+    pub fn tie(&self, pid: u32, local_ports: Vec<u16>, owner: Option<u32>) {
+        let exits = self.exits_tx.clone();
+        tokio::spawn(async move {
+            instance::exited(pid).await;
+            let _ = exits.send((local_ports, owner));
+        });
+    }
+
+    /// Restarts the ssh process of the tunnel at `index` in `tunnels`.
+    pub fn restart(&self, index: usize, reason: &str) {
+        if let Some(tunnel) = self.tunnels.get(index) {
+            audit::record("restart", &tunnel.spec.to_string(), None);
+            tunnel.send(Control::Restart(reason.to_string()));
+        }
+    }
+
+    /// Answers the [`Prompt::Code`] of the tunnel on `local_port`.
+    pub fn answer(&mut self, local_port: u16, code: String) {
+        if let Some(tunnel) = self
+            .tunnels
+            .iter_mut()
+            .find(|t| t.spec.local_port == local_port && t.is_running())
+        {
+            tunnel.send(Control::Answer(code));
+            tunnel.prompt = None;
+        }
+    }
+
+    /// Tags of all tunnels, sorted and without duplicates.
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self
+            .tunnels
+            .iter()
+            .flat_map(|t| t.spec.tags.iter().cloned())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Indices into `tunnels` in the given order.
+    pub fn sorted(&self, sort: SortOrder) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.tunnels.len()).collect();
+        // Stable, so ties keep the order the tunnels were opened in.
+        match sort {
+            SortOrder::Opened => {}
+            SortOrder::Host => {
+                order.sort_by(|&a, &b| self.tunnels[a].spec.host.cmp(&self.tunnels[b].spec.host))
+            }
+            SortOrder::LocalPort => order.sort_by_key(|&i| self.tunnels[i].spec.local_port),
+            SortOrder::Status => order.sort_by_key(|&i| status_rank(&self.tunnels[i].status)),
+            // Tunnels that are down go last.
+            SortOrder::Uptime => order
+                .sort_by_key(|&i| (self.tunnels[i].up_since.is_none(), self.tunnels[i].up_since)),
+        }
+        order
+    }
+
+    pub fn restart_all(&self, reason: &str) {
+        audit::record("restart_all", reason, None);
+        for tunnel in self.tunnels.iter() {
+            tunnel.send(Control::Restart(reason.to_string()));
+        }
+    }
+
+    /// Applies status updates reported by the supervisors. Returns whether
+    /// there were any.
+    pub fn poll_events(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events_rx.try_recv() {
+            // The supervisor may have finished since it reported closing or
+            // failing, so this goes by who it reports to instead.
+            if let Some(t) = self
+                .tunnels
+                .iter_mut()
+                .rev()
+                .find(|t| t.spec.local_port == event.local_port && t.control.is_some())
+            {
+                let dropped = t.status == TunnelStatus::Up
+                    && matches!(
+                        event.status,
+                        TunnelStatus::Reconnecting(_) | TunnelStatus::Failed
+                    );
+                if dropped {
+                    t.dropped_at = Some(Instant::now());
+                }
+                if event.status != TunnelStatus::Up {
+                    t.account();
+                    t.up_since = None;
+                } else if t.status != TunnelStatus::Up || t.up_since.is_none() {
+                    t.up_since = Some(Local::now());
+                }
+                let status_changed = t.status != event.status;
+                // Failing the same way again only makes it to the events now
+                // and then, counted; the history log has every one.
+                let reported = match (&event.detail, &event.status) {
+                    (Some(error), TunnelStatus::Reconnecting(_)) => {
+                        t.repeats.see(error, Instant::now())
+                    }
+                    (Some(error), TunnelStatus::Failed) => {
+                        t.repeats.see(error, Instant::now());
+                        true
+                    }
+                    _ => true,
+                };
+                t.status = event.status;
+                t.last_error = event.detail;
+                t.target = Some(event.target);
+                t.connections = event.connections;
+                t.prompt = event.prompt;
+                if dropped && reported {
+                    emit(&self.changes, t, events::Kind::Unhealthy, t.error_summary());
+                }
+                if status_changed && reported {
+                    let kind = events::Kind::of(&t.status);
+                    emit(&self.changes, t, kind, t.error_summary());
+                }
+            }
+            changed = true;
+        }
+        while let Ok((local_ports, owner)) = self.exits_rx.try_recv() {
+            self.close_matching(|spec| local_ports.contains(&spec.local_port), owner);
+            changed = true;
+        }
+        let queued = self.queue.len();
+        self.take_turns();
+        self.sync_hostnames();
+        changed | (self.queue.len() != queued)
+    }
+
+    /// Has the `hostnames` of the open tunnels, and only those, point at
+    /// the loopback address in /etc/hosts.
+    fn sync_hostnames(&mut self) {
+        let names: BTreeSet<String> = self
+            .tunnels
+            .iter()
+            .filter(|t| {
+                t.control.is_some()
+                    && !matches!(t.status, TunnelStatus::Closed | TunnelStatus::Failed)
+            })
+            .flat_map(|t| t.spec.hostnames.iter().cloned())
+            .collect();
+        if self.hostnames.is_none() && names.is_empty() {
+            return;
+        }
+        let error = self.hostnames_error.clone();
+        let hostnames = self.hostnames.get_or_insert_with(|| {
+            let (wanted, receiver) = watch::channel(BTreeSet::new());
+            tokio::spawn(keep_hostnames(receiver, error));
+            wanted
+        });
+        hostnames.send_if_modified(|current| {
+            let modified = *current != names;
+            *current = names;
+            modified
+        });
+    }
+
+    /// Why /etc/hosts could not be updated lately, once.
+    pub fn take_hostnames_error(&self) -> Option<String> {
+        self.hostnames_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    /// Opens and closes scheduled tunnels according to their schedules.
+    pub fn apply_schedules(&mut self, now: DateTime<Local>) {
+        for tunnel in self.tunnels.iter_mut() {
+            let Some(schedule) = &tunnel.spec.schedule else {
+                continue;
+            };
+            let open = schedule.is_open(now);
+            if open && tunnel.control.is_none() && tunnel.status == TunnelStatus::Scheduled {
+                tunnel.start(&self.config, &self.events_tx, None);
+                emit(&self.changes, tunnel, events::Kind::Opened, None);
+            } else if !open && tunnel.control.is_some() {
+                tunnel.account();
+                tunnel.send(Control::Stop(Stop::Close));
+                tunnel.control = None;
+                tunnel.handle = None;
+                tunnel.status = TunnelStatus::Scheduled;
+                tunnel.up_since = None;
+                tunnel.last_error = None;
+                emit(&self.changes, tunnel, events::Kind::Scheduled, None);
+            }
+        }
+    }
+
+    /// Stops every tunnel and waits for the supervisors to finish. Closing
+    /// them takes their hostnames out of /etc/hosts.
+    pub async fn shutdown(mut self, stop: Stop) {
+        for tunnel in self.tunnels.iter_mut() {
+            tunnel.account();
+            tunnel.send(Control::Stop(stop));
+        }
+        for tunnel in self.tunnels {
+            if let Some(handle) = tunnel.handle {
+                let _ = handle.await;
+            }
+        }
+        // Left for the next instance, with the tunnels, when detaching.
+        if self.hostnames.is_some() && stop == Stop::Close {
+            let _ = tokio::task::spawn_blocking(|| hostsfile::sync(&BTreeSet::new())).await;
+        }
+    }
+}
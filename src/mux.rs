@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use tokio::process::{Child, Command};
+
+use crate::config::SshClient;
+use crate::platform;
+
+const MUX_PATH: &str = ".rtun/mux";
+/// Seconds between checks that the master still runs.
+const CHECK_INTERVAL: u64 = 5;
+
+// Forwards added to masters, by local port of their tunnel, to cancel once
+// the tunnel closes.
+static ADDED: Mutex<BTreeMap<u16, Added>> = Mutex::new(BTreeMap::new());
+
+struct Added {
+    client: SshClient,
+    host: String,
+    forwards: Vec<OsString>,
+}
+
+/// An ssh process the tunnels to the same host add their forwards to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Master {
+    /// Found through the `ControlPath` of the user's ssh config.
+    Configured,
+    /// Started by rtun, listening on a socket under `~/.rtun/mux`.
+    Rtun(PathBuf),
+}
+
+impl Master {
+    fn socket_args(&self) -> Vec<OsString> {
+        match self {
+            Master::Configured => Vec::new(),
+            Master::Rtun(path) => vec!["-S".into(), path.into()],
+        }
+    }
+}
+
+/// `ControlPath` of the masters rtun starts, one per destination through
+/// ssh's `%C` hash of host, port and user.
+pub fn control_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(MUX_PATH).join("%C"))
+}
+
+/// Options that make an ssh the master rtun starts for a host. It goes to
+/// the background once logged in, carrying no forwards of its own, and
+/// stays until the last tunnel using it closes, see [`cancel`].
+pub fn master_options() -> Vec<OsString> {
+    let Some(path) = control_path() else {
+        return Vec::new();
+    };
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    [
+        "ControlMaster=yes".into(),
+        format!("ControlPath={}", path.display()),
+    ]
+    .into_iter()
+    .flat_map(|option| [OsString::from("-o"), option.into()])
+    .chain([OsString::from("-f")])
+    .collect()
+}
+
+/// `ssh -O <operation>` on `master`, as a line of shell.
+fn control_command(
+    client: &SshClient,
+    master: &Master,
+    host: &str,
+    operation: &str,
+    extra: &[OsString],
+) -> String {
+    let mut words = vec![client.program().into_os_string()];
+    words.extend(master.socket_args());
+    words.extend(["-O".into(), operation.into()]);
+    words.extend(extra.iter().cloned());
+    words.push(host.into());
+    quote(&words)
+}
+
+fn quote(words: &[OsString]) -> String {
+    words
+        .iter()
+        .map(|word| platform::shell_quote(&word.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Adds `forwards`, in `-L`/`-D`/`-R` arguments, to the master connected
+/// to `host` with `ssh -O forward`: the one of the user's ssh config, or
+/// else rtun's, started first with `master` as its arguments when there is
+/// none yet, answering its prompts through `askpass`. Returns a process that runs as long as the master does, for
+/// the supervisor to watch. [`cancel`] removes them again.
+pub fn attach(
+    client: &SshClient,
+    host: &str,
+    local_port: u16,
+    forwards: Vec<OsString>,
+    master: Vec<OsString>,
+    askpass: Vec<(&'static str, String)>,
+) -> std::io::Result<Child> {
+    let path = control_path()
+        .ok_or_else(|| std::io::Error::other("Could not determine the home directory"))?;
+    // One tunnel at a time starts the master of a host; whoever finds the
+    // one holding the lock gone takes it over.
+    let lock = path.with_file_name(format!("{}.lock", lock_name(host)));
+    let lock = platform::shell_quote(&lock.to_string_lossy());
+    let rtun = Master::Rtun(path);
+    let mut start = vec![client.program().into_os_string()];
+    start.extend(master);
+    let script = format!(
+        "if {check_configured} 2>/dev/null; then\n\
+         forward() {{ {forward_configured}; }}\n\
+         check() {{ {check_configured}; }}\n\
+         else\n\
+         until mkdir {lock} 2>/dev/null; do\n\
+         pid=$(cat {lock}/pid 2>/dev/null)\n\
+         [ -n \"$pid\" ] && ! kill -0 \"$pid\" 2>/dev/null && rm -rf {lock}\n\
+         sleep 0.2\n\
+         done\n\
+         echo $$ > {lock}/pid\n\
+         {check_rtun} 2>/dev/null || {start}\n\
+         started=$?\n\
+         rm -rf {lock}\n\
+         [ $started -eq 0 ] || exit 1\n\
+         forward() {{ {forward_rtun}; }}\n\
+         check() {{ {check_rtun}; }}\n\
+         fi\n\
+         forward >/dev/null || exit 1\n\
+         while check 2>/dev/null; do sleep {interval}; done\n\
+         echo \"The ssh master of $0 exited\" >&2\n\
+         exit 1",
+        check_configured = control_command(client, &Master::Configured, host, "check", &[]),
+        check_rtun = control_command(client, &rtun, host, "check", &[]),
+        forward_configured =
+            control_command(client, &Master::Configured, host, "forward", &forwards),
+        forward_rtun = control_command(client, &rtun, host, "forward", &forwards),
+        start = quote(&start),
+        interval = CHECK_INTERVAL,
+    );
+    let child = Command::new(platform::shell())
+        .arg("-c")
+        .arg(script)
+        .arg(host)
+        .envs(askpass)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    ADDED.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        local_port,
+        Added {
+            client: client.clone(),
+            host: host.to_string(),
+            forwards,
+        },
+    );
+    Ok(child)
+}
+
+// `host` as a file name.
+fn lock_name(host: &str) -> String {
+    host.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Removes the forwards the tunnel on `local_port` added to a master, if
+/// any, with `ssh -O cancel`, and has rtun's master exit once no tunnel
+/// uses it. Runs in the background.
+pub fn cancel(local_port: u16) {
+    let mut added = ADDED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(tunnel) = added.remove(&local_port) else {
+        return;
+    };
+    let last = !added.values().any(|other| other.host == tunnel.host);
+    drop(added);
+    let Some(rtun) = control_path().map(Master::Rtun) else {
+        return;
+    };
+    let cancel = |master: &Master| {
+        control_command(
+            &tunnel.client,
+            master,
+            &tunnel.host,
+            "cancel",
+            &tunnel.forwards,
+        )
+    };
+    let mut script = format!("{} || {}", cancel(&Master::Configured), cancel(&rtun));
+    if last {
+        let exit = control_command(&tunnel.client, &rtun, &tunnel.host, "exit", &[]);
+        script.push_str(&format!("\n{}", exit));
+    }
+    tokio::spawn(async move {
+        let _ = Command::new(platform::shell())
+            .arg("-c")
+            .arg(script)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+    });
+}
@@ -0,0 +1,136 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::mpsc;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// How far the wall clock may run ahead of a poll tick before we assume the
+// machine was suspended in between.
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkEvent {
+    /// The machine came back from sleep.
+    Resumed,
+    /// Network interfaces or their addresses changed.
+    Changed,
+}
+
+impl NetworkEvent {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            NetworkEvent::Resumed => "resumed from sleep",
+            NetworkEvent::Changed => "network changed",
+        }
+    }
+}
+
+fn addresses() -> Vec<(String, IpAddr)> {
+    let mut addresses: Vec<_> = if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .map(|iface| {
+            let ip = iface.ip();
+            (iface.name, ip)
+        })
+        .collect();
+    addresses.sort();
+    addresses
+}
+
+// Name prefixes of bridges, container links and VPN tunnels, whose addresses
+// other devices on the LAN cannot reach.
+const VIRTUAL_INTERFACES: &[&str] = &[
+    "docker",
+    "br-",
+    "veth",
+    "virbr",
+    "vmnet",
+    "vboxnet",
+    "cni",
+    "flannel",
+    "podman",
+    "lxc",
+    "lxd",
+    "tun",
+    "tap",
+    "wg",
+    "utun",
+    "ppp",
+    "ipsec",
+    "tailscale",
+    "zt",
+    "nordlynx",
+];
+
+fn is_virtual(name: &str) -> bool {
+    VIRTUAL_INTERFACES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// The address the kernel would send from to reach the internet, i.e. the
+/// one of the default-route interface. Connecting a UDP socket only picks the
+/// route; nothing is sent.
+fn default_route_ip() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// The IPv4 address of this machine for reaching it from other devices: the
+/// one of the default-route interface, unless that is a VPN or other virtual
+/// interface, else the first one of a physical interface, private addresses
+/// first.
+pub fn lan_ip() -> Option<IpAddr> {
+    let physical: Vec<IpAddr> = addresses()
+        .into_iter()
+        .filter(|(name, ip)| ip.is_ipv4() && !is_virtual(name))
+        .map(|(_, ip)| ip)
+        .collect();
+    if let Some(ip) = default_route_ip().filter(|ip| physical.contains(ip)) {
+        return Some(ip);
+    }
+    let private = |ip: &IpAddr| matches!(ip, IpAddr::V4(v4) if v4.is_private());
+    physical
+        .iter()
+        .find(|ip| private(ip))
+        .or(physical.first())
+        .copied()
+}
+
+/// Reports sleep/wake cycles and interface changes on `events` until the
+/// receiver goes away.
+///
+/// Sleep is detected by comparing the wall clock, which keeps running while
+/// suspended, with the monotonic timer driving the poll, which does not.
+/// Interfaces are polled rather than watched so the same code works on every
+/// platform.
+pub async fn watch(events: mpsc::UnboundedSender<NetworkEvent>) {
+    let mut known = addresses();
+    let mut last_tick = SystemTime::now();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let now = SystemTime::now();
+        let elapsed = now.duration_since(last_tick).unwrap_or_default();
+        last_tick = now;
+
+        let current = addresses();
+        let event = if elapsed > POLL_INTERVAL + SLEEP_THRESHOLD {
+            Some(NetworkEvent::Resumed)
+        } else if current != known {
+            Some(NetworkEvent::Changed)
+        } else {
+            None
+        };
+        known = current;
+        if let Some(event) = event {
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
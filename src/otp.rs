@@ -0,0 +1,150 @@
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+
+const HOSTS_PATH: &str = ".rtun/otp_hosts";
+const REPLIES_DIR: &str = ".rtun/askpass";
+/// Gives up on a code that is never typed in.
+const CODE_TIMEOUT: Duration = Duration::from_secs(300);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Written to the stderr of ssh when it asks for a one-time code, followed
+/// by its prompt.
+pub const CODE_START: &str = "rtun code: ";
+
+// Clipboard readers, tried in order.
+const PASTE_COMMANDS: [&[&str]; 4] = [
+    &["pbpaste"],
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+
+/// Whether ssh's `prompt` asks for a one-time code rather than a password,
+/// as keyboard-interactive 2FA such as Google Authenticator or Duo does.
+pub fn is_code_prompt(prompt: &str) -> bool {
+    let prompt = prompt.to_lowercase();
+    [
+        "verification code",
+        "one-time",
+        "otp",
+        "token",
+        "2fa",
+        "authenticator",
+        "passcode",
+    ]
+    .iter()
+    .any(|word| prompt.contains(word))
+}
+
+fn hosts_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(HOSTS_PATH))
+}
+
+/// Whether `host` asked for a code before.
+pub fn needs_code(host: &str) -> bool {
+    hosts_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .is_some_and(|hosts| hosts.lines().any(|h| h == host))
+}
+
+/// Notes that `host` asks for a code, so later connections ask for it
+/// before ssh does.
+pub fn remember(host: &str) -> Result<()> {
+    if needs_code(host) {
+        return Ok(());
+    }
+    let path = hosts_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut hosts = fs::read_to_string(&path).unwrap_or_default();
+    hosts.push_str(host);
+    hosts.push('\n');
+    fs::write(&path, hosts).wrap_err_with(|| format!("Could not write {}", path.display()))
+}
+
+// Where the askpass program run by the ssh process `ssh_pid` waits for the
+// code.
+fn reply_path(ssh_pid: u32) -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(REPLIES_DIR).join(format!("{}.sock", ssh_pid)))
+}
+
+/// Run as the askpass program of ssh: shows `prompt` in rtun and waits for
+/// the code typed in.
+pub fn ask(prompt: &str) -> Result<String> {
+    let ssh_pid = std::os::unix::process::parent_id();
+    let path =
+        reply_path(ssh_pid).ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .wrap_err_with(|| format!("Could not listen on {}", path.display()))?;
+    listener.set_nonblocking(true)?;
+    // Our stderr is the one of ssh, which the supervisor reads.
+    eprintln!("{}{}", CODE_START, prompt);
+    let started = Instant::now();
+    let code = loop {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false)?;
+                let mut code = String::new();
+                stream.read_to_string(&mut code)?;
+                break Ok(code.trim().to_string());
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                // ssh went away, or nobody is there to answer.
+                if std::os::unix::process::parent_id() != ssh_pid
+                    || started.elapsed() >= CODE_TIMEOUT
+                {
+                    break Err(eyre!("No code was entered"));
+                }
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => break Err(e.into()),
+        }
+    };
+    let _ = fs::remove_file(&path);
+    code
+}
+
+/// Gives `code` to the askpass program of the ssh process `ssh_pid`, if it
+/// waits for one.
+pub async fn answer(ssh_pid: u32, code: &str) -> std::io::Result<()> {
+    let path = reply_path(ssh_pid).ok_or_else(|| std::io::Error::other("no home directory"))?;
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(code.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// A code copied to the clipboard, such as from an authenticator app.
+pub fn from_clipboard() -> Option<String> {
+    PASTE_COMMANDS.iter().find_map(|command| {
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        let text = String::from_utf8(output.stdout).ok()?;
+        let code = text.trim();
+        // Anything else on the clipboard is none of our business.
+        let is_code = (4..=10).contains(&code.len()) && code.chars().all(|c| c.is_ascii_digit());
+        is_code.then(|| code.to_string())
+    })
+}
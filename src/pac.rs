@@ -0,0 +1,255 @@
+use std::io;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Which domains go through which SOCKS forward, in the order the tunnels
+/// were opened.
+#[derive(Debug, Default)]
+pub struct PacRules(RwLock<Vec<(Vec<String>, u16)>>);
+
+impl PacRules {
+    pub fn insert(&self, domains: &[String], socks_port: u16) {
+        let mut rules = self.0.write().unwrap_or_else(|e| e.into_inner());
+        rules.retain(|(_, port)| *port != socks_port);
+        rules.push((domains.to_vec(), socks_port));
+    }
+
+    pub fn remove(&self, socks_port: u16) {
+        let mut rules = self.0.write().unwrap_or_else(|e| e.into_inner());
+        rules.retain(|(_, port)| *port != socks_port);
+    }
+
+    /// The SOCKS forward `host` goes through, as the PAC file has it.
+    pub fn route(&self, host: &str) -> Option<u16> {
+        let host = host.trim_end_matches('.').to_lowercase();
+        let rules = self.0.read().unwrap_or_else(|e| e.into_inner());
+        rules
+            .iter()
+            .find(|(domains, _)| domains.iter().any(|d| matches(d, &host)))
+            .map(|(_, port)| *port)
+    }
+
+    /// The proxy auto-config script for the current rules.
+    pub fn render(&self) -> String {
+        let mut script = String::from("function FindProxyForURL(url, host) {\n");
+        for (domains, port) in self.0.read().unwrap_or_else(|e| e.into_inner()).iter() {
+            let conditions: Vec<String> = domains.iter().map(|d| condition(d)).collect();
+            if conditions.is_empty() {
+                continue;
+            }
+            script.push_str(&format!(
+                "  if ({}) return \"SOCKS5 127.0.0.1:{port}; SOCKS 127.0.0.1:{port}\";\n",
+                conditions.join(" || "),
+                port = port
+            ));
+        }
+        script.push_str("  return \"DIRECT\";\n}\n");
+        script
+    }
+}
+
+/// PAC expression matching `domain`: a shell pattern when it has wildcards,
+/// otherwise the domain and its subdomains.
+fn condition(domain: &str) -> String {
+    let domain = domain.replace('"', "");
+    if domain.contains('*') || domain.contains('?') {
+        format!("shExpMatch(host, \"{}\")", domain)
+    } else {
+        format!(
+            "host == \"{}\" || dnsDomainIs(host, \".{}\")",
+            domain, domain
+        )
+    }
+}
+
+/// Whether `host` is `domain` as [`condition`] has browsers match it.
+fn matches(domain: &str, host: &str) -> bool {
+    let domain = domain.to_lowercase();
+    if domain.contains('*') || domain.contains('?') {
+        return glob(domain.as_bytes(), host.as_bytes());
+    }
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// `shExpMatch` of `*` and `?` only.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, _) => text.is_empty(),
+        (Some(b'*'), _) => {
+            glob(&pattern[1..], text) || (!text.is_empty() && glob(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => glob(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// Serves the PAC file at `http://127.0.0.1:<port>/proxy.pac`, whatever the
+/// path actually requested.
+pub async fn serve(port: u16, rules: Arc<PacRules>) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let rules = rules.clone();
+            tokio::spawn(async move {
+                // The request itself does not matter, only that it arrived.
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).await;
+                let body = rules.render();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/x-ns-proxy-autoconfig\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+pub fn url(port: u16) -> String {
+    format!("http://127.0.0.1:{}/proxy.pac", port)
+}
+
+fn run(program: &str, args: &[&str]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// What `program` printed, if it succeeded.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+const GNOME_PROXY: &str = "org.gnome.system.proxy";
+
+/// The system proxy settings from before rtun changed them, to put back
+/// when it exits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemProxy {
+    MacOs {
+        network_service: String,
+        /// The auto proxy URL, also kept while turned off.
+        url: Option<String>,
+        enabled: bool,
+    },
+    /// As `gsettings get` prints them, which `gsettings set` takes back.
+    Gnome {
+        mode: String,
+        autoconfig_url: String,
+    },
+}
+
+impl SystemProxy {
+    /// Puts the settings back as they were.
+    pub fn restore(&self) -> io::Result<()> {
+        let done = match self {
+            SystemProxy::MacOs {
+                network_service,
+                url,
+                enabled,
+            } => {
+                let url_set = url.as_deref().is_none_or(|url| {
+                    run("networksetup", &["-setautoproxyurl", network_service, url])
+                });
+                let state = if *enabled { "on" } else { "off" };
+                url_set
+                    && run(
+                        "networksetup",
+                        &["-setautoproxystate", network_service, state],
+                    )
+            }
+            SystemProxy::Gnome {
+                mode,
+                autoconfig_url,
+            } => {
+                run(
+                    "gsettings",
+                    &["set", GNOME_PROXY, "autoconfig-url", autoconfig_url],
+                ) && run("gsettings", &["set", GNOME_PROXY, "mode", mode])
+            }
+        };
+        if done {
+            Ok(())
+        } else {
+            Err(io::Error::other(
+                "could not restore the system proxy settings",
+            ))
+        }
+    }
+}
+
+/// The URL and whether it is on, from what `networksetup -getautoproxyurl`
+/// printed.
+pub fn parse_autoproxy(output: &str) -> (Option<String>, bool) {
+    let field = |name: &str| {
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .map(str::trim)
+    };
+    let url = field("URL:").filter(|url| !url.is_empty() && *url != "(null)");
+    (url.map(str::to_string), field("Enabled:") == Some("Yes"))
+}
+
+/// The system proxy settings as they are now.
+fn current(network_service: &str) -> Option<SystemProxy> {
+    if cfg!(target_os = "macos") {
+        let printed = output("networksetup", &["-getautoproxyurl", network_service])?;
+        let (url, enabled) = parse_autoproxy(&printed);
+        Some(SystemProxy::MacOs {
+            network_service: network_service.to_string(),
+            url,
+            enabled,
+        })
+    } else {
+        Some(SystemProxy::Gnome {
+            mode: output("gsettings", &["get", GNOME_PROXY, "mode"])?,
+            autoconfig_url: output("gsettings", &["get", GNOME_PROXY, "autoconfig-url"])?,
+        })
+    }
+}
+
+/// Points the system proxy settings at `url`, returning those it replaced
+/// for [`SystemProxy::restore`]. Supports macOS, for the given network
+/// service, and GNOME.
+pub fn set_system_proxy(url: &str, network_service: &str) -> io::Result<SystemProxy> {
+    let previous = current(network_service)
+        .ok_or_else(|| io::Error::other("could not read the system proxy settings"))?;
+    let done = if cfg!(target_os = "macos") {
+        run("networksetup", &["-setautoproxyurl", network_service, url])
+    } else {
+        run("gsettings", &["set", GNOME_PROXY, "autoconfig-url", url])
+            && run("gsettings", &["set", GNOME_PROXY, "mode", "auto"])
+    };
+    if done {
+        Ok(previous)
+    } else {
+        // Some of it may have changed already.
+        let _ = previous.restore();
+        Err(io::Error::other(
+            "could not change the system proxy settings",
+        ))
+    }
+}
@@ -0,0 +1,255 @@
+use crate::i18n;
+
+/// Tabs of the TUI, in the order shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tab {
+    Tunnels,
+    Hosts,
+    Logs,
+    Profiles,
+    /// How much each tunnel was used, over all sessions.
+    Stats,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 5] = [
+        Tab::Tunnels,
+        Tab::Hosts,
+        Tab::Logs,
+        Tab::Profiles,
+        Tab::Stats,
+    ];
+
+    pub fn index(self) -> usize {
+        Tab::ALL.iter().position(|&t| t == self).unwrap_or(0)
+    }
+
+    pub fn next(self) -> Tab {
+        Tab::ALL[(self.index() + 1) % Tab::ALL.len()]
+    }
+
+    pub fn previous(self) -> Tab {
+        Tab::ALL[(self.index() + Tab::ALL.len() - 1) % Tab::ALL.len()]
+    }
+
+    pub fn title(self) -> String {
+        i18n::text(match self {
+            Tab::Tunnels => "tab-tunnels",
+            Tab::Hosts => "tab-hosts",
+            Tab::Logs => "tab-logs",
+            Tab::Profiles => "tab-profiles",
+            Tab::Stats => "tab-stats",
+        })
+    }
+
+    /// Number key switching to the tab.
+    pub fn key(self) -> &'static str {
+        match self {
+            Tab::Tunnels => "1",
+            Tab::Hosts => "2",
+            Tab::Logs => "3",
+            Tab::Profiles => "4",
+            Tab::Stats => "5",
+        }
+    }
+}
+
+/// Something the TUI can do, bound to a key and listed in the command
+/// palette.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    NewTunnel,
+    CloseSelected,
+    /// Stop the selected tunnel taking connections, and close it once the
+    /// open ones are over.
+    DrainSelected,
+    /// Point the selected tunnel at another destination.
+    RetargetSelected,
+    /// Have the selected tunnel connect ssh on demand, or always.
+    ToggleLazy,
+    RestartSelected,
+    RestartAll,
+    ShowQrCode,
+    FilterByTag,
+    CycleSort,
+    /// Step through the clients connected to the selected tunnel.
+    SelectConnection,
+    CloseConnection,
+    /// Start or stop writing the traffic of the selected tunnel to a pcap
+    /// file.
+    ToggleCapture,
+    /// Save a report of the tunnels and recent events, and copy it to the
+    /// clipboard.
+    ExportReport,
+    /// Check step by step why connections through the selected tunnel fail.
+    Diagnose,
+    /// Launch the database client of the selected tunnel, such as psql,
+    /// pointed at its local port.
+    LaunchClient,
+    ShowTab(Tab),
+    /// Open the tunnels of a profile from the config.
+    OpenProfile(String),
+    Quit,
+}
+
+impl Action {
+    /// Every action, with one entry per profile.
+    pub fn all<'a>(profiles: impl IntoIterator<Item = &'a String>) -> Vec<Action> {
+        let mut actions = vec![
+            Action::NewTunnel,
+            Action::CloseSelected,
+            Action::DrainSelected,
+            Action::RetargetSelected,
+            Action::ToggleLazy,
+            Action::RestartSelected,
+            Action::RestartAll,
+            Action::ShowQrCode,
+            Action::FilterByTag,
+            Action::CycleSort,
+            Action::SelectConnection,
+            Action::CloseConnection,
+            Action::ToggleCapture,
+            Action::ExportReport,
+            Action::Diagnose,
+            Action::LaunchClient,
+        ];
+        actions.extend(Tab::ALL.map(Action::ShowTab));
+        actions.extend(profiles.into_iter().cloned().map(Action::OpenProfile));
+        actions.push(Action::Quit);
+        actions
+    }
+
+    pub fn title(&self) -> String {
+        let key = match self {
+            Action::NewTunnel => "action-new-tunnel",
+            Action::CloseSelected => "action-close-selected",
+            Action::DrainSelected => "action-drain-selected",
+            Action::RetargetSelected => "action-retarget-selected",
+            Action::ToggleLazy => "action-toggle-lazy",
+            Action::RestartSelected => "action-restart-selected",
+            Action::RestartAll => "action-restart-all",
+            Action::ShowQrCode => "action-show-qr-code",
+            Action::FilterByTag => "action-filter-by-tag",
+            Action::CycleSort => "action-cycle-sort",
+            Action::SelectConnection => "action-select-connection",
+            Action::CloseConnection => "action-close-connection",
+            Action::ToggleCapture => "action-toggle-capture",
+            Action::ExportReport => "action-export-report",
+            Action::Diagnose => "action-diagnose",
+            Action::LaunchClient => "action-launch-client",
+            Action::ShowTab(tab) => {
+                return i18n::format("action-show-tab", &[("tab", &tab.title())])
+            }
+            Action::OpenProfile(name) => {
+                return i18n::format("action-open-profile", &[("profile", name)])
+            }
+            Action::Quit => "action-quit",
+        };
+        i18n::text(key)
+    }
+
+    /// Key bound to the action in the tunnel list, if any.
+    pub fn key(&self) -> Option<&'static str> {
+        match self {
+            Action::NewTunnel => Some("n"),
+            Action::CloseSelected => Some("x"),
+            Action::DrainSelected => Some("D"),
+            Action::RetargetSelected => Some("t"),
+            Action::ToggleLazy => Some("o"),
+            Action::RestartSelected => Some("r"),
+            Action::RestartAll => Some("R"),
+            Action::ShowQrCode => Some("q"),
+            Action::FilterByTag => Some("f"),
+            Action::CycleSort => Some("s"),
+            Action::SelectConnection => Some("c"),
+            Action::CloseConnection => Some("k"),
+            Action::ToggleCapture => Some("p"),
+            Action::ExportReport => Some("e"),
+            Action::Diagnose => Some("d"),
+            Action::LaunchClient => Some("l"),
+            Action::ShowTab(tab) => Some(tab.key()),
+            Action::OpenProfile(_) => None,
+            Action::Quit => Some("esc"),
+        }
+    }
+}
+
+/// Scores how well `query` matches `text`: every character of the query
+/// must appear in order, ignoring case. Consecutive characters and matches
+/// at the start of words score higher.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + text[position..].iter().position(|&t| t == c)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 2;
+        }
+        if found == 0 || text[found - 1] == ' ' {
+            score += 3;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// The actions matching `query`, best first.
+pub fn search<'a>(actions: &'a [Action], query: &str) -> Vec<&'a Action> {
+    let mut matches: Vec<(u32, &Action)> = actions
+        .iter()
+        .filter_map(|action| fuzzy_score(query, &action.title()).map(|score| (score, action)))
+        .collect();
+    // Stable, so equally good matches keep their order.
+    matches.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(_, action)| action).collect()
+}
+
+/// State of the open command palette.
+pub struct Palette {
+    pub query: String,
+    /// Index into the current matches.
+    pub selected: usize,
+    actions: Vec<Action>,
+}
+
+impl Palette {
+    pub fn new(actions: Vec<Action>) -> Self {
+        Palette {
+            query: String::new(),
+            selected: 0,
+            actions,
+        }
+    }
+
+    pub fn matches(&self) -> Vec<&Action> {
+        search(&self.actions, &self.query)
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.selected = 0;
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    pub fn next(&mut self) {
+        let last = self.matches().len().saturating_sub(1);
+        self.selected = (self.selected + 1).min(last);
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// The highlighted action.
+    pub fn chosen(&self) -> Option<Action> {
+        self.matches().get(self.selected).map(|&a| a.clone())
+    }
+}
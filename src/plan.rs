@@ -0,0 +1,111 @@
+use std::fmt;
+
+use crate::status::TunnelInfo;
+use crate::tunnel::TunnelSpec;
+
+/// What `rtun up` does with a tunnel of the profiles, given the running
+/// instance. It only opens tunnels on free ports, and closes none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Nothing runs on its local port, so it opens.
+    Open(TunnelSpec),
+    /// Another tunnel runs on its local port and stays, so it does not open.
+    Blocked {
+        running: TunnelInfo,
+        wanted: TunnelSpec,
+    },
+}
+
+/// `host -> remote_host local:remote`, as [`TunnelSpec`] prints itself, with
+/// the name if any.
+fn describe_running(tunnel: &TunnelInfo) -> String {
+    let mut hops = vec![tunnel.host.as_str()];
+    hops.extend(tunnel.remote_host.as_deref());
+    let mut text = format!(
+        "{} {}:{}",
+        hops.join(" -> "),
+        tunnel.local_port,
+        tunnel.remote_port
+    );
+    if let Some(name) = &tunnel.name {
+        text.push_str(&format!(" ({})", name));
+    }
+    text
+}
+
+fn describe_wanted(spec: &TunnelSpec) -> String {
+    match &spec.name {
+        Some(name) => format!("{} ({})", spec, name),
+        None => spec.to_string(),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Open(spec) => write!(f, "+ {}", describe_wanted(spec)),
+            Change::Blocked { running, wanted } => write!(
+                f,
+                "! {}\n    keeps the port from {}",
+                describe_running(running),
+                describe_wanted(wanted)
+            ),
+        }
+    }
+}
+
+/// What `rtun up` would do to the running instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    /// Tunnels that already run as the profiles have them.
+    pub unchanged: usize,
+}
+
+/// Whether `running` is the tunnel `spec`, as far as the running instance
+/// tells.
+fn same(running: &TunnelInfo, spec: &TunnelSpec) -> bool {
+    running.host == spec.host
+        && running.remote_port == spec.remote_port
+        && running.remote_host == spec.remote_host
+        && running.name == spec.name
+        && running.label == spec.label
+}
+
+/// Compares the tunnels the profiles want with those running, by local port.
+/// Running tunnels in none of the profiles are left alone, so they are not
+/// listed.
+pub fn plan(wanted: &[TunnelSpec], running: &[TunnelInfo]) -> Plan {
+    let mut plan = Plan::default();
+    for spec in wanted {
+        match running.iter().find(|t| t.local_port == spec.local_port) {
+            Some(tunnel) if same(tunnel, spec) => plan.unchanged += 1,
+            Some(tunnel) => plan.changes.push(Change::Blocked {
+                running: tunnel.clone(),
+                wanted: spec.clone(),
+            }),
+            None => plan.changes.push(Change::Open(spec.clone())),
+        }
+    }
+    plan
+}
+
+impl Plan {
+    /// `1 to open, 1 blocked, 3 unchanged`.
+    pub fn summary(&self) -> String {
+        if self.changes.is_empty() {
+            return format!("No changes, {} unchanged", self.unchanged);
+        }
+        let opened = self
+            .changes
+            .iter()
+            .filter(|c| matches!(c, Change::Open(_)))
+            .count();
+        format!(
+            "{} to open, {} blocked, {} unchanged",
+            opened,
+            self.changes.len() - opened,
+            self.unchanged
+        )
+    }
+}
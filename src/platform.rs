@@ -0,0 +1,66 @@
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::doctor;
+
+/// Where Android keeps its own shell, found even when PATH has no `sh`.
+const ANDROID_SHELL: &str = "/system/bin/sh";
+
+/// Programs putting their stdin on the clipboard, tried in order.
+const COPY_COMMANDS: [&[&str]; 5] = [
+    &["pbcopy"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["termux-clipboard-set"],
+];
+
+/// Whether rtun runs in Termux on Android, which sets `TERMUX_VERSION` and
+/// keeps its programs under `PREFIX` in the app's data directory.
+pub fn is_termux() -> bool {
+    env::var_os("TERMUX_VERSION").is_some()
+        || env::var("PREFIX").is_ok_and(|prefix| prefix.contains("com.termux"))
+}
+
+/// `value` as one word for `sh`.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The shell hooks, alerts and commands run with. Usually `sh` from PATH;
+/// shortcuts and boot scripts on Android can start rtun with a PATH lacking
+/// it, so Termux's `$PREFIX/bin/sh` and Android's own shell come next.
+pub fn shell() -> PathBuf {
+    if let Some(path) = doctor::find_program("sh") {
+        return path;
+    }
+    env::var_os("PREFIX")
+        .map(|prefix| Path::new(&prefix).join("bin/sh"))
+        .into_iter()
+        .chain([PathBuf::from(ANDROID_SHELL)])
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from("sh"))
+}
+
+/// Puts `text` on the clipboard with the first of [`COPY_COMMANDS`] that
+/// works. Returns false when none did.
+pub fn copy_to_clipboard(text: &str) -> bool {
+    COPY_COMMANDS.iter().any(|command| {
+        let Ok(mut child) = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            return false;
+        };
+        let written = child
+            .stdin
+            .take()
+            .is_some_and(|mut stdin| stdin.write_all(text.as_bytes()).is_ok());
+        child.wait().is_ok_and(|status| status.success()) && written
+    })
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+
+use crate::config;
+use crate::errors::{ErrorCode, Failure};
+
+/// Name of the file at the root of a git repository mapping its branches
+/// and worktrees to profiles.
+pub const PROJECT_FILE: &str = ".rtun.toml";
+
+/// `.rtun.toml`: profiles by branch and by worktree directory name, with
+/// `*` and `?` as wildcards.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub branches: BTreeMap<String, String>,
+    pub worktrees: BTreeMap<String, String>,
+}
+
+/// The profile a repository maps its checkout to, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub profile: String,
+    /// The branch or worktree name that matched.
+    pub name: String,
+    pub pattern: String,
+}
+
+/// Whether `name` matches the shell pattern `pattern`, where `*` also
+/// matches `/` so that `release/*` covers `release/1.2/hotfix`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was seen and how much of `name` it took.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The mapping for `name` in `mappings`: its own entry, otherwise the
+/// longest matching pattern.
+fn best<'a>(mappings: &'a BTreeMap<String, String>, name: &str) -> Option<(&'a str, &'a str)> {
+    if let Some((pattern, profile)) = mappings.get_key_value(name) {
+        return Some((pattern, profile));
+    }
+    mappings
+        .iter()
+        .filter(|(pattern, _)| matches(pattern, name))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(pattern, profile)| (pattern.as_str(), profile.as_str()))
+}
+
+impl ProjectConfig {
+    /// The profile for a checkout of `branch`, `None` when detached, in the
+    /// worktree directory `worktree`. Worktrees are more specific than
+    /// branches, so they win.
+    pub fn select(&self, branch: Option<&str>, worktree: &str) -> Option<Selection> {
+        let selection = |name: &str, (pattern, profile): (&str, &str)| Selection {
+            profile: profile.to_string(),
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+        };
+        best(&self.worktrees, worktree)
+            .map(|mapping| selection(worktree, mapping))
+            .or_else(|| {
+                let branch = branch?;
+                best(&self.branches, branch).map(|mapping| selection(branch, mapping))
+            })
+    }
+}
+
+/// Reads `.rtun.toml` at `path`, reporting mistakes like the config file.
+pub fn parse(contents: &str, path: &Path) -> Result<ProjectConfig> {
+    toml::from_str(contents)
+        .map_err(|e| Failure::new(ErrorCode::Config, config::diagnose(path, contents, &e)).into())
+}
+
+/// The first line `git` prints for `args` in `dir`, if it succeeds.
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().next().map(str::to_string)
+}
+
+/// Root of the git checkout `dir` is in, and its branch unless detached.
+fn checkout(dir: &Path) -> Option<(PathBuf, Option<String>)> {
+    let root = git(dir, &["rev-parse", "--show-toplevel"])?;
+    // Unlike `rev-parse --abbrev-ref`, this works before the first commit.
+    let branch = git(dir, &["symbolic-ref", "--quiet", "--short", "HEAD"]);
+    Some((PathBuf::from(root), branch))
+}
+
+/// The profile `.rtun.toml` maps the git checkout `dir` is in to, if it is
+/// in one that has the file. The profile is not checked against the config.
+pub fn detect(dir: &Path) -> Result<Option<Selection>> {
+    let Some((root, branch)) = checkout(dir) else {
+        return Ok(None);
+    };
+    let path = root.join(PROJECT_FILE);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            let message = format!("Could not read {}: {}", path.display(), e);
+            return Err(Failure::new(ErrorCode::Config, message).into());
+        }
+    };
+    let worktree = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(parse(&contents, &path)?.select(branch.as_deref(), &worktree))
+}
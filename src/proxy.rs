@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// Requests with larger headers are refused rather than buffered.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Service names and the local ports of the tunnels serving them.
+#[derive(Debug, Default)]
+pub struct Routes(RwLock<BTreeMap<String, u16>>);
+
+impl Routes {
+    pub fn insert(&self, name: &str, local_port: u16) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_lowercase(), local_port);
+    }
+
+    /// Drops `name` if it still goes to `local_port`, rather than to a
+    /// tunnel opened under the same name since.
+    pub fn remove(&self, name: &str, local_port: u16) {
+        let mut routes = self.0.write().unwrap_or_else(|e| e.into_inner());
+        let name = name.to_lowercase();
+        if routes.get(&name) == Some(&local_port) {
+            routes.remove(&name);
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<u16> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .copied()
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Serves plain HTTP on `port`, sending requests for `<name>.localhost` to
+/// the tunnel registered as `name`.
+pub async fn serve(port: u16, routes: Arc<Routes>) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    tokio::spawn(async move {
+        loop {
+            if let Ok((inbound, _)) = listener.accept().await {
+                tokio::spawn(route(inbound, routes.clone()));
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reads the request head, up to and including the blank line.
+async fn read_head(inbound: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 4096];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD {
+            return None;
+        }
+        let n = inbound.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Some(head)
+}
+
+/// The service name in the `Host` header, e.g. `grafana` for
+/// `grafana.localhost:8000`.
+fn service_name(head: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(head);
+    let host = head.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("host").then(|| value.trim())
+    })?;
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    host.strip_suffix(".localhost").map(str::to_lowercase)
+}
+
+async fn respond(inbound: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = inbound.write_all(response.as_bytes()).await;
+}
+
+async fn route(mut inbound: TcpStream, routes: Arc<Routes>) {
+    let Some(head) = read_head(&mut inbound).await else {
+        return;
+    };
+    let Some(port) = service_name(&head).and_then(|name| routes.get(&name)) else {
+        let body = format!(
+            "rtun: unknown service. Known services: {}\n",
+            routes.names().join(", ")
+        );
+        respond(&mut inbound, "404 Not Found", &body).await;
+        return;
+    };
+    let Ok(mut outbound) = TcpStream::connect(("127.0.0.1", port)).await else {
+        respond(&mut inbound, "502 Bad Gateway", "rtun: tunnel is not up\n").await;
+        return;
+    };
+    if outbound.write_all(&head).await.is_err() {
+        return;
+    }
+    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+}
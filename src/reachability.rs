@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::process::Command;
+
+use crate::config::SshClient;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Host name and port ssh would connect to for `host`, as reported by
+/// `ssh -G`. `None` when ssh goes through a proxy or cannot tell.
+pub async fn resolve(client: &SshClient, host: &str) -> Option<(String, u16)> {
+    if !client.is_openssh() {
+        return None;
+    }
+    let output = Command::new(client.program())
+        .arg("-G")
+        .arg(host)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut hostname = None;
+    let mut port = 22;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match line.split_once(' ') {
+            Some(("hostname", value)) => hostname = Some(value.to_string()),
+            Some(("port", value)) => port = value.parse().ok()?,
+            Some(("proxyjump" | "proxycommand", value)) if value != "none" => return None,
+            _ => {}
+        }
+    }
+    Some((hostname?, port))
+}
+
+/// Checks that the ssh server of `host` resolves and accepts connections,
+/// describing the problem when it does not. Skipped for clients other than
+/// OpenSSH, which cannot tell where they connect to.
+pub async fn check(client: &SshClient, host: &str) -> Result<(), String> {
+    let Some((hostname, port)) = resolve(client, host).await else {
+        return Ok(());
+    };
+    let addresses = match tokio::time::timeout(
+        CHECK_TIMEOUT,
+        tokio::net::lookup_host((hostname.as_str(), port)),
+    )
+    .await
+    {
+        Ok(Ok(addresses)) => addresses,
+        Ok(Err(e)) => return Err(format!("DNS lookup failed for {}: {}", hostname, e)),
+        Err(_) => return Err(format!("DNS lookup timed out for {}", hostname)),
+    };
+    let mut error = format!("{} did not resolve to any address", hostname);
+    for address in addresses {
+        match tokio::time::timeout(CHECK_TIMEOUT, TcpStream::connect(address)).await {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(e)) => error = format!("Host unreachable: {}:{}: {}", hostname, port, e),
+            Err(_) => error = format!("Host unreachable: {}:{}: timed out", hostname, port),
+        }
+    }
+    Err(error)
+}
@@ -0,0 +1,52 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::status::{Health, TunnelInfo};
+
+/// How long a local port gets to take a connection while checked.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Parses a timeout such as `30s`, `2m` or `1h`; plain numbers are seconds.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => {
+            return Err(format!(
+                "'{}' is not a timeout such as 30s, 2m or 1h",
+                value
+            ))
+        }
+    };
+    number
+        .parse::<u64>()
+        .map(|n| Duration::from_secs(n * seconds))
+        .map_err(|_| format!("'{}' is not a timeout such as 30s, 2m or 1h", value))
+}
+
+fn accepts(port: u16) -> bool {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).is_ok()
+}
+
+/// Those of `ports`, or of the ports of all `tunnels` when empty, whose
+/// tunnel is missing, not up yet, or up but not taking connections.
+pub fn waiting_for(tunnels: &[TunnelInfo], ports: &[u16]) -> Vec<u16> {
+    let ports = match ports.is_empty() {
+        true => tunnels.iter().map(|t| t.local_port).collect(),
+        false => ports.to_vec(),
+    };
+    ports
+        .into_iter()
+        .filter(|port| {
+            let tunnel = tunnels.iter().find(|t| t.local_port == *port);
+            !tunnel.is_some_and(|t| t.health == Health::Up && accepts(t.local_port))
+        })
+        .collect()
+}
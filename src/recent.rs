@@ -0,0 +1,126 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::tunnel::TunnelSpec;
+
+const RECENT_PATH: &str = ".rtun/recent.json";
+/// Tunnels offered at the top of the new-tunnel flow, unless more are pinned.
+const QUICK_LIMIT: usize = 5;
+/// Quick entries get the keys `1` to `9`.
+const QUICK_MAX: usize = 9;
+
+// The TUI and the control socket open tunnels concurrently.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// A tunnel opened from the new-tunnel flow, as recorded in
+/// `~/.rtun/recent.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recent {
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// How often it was opened.
+    pub opens: u32,
+    pub last_opened: DateTime<Utc>,
+    /// Always offered first, however rarely it is opened.
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl Recent {
+    pub fn spec(&self) -> TunnelSpec {
+        TunnelSpec::new(&self.host, self.local_port, self.remote_port)
+    }
+
+    fn is(&self, host: &str, local_port: u16, remote_port: u16) -> bool {
+        self.host == host && self.local_port == local_port && self.remote_port == remote_port
+    }
+}
+
+/// As typed in the new-tunnel flow, `HOST LOCAL:REMOTE`.
+impl fmt::Display for Recent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}:{}", self.host, self.local_port, self.remote_port)
+    }
+}
+
+/// The entries worth offering, best first: the pinned ones, then the most
+/// opened, the latest first among equals.
+pub fn quick(entries: &[Recent]) -> Vec<Recent> {
+    let mut entries = entries.to_vec();
+    entries.sort_by(|a, b| {
+        b.pinned
+            .cmp(&a.pinned)
+            .then(b.opens.cmp(&a.opens))
+            .then(b.last_opened.cmp(&a.last_opened))
+    });
+    let pinned = entries.iter().filter(|entry| entry.pinned).count();
+    entries.truncate(pinned.clamp(QUICK_LIMIT, QUICK_MAX));
+    entries
+}
+
+fn recent_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(RECENT_PATH))
+}
+
+/// Everything recorded, in no particular order.
+pub fn load() -> Vec<Recent> {
+    recent_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write(entries: &[Recent]) -> std::io::Result<()> {
+    let path = recent_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(tmp, path)
+}
+
+fn update(f: impl FnOnce(&mut Vec<Recent>)) {
+    let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = load();
+    f(&mut entries);
+    let _ = write(&entries);
+}
+
+/// Counts an opening of `spec`.
+pub fn record(spec: &TunnelSpec) {
+    update(|entries| {
+        let (host, local, remote) = (&spec.host, spec.local_port, spec.remote_port);
+        match entries.iter_mut().find(|e| e.is(host, local, remote)) {
+            Some(entry) => {
+                entry.opens += 1;
+                entry.last_opened = Utc::now();
+            }
+            None => entries.push(Recent {
+                host: host.clone(),
+                local_port: local,
+                remote_port: remote,
+                opens: 1,
+                last_opened: Utc::now(),
+                pinned: false,
+            }),
+        }
+    });
+}
+
+/// Pins `entry`, or unpins it when it is pinned.
+pub fn toggle_pin(entry: &Recent) {
+    update(|entries| {
+        let (host, local, remote) = (&entry.host, entry.local_port, entry.remote_port);
+        if let Some(e) = entries.iter_mut().find(|e| e.is(host, local, remote)) {
+            e.pinned = !e.pinned;
+        }
+    });
+}
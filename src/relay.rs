@@ -0,0 +1,707 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::activation;
+use crate::capture::Capture;
+use crate::history::{self, EventKind};
+use crate::httplog::{AccessLog, Exchange, Request};
+use crate::sniff::{self, Protocol};
+use crate::socks::{Destination, Destinations, Handshake};
+use crate::tunnel::{RemoteTarget, TunnelSpec};
+
+/// Throughput samples kept.
+const SAMPLES: usize = 300;
+/// How long a client of a lazy tunnel waits for the ssh session it woke up.
+const BACKEND_WAIT: Duration = Duration::from_secs(30);
+const BACKEND_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a client turned away by `allow` is noted in the history, per
+/// address.
+const REJECTED_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A range of client addresses, written as `10.0.0.0/8`, `fd00::/8`, or a
+/// single address.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    pub network: IpAddr,
+    pub prefix: u8,
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.as_str(), None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid address in '{}'", value))?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| format!("Invalid prefix length in '{}'", value))?,
+            None => bits,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual-stack listeners see IPv4 clients as ::ffff:a.b.c.d.
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether `allow` lets `ip` connect. An empty list lets everyone in, and
+/// this machine is always let in, for health checks and TLS termination.
+pub fn is_allowed(allow: &[Cidr], ip: IpAddr) -> bool {
+    allow.is_empty()
+        || ip.to_canonical().is_loopback()
+        || allow.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// Connection counters of one destination behind a relay.
+#[derive(Debug)]
+pub struct TargetStats {
+    pub target: String,
+    active: AtomicUsize,
+    total: AtomicU64,
+}
+
+impl TargetStats {
+    /// Connections currently open.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Connections accepted since the tunnel was opened.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+/// A client connected through a relay.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub id: u64,
+    pub peer: SocketAddr,
+    pub since: Instant,
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+    /// Shared, as the handle itself cannot be cloned with every tokio rtun
+    /// builds with.
+    abort: Option<Arc<AbortHandle>>,
+}
+
+impl Client {
+    /// Bytes the client sent through the tunnel.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes the client got back.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct RelayStats {
+    pub targets: Vec<TargetStats>,
+    clients: Mutex<BTreeMap<u64, Client>>,
+    next_client: AtomicU64,
+    protocol: Mutex<Option<Protocol>>,
+    /// Set when the tunnel logs HTTP requests.
+    access_log: Option<Arc<AccessLog>>,
+    /// Set when the tunnel is a SOCKS proxy.
+    destinations: Option<Arc<Destinations>>,
+    capture: Mutex<Option<Capture>>,
+    /// File of the last capture that stopped, on its own or not.
+    captured: Mutex<Option<PathBuf>>,
+    /// Bytes relayed either way since the tunnel was opened.
+    transferred: AtomicU64,
+    throughput: Mutex<VecDeque<u64>>,
+}
+
+/// A capture in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capturing {
+    pub path: PathBuf,
+    pub size: u64,
+    pub elapsed: Duration,
+    pub limit: Duration,
+}
+
+impl RelayStats {
+    /// What the last connection that said anything spoke.
+    pub fn protocol(&self) -> Option<Protocol> {
+        self.protocol
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// HTTP requests seen lately, oldest first, if the tunnel logs them.
+    pub fn requests(&self) -> Option<Vec<Request>> {
+        self.access_log.as_ref().map(|log| log.requests())
+    }
+
+    /// Hosts clients reached through the tunnel lately, the most recent
+    /// first, if it is a SOCKS proxy.
+    pub fn destinations(&self) -> Option<Vec<Destination>> {
+        self.destinations.as_ref().map(|log| log.recent())
+    }
+
+    /// Starts writing the traffic to `capture`, until it reaches its limits
+    /// or [`RelayStats::stop_capture`].
+    pub fn start_capture(&self, capture: Capture) {
+        let mut current = self.capture.lock().unwrap_or_else(|e| e.into_inner());
+        *current = Some(capture);
+    }
+
+    /// Stops capturing, returning the file written, if there was a capture.
+    pub fn stop_capture(&self) -> Option<io::Result<PathBuf>> {
+        let capture = self
+            .capture
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()?;
+        let finished = capture.finish();
+        if let Ok(path) = &finished {
+            *self.captured.lock().unwrap_or_else(|e| e.into_inner()) = Some(path.clone());
+        }
+        Some(finished)
+    }
+
+    /// The capture in progress, stopping it first if it reached its limits.
+    pub fn capturing(&self) -> Option<Capturing> {
+        let capture = self.capture.lock().unwrap_or_else(|e| e.into_inner());
+        match capture.as_ref()? {
+            capture if !capture.is_over() => Some(Capturing {
+                path: capture.path.clone(),
+                size: capture.size,
+                elapsed: capture.started.elapsed(),
+                limit: capture.max_duration,
+            }),
+            _ => {
+                drop(capture);
+                self.stop_capture();
+                None
+            }
+        }
+    }
+
+    /// File of the last capture, once stopped.
+    pub fn captured(&self) -> Option<PathBuf> {
+        self.captured
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn record(&self, from: SocketAddr, to: SocketAddr, seq: u64, ack: u64, payload: &[u8]) {
+        let mut capture = self.capture.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(current) = capture.as_mut() else {
+            return;
+        };
+        if current.packet(from, to, seq, ack, payload).is_err() || current.is_over() {
+            // Without waiting on the writer, as this runs while relaying.
+            let path = capture.take().map(Capture::close);
+            drop(capture);
+            *self.captured.lock().unwrap_or_else(|e| e.into_inner()) = path;
+        }
+    }
+
+    /// Bytes relayed either way since the tunnel was opened.
+    pub fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Bytes relayed per sample interval lately, oldest first.
+    pub fn throughput(&self) -> Vec<u64> {
+        let throughput = self.throughput.lock().unwrap_or_else(|e| e.into_inner());
+        throughput.iter().copied().collect()
+    }
+
+    /// Number of clients connected right now.
+    pub fn open(&self) -> usize {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Clients connected right now, oldest first.
+    pub fn clients(&self) -> Vec<Client> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.values().cloned().collect()
+    }
+
+    /// Drops the connection of the client `id`, returning whether it was
+    /// still connected.
+    pub fn kill(&self, id: u64) -> bool {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        match clients.remove(&id) {
+            Some(client) => {
+                if let Some(abort) = client.abort {
+                    abort.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Forgets a client once its connection is over, however it ended.
+struct Tracked {
+    stats: Arc<RelayStats>,
+    id: u64,
+    /// Index of the destination it was handed to, once one accepted.
+    target: Option<usize>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Some(i) = self.target {
+            self.stats.targets[i].active.fetch_sub(1, Ordering::Relaxed);
+        }
+        let mut clients = self.stats.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.remove(&self.id);
+    }
+}
+
+/// A client's side of a connection, counting the bytes through it and
+/// passing them on to captures.
+struct Counted {
+    inner: TcpStream,
+    peer: SocketAddr,
+    local: SocketAddr,
+    stats: Arc<RelayStats>,
+    read: Arc<AtomicU64>,
+    written: Arc<AtomicU64>,
+    exchange: Option<Exchange>,
+    handshake: Option<Handshake>,
+}
+
+impl AsyncRead for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[before..];
+            let seq = self.read.fetch_add(read.len() as u64, Ordering::Relaxed);
+            self.stats
+                .transferred
+                .fetch_add(read.len() as u64, Ordering::Relaxed);
+            let ack = self.written.load(Ordering::Relaxed);
+            self.stats.record(self.peer, self.local, seq, ack, read);
+            if let Some(exchange) = &mut self.exchange {
+                exchange.sent(read);
+            }
+            if let Some(handshake) = &mut self.handshake {
+                handshake.sent(read);
+            }
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for Counted {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            let seq = self.written.fetch_add(written as u64, Ordering::Relaxed);
+            self.stats
+                .transferred
+                .fetch_add(written as u64, Ordering::Relaxed);
+            let ack = self.read.load(Ordering::Relaxed);
+            self.stats
+                .record(self.local, self.peer, seq, ack, &buf[..written]);
+            if let Some(exchange) = &mut self.exchange {
+                exchange.received(&buf[..written]);
+            }
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Native forwarding: rtun listens on the tunnel's local port itself and
+/// hands each connection to one of the ssh forwards behind it, one forward
+/// per destination on a free local port, or the SOCKS proxy of ssh, which
+/// tells the destinations clients asked for. Clients outside the tunnel's
+/// `allow` list, or beyond its `max_connections`, are turned away. Those of
+/// lazy tunnels wait for the ssh session they wake up.
+///
+/// Stops accepting when dropped.
+pub struct Relay {
+    /// Local ports the ssh forwards listen on, in the order of the targets.
+    backends: Arc<Mutex<Arc<[u16]>>>,
+    /// Told of every client let in, for lazy tunnels to connect on.
+    demand: Arc<Notify>,
+    /// Whether clients wait for the ssh session, as those of lazy tunnels.
+    lazy: Arc<AtomicBool>,
+    pub stats: Arc<RelayStats>,
+    handle: JoinHandle<()>,
+    sampler: JoinHandle<()>,
+}
+
+impl Relay {
+    /// Starts relaying to `targets`, sampling the throughput every
+    /// `sample_interval`. Accepts on the socket systemd listens on the
+    /// local port with, if it does.
+    pub async fn start(
+        spec: &TunnelSpec,
+        targets: &[RemoteTarget],
+        sample_interval: Duration,
+    ) -> io::Result<Self> {
+        let listener = match activation::take(spec.local_port) {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)?
+            }
+            None => TcpListener::bind((spec.bind_address(), spec.local_port)).await?,
+        };
+        let backends = targets
+            .iter()
+            .map(|_| free_port())
+            .collect::<io::Result<Arc<[u16]>>>()?;
+        let backends = Arc::new(Mutex::new(backends));
+        let stats = Arc::new(RelayStats {
+            targets: targets
+                .iter()
+                .map(|target| TargetStats {
+                    target: target.to_string(),
+                    active: AtomicUsize::new(0),
+                    total: AtomicU64::new(0),
+                })
+                .collect(),
+            clients: Mutex::new(BTreeMap::new()),
+            next_client: AtomicU64::new(0),
+            protocol: Mutex::new(None),
+            access_log: spec.log_requests.then(Arc::default),
+            destinations: spec.socks.then(Arc::default),
+            capture: Mutex::new(None),
+            captured: Mutex::new(None),
+            transferred: AtomicU64::new(0),
+            throughput: Mutex::new(VecDeque::with_capacity(SAMPLES)),
+        });
+        let demand = Arc::new(Notify::new());
+        let lazy = Arc::new(AtomicBool::new(spec.lazy));
+        let handle = tokio::spawn(serve(
+            listener,
+            spec.clone(),
+            backends.clone(),
+            demand.clone(),
+            lazy.clone(),
+            stats.clone(),
+        ));
+        let sampler = tokio::spawn(sample(stats.clone(), sample_interval));
+        Ok(Relay {
+            backends,
+            demand,
+            lazy,
+            stats,
+            handle,
+            sampler,
+        })
+    }
+
+    /// Local ports the ssh forwards listen on, in the order of the targets.
+    pub fn backends(&self) -> Arc<[u16]> {
+        self.backends
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Resolves once a client connects, or right away if one connected since
+    /// the last call.
+    pub async fn demand(&self) {
+        self.demand.notified().await;
+    }
+
+    /// Has new clients wait for the ssh session to come up, or not, as the
+    /// tunnel turns lazy or not.
+    pub fn set_lazy(&self, lazy: bool) {
+        self.lazy.store(lazy, Ordering::Relaxed);
+    }
+
+    /// Stops accepting connections, leaving the open ones be. New clients
+    /// are refused from then on.
+    pub fn drain(&self) {
+        self.handle.abort();
+    }
+
+    /// Hands new connections to a fresh backend port from now on, for a
+    /// forward to another destination, while those connected already stay
+    /// on the old one. Returns the id of the first client on the new port;
+    /// only relays with a single backend can.
+    pub fn retarget(&self) -> io::Result<u64> {
+        let mut backends = self.backends.lock().unwrap_or_else(|e| e.into_inner());
+        if backends.len() != 1 {
+            return Err(io::Error::other(
+                "Load balanced tunnels cannot change their destination",
+            ));
+        }
+        *backends = Arc::from([free_port()?]);
+        Ok(self.stats.next_client.load(Ordering::Relaxed))
+    }
+
+    /// Moves the forwards to fresh backend ports, for ssh to try again after
+    /// another process took one of them first.
+    pub fn rebind(&self) -> io::Result<()> {
+        let mut backends = self.backends.lock().unwrap_or_else(|e| e.into_inner());
+        *backends = backends
+            .iter()
+            .map(|_| free_port())
+            .collect::<io::Result<Arc<[u16]>>>()?;
+        Ok(())
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.handle.abort();
+        self.sampler.abort();
+    }
+}
+
+async fn sample(stats: Arc<RelayStats>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+    let mut last = 0;
+    loop {
+        interval.tick().await;
+        let transferred = stats.transferred.load(Ordering::Relaxed);
+        let mut throughput = stats.throughput.lock().unwrap_or_else(|e| e.into_inner());
+        if throughput.len() == SAMPLES {
+            throughput.pop_front();
+        }
+        throughput.push_back(transferred - last);
+        last = transferred;
+    }
+}
+
+/// A port free right now. It is not held on to, as ssh binds it itself, so
+/// another process can take it in between; see [`Relay::rebind`].
+fn free_port() -> io::Result<u16> {
+    Ok(std::net::TcpListener::bind(("127.0.0.1", 0))?
+        .local_addr()?
+        .port())
+}
+
+async fn serve(
+    listener: TcpListener,
+    spec: TunnelSpec,
+    backends: Arc<Mutex<Arc<[u16]>>>,
+    demand: Arc<Notify>,
+    lazy: Arc<AtomicBool>,
+    stats: Arc<RelayStats>,
+) {
+    let mut next = 0;
+    // Set while at the limit, so a flood of clients is logged once.
+    let mut limited = false;
+    // When each address turned away was last logged, so a client retrying
+    // in a loop does not flood the history.
+    let mut rejected: HashMap<IpAddr, Instant> = HashMap::new();
+    loop {
+        let inbound = match listener.accept().await {
+            Ok((_, peer)) if !is_allowed(&spec.allow, peer.ip()) => {
+                let now = Instant::now();
+                if rejected
+                    .get(&peer.ip())
+                    .is_none_or(|logged| now - *logged >= REJECTED_LOG_INTERVAL)
+                {
+                    rejected.retain(|_, logged| now - *logged < REJECTED_LOG_INTERVAL);
+                    rejected.insert(peer.ip(), now);
+                    let detail = format!("rejected a connection from {}", peer.ip());
+                    history::record(EventKind::Rejected, &spec, Some(detail));
+                }
+                continue;
+            }
+            Ok((_, peer)) if spec.max_connections.is_some_and(|max| stats.open() >= max) => {
+                if !limited {
+                    let detail = format!(
+                        "rejected a connection from {}, {} are open already",
+                        peer.ip(),
+                        stats.open()
+                    );
+                    history::record(EventKind::Rejected, &spec, Some(detail));
+                }
+                limited = true;
+                continue;
+            }
+            Ok(inbound) => {
+                limited = false;
+                inbound
+            }
+            Err(_) => {
+                // Typically out of file descriptors; give connections a
+                // chance to close instead of spinning.
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let (inbound, peer) = inbound;
+        // Together, so that a client gets the backend of its id.
+        let (id, current) = {
+            let backends = backends.lock().unwrap_or_else(|e| e.into_inner());
+            let id = stats.next_client.fetch_add(1, Ordering::Relaxed);
+            (id, backends.clone())
+        };
+        let client = Client {
+            id,
+            peer,
+            since: Instant::now(),
+            sent: Arc::default(),
+            received: Arc::default(),
+            abort: None,
+        };
+        let Ok(local) = inbound.local_addr() else {
+            continue;
+        };
+        let inbound = Counted {
+            inner: inbound,
+            peer,
+            local,
+            stats: stats.clone(),
+            read: client.sent.clone(),
+            written: client.received.clone(),
+            exchange: stats
+                .access_log
+                .clone()
+                .map(|log| Exchange::new(peer.ip(), log)),
+            handshake: stats.destinations.clone().map(Handshake::new),
+        };
+        let tracked = Tracked {
+            stats: stats.clone(),
+            id: client.id,
+            target: None,
+        };
+        stats
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, client);
+        let task = tokio::spawn(forward(
+            inbound,
+            tracked,
+            next,
+            current.clone(),
+            lazy.load(Ordering::Relaxed),
+        ));
+        demand.notify_one();
+        // Unless it is over already.
+        if let Some(client) = stats
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&id)
+        {
+            client.abort = Some(Arc::new(task.abort_handle()));
+        }
+        next = (next + 1) % current.len();
+    }
+}
+
+/// Peeks at whichever side speaks first, leaving the bytes for the copy.
+async fn sniff(client: &TcpStream, server: &TcpStream) -> Option<Protocol> {
+    let mut sent = [0; 1024];
+    let mut received = [0; 16];
+    tokio::select! {
+        read = client.peek(&mut sent) => sniff::client(&sent[..read.ok()?]),
+        read = server.peek(&mut received) => sniff::server(&received[..read.ok()?]),
+    }
+}
+
+/// The backend at `first` if it accepts, or the next one that does. With
+/// `wait`, for lazy tunnels whose ssh session may still be coming up, tries
+/// again until one does or [`BACKEND_WAIT`] is over.
+async fn connect(first: usize, backends: &[u16], wait: bool) -> Option<(usize, TcpStream)> {
+    let deadline = Instant::now() + BACKEND_WAIT;
+    loop {
+        for i in (0..backends.len()).map(|offset| (first + offset) % backends.len()) {
+            if let Ok(outbound) = TcpStream::connect(("127.0.0.1", backends[i])).await {
+                return Some((i, outbound));
+            }
+        }
+        if !wait || Instant::now() >= deadline {
+            return None;
+        }
+        tokio::time::sleep(BACKEND_RETRY_INTERVAL).await;
+    }
+}
+
+/// Forwards `inbound` to the backend at `first`, or the next one that
+/// accepts when it is down.
+async fn forward(
+    mut inbound: Counted,
+    mut tracked: Tracked,
+    first: usize,
+    backends: Arc<[u16]>,
+    lazy: bool,
+) {
+    if let Some((i, mut outbound)) = connect(first, &backends, lazy).await {
+        let target = &tracked.stats.targets[i];
+        target.active.fetch_add(1, Ordering::Relaxed);
+        target.total.fetch_add(1, Ordering::Relaxed);
+        tracked.target = Some(i);
+        if let Some(protocol) = sniff(&inbound.inner, &outbound).await {
+            *tracked
+                .stats
+                .protocol
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(protocol);
+        }
+        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+    }
+}
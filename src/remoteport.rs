@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::config::SshClient;
+use crate::i18n;
+use crate::tunnel::RemoteTarget;
+use crate::wizard;
+
+/// How far from the forwarded port a listening one can be to be suggested.
+const SUGGEST_WITHIN: u16 = 10;
+
+/// Warnings about forwards to ports nothing listens on, by local port.
+static NOT_LISTENING: Mutex<BTreeMap<u16, String>> = Mutex::new(BTreeMap::new());
+
+/// The listening port closest to `port`, if one is near enough to be a typo.
+pub fn suggest(port: u16, listening: &[u16]) -> Option<u16> {
+    listening
+        .iter()
+        .copied()
+        .filter(|&p| p != port && p.abs_diff(port) <= SUGGEST_WITHIN)
+        .min_by_key(|&p| (p.abs_diff(port), p))
+}
+
+/// The warning for a forward to `port` of a host listening on `listening`,
+/// `None` when something listens there.
+pub fn warning(port: u16, listening: &[u16]) -> Option<String> {
+    if listening.contains(&port) {
+        return None;
+    }
+    Some(match suggest(port, listening) {
+        Some(near) => i18n::format(
+            "remote-not-listening-near",
+            &[("port", &port), ("near", &near)],
+        ),
+        None => i18n::format("remote-not-listening", &[("port", &port)]),
+    })
+}
+
+/// Whether `target` is the ssh host itself, the only destination whose
+/// listening ports it can list.
+pub(crate) fn on_host(target: &RemoteTarget) -> bool {
+    matches!(target.host.as_str(), "127.0.0.1" | "localhost" | "::1")
+}
+
+/// Asks `host` whether anything listens on the port `target` forwards to,
+/// keeping a warning for the tunnel on `local_port` when nothing does. Other
+/// destinations, and hosts that cannot be asked, are left alone.
+pub async fn check(client: SshClient, host: String, local_port: u16, target: RemoteTarget) {
+    if !on_host(&target) {
+        forget(local_port);
+        return;
+    }
+    let Ok(listening) = wizard::listening_ports(&client, &host).await else {
+        return;
+    };
+    let mut warnings = NOT_LISTENING.lock().unwrap_or_else(|e| e.into_inner());
+    match warning(target.port, &listening) {
+        Some(warning) => warnings.insert(local_port, warning),
+        None => warnings.remove(&local_port),
+    };
+}
+
+/// The warning [`check`] left for the tunnel on `local_port`, if any.
+pub fn not_listening(local_port: u16) -> Option<String> {
+    NOT_LISTENING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&local_port)
+        .cloned()
+}
+
+/// Drops the warning of the tunnel on `local_port`, once it is closed.
+pub fn forget(local_port: u16) {
+    NOT_LISTENING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&local_port);
+}
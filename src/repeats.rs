@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::errors::ErrorCode;
+use crate::i18n;
+
+/// How far back repeats of an error are counted.
+pub const WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How long a repeated error goes unreported at most.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The errors a tunnel ran into lately, so that one failing the same way on
+/// every reconnect is reported once in a while, with a count, instead of
+/// each time. The history log still gets every one of them in full.
+#[derive(Debug, Clone, Default)]
+pub struct Repeats {
+    error: Option<String>,
+    // Oldest first, only the last `WINDOW` of them.
+    seen: VecDeque<Instant>,
+    reported: Option<Instant>,
+}
+
+impl Repeats {
+    /// Counts `error` as seen at `now`, and tells whether it is worth
+    /// reporting: it is new, or went unreported for `REPORT_INTERVAL`.
+    pub fn see(&mut self, error: &str, now: Instant) -> bool {
+        if self.error.as_deref() != Some(error) {
+            self.error = Some(error.to_string());
+            self.seen.clear();
+            self.reported = None;
+        }
+        self.seen.push_back(now);
+        self.forget(now);
+        let due = self
+            .reported
+            .is_none_or(|reported| now.duration_since(reported) >= REPORT_INTERVAL);
+        if due {
+            self.reported = Some(now);
+        }
+        due
+    }
+
+    fn forget(&mut self, now: Instant) {
+        while self
+            .seen
+            .front()
+            .is_some_and(|seen| now.duration_since(*seen) > WINDOW)
+        {
+            self.seen.pop_front();
+        }
+    }
+
+    /// How many times the last error was seen in the `WINDOW` before `now`.
+    pub fn count(&self, now: Instant) -> usize {
+        self.seen
+            .iter()
+            .filter(|seen| now.duration_since(**seen) <= WINDOW)
+            .count()
+    }
+
+    /// `auth failed ×12 in last 5m` for an error seen more than once lately,
+    /// otherwise `error` as is.
+    pub fn summary(&self, error: &str, now: Instant) -> String {
+        let count = self.count(now);
+        if self.error.as_deref() != Some(error) || count < 2 {
+            return error.to_string();
+        }
+        i18n::format(
+            "repeats-summary",
+            &[
+                ("error", &short(error)),
+                ("count", &count),
+                ("minutes", &(WINDOW.as_secs() / 60)),
+            ],
+        )
+    }
+}
+
+/// A few words for `error` when its kind says enough, the error otherwise.
+fn short(error: &str) -> String {
+    match ErrorCode::of_tunnel_error(error) {
+        ErrorCode::HostUnreachable => i18n::text("repeats-host-unreachable"),
+        ErrorCode::AuthFailure => i18n::text("repeats-auth-failed"),
+        ErrorCode::PortConflict => i18n::text("repeats-port-in-use"),
+        _ => error.to_string(),
+    }
+}
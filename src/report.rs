@@ -0,0 +1,149 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+use crate::history::HistoryEntry;
+use crate::status::TunnelInfo;
+
+const REPORTS_PATH: &str = ".rtun/reports";
+/// Events of the history log a report ends with.
+pub const REPORT_EVENTS: usize = 50;
+// UTC, so reports read the same whoever they are handed over to.
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+/// Formats of `rtun report`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Headings, a table of the tunnels and a list of events
+    Markdown,
+    /// Indented lines, for chat messages and tickets without Markdown
+    Text,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Text => "txt",
+        }
+    }
+}
+
+fn up_for(tunnel: &TunnelInfo, now: DateTime<Utc>) -> String {
+    let Some(since) = tunnel.up_since else {
+        return String::new();
+    };
+    let seconds = (now - since).num_seconds().max(0);
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+fn name(tunnel: &TunnelInfo) -> String {
+    let mut name = format!(
+        "{} {}:{}",
+        tunnel.host, tunnel.local_port, tunnel.remote_port
+    );
+    if let Some(label) = tunnel.name.as_ref().or(tunnel.label.as_ref()) {
+        name.push_str(&format!(" ({})", label));
+    }
+    name
+}
+
+fn event(entry: &HistoryEntry) -> String {
+    let mut line = format!(
+        "{}  {}  {}  {} {}:{}",
+        entry.timestamp.format(TIME_FORMAT),
+        entry.event,
+        entry.user,
+        entry.host,
+        entry.local_port,
+        entry.remote_port
+    );
+    if let Some(detail) = &entry.detail {
+        line.push_str(&format!("  ({})", detail));
+    }
+    line
+}
+
+/// The tunnels of a running rtun and the events that led there, as of
+/// `now`, for handover notes and incident timelines.
+pub fn render(
+    format: ReportFormat,
+    tunnels: &[TunnelInfo],
+    events: &[HistoryEntry],
+    now: DateTime<Utc>,
+) -> String {
+    let mut report = String::new();
+    let time = now.format(TIME_FORMAT);
+    // Writing to a String cannot fail.
+    match format {
+        ReportFormat::Markdown => {
+            let _ = writeln!(report, "# rtun session report\n\nAs of {}.\n", time);
+            let _ = writeln!(report, "## Tunnels\n");
+            if tunnels.is_empty() {
+                let _ = writeln!(report, "None open.");
+            } else {
+                let _ = writeln!(report, "| Tunnel | Status | Up for | Error |");
+                let _ = writeln!(report, "| --- | --- | --- | --- |");
+            }
+            for tunnel in tunnels {
+                let error = tunnel.error.as_ref().map(|e| e.message.as_str());
+                let cells = [
+                    name(tunnel),
+                    tunnel.status.clone(),
+                    up_for(tunnel, now),
+                    error.unwrap_or_default().to_string(),
+                ]
+                .map(|cell| cell.replace('|', "\\|"));
+                let _ = writeln!(report, "| {} |", cells.join(" | "));
+            }
+            let _ = writeln!(report, "\n## Recent events\n");
+            for entry in events {
+                let _ = writeln!(report, "- {}", event(entry).replace('|', "\\|"));
+            }
+        }
+        ReportFormat::Text => {
+            let _ = writeln!(report, "rtun session report as of {}\n\nTunnels:", time);
+            if tunnels.is_empty() {
+                let _ = writeln!(report, "  none open");
+            }
+            for tunnel in tunnels {
+                let mut line = format!("  {}  {}", name(tunnel), tunnel.status);
+                let up = up_for(tunnel, now);
+                if !up.is_empty() {
+                    line.push_str(&format!(" for {}", up));
+                }
+                if let Some(error) = &tunnel.error {
+                    line.push_str(&format!(": {}", error.message));
+                }
+                let _ = writeln!(report, "{}", line);
+            }
+            let _ = writeln!(report, "\nRecent events:");
+            for entry in events {
+                let _ = writeln!(report, "  {}", event(entry));
+            }
+        }
+    }
+    report
+}
+
+/// Writes `report` to a new file under `~/.rtun/reports`, named after `now`.
+pub fn save(report: &str, format: ReportFormat, now: DateTime<Utc>) -> std::io::Result<PathBuf> {
+    let dir = env::home_dir()
+        .map(|home| home.join(REPORTS_PATH))
+        .ok_or(std::io::ErrorKind::NotFound)?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "{}.{}",
+        now.format("%Y-%m-%dT%H%M%SZ"),
+        format.extension()
+    ));
+    fs::write(&path, report)?;
+    Ok(path)
+}
@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, Weekday};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+/// When a tunnel should be open.
+///
+/// Either a daily time window:
+///
+/// ```toml
+/// schedule = { days = ["mon", "tue", "wed", "thu", "fri"], from = "09:00", until = "18:00" }
+/// ```
+///
+/// or a pair of cron expressions marking when to open and when to close:
+///
+/// ```toml
+/// schedule = { open = "0 9 * * Mon-Fri", close = "0 18 * * Mon-Fri" }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Schedule {
+    Window {
+        /// Days the window applies to; every day when empty.
+        #[serde(default)]
+        days: Vec<Weekday>,
+        from: String,
+        until: String,
+    },
+    Cron {
+        open: String,
+        close: String,
+    },
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .wrap_err_with(|| format!("Invalid time '{}', expected HH:MM", time))
+}
+
+fn parse_cron(expression: &str) -> Result<cron::Schedule> {
+    // The cron crate wants a seconds field; accept the usual five-field form too.
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&expression)
+        .map_err(|e| eyre!("Invalid cron expression '{}': {}", expression, e))
+}
+
+impl Schedule {
+    /// Checks that the times and expressions parse, so mistakes show up when
+    /// the config is loaded instead of silently keeping a tunnel closed.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Schedule::Window { from, until, .. } => {
+                parse_time(from)?;
+                parse_time(until)?;
+            }
+            Schedule::Cron { open, close } => {
+                parse_cron(open)?;
+                parse_cron(close)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self, now: DateTime<Local>) -> bool {
+        match self {
+            Schedule::Window { days, from, until } => {
+                let (Ok(from), Ok(until)) = (parse_time(from), parse_time(until)) else {
+                    return false;
+                };
+                let time = now.time();
+                // A window like 22:00-06:00 runs past midnight and belongs to
+                // the day it started on.
+                let (in_window, day) = if from <= until {
+                    (time >= from && time < until, now.weekday())
+                } else if time >= from {
+                    (true, now.weekday())
+                } else {
+                    (time < until, now.weekday().pred())
+                };
+                in_window && (days.is_empty() || days.contains(&day))
+            }
+            Schedule::Cron { open, close } => {
+                let (Ok(open), Ok(close)) = (parse_cron(open), parse_cron(close)) else {
+                    return false;
+                };
+                let last_open = open.after(&now).next_back();
+                let last_close = close.after(&now).next_back();
+                match (last_open, last_close) {
+                    (Some(opened), Some(closed)) => opened > closed,
+                    (Some(_), None) => true,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::tunnel::TunnelSpec;
+
+/// A line read from stdin with `--stdin`, for expect scripts and other
+/// programs driving rtun.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `add HOST LOCAL:REMOTE[:DEST] [# label]`, or the flag form of
+    /// [`TunnelSpec::parse`]
+    Add(Box<TunnelSpec>),
+    /// `close LOCAL_PORT`
+    Close(u16),
+    /// `restart LOCAL_PORT`
+    Restart(u16),
+    /// `up PROFILE`
+    Up(String),
+    /// `list`
+    List,
+    /// `yes` or `no`, to the tunnel waiting for confirmation.
+    Confirm(bool),
+    /// `code LOCAL_PORT CODE`, to the tunnel on the port asking for a code.
+    Code(u16, String),
+    /// `quit`
+    Quit,
+}
+
+fn port(argument: &str) -> Result<u16, String> {
+    argument
+        .parse()
+        .map_err(|_| format!("'{}' is not a port", argument))
+}
+
+impl Command {
+    /// Parses one line, or `None` for blank lines and `#` comments.
+    pub fn parse(line: &str) -> Option<Result<Command, String>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (name, argument) = match line.split_once(' ') {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        let command = match (name, argument) {
+            ("add", _) => TunnelSpec::parse(argument)
+                .map(|spec| Command::Add(Box::new(spec)))
+                .map_err(str::to_string),
+            ("close", _) => port(argument).map(Command::Close),
+            ("restart", _) => port(argument).map(Command::Restart),
+            ("up", "") => Err("Name a profile: up PROFILE".to_string()),
+            ("up", _) => Ok(Command::Up(argument.to_string())),
+            ("code", _) => match argument.split_once(' ') {
+                Some((local_port, code)) => {
+                    port(local_port).map(|p| Command::Code(p, code.trim().to_string()))
+                }
+                None => Err("Give the port and the code: code LOCAL_PORT CODE".to_string()),
+            },
+            ("list", "") => Ok(Command::List),
+            ("yes", "") => Ok(Command::Confirm(true)),
+            ("no", "") => Ok(Command::Confirm(false)),
+            ("quit", "") => Ok(Command::Quit),
+            _ => Err(format!("Unknown command '{}'", line)),
+        };
+        Some(command)
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::otp;
+use crate::platform;
+
+const SECRETS_PATH: &str = ".rtun/secrets.enc";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Service the keychain entries of rtun are filed under.
+const KEYCHAIN_SERVICE: &str = "rtun";
+/// Where the askpass programs of ssh claim passwords from the running rtun.
+const CLAIMS_DIR: &str = ".rtun/askpass";
+/// Passwords not claimed by then are dropped, as ssh logged in without them.
+const CLAIM_TTL: Duration = Duration::from_secs(300);
+
+/// Set on ssh processes so that rtun, run as their askpass program, answers
+/// the prompt with the password it claims with the value, once, over the
+/// socket in [`ASKPASS_SOCKET`]. The password itself never goes into the
+/// environment, which ssh passes on to every command it runs.
+pub const ASKPASS_TOKEN: &str = "RTUN_ASKPASS_TOKEN";
+/// The owner-only socket of the rtun that set [`ASKPASS_TOKEN`].
+pub const ASKPASS_SOCKET: &str = "RTUN_ASKPASS_SOCKET";
+/// Answers with the output of the command instead.
+pub const ASKPASS_COMMAND: &str = "RTUN_ASKPASS_COMMAND";
+/// Set on every ssh process rtun starts, so that rtun knows it runs as their
+/// askpass program even without a password to answer with.
+pub const ASKPASS: &str = "RTUN_ASKPASS";
+
+/// Written to the stderr of ssh when it starts waiting on the user, such as
+/// for a touch of a FIDO2 security key, followed by what it waits for.
+pub const PROMPT_START: &str = "rtun prompt: ";
+/// Written once ssh stopped waiting.
+pub const PROMPT_END: &str = "rtun prompt done";
+
+// Secrets of the store unlocked at startup.
+static UNLOCKED: RwLock<Option<BTreeMap<String, String>>> = RwLock::new(None);
+// Passwords waiting for the askpass program of an ssh process, by token,
+// with when they were handed out.
+static CLAIMS: Mutex<BTreeMap<String, (String, Instant)>> = Mutex::new(BTreeMap::new());
+// The socket they are claimed over, once listened on.
+static CLAIMS_SOCKET: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Where the password for an ssh host comes from.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Secret {
+    /// An entry of the encrypted store, set with `rtun secret set`.
+    Store(String),
+    /// An entry of the macOS Keychain, the Secret Service (GNOME Keyring,
+    /// KWallet) or the Windows Credential Manager, set with
+    /// `rtun secret set --keychain`.
+    Keychain(String),
+    /// Output of a shell command, such as `op read op://dev/bastion/password`,
+    /// run every time the password is needed.
+    Command(String),
+}
+
+/// Passwords and passphrases encrypted with a passphrase in
+/// `~/.rtun/secrets.enc`.
+pub struct Store {
+    passphrase: String,
+    secrets: BTreeMap<String, String>,
+}
+
+fn store_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(SECRETS_PATH))
+}
+
+/// Whether anything was ever saved to the store.
+pub fn store_exists() -> bool {
+    store_path().is_some_and(|path| path.exists())
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| eyre!("Could not derive the key: {}", e))?;
+    Ok(ChaCha20Poly1305::new(&key))
+}
+
+impl Store {
+    /// Decrypts the store with `passphrase`, or starts an empty one that
+    /// will be encrypted with it.
+    pub fn open(passphrase: &str) -> Result<Self> {
+        let path = store_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+        let secrets = match fs::read(&path) {
+            Ok(contents) if contents.len() > SALT_LEN + NONCE_LEN => {
+                let (salt, rest) = contents.split_at(SALT_LEN);
+                let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+                let plaintext = cipher(passphrase, salt)?
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| eyre!("Wrong passphrase"))?;
+                serde_json::from_slice(&plaintext).wrap_err("Corrupt secret store")?
+            }
+            Ok(_) => return Err(eyre!("Corrupt secret store {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", path.display())),
+        };
+        Ok(Store {
+            passphrase: passphrase.to_string(),
+            secrets,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.secrets.get(name).map(String::as_str)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.secrets.keys()
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.secrets.insert(name.to_string(), value.to_string());
+    }
+
+    /// Forgets the secret `name`, returning whether there was one.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.secrets.remove(name).is_some()
+    }
+
+    /// Encrypts the store to disk, readable by the user only.
+    pub fn save(&self) -> Result<()> {
+        let path = store_path().ok_or_else(|| eyre!("Could not determine the home directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut salt = [0u8; SALT_LEN];
+        OsRng
+            .try_fill_bytes(&mut salt)
+            .map_err(|e| eyre!("Could not generate a salt: {}", e))?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(&self.passphrase, &salt)?
+            .encrypt(&nonce, serde_json::to_vec(&self.secrets)?.as_slice())
+            .map_err(|_| eyre!("Could not encrypt the secret store"))?;
+        let tmp = path.with_extension("enc.tmp");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&salt)?;
+        file.write_all(&nonce)?;
+        file.write_all(&ciphertext)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Makes the secrets available to tunnels for the rest of the run.
+    pub fn unlock(self) {
+        *UNLOCKED.write().unwrap_or_else(|e| e.into_inner()) = Some(self.secrets);
+    }
+}
+
+fn keychain_entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, name).wrap_err("Could not open the keychain")
+}
+
+/// Saves `value` as `name` in the OS keychain.
+pub fn set_in_keychain(name: &str, value: &str) -> Result<()> {
+    keychain_entry(name)?
+        .set_password(value)
+        .wrap_err_with(|| format!("Could not save '{}' to the keychain", name))
+}
+
+/// Deletes `name` from the OS keychain.
+pub fn remove_from_keychain(name: &str) -> Result<()> {
+    keychain_entry(name)?
+        .delete_credential()
+        .wrap_err_with(|| format!("Could not delete '{}' from the keychain", name))
+}
+
+/// The value of `secret`, from the store unlocked at startup or the
+/// keychain.
+pub fn resolve(secret: &Secret) -> Result<String> {
+    match secret {
+        Secret::Store(name) => {
+            let unlocked = UNLOCKED.read().unwrap_or_else(|e| e.into_inner());
+            let secrets = unlocked
+                .as_ref()
+                .ok_or_else(|| eyre!("The secret store is locked"))?;
+            secrets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| eyre!("No secret named '{}' in the store", name))
+        }
+        Secret::Keychain(name) => keychain_entry(name)?
+            .get_password()
+            .wrap_err_with(|| format!("Could not read '{}' from the keychain", name)),
+        Secret::Command(command) => run_command(command),
+    }
+}
+
+fn run_command(command: &str) -> Result<String> {
+    let output = Command::new(platform::shell())
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .wrap_err_with(|| format!("Could not run '{}'", command))?;
+    if !output.status.success() {
+        return Err(eyre!("'{}' failed with {}", command, output.status));
+    }
+    let stdout = String::from_utf8(output.stdout).wrap_err("The password is not UTF-8")?;
+    Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+}
+
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+/// Passes on a notification of ssh, such as "Confirm user presence for key
+/// ED25519-SK ...", until ssh is done waiting and terminates us.
+fn notify(message: &str) -> ! {
+    unsafe {
+        libc::signal(
+            libc::SIGTERM,
+            ignore_signal as *const () as libc::sighandler_t,
+        )
+    };
+    // Our stderr is the one of ssh, which the supervisor reads.
+    eprintln!("{}{}", PROMPT_START, message);
+    unsafe { libc::pause() };
+    eprintln!("{}", PROMPT_END);
+    std::process::exit(0)
+}
+
+/// The answer to give when rtun runs as the askpass program of ssh, if it
+/// does.
+pub fn askpass() -> Option<Result<String>> {
+    env::var_os(ASKPASS)?;
+    if env::var("SSH_ASKPASS_PROMPT").as_deref() == Ok("none") {
+        notify(&env::args().nth(1).unwrap_or_default());
+    }
+    if let Some(prompt) = env::args().nth(1).filter(|p| otp::is_code_prompt(p)) {
+        return Some(otp::ask(&prompt));
+    }
+    if let (Ok(socket), Ok(token)) = (env::var(ASKPASS_SOCKET), env::var(ASKPASS_TOKEN)) {
+        return Some(claim(Path::new(&socket), &token));
+    }
+    Some(match env::var(ASKPASS_COMMAND) {
+        Ok(command) => run_command(&command),
+        Err(_) => Err(eyre!("No password configured for this host")),
+    })
+}
+
+/// Environment making ssh ask rtun for `secret`, if any, instead of
+/// prompting, and tell rtun when it waits on the user.
+pub fn askpass_env(secret: Option<&Secret>) -> Result<Vec<(&'static str, String)>> {
+    let exe = env::current_exe().wrap_err("Could not find the rtun executable")?;
+    let mut vars = vec![
+        ("SSH_ASKPASS", exe.to_string_lossy().into_owned()),
+        ("SSH_ASKPASS_REQUIRE", "force".to_string()),
+        (ASKPASS, "1".to_string()),
+    ];
+    match secret {
+        // Run when ssh asks, so the secret is only fetched when needed.
+        Some(Secret::Command(command)) => vars.push((ASKPASS_COMMAND, command.clone())),
+        Some(secret) => {
+            let password = resolve(secret)?;
+            let socket = claims_socket()?;
+            vars.push((ASKPASS_SOCKET, socket.to_string_lossy().into_owned()));
+            vars.push((ASKPASS_TOKEN, hand_out(password)));
+        }
+        None => {}
+    }
+    Ok(vars)
+}
+
+/// Keeps `password` for the askpass program of one ssh process, returning
+/// the token it claims it with.
+fn hand_out(password: String) -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let mut claims = CLAIMS.lock().unwrap_or_else(|e| e.into_inner());
+    claims.retain(|_, (_, handed_out)| handed_out.elapsed() < CLAIM_TTL);
+    claims.insert(token.clone(), (password, Instant::now()));
+    token
+}
+
+/// The socket passwords are claimed over, listened on by a thread of this
+/// process from the first call on. Only its owner may connect to it.
+fn claims_socket() -> Result<PathBuf> {
+    let mut socket = CLAIMS_SOCKET.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(path) = socket.as_ref() {
+        return Ok(path.clone());
+    }
+    let dir = env::home_dir()
+        .map(|home| home.join(CLAIMS_DIR))
+        .ok_or_else(|| eyre!("Could not determine the home directory"))?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    let path = dir.join(format!("rtun-{}.sock", process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .wrap_err_with(|| format!("Could not listen on {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = answer_claim(stream);
+        }
+    });
+    *socket = Some(path.clone());
+    Ok(path)
+}
+
+// Reads a token and writes back its password, forgetting it; nothing for
+// unknown or claimed tokens.
+fn answer_claim(mut stream: UnixStream) -> std::io::Result<()> {
+    let mut token = String::new();
+    BufReader::new(&stream).take(256).read_line(&mut token)?;
+    let claimed = CLAIMS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(token.trim());
+    if let Some((password, _)) = claimed {
+        stream.write_all(password.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Claims the password handed out with `token` from the rtun listening on
+/// `socket`. Each token gives it once.
+pub fn claim(socket: &Path, token: &str) -> Result<String> {
+    let mut stream = UnixStream::connect(socket)
+        .wrap_err_with(|| format!("Could not reach rtun on {}", socket.display()))?;
+    writeln!(stream, "{}", token)?;
+    let mut password = String::new();
+    stream.read_to_string(&mut password)?;
+    if password.is_empty() {
+        return Err(eyre!("The password was claimed already"));
+    }
+    Ok(password)
+}
+
+/// Reads a line from the terminal without echoing it.
+pub fn read_hidden(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let stdin = std::io::stdin();
+    let fd = libc::STDIN_FILENO;
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    let is_tty = unsafe { libc::tcgetattr(fd, &mut original) } == 0;
+    if is_tty {
+        let mut hidden = original;
+        hidden.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) };
+    }
+    let mut line = String::new();
+    let read = stdin.lock().read_line(&mut line);
+    if is_tty {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
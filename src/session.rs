@@ -0,0 +1,179 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::state;
+use crate::tunnel::TunnelSpec;
+
+const SESSION_PATH: &str = ".rtun/session.json";
+
+/// Set when rtun is stopped the way service managers stop it, to restart
+/// it: the session is then kept for the next instance to restore.
+static KEEP: AtomicBool = AtomicBool::new(false);
+
+/// A tunnel to open again after a restart, as recorded in
+/// `~/.rtun/session.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SavedTunnel {
+    /// A tunnel of a profile, restored from the config as it is by then.
+    Profile { profile: String, local_port: u16 },
+    /// A tunnel opened on its own, such as with `rtun add` or the menu.
+    Single {
+        host: String,
+        local_port: u16,
+        remote_port: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        remote_host: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    },
+    /// A tunnel with options the session cannot hold, such as jump hosts,
+    /// flagged instead of restored half-way.
+    Unsaved { tunnel: String, local_port: u16 },
+}
+
+impl SavedTunnel {
+    /// How `spec` is saved: by profile when one has the very same tunnel,
+    /// otherwise on its own if that loses nothing.
+    pub fn of(config: &Config, spec: &TunnelSpec) -> Self {
+        let profile = config.profiles.keys().find(|name| {
+            config
+                .profile_tunnels(name)
+                .is_ok_and(|tunnels| tunnels.contains(spec))
+        });
+        if let Some(profile) = profile {
+            return SavedTunnel::Profile {
+                profile: profile.clone(),
+                local_port: spec.local_port,
+            };
+        }
+        let single = SavedTunnel::Single {
+            host: spec.host.clone(),
+            local_port: spec.local_port,
+            remote_port: spec.remote_port,
+            remote_host: spec.remote_host.clone(),
+            name: spec.name.clone(),
+            label: spec.label.clone(),
+            tags: spec.tags.clone(),
+        };
+        match single.restore(config) {
+            Ok(restored) if restored == *spec => single,
+            _ => SavedTunnel::Unsaved {
+                tunnel: spec.to_string(),
+                local_port: spec.local_port,
+            },
+        }
+    }
+
+    pub fn local_port(&self) -> u16 {
+        match self {
+            SavedTunnel::Profile { local_port, .. }
+            | SavedTunnel::Single { local_port, .. }
+            | SavedTunnel::Unsaved { local_port, .. } => *local_port,
+        }
+    }
+
+    /// The tunnel to open again, or why it cannot be.
+    pub fn restore(&self, config: &Config) -> Result<TunnelSpec, String> {
+        match self {
+            SavedTunnel::Profile {
+                profile,
+                local_port,
+            } => config
+                .profile_tunnels(profile)
+                .map_err(|e| format!("{:#}", e))?
+                .into_iter()
+                .find(|spec| spec.local_port == *local_port)
+                .ok_or_else(|| {
+                    format!(
+                        "Profile '{}' has no tunnel on port {} anymore",
+                        profile, local_port
+                    )
+                }),
+            SavedTunnel::Single {
+                host,
+                local_port,
+                remote_port,
+                remote_host,
+                name,
+                label,
+                tags,
+            } => Ok(TunnelSpec {
+                remote_host: remote_host.clone(),
+                name: name.clone(),
+                label: label.clone(),
+                tags: tags.clone(),
+                ..TunnelSpec::new(host, *local_port, *remote_port)
+            }),
+            SavedTunnel::Unsaved { tunnel, .. } => Err(format!(
+                "{} has options a session cannot hold, open it again by hand",
+                tunnel
+            )),
+        }
+    }
+}
+
+/// The tunnels an instance had open, kept while it runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Pid of the rtun instance.
+    pub owner: u32,
+    pub saved_at: DateTime<Utc>,
+    pub tunnels: Vec<SavedTunnel>,
+}
+
+fn session_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(SESSION_PATH))
+}
+
+/// Records `tunnels` as the session of this instance.
+pub fn save(tunnels: Vec<SavedTunnel>) -> std::io::Result<()> {
+    let path = session_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let session = Session {
+        owner: process::id(),
+        saved_at: Utc::now(),
+        tunnels,
+    };
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&session)?)?;
+    fs::rename(tmp, path)
+}
+
+/// The session of an instance that is gone without ending it, which crashed
+/// or was stopped to be restarted.
+pub fn left_behind() -> Option<Session> {
+    let contents = fs::read_to_string(session_path()?).ok()?;
+    let session: Session = serde_json::from_str(&contents).ok()?;
+    (session.owner != process::id() && !state::is_alive(session.owner)).then_some(session)
+}
+
+/// Forgets the session, once the user ended it.
+pub fn clear() {
+    if let Some(path) = session_path() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Keeps the session of this instance for the next one, as on SIGTERM.
+pub fn keep() {
+    KEEP.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`keep`] was called.
+pub fn kept() -> bool {
+    KEEP.load(Ordering::Relaxed)
+}
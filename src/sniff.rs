@@ -0,0 +1,155 @@
+use std::fmt;
+
+/// What a connection through a relay speaks, guessed from its first bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    Http {
+        host: Option<String>,
+    },
+    /// With the server name the client asked for, if it sent one.
+    Tls {
+        server_name: Option<String>,
+    },
+    Postgres,
+    Redis,
+    Ssh,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Http { .. } => "HTTP",
+            Protocol::Tls { .. } => "TLS",
+            Protocol::Postgres => "Postgres",
+            Protocol::Redis => "Redis",
+            Protocol::Ssh => "SSH",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Http { host: Some(host) } => write!(f, "HTTP ({})", host),
+            Protocol::Tls {
+                server_name: Some(name),
+            } => write!(f, "TLS ({})", name),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+/// Name of the protocol usually found on `port`, if it has one.
+pub fn usual(port: u16) -> Option<&'static str> {
+    match port {
+        22 => Some("SSH"),
+        80 => Some("HTTP"),
+        443 => Some("TLS"),
+        5432 => Some("Postgres"),
+        6379 => Some("Redis"),
+        _ => None,
+    }
+}
+
+const HTTP_METHODS: [&str; 9] = [
+    "GET ", "POST ", "PUT ", "HEAD ", "DELETE ", "OPTIONS ", "PATCH ", "CONNECT ", "PRI ",
+];
+// Codes of the Postgres startup, SSL and GSSAPI encryption requests.
+const POSTGRES_CODES: [u32; 3] = [196608, 80877103, 80877104];
+
+pub fn is_http_method(word: &str) -> bool {
+    HTTP_METHODS.iter().any(|method| method.trim_end() == word)
+}
+
+/// Guesses the protocol from what a client sent first.
+pub fn client(bytes: &[u8]) -> Option<Protocol> {
+    if bytes.starts_with(b"SSH-") {
+        return Some(Protocol::Ssh);
+    }
+    if bytes.first() == Some(&0x16) {
+        return Some(Protocol::Tls {
+            server_name: server_name(bytes),
+        });
+    }
+    if HTTP_METHODS.iter().any(|m| bytes.starts_with(m.as_bytes())) {
+        return Some(Protocol::Http {
+            host: http_host(bytes),
+        });
+    }
+    if let [b'*', digit, ..] = bytes {
+        if digit.is_ascii_digit() {
+            return Some(Protocol::Redis);
+        }
+    }
+    if let [a, b, c, d, code @ ..] = bytes {
+        let length = u32::from_be_bytes([*a, *b, *c, *d]);
+        let code = code.first_chunk().copied().map(u32::from_be_bytes);
+        if length >= 8 && code.is_some_and(|code| POSTGRES_CODES.contains(&code)) {
+            return Some(Protocol::Postgres);
+        }
+    }
+    None
+}
+
+/// Guesses the protocol from what a server sent first, for protocols whose
+/// clients wait for the server.
+pub fn server(bytes: &[u8]) -> Option<Protocol> {
+    bytes.starts_with(b"SSH-").then_some(Protocol::Ssh)
+}
+
+fn http_host(bytes: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(bytes);
+    head.lines().skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("host")
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Reads big-endian numbers and slices off a TLS record.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.0.split_at_checked(count)?;
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn number(&mut self, size: usize) -> Option<usize> {
+        let bytes = self.bytes(size)?;
+        Some(bytes.iter().fold(0, |n, b| n << 8 | *b as usize))
+    }
+
+    /// Takes data prefixed with its length, itself `size` bytes long.
+    fn prefixed(&mut self, size: usize) -> Option<&'a [u8]> {
+        let length = self.number(size)?;
+        self.bytes(length)
+    }
+}
+
+/// The server name of a TLS ClientHello, if the record holds it whole.
+fn server_name(bytes: &[u8]) -> Option<String> {
+    let mut record = Reader(bytes);
+    // Content type, version, length, handshake type and length, client
+    // version and random.
+    record.bytes(1 + 2 + 2 + 1 + 3 + 2 + 32)?;
+    record.prefixed(1)?; // session id
+    record.prefixed(2)?; // cipher suites
+    record.prefixed(1)?; // compression methods
+    let mut extensions = Reader(record.prefixed(2)?);
+    while let Some(kind) = extensions.number(2) {
+        let data = extensions.prefixed(2)?;
+        if kind == 0 {
+            let mut names = Reader(data);
+            let mut names = Reader(names.prefixed(2)?);
+            while let Some(name_type) = names.number(1) {
+                let name = names.prefixed(2)?;
+                if name_type == 0 {
+                    return String::from_utf8(name.to_vec()).ok();
+                }
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Destinations kept per tunnel, the least recent dropped first.
+const DESTINATIONS_LIMIT: usize = 50;
+/// Clients that asked for no destination by then do not speak SOCKS.
+const MAX_HANDSHAKE: usize = 1024;
+const CONNECT: u8 = 1;
+
+/// A host clients of a SOCKS tunnel reached through it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    /// The name the ssh host resolved, or the address the client resolved
+    /// itself.
+    pub host: String,
+    pub port: u16,
+    /// When a client last connected to it.
+    pub at: DateTime<Local>,
+    pub connections: u64,
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        write!(f, "{} {}:{}", self.at.format("%H:%M:%S"), host, self.port)?;
+        if self.connections > 1 {
+            write!(f, " ×{}", self.connections)?;
+        }
+        Ok(())
+    }
+}
+
+/// The destinations of a tunnel, shared by its connections.
+#[derive(Debug, Default)]
+pub struct Destinations {
+    destinations: Mutex<VecDeque<Destination>>,
+}
+
+impl Destinations {
+    /// The destinations reached lately, the most recent first.
+    pub fn recent(&self) -> Vec<Destination> {
+        let destinations = self.destinations.lock().unwrap_or_else(|e| e.into_inner());
+        destinations.iter().cloned().collect()
+    }
+
+    fn push(&self, host: String, port: u16) {
+        let mut destinations = self.destinations.lock().unwrap_or_else(|e| e.into_inner());
+        let earlier = destinations
+            .iter()
+            .position(|d| d.host == host && d.port == port)
+            .and_then(|i| destinations.remove(i));
+        if earlier.is_none() && destinations.len() == DESTINATIONS_LIMIT {
+            destinations.pop_back();
+        }
+        destinations.push_front(Destination {
+            host,
+            port,
+            at: Local::now(),
+            connections: earlier.map_or(0, |d| d.connections) + 1,
+        });
+    }
+}
+
+/// Watches what a client of a SOCKS tunnel sends for the destination it
+/// asks for, then lets the rest pass.
+pub struct Handshake {
+    log: Arc<Destinations>,
+    sent: Vec<u8>,
+    done: bool,
+}
+
+impl Handshake {
+    pub fn new(log: Arc<Destinations>) -> Self {
+        Handshake {
+            log,
+            sent: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Bytes the client sent.
+    pub fn sent(&mut self, bytes: &[u8]) {
+        if self.done {
+            return;
+        }
+        self.sent.extend_from_slice(bytes);
+        if let Some((host, port)) = request(&self.sent) {
+            self.log.push(host, port);
+            self.done = true;
+        } else if self.sent.len() > MAX_HANDSHAKE || !matches!(self.sent[..], [] | [4 | 5, ..]) {
+            self.done = true;
+        }
+        if self.done {
+            self.sent = Vec::new();
+        }
+    }
+}
+
+/// The first `n` bytes of `bytes`, moving past them.
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    let (taken, rest) = bytes.split_at_checked(n)?;
+    *bytes = rest;
+    Some(taken)
+}
+
+/// Up to a NUL, moving past it.
+fn take_string(bytes: &mut &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|b| *b == 0)?;
+    let text = String::from_utf8_lossy(take(bytes, end)?).into_owned();
+    take(bytes, 1)?;
+    Some(text)
+}
+
+fn port(bytes: &mut &[u8]) -> Option<u16> {
+    let port = take(bytes, 2)?;
+    Some(u16::from_be_bytes([port[0], port[1]]))
+}
+
+/// The host and port a client connects to, from the first bytes it sent: a
+/// SOCKS 5 greeting and request, or a SOCKS 4 or 4a request. `None` until
+/// they are all there, or for anything else.
+pub fn request(mut bytes: &[u8]) -> Option<(String, u16)> {
+    let bytes = &mut bytes;
+    match take(bytes, 2)? {
+        [5, methods] => {
+            take(bytes, *methods as usize)?;
+            let [5, CONNECT, 0, kind] = take(bytes, 4)? else {
+                return None;
+            };
+            let host = match kind {
+                1 => Ipv4Addr::from(<[u8; 4]>::try_from(take(bytes, 4)?).ok()?).to_string(),
+                3 => {
+                    let len = take(bytes, 1)?[0] as usize;
+                    String::from_utf8_lossy(take(bytes, len)?).into_owned()
+                }
+                4 => Ipv6Addr::from(<[u8; 16]>::try_from(take(bytes, 16)?).ok()?).to_string(),
+                _ => return None,
+            };
+            Some((host, port(bytes)?))
+        }
+        [4, CONNECT] => {
+            let port = port(bytes)?;
+            let address = Ipv4Addr::from(<[u8; 4]>::try_from(take(bytes, 4)?).ok()?);
+            // The user id.
+            take_string(bytes)?;
+            // 0.0.0.x, x not 0, has SOCKS 4a send the name instead.
+            let host = match address.octets() {
+                [0, 0, 0, x] if x != 0 => take_string(bytes)?,
+                _ => address.to_string(),
+            };
+            Some((host, port))
+        }
+        _ => None,
+    }
+}
+
+/// Connects to `host` and `port` through the SOCKS 5 proxy on the local
+/// `proxy_port`, leaving names for the far end of the proxy to resolve.
+pub async fn connect(proxy_port: u16, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port)).await?;
+    // No authentication, the only method ssh offers.
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut method = [0; 2];
+    stream.read_exact(&mut method).await?;
+    if method != [5, 0] {
+        return Err(io::Error::other("the SOCKS proxy wants authentication"));
+    }
+    let mut request = vec![5, CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => {
+            request.push(1);
+            request.extend_from_slice(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            request.push(4);
+            request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "name too long"))?;
+            request.push(3);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::other(format!(
+            "the SOCKS proxy could not connect to {}:{}",
+            host, port
+        )));
+    }
+    // The address the proxy connected from, of no use here.
+    let len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        _ => stream.read_u8().await? as usize,
+    };
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(stream)
+}
@@ -0,0 +1,144 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tunnel::TunnelSpec;
+
+const STATE_PATH: &str = ".rtun/state.json";
+
+// Tunnel tasks update the state file concurrently.
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// An ssh process started by rtun, as recorded in `~/.rtun/state.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProcessRecord {
+    /// Pid of the ssh process.
+    pub pid: u32,
+    /// Pid of the rtun instance managing it.
+    pub owner: u32,
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// ControlMaster socket opened for this process, removed on cleanup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control_path: Option<PathBuf>,
+}
+
+impl ProcessRecord {
+    pub fn spec(&self) -> TunnelSpec {
+        TunnelSpec::new(&self.host, self.local_port, self.remote_port)
+    }
+}
+
+fn state_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(STATE_PATH))
+}
+
+fn read() -> Vec<ProcessRecord> {
+    state_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write(records: &[ProcessRecord]) -> std::io::Result<()> {
+    let path = state_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(records)?)?;
+    fs::rename(tmp, path)
+}
+
+fn update(f: impl FnOnce(&mut Vec<ProcessRecord>)) {
+    let _lock = STATE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut records = read();
+    f(&mut records);
+    let _ = write(&records);
+}
+
+pub fn is_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists and we may signal it.
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+/// Whether `pid` is alive and still an ssh process, guarding against pid reuse.
+fn is_ssh(pid: u32) -> bool {
+    if !is_alive(pid) {
+        return false;
+    }
+    Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "command="])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("ssh"))
+        .unwrap_or(false)
+}
+
+pub fn terminate(pid: u32) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGTERM);
+    }
+}
+
+/// Records an ssh process started by this rtun instance.
+pub fn register(pid: u32, spec: &TunnelSpec) {
+    update(|records| {
+        records.retain(|r| r.pid != pid);
+        records.push(ProcessRecord {
+            pid,
+            owner: process::id(),
+            host: spec.host.clone(),
+            local_port: spec.local_port,
+            remote_port: spec.remote_port,
+            control_path: None,
+        });
+    });
+}
+
+pub fn unregister(pid: u32) {
+    update(|records| records.retain(|r| r.pid != pid));
+}
+
+/// Returns ssh processes left behind by rtun instances that are no longer
+/// running, and forgets about the ones that have exited since.
+pub fn orphans() -> Vec<ProcessRecord> {
+    let mut orphans = Vec::new();
+    update(|records| {
+        records.retain(|r| is_alive(r.owner) || is_ssh(r.pid));
+        orphans = records
+            .iter()
+            .filter(|r| !is_alive(r.owner))
+            .cloned()
+            .collect();
+    });
+    orphans
+}
+
+/// Recorded ssh processes that are still running, whether or not their
+/// rtun instance is.
+pub fn running() -> Vec<ProcessRecord> {
+    read().into_iter().filter(|r| is_alive(r.pid)).collect()
+}
+
+/// Takes ownership of an orphaned process for this rtun instance.
+pub fn adopt(record: &ProcessRecord) {
+    update(|records| {
+        if let Some(r) = records.iter_mut().find(|r| r.pid == record.pid) {
+            r.owner = process::id();
+        }
+    });
+}
+
+/// Terminates an orphaned process and cleans up after it.
+pub fn kill(record: &ProcessRecord) {
+    terminate(record.pid);
+    if let Some(control_path) = &record.control_path {
+        let _ = fs::remove_file(control_path);
+    }
+    unregister(record.pid);
+}
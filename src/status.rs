@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ErrorInfo;
+use crate::manager::ManagedTunnel;
+use crate::state::ProcessRecord;
+use crate::tunnel::TunnelStatus;
+
+/// Output formats of `rtun status`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `5 up, 1 down`, one line
+    Plain,
+    /// Everything as JSON
+    Json,
+    /// Short text for a starship custom module, empty without tunnels
+    Starship,
+    /// JSON for a waybar custom module, with a tooltip and a CSS class
+    Waybar,
+    /// Text with tmux color markup for status-right
+    Tmux,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Up,
+    /// Reconnecting or given up on.
+    Down,
+    /// Closed, scheduled, queued or waiting for a VPN.
+    Idle,
+}
+
+/// A tunnel as reported by `rtun status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TunnelInfo {
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// Where `remote_port` lives, as seen from `host`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// As shown in the TUI, e.g. `reconnecting (2)`.
+    pub status: String,
+    pub health: Health,
+    /// Why the tunnel is down, while it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorInfo>,
+    /// When the tunnel last came up, while it is up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up_since: Option<DateTime<Utc>>,
+}
+
+impl From<&ManagedTunnel> for TunnelInfo {
+    fn from(tunnel: &ManagedTunnel) -> Self {
+        let health = match tunnel.status {
+            // A parked tunnel takes connections, connecting on the first.
+            TunnelStatus::Up | TunnelStatus::Draining | TunnelStatus::Parked => Health::Up,
+            TunnelStatus::Reconnecting(_) | TunnelStatus::Failed => Health::Down,
+            _ => Health::Idle,
+        };
+        TunnelInfo {
+            host: tunnel.spec.host.clone(),
+            local_port: tunnel.spec.local_port,
+            remote_port: tunnel.spec.remote_port,
+            remote_host: tunnel.spec.remote_host.clone(),
+            name: tunnel.spec.name.clone(),
+            label: tunnel.spec.label.clone(),
+            status: tunnel.status.to_string(),
+            health,
+            error: tunnel
+                .last_error
+                .as_deref()
+                .filter(|_| health == Health::Down)
+                .map(ErrorInfo::of_tunnel_error),
+            up_since: tunnel.up_since.map(|since| since.with_timezone(&Utc)),
+        }
+    }
+}
+
+impl From<&ProcessRecord> for TunnelInfo {
+    /// An ssh process left running without an rtun instance.
+    fn from(record: &ProcessRecord) -> Self {
+        TunnelInfo {
+            host: record.host.clone(),
+            local_port: record.local_port,
+            remote_port: record.remote_port,
+            remote_host: None,
+            name: None,
+            label: None,
+            status: "orphaned".to_string(),
+            health: Health::Up,
+            error: None,
+            up_since: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// Whether an rtun instance answered, rather than the state file.
+    pub running: bool,
+    pub up: usize,
+    pub down: usize,
+    pub tunnels: Vec<TunnelInfo>,
+}
+
+impl Summary {
+    pub fn new(running: bool, tunnels: Vec<TunnelInfo>) -> Self {
+        let count = |health| tunnels.iter().filter(|t| t.health == health).count();
+        Summary {
+            running,
+            up: count(Health::Up),
+            down: count(Health::Down),
+            tunnels,
+        }
+    }
+
+    fn short(&self) -> String {
+        if self.down > 0 {
+            format!("{}↑ {}↓", self.up, self.down)
+        } else {
+            format!("{}↑", self.up)
+        }
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Plain => format!("{} up, {} down", self.up, self.down),
+            Format::Json => serde_json::to_string(self).unwrap_or_default(),
+            Format::Starship if self.tunnels.is_empty() => String::new(),
+            Format::Starship => self.short(),
+            Format::Waybar => {
+                let tooltip: Vec<String> = self
+                    .tunnels
+                    .iter()
+                    .map(|t| format!("{} {}:{} {}", t.host, t.local_port, t.remote_port, t.status))
+                    .collect();
+                let class = match (self.up, self.down) {
+                    (_, 0) => "ok",
+                    (0, _) => "down",
+                    _ => "degraded",
+                };
+                serde_json::json!({
+                    "text": self.short(),
+                    "tooltip": tooltip.join("\n"),
+                    "class": class,
+                })
+                .to_string()
+            }
+            Format::Tmux if self.down > 0 => format!(
+                "#[fg=green]{}↑#[default] #[fg=red]{}↓#[default]",
+                self.up, self.down
+            ),
+            Format::Tmux => format!("#[fg=green]{}↑#[default]", self.up),
+        }
+    }
+}
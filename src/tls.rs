@@ -0,0 +1,327 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Datelike, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime,
+};
+use tokio_rustls::rustls::{
+    self, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::certs;
+use crate::config::TlsBackendConfig;
+use crate::tunnel::TunnelSpec;
+
+const TLS_DIR: &str = ".rtun/tls";
+// Apple platforms reject server certificates valid for longer than this.
+const LEAF_VALIDITY_DAYS: i64 = 825;
+
+/// Path of the local CA certificate, for adding it to the trust store.
+pub fn ca_cert_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(TLS_DIR).join("ca.pem"))
+}
+
+fn ca_key_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(TLS_DIR).join("ca-key.pem"))
+}
+
+fn ca_params() -> Result<CertificateParams> {
+    let mut params = CertificateParams::new(Vec::new())?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "rtun local CA");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "rtun");
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    Ok(params)
+}
+
+/// Held while the CA is loaded or created, so that tunnels starting at once
+/// do not each create one and overwrite the others'.
+static CA_LOCK: Mutex<()> = Mutex::new(());
+
+/// Writes `contents` to `path` with `mode` through a temporary file beside
+/// it, so that `path` is either missing or complete.
+fn write_atomically(path: &Path, contents: &str, mode: u32) -> io::Result<()> {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(format!(".{}.tmp", std::process::id()));
+    let staged = PathBuf::from(staged);
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&staged)
+        .and_then(|mut file| {
+            io::Write::write_all(&mut file, contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&staged, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&staged);
+    }
+    written
+}
+
+/// Loads the local CA, creating it on first use. The key never leaves
+/// `~/.rtun/tls` and is only readable by the user.
+pub fn load_or_create_ca() -> Result<(Certificate, KeyPair)> {
+    let (Some(cert_path), Some(key_path)) = (ca_cert_path(), ca_key_path()) else {
+        return Err(eyre!("Could not determine the home directory"));
+    };
+    let _lock = CA_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if cert_path.exists() && key_path.exists() {
+        let key = KeyPair::from_pem(&fs::read_to_string(&key_path)?)
+            .wrap_err_with(|| format!("Invalid key in {}", key_path.display()))?;
+        let params = CertificateParams::from_ca_cert_pem(&fs::read_to_string(&cert_path)?)
+            .wrap_err_with(|| format!("Invalid certificate in {}", cert_path.display()))?;
+        // Re-signing yields the same name and key, which is all that
+        // issuing certificates needs.
+        return Ok((params.self_signed(&key)?, key));
+    }
+
+    let key = KeyPair::generate()?;
+    let cert = ca_params()?.self_signed(&key)?;
+    if let Some(dir) = cert_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomically(&key_path, &key.serialize_pem(), 0o600)
+        .wrap_err_with(|| format!("Could not write {}", key_path.display()))?;
+    write_atomically(&cert_path, &cert.pem(), 0o644)
+        .wrap_err_with(|| format!("Could not write {}", cert_path.display()))?;
+    Ok((cert, key))
+}
+
+/// TLS settings with a fresh `localhost` certificate issued by the local CA.
+fn server_config() -> Result<Arc<ServerConfig>> {
+    let (ca, ca_key) = load_or_create_ca()?;
+    let key = KeyPair::generate()?;
+    let mut params = CertificateParams::new(vec![
+        "localhost".to_string(),
+        "*.localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ])?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "localhost");
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let yesterday = Utc::now() - chrono::Duration::days(1);
+    let expires = yesterday + chrono::Duration::days(LEAF_VALIDITY_DAYS);
+    params.not_before = rcgen::date_time_ymd(
+        yesterday.year(),
+        yesterday.month() as u8,
+        yesterday.day() as u8,
+    );
+    params.not_after =
+        rcgen::date_time_ymd(expires.year(), expires.month() as u8, expires.day() as u8);
+    let cert = params.signed_by(&key, &ca, &ca_key)?;
+
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone(), ca.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )?;
+    Ok(Arc::new(config))
+}
+
+/// Takes any certificate the forward shows, for an `insecure` `tls_backend`.
+/// Its signatures are still checked.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// The forward speaking TLS itself, and the name its certificate is for.
+#[derive(Clone)]
+struct Backend {
+    connector: TlsConnector,
+    name: ServerName<'static>,
+}
+
+/// The CAs the system trusts, for forwards without a `ca` of their own.
+fn platform_roots() -> Result<RootCertStore> {
+    let found = rustls_native_certs::load_native_certs();
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(found.certs);
+    if roots.is_empty() {
+        let reasons: Vec<_> = found.errors.iter().map(ToString::to_string).collect();
+        return Err(eyre!(
+            "No trusted certificates found on this system ({}); set the ca of tls_backend",
+            reasons.join(", ")
+        ));
+    }
+    Ok(roots)
+}
+
+/// TLS settings towards a forward to `destination`, trusting the CA of
+/// `config`, or else the ones of the system, unless it is `insecure`.
+fn backend(config: &TlsBackendConfig, destination: &str) -> Result<Backend> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let client = match &config.ca {
+        _ if config.insecure => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider))),
+        Some(path) => {
+            let path = certs::expand_home(path);
+            let pem =
+                fs::read(&path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_slice_iter(&pem) {
+                let cert =
+                    cert.map_err(|e| eyre!("Invalid certificate in {}: {:?}", path.display(), e))?;
+                roots.add(cert)?;
+            }
+            if roots.is_empty() {
+                return Err(eyre!("No certificate in {}", path.display()));
+            }
+            builder.with_root_certificates(roots)
+        }
+        None => builder.with_root_certificates(platform_roots()?),
+    }
+    .with_no_client_auth();
+    let name = config.server_name.as_deref().unwrap_or(destination);
+    let name = ServerName::try_from(name.to_string())
+        .map_err(|_| eyre!("'{}' is not a server name", name))?;
+    Ok(Backend {
+        connector: TlsConnector::from(Arc::new(client)),
+        name,
+    })
+}
+
+/// Accepts TLS on `tls_port` and passes the decrypted traffic on to the
+/// forward on `local_port`, in plain or, for a `tls_backend`, encrypted
+/// again.
+///
+/// Stops accepting when dropped.
+pub struct TlsTerminator {
+    handle: JoinHandle<()>,
+}
+
+impl TlsTerminator {
+    pub async fn start(spec: &TunnelSpec, tls_port: u16) -> Result<Self> {
+        let acceptor = TlsAcceptor::from(server_config()?);
+        let backend = match &spec.tls_backend {
+            Some(config) => Some(backend(config, &spec.targets()[0].host)?),
+            None => None,
+        };
+        let listener = TcpListener::bind(("127.0.0.1", tls_port))
+            .await
+            .wrap_err_with(|| format!("Could not listen on port {}", tls_port))?;
+        let handle = tokio::spawn(serve(listener, acceptor, spec.local_port, backend));
+        Ok(TlsTerminator { handle })
+    }
+}
+
+impl Drop for TlsTerminator {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    local_port: u16,
+    backend: Option<Backend>,
+) {
+    loop {
+        let inbound = match listener.accept().await {
+            Ok((inbound, _)) => inbound,
+            Err(_) => {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let (acceptor, backend) = (acceptor.clone(), backend.clone());
+        tokio::spawn(async move {
+            let Ok(mut inbound) = acceptor.accept(inbound).await else {
+                return;
+            };
+            let Ok(outbound) = TcpStream::connect(("127.0.0.1", local_port)).await else {
+                return;
+            };
+            let _ = match backend {
+                Some(Backend { connector, name }) => {
+                    let Ok(mut outbound) = connector.connect(name, outbound).await else {
+                        return;
+                    };
+                    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await
+                }
+                None => {
+                    let mut outbound = outbound;
+                    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await
+                }
+            };
+        });
+    }
+}
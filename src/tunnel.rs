@@ -0,0 +1,1183 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStderr};
+use tokio::sync::{mpsc, watch};
+
+use crate::activation;
+use crate::alerts;
+use crate::backend::{self, Forward};
+use crate::config::{
+    AlertEvent, ClientConfig, Config, HealthCheckConfig, HostKeyConfig, KnockConfig,
+    ReconnectConfig, TlsBackendConfig, WatchdogConfig,
+};
+use crate::errors::ErrorCode;
+use crate::health;
+use crate::history::{self, EventKind};
+use crate::hostkeys;
+use crate::idle;
+use crate::knock;
+use crate::otp;
+use crate::reachability;
+use crate::relay::{Cidr, Relay, RelayStats};
+use crate::remoteport;
+use crate::schedule::Schedule;
+use crate::secrets;
+use crate::state::{self, ProcessRecord};
+use crate::tls::TlsTerminator;
+use crate::vpn;
+use crate::watchdog;
+
+// A tunnel that stayed up this long is considered healthy again and gets a
+// fresh set of reconnect attempts.
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+const ADOPTED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const VPN_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TunnelSpec {
+    /// Service name, reachable as `<name>.localhost` through the HTTP proxy.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Free-text description shown next to the tunnel.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Tags such as `env:staging`, for filtering and bulk commands.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// Where `remote_port` lives, as seen from `host`. Defaults to `host` itself.
+    #[serde(default)]
+    pub remote_host: Option<String>,
+    /// Jump hosts in front of `host`, outermost first.
+    #[serde(default)]
+    pub via: Vec<String>,
+    /// Per-tunnel reconnect overrides.
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    /// Keeps the tunnel open only while the schedule says so.
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// Destinations to fail over to, in order, when health checks fail.
+    #[serde(default)]
+    pub fallbacks: Vec<RemoteTarget>,
+    /// Probes the forward periodically; on by default when there are fallbacks.
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+    /// Restarts ssh when its session stops carrying data.
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    /// Name of a `[vpn]` entry that must be up before connecting.
+    #[serde(default)]
+    pub requires_vpn: Option<String>,
+    /// Knocks before every connection; defaults to the `[knock]` entry of the
+    /// first hop.
+    #[serde(default)]
+    pub knock: Option<KnockConfig>,
+    /// Host key checking; defaults to the `[host_keys]` entry of the host.
+    #[serde(default)]
+    pub host_key: Option<HostKeyConfig>,
+    /// Dynamic SOCKS forward on `local_port` instead of a forward to
+    /// `remote_port`.
+    #[serde(default)]
+    pub socks: bool,
+    /// Domains the generated PAC file sends through this SOCKS forward,
+    /// e.g. `*.internal`.
+    #[serde(default)]
+    pub pac_domains: Vec<String>,
+    /// Listen on all interfaces instead of localhost only, so other devices
+    /// on the network can use the tunnel.
+    #[serde(default)]
+    pub lan: bool,
+    /// Names pointed at 127.0.0.1 in /etc/hosts while the tunnel is open,
+    /// for clients that need the real name, such as for cookies.
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    /// Also serves the forward over TLS on this port, with a certificate
+    /// from rtun's local CA.
+    #[serde(default)]
+    pub tls_port: Option<u16>,
+    /// Set when the forward speaks TLS itself, for `tls_port` to encrypt
+    /// again towards it instead of passing on plain traffic.
+    #[serde(default)]
+    pub tls_backend: Option<TlsBackendConfig>,
+    /// Parameters of the database client launched for the forward, such as
+    /// the user to log in as.
+    #[serde(default)]
+    pub client: Option<ClientConfig>,
+    /// Spreads connections over the primary and the fallbacks instead of
+    /// failing over between them.
+    #[serde(default)]
+    pub load_balance: Option<LoadBalance>,
+    /// Transport carrying the forwards, `ssh` by default; see
+    /// [`backend::register`].
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Closes the tunnel after this many minutes without connections.
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+    /// Client addresses allowed to connect, e.g. `192.168.1.0/24`; everyone
+    /// when empty. Set, the forward goes through the native relay.
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    /// Turns away clients beyond this many open connections. Set, the
+    /// forward goes through the native relay.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Logs the method, path and status of HTTP requests through the
+    /// tunnel. Set, the forward goes through the native relay.
+    #[serde(default)]
+    pub log_requests: bool,
+    /// Connects ssh only once a client connects, and, with an idle timeout,
+    /// disconnects it again instead of closing the tunnel. Set, the forward
+    /// goes through the native relay.
+    #[serde(default)]
+    pub lazy: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoadBalance {
+    RoundRobin,
+}
+
+impl std::fmt::Display for TunnelSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.socks {
+            write!(f, "{} socks:{}", self.route(), self.local_port)
+        } else {
+            write!(
+                f,
+                "{} {}:{}",
+                self.route(),
+                self.local_port,
+                self.remote_port
+            )
+        }
+    }
+}
+
+/// A destination reached from the ssh host, written as `host:port`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct RemoteTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+impl TryFrom<String> for RemoteTarget {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (host, port) = value
+            .rsplit_once(':')
+            .ok_or_else(|| format!("Expected HOST:PORT, got '{}'", value))?;
+        let port = port
+            .parse()
+            .map_err(|_| format!("Invalid port in '{}'", value))?;
+        Ok(RemoteTarget {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for RemoteTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl TunnelSpec {
+    pub fn new(host: &str, local_port: u16, remote_port: u16) -> Self {
+        TunnelSpec {
+            host: host.to_string(),
+            local_port,
+            remote_port,
+            ..Default::default()
+        }
+    }
+
+    /// Parses `HOST LOCAL:REMOTE[:DEST]`, optionally followed by `# label`,
+    /// where `DEST` is the host `REMOTE` lives on as seen from `HOST`. The
+    /// flags `--host HOST --local-port LOCAL --remote REMOTE [--dest DEST]`
+    /// may stand in for the first part, for shells that mangle it.
+    pub fn parse(input: &str) -> Result<Self, &'static str> {
+        let (input, label) = match input.split_once('#') {
+            Some((input, label)) => (input.trim_end(), Some(label.trim())),
+            None => (input, None),
+        };
+        let label = label.filter(|l| !l.is_empty()).map(str::to_string);
+        if input.trim_start().starts_with("--") {
+            return Ok(TunnelSpec {
+                label,
+                ..TunnelSpec::parse_flags(input)?
+            });
+        }
+        let parts: Vec<&str> = input.split(' ').collect();
+        if parts.len() != 2 {
+            return Err("Input does not match expected format 'HOST_NAME 12234:45321'");
+        }
+
+        let host = parts[0];
+
+        let ports: Vec<&str> = parts[1].split(':').collect();
+        if !(2..=3).contains(&ports.len()) || ports.get(2).is_some_and(|dest| dest.is_empty()) {
+            return Err("Ports part does not match expected format '12234:45321'");
+        }
+
+        let local_port = ports[0]
+            .parse::<u16>()
+            .map_err(|_| "Failed to parse host_port")?;
+        let remote_port = ports[1]
+            .parse::<u16>()
+            .map_err(|_| "Failed to parse remote_port")?;
+
+        Ok(TunnelSpec {
+            label,
+            remote_host: ports.get(2).map(|dest| dest.to_string()),
+            ..TunnelSpec::new(host, local_port, remote_port)
+        })
+    }
+
+    /// The flag form of [`TunnelSpec::parse`], each flag followed by its
+    /// value or joined to it with `=`.
+    fn parse_flags(input: &str) -> Result<Self, &'static str> {
+        let mut words = input.split_whitespace();
+        let (mut host, mut local_port, mut remote_port, mut dest) = (None, None, None, None);
+        while let Some(word) = words.next() {
+            let (flag, value) = match word.split_once('=') {
+                Some((flag, value)) => (flag, Some(value)),
+                None => (word, words.next()),
+            };
+            let value = value.ok_or("A flag is missing its value")?;
+            match flag {
+                "--host" => host = Some(value),
+                "--local-port" => {
+                    local_port = Some(
+                        value
+                            .parse::<u16>()
+                            .map_err(|_| "Failed to parse host_port")?,
+                    )
+                }
+                "--remote" => {
+                    remote_port = Some(
+                        value
+                            .parse::<u16>()
+                            .map_err(|_| "Failed to parse remote_port")?,
+                    )
+                }
+                "--dest" => dest = Some(value),
+                _ => return Err("Expected --host, --local-port, --remote and optionally --dest"),
+            }
+        }
+        let (Some(host), Some(local_port), Some(remote_port)) = (host, local_port, remote_port)
+        else {
+            return Err("Give all of --host, --local-port and --remote");
+        };
+        Ok(TunnelSpec {
+            remote_host: dest.map(str::to_string),
+            ..TunnelSpec::new(host, local_port, remote_port)
+        })
+    }
+
+    /// Parses a list of tunnels, one `HOST LOCAL:REMOTE[:DEST] # label` or its
+    /// flag form per line, numbering the lines from 1. Blank lines and lines starting with `#`
+    /// are skipped.
+    pub fn parse_list(text: &str) -> Vec<(usize, Result<Self, &'static str>)> {
+        text.lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(i, line)| (i + 1, TunnelSpec::parse(line)))
+            .collect()
+    }
+
+    /// Whether the tunnel carries every one of `tags`.
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// Address the local end listens on.
+    pub fn bind_address(&self) -> &'static str {
+        if self.lan {
+            "0.0.0.0"
+        } else {
+            "127.0.0.1"
+        }
+    }
+
+    /// The host ssh connects to first.
+    pub fn first_hop(&self) -> &str {
+        self.via.first().unwrap_or(&self.host)
+    }
+
+    /// The hops from here to the destination, e.g. `bastion -> gw -> db`.
+    pub fn route(&self) -> String {
+        let mut hops: Vec<&str> = self.via.iter().map(String::as_str).collect();
+        hops.push(&self.host);
+        hops.extend(self.remote_host.as_deref());
+        hops.join(" -> ")
+    }
+
+    /// The primary destination followed by the fallbacks.
+    pub fn targets(&self) -> Vec<RemoteTarget> {
+        let primary = RemoteTarget {
+            host: self
+                .remote_host
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: self.remote_port,
+        };
+        std::iter::once(primary)
+            .chain(self.fallbacks.iter().cloned())
+            .collect()
+    }
+
+    /// Whether rtun listens on the local port itself, see [`Relay`]. It
+    /// always does for SOCKS proxies, to list where they lead, and on ports
+    /// systemd listens on for it.
+    pub fn relayed(&self) -> bool {
+        self.socks
+            || self.load_balance.is_some()
+            || !self.allow.is_empty()
+            || self.max_connections.is_some()
+            || self.log_requests
+            || self.lazy
+            || activation::listens(self.local_port)
+    }
+
+    pub fn health_check(&self) -> Option<HealthCheckConfig> {
+        if self.load_balance.is_some() {
+            // The relay skips destinations that are down on its own.
+            return None;
+        }
+        match self.health_check {
+            Some(check) => Some(check),
+            None if !self.fallbacks.is_empty() => Some(HealthCheckConfig::default()),
+            None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelStatus {
+    Up,
+    Reconnecting(u32),
+    Failed,
+    Closed,
+    /// No longer taking connections, closing once the open ones are over.
+    Draining,
+    /// Listening, with no ssh session until a client connects.
+    Parked,
+    /// Outside its schedule, waiting for the next window.
+    Scheduled,
+    /// Waiting for other tunnels to connect first.
+    Queued,
+    /// Holding off until the VPN it requires is connected.
+    WaitingForVpn,
+}
+
+impl std::fmt::Display for TunnelStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TunnelStatus::Up => write!(f, "up"),
+            TunnelStatus::Reconnecting(attempt) => write!(f, "reconnecting ({})", attempt),
+            TunnelStatus::Failed => write!(f, "failed"),
+            TunnelStatus::Closed => write!(f, "closed"),
+            TunnelStatus::Draining => write!(f, "draining"),
+            TunnelStatus::Parked => write!(f, "parked"),
+            TunnelStatus::Scheduled => write!(f, "scheduled"),
+            TunnelStatus::Queued => write!(f, "queued"),
+            TunnelStatus::WaitingForVpn => write!(f, "waiting for VPN"),
+        }
+    }
+}
+
+/// How a supervisor should stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// Kill the ssh process and close the tunnel.
+    Close,
+    /// Leave the ssh process running for another rtun instance to adopt.
+    Detach,
+}
+
+/// Commands accepted by a running supervisor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    Stop(Stop),
+    /// Replace the ssh process right away, e.g. after the network changed.
+    Restart(String),
+    /// The code asked for by a [`Prompt::Code`].
+    Answer(String),
+    /// Stop taking connections and close once the open ones are over. Only
+    /// tunnels with a [`Relay`] can; others close right away.
+    Drain,
+    /// Forward to another destination from now on. Tunnels with a [`Relay`]
+    /// keep the connections they carry on the old one until they are over;
+    /// others restart on the new one.
+    Retarget(RemoteTarget),
+    /// Connect ssh only once a client connects from now on, or right away.
+    /// Turned on, the session goes when no client would notice, and tunnels
+    /// without a [`Relay`] get one.
+    Lazy(bool),
+}
+
+/// Something ssh waits on the user for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prompt {
+    /// A touch of a FIDO2 security key.
+    Touch(String),
+    /// A one-time code, answered with [`Control::Answer`].
+    Code(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelEvent {
+    pub local_port: u16,
+    pub status: TunnelStatus,
+    /// Why the tunnel went down, when it did.
+    pub detail: Option<String>,
+    /// Destination the forward currently points at.
+    pub target: String,
+    /// Per-destination connection counts of load balanced tunnels.
+    pub connections: Option<Arc<RelayStats>>,
+    /// What ssh waits on the user for, such as touching a security key.
+    pub prompt: Option<Prompt>,
+}
+
+/// Something the ssh process did.
+enum Activity {
+    /// It started or stopped waiting on the user.
+    Prompt(Option<Prompt>),
+    /// It exited, for the given reason.
+    Exited(String),
+}
+
+/// The ssh process backing a tunnel: started by this supervisor, or adopted
+/// from an rtun instance that went away without cleaning up.
+enum SshProcess {
+    Spawned {
+        child: Child,
+        // Read while the process runs, to pass prompts on.
+        stderr: Option<Box<Lines<BufReader<ChildStderr>>>>,
+        last_line: Option<String>,
+    },
+    Adopted(u32),
+}
+
+impl SshProcess {
+    fn spawned(mut child: Child) -> Self {
+        let stderr = child
+            .stderr
+            .take()
+            .map(|e| Box::new(BufReader::new(e).lines()));
+        SshProcess::Spawned {
+            child,
+            stderr,
+            last_line: None,
+        }
+    }
+
+    fn id(&self) -> Option<u32> {
+        match self {
+            SshProcess::Spawned { child, .. } => child.id(),
+            SshProcess::Adopted(pid) => Some(*pid),
+        }
+    }
+
+    /// Waits for the process to prompt or exit, describing why it exited.
+    async fn next(&mut self) -> Activity {
+        match self {
+            SshProcess::Spawned {
+                child,
+                stderr,
+                last_line,
+            } => loop {
+                let line = match stderr {
+                    Some(lines) => tokio::select! {
+                        line = lines.next_line() => line.ok().flatten(),
+                        status = child.wait() => {
+                            // Whatever ssh said last explains why it exited.
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                remember(last_line, line);
+                            }
+                            return Activity::Exited(exit_reason(status, last_line));
+                        }
+                    },
+                    None => {
+                        let status = child.wait().await;
+                        return Activity::Exited(exit_reason(status, last_line));
+                    }
+                };
+                let Some(line) = line else {
+                    *stderr = None;
+                    continue;
+                };
+                if let Some(prompt) = line.strip_prefix(secrets::PROMPT_START) {
+                    return Activity::Prompt(Some(Prompt::Touch(prompt.to_string())));
+                }
+                if let Some(prompt) = line.strip_prefix(otp::CODE_START) {
+                    return Activity::Prompt(Some(Prompt::Code(prompt.to_string())));
+                }
+                if line == secrets::PROMPT_END {
+                    return Activity::Prompt(None);
+                }
+                remember(last_line, line);
+            },
+            SshProcess::Adopted(pid) => {
+                while state::is_alive(*pid) {
+                    tokio::time::sleep(ADOPTED_POLL_INTERVAL).await;
+                }
+                Activity::Exited(format!("adopted ssh process {} exited", pid))
+            }
+        }
+    }
+
+    async fn kill(&mut self) {
+        match self {
+            SshProcess::Spawned { child, .. } => {
+                let _ = child.kill().await;
+            }
+            SshProcess::Adopted(pid) => state::terminate(*pid),
+        }
+    }
+}
+
+fn remember(last_line: &mut Option<String>, line: String) {
+    let prompt = line.starts_with(secrets::PROMPT_START) || line.starts_with(otp::CODE_START);
+    if !line.trim().is_empty() && !prompt {
+        *last_line = Some(line.trim().to_string());
+    }
+}
+
+fn exit_reason(
+    status: std::io::Result<std::process::ExitStatus>,
+    last_line: &Option<String>,
+) -> String {
+    match (last_line, status) {
+        (Some(line), _) => line.clone(),
+        (None, Ok(status)) => format!("ssh exited with {}", status),
+        (None, Err(e)) => e.to_string(),
+    }
+}
+
+/// Keeps the ssh process for `spec` alive until told to stop, restarting it
+/// when it exits unexpectedly. Every lifecycle change is written to the history
+/// log and reported on `events`, and configured alerts fire when it goes down.
+pub async fn supervise(
+    spec: TunnelSpec,
+    config: Arc<Config>,
+    events: mpsc::UnboundedSender<TunnelEvent>,
+    control: mpsc::UnboundedReceiver<Control>,
+) {
+    run(spec, None, config, events, control).await
+}
+
+/// Like [`supervise`], but starts by managing an ssh process left behind by a
+/// previous rtun run instead of spawning a new one.
+pub async fn adopt(
+    record: ProcessRecord,
+    config: Arc<Config>,
+    events: mpsc::UnboundedSender<TunnelEvent>,
+    control: mpsc::UnboundedReceiver<Control>,
+) {
+    state::adopt(&record);
+    run(record.spec(), Some(record.pid), config, events, control).await
+}
+
+/// Resolves once the health check gives up on the forward; never without one.
+async fn unhealthy(local_port: u16, check: Option<HealthCheckConfig>) {
+    match check {
+        Some(check) => health::wait_unhealthy(local_port, check).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves with the reason once the watchdog found the session wedged;
+/// never without one.
+async fn wedged(spec: &TunnelSpec, config: &Config) -> String {
+    match spec.watchdog {
+        Some(check) => watchdog::wait_wedged(spec, check, config.ssh.client(&spec.host)).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once the clients of `relay` are all gone; never without one.
+async fn drained(relay: Option<&Relay>) {
+    let Some(relay) = relay else {
+        return std::future::pending().await;
+    };
+    while relay.stats.open() > 0 {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Keeps the ssh process of a forward swapped out by [`Control::Retarget`]
+/// until the clients it carries, those before `first_new`, are gone, or
+/// until the supervisor ends and drops the sender of `ended`.
+async fn retire(
+    mut process: SshProcess,
+    stats: Arc<RelayStats>,
+    first_new: u64,
+    mut ended: watch::Receiver<()>,
+) {
+    let pid = process.id();
+    let carried = || stats.clients().iter().any(|client| client.id < first_new);
+    while carried() {
+        tokio::select! {
+            _ = tokio::time::sleep(DRAIN_POLL_INTERVAL) => {}
+            _ = ended.changed() => break,
+        }
+    }
+    process.kill().await;
+    if let Some(pid) = pid {
+        state::unregister(pid);
+    }
+}
+
+/// Resolves once the forward went unused for `timeout`; never without one.
+async fn idle(local_port: u16, timeout: Option<Duration>, interval: Duration) {
+    match timeout {
+        Some(timeout) => idle::wait_idle(local_port, timeout, interval).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn run(
+    spec: TunnelSpec,
+    mut adopted: Option<u32>,
+    config: Arc<Config>,
+    events: mpsc::UnboundedSender<TunnelEvent>,
+    mut control: mpsc::UnboundedReceiver<Control>,
+) {
+    let targets = spec.targets();
+    let mut active = 0;
+    let sample_interval = config.intervals.stats_sample();
+    let relay = match (spec.relayed(), spec.load_balance) {
+        (true, Some(_)) => Some(Relay::start(&spec, &targets, sample_interval).await),
+        // Failing over swaps the destination behind the single backend.
+        (true, None) => Some(Relay::start(&spec, &targets[..1], sample_interval).await),
+        (false, _) => None,
+    };
+    // Set once a tunnel turned lazy gets its relay.
+    let connections = Mutex::new(match &relay {
+        Some(Ok(relay)) => Some(relay.stats.clone()),
+        _ => None,
+    });
+    // Changed by Control::Retarget, while the closures below read it.
+    let targets = Mutex::new(targets);
+    // Dropped when the supervisor ends, which stops the retired processes.
+    let (_ended, ended) = watch::channel(());
+    let notify = |status, detail: Option<&str>, active: usize| {
+        let _ = events.send(TunnelEvent {
+            local_port: spec.local_port,
+            status,
+            detail: detail.map(str::to_string),
+            target: targets.lock().unwrap_or_else(|e| e.into_inner())[active].to_string(),
+            connections: connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            prompt: None,
+        });
+    };
+    let prompt = |status, prompt: Option<Prompt>, active: usize| {
+        let _ = events.send(TunnelEvent {
+            local_port: spec.local_port,
+            status,
+            detail: None,
+            target: targets.lock().unwrap_or_else(|e| e.into_inner())[active].to_string(),
+            connections: connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            prompt,
+        });
+    };
+    let alert = |event, error: &str| {
+        if let Some(alerts) = &config.alerts {
+            alerts::fire(alerts, event, &spec, error);
+        }
+    };
+    let policy = spec.reconnect.or(config.reconnect).policy();
+    let backend_name = spec.backend.as_deref().unwrap_or(backend::DEFAULT);
+    let Some(backend) = backend::get(backend_name) else {
+        let error = format!("Unknown backend '{}'", backend_name);
+        history::record(EventKind::Failure, &spec, Some(error.clone()));
+        notify(TunnelStatus::Failed, Some(&error), active);
+        return;
+    };
+    let health_check = backend
+        .health_check(&spec)
+        .map(|check| match spec.health_check {
+            Some(_) => check,
+            None => HealthCheckConfig {
+                interval: config.intervals.health_check,
+                ..check
+            },
+        });
+    if let Some(error) = config.strict_violation(&spec) {
+        history::record(EventKind::Failure, &spec, Some(error.clone()));
+        notify(TunnelStatus::Failed, Some(&error), active);
+        return;
+    }
+    let idle_timeout = config.idle_timeout(&spec);
+    let vpn = match &spec.requires_vpn {
+        Some(name) => match config.vpn.get(name) {
+            Some(vpn) => Some((name, vpn)),
+            None => {
+                let error = format!("Unknown VPN '{}'", name);
+                history::record(EventKind::Failure, &spec, Some(error.clone()));
+                notify(TunnelStatus::Failed, Some(&error), active);
+                return;
+            }
+        },
+        None => None,
+    };
+    let knock = spec
+        .knock
+        .clone()
+        .or_else(|| config.knock.get(spec.first_hop()).cloned());
+    let mut relay = match relay {
+        Some(Ok(relay)) => Some(relay),
+        Some(Err(e)) => {
+            let error = format!("Could not listen on port {}: {}", spec.local_port, e);
+            history::record(EventKind::Failure, &spec, Some(error.clone()));
+            alert(AlertEvent::Failed, &error);
+            notify(TunnelStatus::Failed, Some(&error), active);
+            return;
+        }
+        None => None,
+    };
+    let _tls = match spec.tls_port {
+        Some(tls_port) => match TlsTerminator::start(&spec, tls_port).await {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                let error = format!("{:#}", e);
+                history::record(EventKind::Failure, &spec, Some(error.clone()));
+                alert(AlertEvent::Failed, &error);
+                notify(TunnelStatus::Failed, Some(&error), active);
+                return;
+            }
+        },
+        None => None,
+    };
+    // Left by an earlier tunnel on the port.
+    remoteport::forget(spec.local_port);
+    let mut attempt = 0;
+    let mut restart_reason: Option<String> = None;
+    let mut draining = false;
+    // Lazy tunnels wait for a client before connecting, and again once idle.
+    let mut lazy = spec.lazy;
+    let mut parked = lazy && adopted.is_none();
+    'supervise: loop {
+        let started = Instant::now();
+        let detail = 'attempt: {
+            // Asked for ahead of ssh, for hosts known to want one.
+            let mut code: Option<String> = None;
+            let mut process = if let Some(pid) = adopted.take() {
+                history::record(
+                    EventKind::Open,
+                    &spec,
+                    Some(format!("adopted ssh process {}", pid)),
+                );
+                SshProcess::Adopted(pid)
+            } else {
+                if lazy && relay.is_none() {
+                    // The first target alone, as failing over swaps it.
+                    let first = targets.lock().unwrap_or_else(|e| e.into_inner())[..1].to_vec();
+                    match Relay::start(&spec, &first, sample_interval).await {
+                        Ok(started) => {
+                            *connections.lock().unwrap_or_else(|e| e.into_inner()) =
+                                Some(started.stats.clone());
+                            relay = Some(started);
+                        }
+                        Err(e) => {
+                            let error =
+                                format!("Could not listen on port {}: {}", spec.local_port, e);
+                            history::record(EventKind::Failure, &spec, Some(error.clone()));
+                            alert(AlertEvent::Failed, &error);
+                            notify(TunnelStatus::Failed, Some(&error), active);
+                            return;
+                        }
+                    }
+                }
+                if let Some(relay) = &relay {
+                    relay.set_lazy(lazy);
+                }
+                if let Some(relay) = relay.as_ref().filter(|_| parked) {
+                    notify(TunnelStatus::Parked, None, active);
+                    // Woken by every client, also those of the last session.
+                    while relay.stats.open() == 0 {
+                        tokio::select! {
+                            command = control.recv() => match command {
+                                Some(Control::Restart(_) | Control::Answer(_) | Control::Lazy(true)) => {}
+                                Some(Control::Lazy(false)) => {
+                                    lazy = false;
+                                    relay.set_lazy(false);
+                                    break;
+                                }
+                                Some(Control::Retarget(target)) => {
+                                    *targets.lock().unwrap_or_else(|e| e.into_inner()) = vec![target];
+                                    active = 0;
+                                }
+                                Some(Control::Stop(_) | Control::Drain) | None => {
+                                    history::record(EventKind::Close, &spec, None);
+                                    notify(TunnelStatus::Closed, None, active);
+                                    return;
+                                }
+                            },
+                            _ = relay.demand() => {}
+                        }
+                    }
+                    parked = false;
+                }
+                if let Some((name, vpn)) = vpn {
+                    if !vpn::is_up(vpn).await {
+                        notify(TunnelStatus::WaitingForVpn, Some(name), active);
+                    }
+                    while !vpn::is_up(vpn).await {
+                        tokio::select! {
+                            command = control.recv() => match command {
+                                Some(Control::Restart(_) | Control::Answer(_)) => {}
+                                Some(Control::Lazy(on)) => lazy = on,
+                                Some(Control::Retarget(target)) => {
+                                    *targets.lock().unwrap_or_else(|e| e.into_inner()) = vec![target];
+                                    active = 0;
+                                }
+                                Some(Control::Stop(_) | Control::Drain) | None => {
+                                    history::record(EventKind::Close, &spec, None);
+                                    notify(TunnelStatus::Closed, None, active);
+                                    return;
+                                }
+                            },
+                            _ = tokio::time::sleep(VPN_POLL_INTERVAL) => {}
+                        }
+                    }
+                }
+                if let Some(knock) = &knock {
+                    knock::knock(spec.first_hop(), knock).await;
+                }
+                if spec.via.is_empty() {
+                    // Alongside ssh, which checks the keys its own way.
+                    tokio::spawn(hostkeys::check(
+                        config.ssh.client(&spec.host),
+                        spec.host.clone(),
+                    ));
+                }
+                if config.remote_port_check && spec.via.is_empty() && !spec.socks {
+                    // A warning rather than a failure, so no need to wait.
+                    tokio::spawn(remoteport::check(
+                        config.ssh.client(&spec.host),
+                        spec.host.clone(),
+                        spec.local_port,
+                        targets.lock().unwrap_or_else(|e| e.into_inner())[active].clone(),
+                    ));
+                }
+                if config.reachability_check {
+                    // Fail fast instead of waiting for ssh to time out.
+                    if let Err(e) =
+                        reachability::check(&config.ssh.client(spec.first_hop()), spec.first_hop())
+                            .await
+                    {
+                        break 'attempt e;
+                    }
+                }
+                if otp::needs_code(&spec.host) {
+                    // Typed in before ssh starts, so the server's login grace
+                    // time does not run out meanwhile.
+                    let status = match attempt {
+                        0 => TunnelStatus::Up,
+                        attempt => TunnelStatus::Reconnecting(attempt),
+                    };
+                    let text = format!("Verification code for {}", spec.host);
+                    prompt(status, Some(Prompt::Code(text)), active);
+                    code = loop {
+                        match control.recv().await {
+                            Some(Control::Answer(answer)) => break Some(answer),
+                            Some(Control::Restart(_)) => {}
+                            Some(Control::Lazy(on)) => lazy = on,
+                            Some(Control::Retarget(target)) => {
+                                *targets.lock().unwrap_or_else(|e| e.into_inner()) = vec![target];
+                                active = 0;
+                            }
+                            Some(Control::Stop(_) | Control::Drain) | None => {
+                                history::record(EventKind::Close, &spec, None);
+                                notify(TunnelStatus::Closed, None, active);
+                                return;
+                            }
+                        }
+                    };
+                }
+                let forwards: Vec<Forward> = backend::forwards(
+                    &spec,
+                    &targets.lock().unwrap_or_else(|e| e.into_inner())[active],
+                    relay.as_ref(),
+                );
+                let child = match backend.connect(&spec, &forwards, &config) {
+                    Ok(child) => child,
+                    Err(e) => {
+                        history::record(EventKind::Failure, &spec, Some(e.to_string()));
+                        alert(AlertEvent::Failed, &e.to_string());
+                        notify(TunnelStatus::Failed, Some(&e.to_string()), active);
+                        return;
+                    }
+                };
+                if let Some(pid) = child.id() {
+                    state::register(pid, &spec);
+                }
+                let kind = if attempt == 0 && restart_reason.is_none() {
+                    EventKind::Open
+                } else {
+                    EventKind::Reconnect
+                };
+                history::record(kind, &spec, restart_reason.take());
+                SshProcess::spawned(child)
+            };
+            notify(TunnelStatus::Up, None, active);
+            // Taken now: a child no longer reports its pid once it has exited.
+            let pid = process.id();
+
+            let gave_up = unhealthy(spec.local_port, health_check);
+            tokio::pin!(gave_up);
+            let unused = idle(spec.local_port, idle_timeout, config.intervals.idle_check());
+            tokio::pin!(unused);
+            let wedged = wedged(&spec, &config);
+            tokio::pin!(wedged);
+            let detail = loop {
+                tokio::select! {
+                    command = control.recv() => {
+                        if let Some(Control::Answer(answer)) = command {
+                            if let Some(pid) = pid {
+                                // Fails if ssh stopped asking meanwhile.
+                                let _ = otp::answer(pid, &answer).await;
+                            }
+                            prompt(TunnelStatus::Up, None, active);
+                            continue;
+                        }
+                        if command == Some(Control::Drain) && relay.is_some() {
+                            if let Some(relay) = &relay {
+                                relay.drain();
+                            }
+                            draining = true;
+                            notify(TunnelStatus::Draining, None, active);
+                            continue;
+                        }
+                        // The listener is gone, so a new ssh process would
+                        // have no clients.
+                        if draining && matches!(command, Some(Control::Restart(_) | Control::Retarget(_) | Control::Lazy(_))) {
+                            continue;
+                        }
+                        if let Some(Control::Lazy(on)) = command {
+                            lazy = on;
+                            if let Some(relay) = &relay {
+                                relay.set_lazy(on);
+                            }
+                            // Right away when no client would notice; tunnels
+                            // without a relay restart to get one.
+                            let unused = relay.as_ref().is_none_or(|relay| relay.stats.open() == 0);
+                            if on && unused {
+                                process.kill().await;
+                                if let Some(pid) = pid {
+                                    state::unregister(pid);
+                                }
+                                backend.teardown(&spec);
+                                let detail = "connecting on demand".to_string();
+                                history::record(EventKind::Close, &spec, Some(detail));
+                                parked = true;
+                                attempt = 0;
+                                continue 'supervise;
+                            }
+                            continue;
+                        }
+                        if let Some(Control::Retarget(target)) = command {
+                            let previous = targets.lock().unwrap_or_else(|e| e.into_inner())[active].to_string();
+                            let handover = relay.as_ref().map(|relay| (relay.retarget(), relay.stats.clone()));
+                            // With a master connection, this only stops listening
+                            // on the old port; its channels carry on.
+                            backend.teardown(&spec);
+                            match handover {
+                                Some((Ok(first_new), stats)) => {
+                                    tokio::spawn(retire(process, stats, first_new, ended.clone()));
+                                }
+                                _ => {
+                                    process.kill().await;
+                                    if let Some(pid) = pid {
+                                        state::unregister(pid);
+                                    }
+                                }
+                            }
+                            restart_reason = Some(format!("retargeted from {} to {}", previous, target));
+                            *targets.lock().unwrap_or_else(|e| e.into_inner()) = vec![target];
+                            active = 0;
+                            attempt = 0;
+                            continue 'supervise;
+                        }
+                        // The relay listener dies with us, so there is nothing to hand over.
+                        if command == Some(Control::Stop(Stop::Detach)) && relay.is_none() {
+                            notify(TunnelStatus::Closed, None, active);
+                            return;
+                        }
+                        process.kill().await;
+                        if let Some(pid) = pid {
+                            state::unregister(pid);
+                        }
+                        backend.teardown(&spec);
+                        if let Some(Control::Restart(reason)) = command {
+                            attempt = 0;
+                            restart_reason = Some(reason);
+                            continue 'supervise;
+                        }
+                        history::record(EventKind::Close, &spec, None);
+                        notify(TunnelStatus::Closed, None, active);
+                        return;
+                    }
+                    activity = process.next() => match activity {
+                        Activity::Prompt(Some(Prompt::Code(text))) => {
+                            // Forgetting only costs asking later next time.
+                            let _ = otp::remember(&spec.host);
+                            let answered = match (code.take(), pid) {
+                                (Some(answer), Some(pid)) => {
+                                    otp::answer(pid, &answer).await.is_ok()
+                                }
+                                _ => false,
+                            };
+                            if !answered {
+                                prompt(TunnelStatus::Up, Some(Prompt::Code(text)), active);
+                            }
+                        }
+                        Activity::Prompt(text) => prompt(TunnelStatus::Up, text, active),
+                        Activity::Exited(detail) => break detail,
+                    },
+                    _ = drained(relay.as_ref()), if draining => {
+                        process.kill().await;
+                        if let Some(pid) = pid {
+                            state::unregister(pid);
+                        }
+                        backend.teardown(&spec);
+                        let detail = "drained".to_string();
+                        history::record(EventKind::Close, &spec, Some(detail.clone()));
+                        notify(TunnelStatus::Closed, Some(&detail), active);
+                        return;
+                    }
+                    _ = &mut gave_up, if !draining => {
+                        process.kill().await;
+                        if let Some(pid) = pid {
+                            state::unregister(pid);
+                        }
+                        backend.teardown(&spec);
+                        let targets = targets.lock().unwrap_or_else(|e| e.into_inner());
+                        let failed = &targets[active];
+                        active = (active + 1) % targets.len();
+                        attempt = 0;
+                        restart_reason = Some(format!(
+                            "health check failed on {}, switching to {}",
+                            failed, targets[active]
+                        ));
+                        continue 'supervise;
+                    }
+                    reason = &mut wedged, if !draining => {
+                        process.kill().await;
+                        if let Some(pid) = pid {
+                            state::unregister(pid);
+                        }
+                        backend.teardown(&spec);
+                        attempt = 0;
+                        restart_reason = Some(reason);
+                        continue 'supervise;
+                    }
+                    _ = &mut unused => {
+                        process.kill().await;
+                        if let Some(pid) = pid {
+                            state::unregister(pid);
+                        }
+                        backend.teardown(&spec);
+                        let detail = format!(
+                            "idle for {} minutes",
+                            idle_timeout.unwrap_or_default().as_secs() / 60
+                        );
+                        history::record(EventKind::Close, &spec, Some(detail.clone()));
+                        // Only the ssh session goes, the tunnel stays.
+                        if lazy && !draining {
+                            parked = true;
+                            attempt = 0;
+                            continue 'supervise;
+                        }
+                        notify(TunnelStatus::Closed, Some(&detail), active);
+                        return;
+                    }
+                }
+            };
+            if let Some(pid) = pid {
+                state::unregister(pid);
+            }
+            backend.teardown(&spec);
+            detail
+        };
+        if draining {
+            // Its clients went down with ssh; there is nothing left to drain.
+            history::record(EventKind::Close, &spec, Some(detail.clone()));
+            notify(TunnelStatus::Closed, Some(&detail), active);
+            return;
+        }
+        alert(AlertEvent::Down, &detail);
+        history::record(EventKind::Failure, &spec, Some(detail.clone()));
+        // rtun holds the local port itself, so a taken port is a backend one.
+        if let Some(relay) = &relay {
+            if ErrorCode::of_tunnel_error(&detail) == ErrorCode::PortConflict {
+                let _ = relay.rebind();
+            }
+        }
+        // Nobody to reconnect for; the next client tries again.
+        if lazy && relay.as_ref().is_some_and(|relay| relay.stats.open() == 0) {
+            parked = true;
+            attempt = 0;
+            continue;
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            attempt = 0;
+        }
+        attempt += 1;
+        if policy.gives_up_after(attempt) {
+            alert(AlertEvent::Failed, &detail);
+            notify(TunnelStatus::Failed, Some(&detail), active);
+            return;
+        }
+        notify(TunnelStatus::Reconnecting(attempt), Some(&detail), active);
+        tokio::select! {
+            command = control.recv() => match command {
+                // No point in waiting out the backoff once the network is back.
+                Some(Control::Restart(reason)) => {
+                    attempt = 0;
+                    restart_reason = Some(reason);
+                }
+                Some(Control::Retarget(target)) => {
+                    attempt = 0;
+                    restart_reason = Some(format!("retargeted to {}", target));
+                    *targets.lock().unwrap_or_else(|e| e.into_inner()) = vec![target];
+                    active = 0;
+                }
+                // Taken up by the next attempt.
+                Some(Control::Lazy(on)) => lazy = on,
+                // Left over from a prompt that went away.
+                Some(Control::Answer(_)) => {}
+                // No ssh process, so no clients to wait for.
+                Some(Control::Stop(_) | Control::Drain) | None => {
+                    history::record(EventKind::Close, &spec, None);
+                    notify(TunnelStatus::Closed, None, active);
+                    return;
+                }
+            },
+            _ = tokio::time::sleep(policy.delay(attempt)) => {}
+        }
+    }
+}
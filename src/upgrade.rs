@@ -0,0 +1,186 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/andycancado/rtun/releases/latest";
+/// Asset of a release listing the SHA-256 of the others, as `sha256sum` prints them.
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+/// Lines of the release notes shown before upgrading.
+pub const CHANGELOG_LINES: usize = 15;
+
+/// A GitHub release, as much of it as `rtun upgrade` needs.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| eyre!("Release {} has no {}", self.tag_name, name))
+    }
+}
+
+/// `1.2.3` as numbers, ignoring a leading `v` and anything after a `-`.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim_start_matches('v');
+    let version = version.split('-').next()?;
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    let version = (
+        parts.next()??,
+        parts.next()??,
+        parts.next().unwrap_or(Some(0))?,
+    );
+    parts.next().is_none().then_some(version)
+}
+
+/// Whether `latest` is a later version than `current`.
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Name of the release asset built for this platform, such as
+/// `rtun-x86_64-linux`.
+pub fn asset_name() -> String {
+    format!("rtun-{}-{}", env::consts::ARCH, env::consts::OS)
+}
+
+/// The SHA-256 `sums`, in the format of `sha256sum`, gives for `name`.
+pub fn checksum_for<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (sum, file) = line.split_once(char::is_whitespace)?;
+        // `sha256sum -b` marks binary files with a `*`.
+        (file.trim_start().trim_start_matches('*') == name).then_some(sum)
+    })
+}
+
+/// The first lines of the release notes, without blank ones.
+pub fn changelog(body: &str, lines: usize) -> Vec<&str> {
+    body.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .take(lines)
+        .collect()
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args([
+            "-fsSL",
+            "-m",
+            "120",
+            "-H",
+            "Accept: application/octet-stream",
+        ])
+        .args(["-H", "User-Agent: rtun"])
+        .arg(url)
+        .output()
+        .wrap_err("Could not run curl")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("Could not download {}: {}", url, stderr.trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// The latest release on GitHub.
+pub fn latest() -> Result<Release> {
+    let output = Command::new("curl")
+        .args([
+            "-fsSL",
+            "-m",
+            "30",
+            "-H",
+            "Accept: application/vnd.github+json",
+        ])
+        .args(["-H", "User-Agent: rtun"])
+        .arg(LATEST_RELEASE_URL)
+        .output()
+        .wrap_err("Could not run curl")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("Could not check for releases: {}", stderr.trim()));
+    }
+    serde_json::from_slice(&output.stdout).wrap_err("Unexpected answer from GitHub")
+}
+
+/// Downloads the binary of `release` for this platform and checks it
+/// against the release's `SHA256SUMS`.
+pub fn download(release: &Release) -> Result<Vec<u8>> {
+    let name = asset_name();
+    let binary = release.asset(&name)?;
+    let sums = fetch(&release.asset(CHECKSUMS_ASSET)?.browser_download_url)?;
+    let sums = String::from_utf8_lossy(&sums);
+    let expected = checksum_for(&sums, &name).ok_or_else(|| {
+        eyre!(
+            "{} of {} has no entry for {}",
+            CHECKSUMS_ASSET,
+            release.tag_name,
+            name
+        )
+    })?;
+    let bytes = fetch(&binary.browser_download_url)?;
+    let actual = format!("{:x}", Sha256::digest(&bytes));
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(eyre!(
+            "Checksum mismatch for {}: expected {}, got {}. Nothing was replaced.",
+            name,
+            expected,
+            actual
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Puts `bytes` in place of the executable at `path`, by writing them next
+/// to it and renaming over it, so that it is never left half-written.
+pub fn replace(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut name = path
+        .file_name()
+        .ok_or_else(|| eyre!("{} is not a file", path.display()))?
+        .to_os_string();
+    name.push(".new");
+    let tmp: PathBuf = path.with_file_name(name);
+    let written = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        fs::rename(&tmp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written.wrap_err_with(|| {
+        format!(
+            "Could not replace {}; run rtun upgrade as a user who can write there",
+            path.display()
+        )
+    })
+}
@@ -0,0 +1,115 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::tunnel::TunnelSpec;
+
+const USAGE_PATH: &str = ".rtun/usage.json";
+
+// The usage of tunnels is added up as they go down, possibly at once.
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+
+/// How much a tunnel was used, over all rtun sessions, as recorded in
+/// `~/.rtun/usage.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    pub host: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// How often it was opened.
+    pub opens: u64,
+    /// Seconds it was up.
+    pub uptime: u64,
+    /// Bytes relayed either way, only counted for tunnels going through the
+    /// native relay.
+    pub bytes: u64,
+    pub last_used: DateTime<Utc>,
+}
+
+impl Usage {
+    fn is(&self, spec: &TunnelSpec) -> bool {
+        self.host == spec.host
+            && self.local_port == spec.local_port
+            && self.remote_port == spec.remote_port
+    }
+}
+
+fn usage_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(USAGE_PATH))
+}
+
+fn read() -> Vec<Usage> {
+    usage_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write(entries: &[Usage]) -> std::io::Result<()> {
+    let path = usage_path().ok_or(std::io::ErrorKind::NotFound)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(entries)?)?;
+    fs::rename(tmp, path)
+}
+
+/// Updates the usage of `spec`, starting from nothing when it has none yet.
+/// Failing to write must never take a tunnel down, so errors are ignored.
+fn update(spec: &TunnelSpec, f: impl FnOnce(&mut Usage)) {
+    let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = read();
+    let index = match entries.iter().position(|u| u.is(spec)) {
+        Some(index) => index,
+        None => {
+            entries.push(Usage {
+                host: spec.host.clone(),
+                local_port: spec.local_port,
+                remote_port: spec.remote_port,
+                opens: 0,
+                uptime: 0,
+                bytes: 0,
+                last_used: Utc::now(),
+            });
+            entries.len() - 1
+        }
+    };
+    f(&mut entries[index]);
+    entries[index].last_used = Utc::now();
+    let _ = write(&entries);
+}
+
+/// Counts an opening of `spec`.
+pub fn opened(spec: &TunnelSpec) {
+    update(spec, |usage| usage.opens += 1);
+}
+
+/// Adds a stretch of `up` time and `bytes` relayed meanwhile to `spec`.
+pub fn add(spec: &TunnelSpec, up: Duration, bytes: u64) {
+    update(spec, |usage| {
+        usage.uptime += up.as_secs();
+        usage.bytes += bytes;
+    });
+}
+
+/// Everything recorded, the longest up first.
+pub fn load() -> Vec<Usage> {
+    let mut entries = read();
+    entries.sort_by(|a, b| b.uptime.cmp(&a.uptime).then(b.opens.cmp(&a.opens)));
+    entries
+}
+
+/// Those of `tunnels` that were never opened, say of profiles worth
+/// pruning.
+pub fn unused<'a>(usage: &[Usage], tunnels: &'a [TunnelSpec]) -> Vec<&'a TunnelSpec> {
+    tunnels
+        .iter()
+        .filter(|spec| !usage.iter().any(|u| u.is(spec)))
+        .collect()
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+use crate::config::VpnConfig;
+use crate::platform;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Whether every condition of `vpn` currently holds.
+pub async fn is_up(vpn: &VpnConfig) -> bool {
+    if let Some(interface) = &vpn.interface {
+        let present = if_addrs::get_if_addrs()
+            .unwrap_or_default()
+            .iter()
+            .any(|iface| &iface.name == interface);
+        if !present {
+            return false;
+        }
+    }
+    if let Some(probe) = &vpn.probe {
+        let reachable = tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(probe.as_str()))
+            .await
+            .is_ok_and(|stream| stream.is_ok());
+        if !reachable {
+            return false;
+        }
+    }
+    if let Some(command) = &vpn.command {
+        let succeeded = Command::new(platform::shell())
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .is_ok_and(|status| status.success());
+        if !succeeded {
+            return false;
+        }
+    }
+    true
+}
+
+/// Reports `(name, up)` whenever one of `vpns` connects or disconnects,
+/// starting with those already up. Runs until `changes` is dropped.
+pub async fn watch(
+    vpns: BTreeMap<String, VpnConfig>,
+    changes: mpsc::UnboundedSender<(String, bool)>,
+) {
+    let mut up: BTreeMap<&String, bool> = BTreeMap::new();
+    loop {
+        for (name, vpn) in vpns.iter() {
+            let now_up = is_up(vpn).await;
+            if up.insert(name, now_up).unwrap_or(false) != now_up
+                && changes.send((name.clone(), now_up)).is_err()
+            {
+                return;
+            }
+        }
+        tokio::time::sleep(WATCH_INTERVAL).await;
+    }
+}
@@ -0,0 +1,107 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+
+use crate::config::{SshClient, WatchdogConfig};
+use crate::tunnel::TunnelSpec;
+
+const CONTROL_SOCKETS_PATH: &str = ".rtun/control";
+// How long test data may take through the loop of forwards, or ssh to
+// answer `-O check`.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Control socket of the ssh process of the tunnel on `local_port`, for
+/// watchdogs without a monitor port.
+pub fn control_path(local_port: u16) -> Option<PathBuf> {
+    env::home_dir().map(|home| {
+        home.join(CONTROL_SOCKETS_PATH)
+            .join(format!("{}.sock", local_port))
+    })
+}
+
+/// Whether the watchdog of `spec` asks ssh through a control socket, rather
+/// than sending data through a monitor port.
+pub fn uses_control_socket(spec: &TunnelSpec) -> bool {
+    spec.watchdog.is_some_and(|w| w.monitor_port.is_none())
+}
+
+/// Whether test data sent to the forward on `port` comes back on
+/// `port + 1`, through the remote forward ssh carries from the remote end
+/// of `port` back here.
+pub async fn monitor(port: u16) -> bool {
+    let Ok(listener) = TcpListener::bind(("127.0.0.1", port.wrapping_add(1))).await else {
+        return false;
+    };
+    let token = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string();
+    let round_trip = async {
+        let mut sent = TcpStream::connect(("127.0.0.1", port)).await.ok()?;
+        sent.write_all(token.as_bytes()).await.ok()?;
+        let (mut received, _) = listener.accept().await.ok()?;
+        let mut echoed = vec![0; token.len()];
+        received.read_exact(&mut echoed).await.ok()?;
+        Some(echoed == token.as_bytes())
+    };
+    matches!(
+        tokio::time::timeout(CHECK_TIMEOUT, round_trip).await,
+        Ok(Some(true))
+    )
+}
+
+/// Whether the ssh process behind the control socket of `spec` answers.
+async fn control_check(client: &SshClient, spec: &TunnelSpec) -> bool {
+    let Some(path) = control_path(spec.local_port) else {
+        return false;
+    };
+    let status = Command::new(client.program())
+        .arg("-S")
+        .arg(path)
+        .args(["-O", "check"])
+        .arg(&spec.host)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status();
+    matches!(
+        tokio::time::timeout(CHECK_TIMEOUT, status).await,
+        Ok(Ok(status)) if status.success()
+    )
+}
+
+/// Checks the session of `spec` every `watchdog.interval` seconds and
+/// returns why once `watchdog.failures` checks in a row have failed. Never
+/// returns for clients other than OpenSSH without a monitor port, as they
+/// have no control socket to ask.
+pub async fn wait_wedged(spec: &TunnelSpec, watchdog: WatchdogConfig, client: SshClient) -> String {
+    if watchdog.monitor_port.is_none() && !client.is_openssh() {
+        return std::future::pending().await;
+    }
+    let mut failures = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(watchdog.interval.max(1))).await;
+        let alive = match watchdog.monitor_port {
+            Some(port) => monitor(port).await,
+            None => control_check(&client, spec).await,
+        };
+        if alive {
+            failures = 0;
+            continue;
+        }
+        failures += 1;
+        if failures >= watchdog.failures.max(1) {
+            return match watchdog.monitor_port {
+                Some(port) => format!("watchdog: no data back through monitor port {}", port),
+                None => "watchdog: ssh -O check failed".to_string(),
+            };
+        }
+    }
+}
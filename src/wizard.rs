@@ -0,0 +1,354 @@
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::process::Command;
+use tokio::sync::oneshot;
+
+use crate::audit;
+use crate::certs;
+use crate::config::{SshClient, SshConfig};
+use crate::palette;
+use crate::recent::Recent;
+use crate::tunnel::TunnelSpec;
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lists listening TCP sockets with whichever tool the host has.
+const DISCOVERY_COMMAND: &str =
+    "ss -Hltn 2>/dev/null || netstat -an -p tcp 2>/dev/null || netstat -ltn";
+
+/// Ports in the output of `ss -Hltn` or `netstat`, whose fourth column is the
+/// local address of the socket: `0.0.0.0:80`, `[::]:80` or BSD's `*.80`.
+pub fn parse_listening_ports(output: &str) -> Vec<u16> {
+    let mut ports: Vec<u16> = output
+        .lines()
+        .filter(|line| line.contains("LISTEN"))
+        .filter_map(|line| line.split_whitespace().nth(3))
+        .filter_map(|address| address.rsplit([':', '.']).next()?.parse().ok())
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+/// Runs `command` on `host` over a separate, non-interactive ssh session,
+/// recording it in the audit log. Fails when ssh could not run it, not when
+/// the command itself fails.
+pub async fn remote_command(
+    client: &SshClient,
+    host: &str,
+    command: &str,
+) -> Result<std::process::Output, String> {
+    if !client.is_openssh() {
+        return Err("not an OpenSSH client".to_string());
+    }
+    audit::record("remote_command", &format!("{}: {}", host, command), None);
+    let output = Command::new(client.program())
+        .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=5"])
+        .arg(host)
+        .arg(command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(DISCOVERY_TIMEOUT, output).await {
+        Ok(Ok(output)) => Ok(output),
+        Ok(Err(e)) => Err(format!("Could not run ssh: {}", e)),
+        Err(_) => Err("timed out".to_string()),
+    }
+}
+
+/// Asks `host` over ssh which TCP ports it listens on.
+pub async fn listening_ports(client: &SshClient, host: &str) -> Result<Vec<u16>, String> {
+    let output = remote_command(client, host, DISCOVERY_COMMAND).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().last().unwrap_or("ssh failed").to_string());
+    }
+    Ok(parse_listening_ports(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// A local port to suggest for `remote_port`: the same one when it is
+/// unprivileged and free, otherwise any free port.
+pub fn suggest_local_port(remote_port: u16) -> u16 {
+    if remote_port >= 1024 && TcpListener::bind(("127.0.0.1", remote_port)).is_ok() {
+        return remote_port;
+    }
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map_or(remote_port, |address| address.port())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Host,
+    RemotePort,
+    LocalPort,
+}
+
+/// What a step of the wizard decided.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// Moved on to the next step; the input should show `prefill`.
+    Next {
+        prefill: String,
+    },
+    Done(Box<TunnelSpec>),
+    /// Several tunnels typed one per line or read from an `@file`, each with
+    /// its line number and whether it parsed.
+    Batch(Vec<(usize, Result<TunnelSpec, String>)>),
+    Invalid(String),
+}
+
+/// Whether the input of the first step is a list of tunnels rather than one.
+fn is_batch(input: &str) -> bool {
+    let input = input.trim();
+    input.contains('\n') || input.starts_with('@')
+}
+
+/// The tunnels listed in `input`, or in the file named by an `@path` input.
+fn batch(input: &str) -> Outcome {
+    let input = input.trim();
+    let text = match input.strip_prefix('@') {
+        Some(path) => {
+            let path = certs::expand_home(Path::new(path.trim()));
+            match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    return Outcome::Invalid(format!("Could not read {}: {}", path.display(), e))
+                }
+            }
+        }
+        None => input.to_string(),
+    };
+    let specs: Vec<_> = TunnelSpec::parse_list(&text)
+        .into_iter()
+        .map(|(line, spec)| (line, spec.map_err(str::to_string)))
+        .collect();
+    match specs.is_empty() {
+        true => Outcome::Invalid("No tunnels listed".to_string()),
+        false => Outcome::Batch(specs),
+    }
+}
+
+/// Guided creation of a tunnel: pick a host, then a remote port among those
+/// discovered on it, then confirm the local port. A full one-line spec typed
+/// in the first step opens the tunnel right away, and so does picking one of
+/// the recent tunnels listed above the hosts. Several specs, one per line, or
+/// `@path` to a file of them open them all at once.
+pub struct Wizard {
+    pub step: Step,
+    pub host: String,
+    pub remote_port: u16,
+    /// Index into the current candidates.
+    pub selected: Option<usize>,
+    hosts: Vec<String>,
+    /// Tunnels offered ahead of the hosts, see [`recent::quick`](crate::recent::quick).
+    recent: Vec<Recent>,
+    /// Client the ports are discovered with.
+    ssh: SshConfig,
+    /// Ports listening on the host, or why they could not be found.
+    discovered: Option<Result<Vec<u16>, String>>,
+    discovery: Option<oneshot::Receiver<Result<Vec<u16>, String>>>,
+}
+
+impl Wizard {
+    pub fn new(hosts: Vec<String>) -> Self {
+        Wizard {
+            step: Step::Host,
+            host: String::new(),
+            remote_port: 0,
+            selected: None,
+            hosts,
+            recent: Vec::new(),
+            ssh: SshConfig::default(),
+            discovered: None,
+            discovery: None,
+        }
+    }
+
+    /// Discovers ports with the clients of `ssh` instead of `ssh`.
+    pub fn with_ssh(self, ssh: &SshConfig) -> Self {
+        Wizard {
+            ssh: ssh.clone(),
+            ..self
+        }
+    }
+
+    /// Offers `recent` ahead of the hosts, in that order.
+    pub fn with_recent(self, recent: Vec<Recent>) -> Self {
+        Wizard { recent, ..self }
+    }
+
+    /// The recent tunnels offered for `input` in the first step.
+    pub fn recent(&self, input: &str) -> Vec<&Recent> {
+        match self.step {
+            Step::Host if is_batch(input) => Vec::new(),
+            Step::Host => self
+                .recent
+                .iter()
+                .filter(|entry| palette::fuzzy_score(input, &entry.to_string()).is_some())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The recent tunnel picked with Alt and the key `n`, counting from 1,
+    /// while the input of the first step is empty.
+    pub fn quick_pick(&self, input: &str, n: usize) -> Option<&Recent> {
+        match input.trim().is_empty() {
+            true => self.recent(input).get(n.checked_sub(1)?).copied(),
+            false => None,
+        }
+    }
+
+    /// The highlighted candidate, when it is a recent tunnel.
+    pub fn selected_recent(&self, input: &str) -> Option<&Recent> {
+        self.recent(input).get(self.selected?).copied()
+    }
+
+    /// The candidates as listed: recent tunnels numbered while the input is
+    /// empty, and marked when pinned.
+    pub fn labels(&self, input: &str) -> Vec<String> {
+        let recent = self.recent(input);
+        let numbered = input.trim().is_empty();
+        let mut labels: Vec<String> = recent
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let pin = if entry.pinned { " ★" } else { "" };
+                match numbered {
+                    true => format!("{} {}{}", i + 1, entry, pin),
+                    false => format!("{}{}", entry, pin),
+                }
+            })
+            .collect();
+        labels.extend(self.candidates(input).into_iter().skip(recent.len()));
+        labels
+    }
+
+    /// Picks up the result of the port discovery once it finished.
+    pub fn poll(&mut self) {
+        if let Some(discovery) = self.discovery.as_mut() {
+            if let Ok(result) = discovery.try_recv() {
+                self.discovered = Some(result);
+                self.discovery = None;
+            }
+        }
+    }
+
+    /// Whether the remote ports are still being looked up.
+    pub fn discovering(&self) -> bool {
+        self.discovery.is_some()
+    }
+
+    pub fn discovery_error(&self) -> Option<&str> {
+        match &self.discovered {
+            Some(Err(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Choices offered for the current step that match `input`.
+    pub fn candidates(&self, input: &str) -> Vec<String> {
+        match self.step {
+            Step::Host if is_batch(input) => Vec::new(),
+            Step::Host => {
+                let mut hosts: Vec<(u32, &String)> = self
+                    .hosts
+                    .iter()
+                    .filter_map(|host| palette::fuzzy_score(input, host).map(|s| (s, host)))
+                    .collect();
+                hosts.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+                let recent = self.recent(input).into_iter().map(Recent::to_string);
+                recent
+                    .chain(hosts.into_iter().map(|(_, host)| host.clone()))
+                    .collect()
+            }
+            Step::RemotePort => match &self.discovered {
+                Some(Ok(ports)) => ports
+                    .iter()
+                    .map(u16::to_string)
+                    .filter(|port| port.starts_with(input.trim()))
+                    .collect(),
+                _ => Vec::new(),
+            },
+            Step::LocalPort => Vec::new(),
+        }
+    }
+
+    pub fn move_selection(&mut self, input: &str, down: bool) {
+        let len = self.candidates(input).len();
+        self.selected = match (self.selected, down) {
+            _ if len == 0 => None,
+            (None, true) => Some(0),
+            (None, false) => None,
+            (Some(i), true) => Some((i + 1).min(len - 1)),
+            (Some(0), false) => None,
+            (Some(i), false) => Some(i - 1),
+        };
+    }
+
+    /// Completes the current step with `input`, or the highlighted candidate.
+    pub fn submit(&mut self, input: &str) -> Outcome {
+        let input = input.trim();
+        let chosen = self
+            .selected
+            .and_then(|i| self.candidates(input).into_iter().nth(i));
+        self.selected = None;
+        match self.step {
+            Step::Host if is_batch(input) => batch(input),
+            Step::Host => {
+                let spec = chosen
+                    .as_deref()
+                    .filter(|chosen| chosen.contains(' '))
+                    .or(Some(input).filter(|input| input.contains(' ') && chosen.is_none()));
+                if let Some(spec) = spec {
+                    return match TunnelSpec::parse(spec) {
+                        Ok(spec) => Outcome::Done(Box::new(spec)),
+                        Err(e) => Outcome::Invalid(e.to_string()),
+                    };
+                }
+                let Some(host) =
+                    chosen.or_else(|| Some(input.to_string()).filter(|h| !h.is_empty()))
+                else {
+                    return Outcome::Invalid("Pick or type a host".to_string());
+                };
+                self.host = host.clone();
+                self.step = Step::RemotePort;
+                let (tx, rx) = oneshot::channel();
+                let client = self.ssh.client(&host);
+                tokio::spawn(async move {
+                    let _ = tx.send(listening_ports(&client, &host).await);
+                });
+                self.discovery = Some(rx);
+                Outcome::Next {
+                    prefill: String::new(),
+                }
+            }
+            Step::RemotePort => {
+                let port = chosen.as_deref().unwrap_or(input);
+                let Ok(port) = port.parse::<u16>() else {
+                    return Outcome::Invalid(format!("'{}' is not a port", port));
+                };
+                self.remote_port = port;
+                self.step = Step::LocalPort;
+                Outcome::Next {
+                    prefill: suggest_local_port(port).to_string(),
+                }
+            }
+            Step::LocalPort => match input.parse::<u16>() {
+                Ok(local_port) => Outcome::Done(Box::new(TunnelSpec::new(
+                    &self.host,
+                    local_port,
+                    self.remote_port,
+                ))),
+                Err(_) => Outcome::Invalid(format!("'{}' is not a port", input)),
+            },
+        }
+    }
+}
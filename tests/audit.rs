@@ -0,0 +1,52 @@
+mod common;
+
+use std::fs;
+
+use rtun::audit;
+
+use common::harness;
+
+#[test]
+fn chains_audit_entries() {
+    let harness = harness();
+    audit::record("open", "bastion 8080:80", None);
+    assert!(audit::load().unwrap().is_empty(), "recorded while disabled");
+
+    audit::enable();
+    audit::record("open", "bastion 8080:80", None);
+    audit::record("close", "bastion 8080:80", Some("uid 1001".to_string()));
+    audit::record("remote_command", "bastion: ss -Hltn", None);
+    let entries = audit::load().unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].prev, "");
+    assert_eq!(entries[1].prev, entries[0].hash);
+    assert_eq!(entries[1].on_behalf_of.as_deref(), Some("uid 1001"));
+    assert_eq!(entries[2].action, "remote_command");
+
+    let path = harness.home.join(".rtun/audit.jsonl");
+    let log = fs::read_to_string(&path).unwrap();
+    fs::write(&path, log.replace("uid 1001", "uid 0")).unwrap();
+    let error = audit::load().unwrap_err().to_string();
+    assert!(error.contains("line 2"), "{}", error);
+
+    let mut lines: Vec<&str> = log.lines().collect();
+    lines.remove(1);
+    fs::write(&path, lines.join("\n")).unwrap();
+    assert!(audit::load().is_err());
+
+    fs::remove_file(&path).unwrap();
+    audit::record("open", "bastion 8080:80", None);
+    let first = fs::read_to_string(&path).unwrap();
+    audit::record("open", "bastion 5432:5432", None);
+    // Changed behind its back, as by another rtun writing to it.
+    fs::write(&path, &first).unwrap();
+    audit::record("open", "db 3306:3306", None);
+    let entries = audit::load().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].target, "db 3306:3306");
+    assert_eq!(audit::take_error(), None);
+
+    fs::write(&path, format!("{}{{not json\n", first)).unwrap();
+    audit::record("close", "db 3306:3306", None);
+    assert!(audit::take_error().is_some_and(|e| e.contains("Corrupt audit log")));
+}
@@ -0,0 +1,66 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use rtun::certs::{self, Validity};
+
+fn at(day: u32, hour: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2026, 3, day)
+        .unwrap()
+        .and_hms_opt(hour, 0, 0)
+        .unwrap()
+}
+
+const LISTING: &str = "/home/me/.ssh/id_ed25519-cert.pub:
+        Type: ssh-ed25519-cert-v01@openssh.com user certificate
+        Public key: ED25519-CERT SHA256:Qm9ndXMga2V5IGZpbmdlcnByaW50IGZvciB0ZXN0cw
+        Signing CA: ED25519 SHA256:U2lnbmluZyBDQSBmaW5nZXJwcmludCBmb3IgdGVzdHM (using ssh-ed25519)
+        Key ID: \"me@example.com\"
+        Serial: 7
+        Valid: from 2026-03-02T08:00:00 to 2026-03-02T20:00:00
+        Principals: 
+                me
+        Critical Options: (none)
+        Extensions: 
+                permit-port-forwarding
+";
+
+#[test]
+fn reads_certificate_validity() {
+    let validity = certs::parse(LISTING).unwrap();
+    assert_eq!(
+        validity,
+        Validity {
+            from: Some(at(2, 8)),
+            to: Some(at(2, 20)),
+        }
+    );
+    assert_eq!(
+        certs::parse("Valid: forever"),
+        Some(Validity {
+            from: None,
+            to: None
+        })
+    );
+    assert_eq!(
+        certs::parse("Valid: before 2026-03-02T20:00:00")
+            .unwrap()
+            .to,
+        Some(at(2, 20))
+    );
+    assert_eq!(
+        certs::parse("Valid: after 2026-03-02T08:00:00").unwrap().to,
+        None
+    );
+    assert_eq!(certs::parse("Type: ssh-ed25519 key"), None);
+
+    assert_eq!(
+        validity.describe(at(2, 10)),
+        "expires in 10 hours (2026-03-02 20:00:00)"
+    );
+    assert_eq!(validity.describe(at(2, 21)), "expired 2026-03-02 20:00:00");
+    assert_eq!(
+        validity.describe(at(2, 7)),
+        "not valid before 2026-03-02 08:00:00"
+    );
+    assert!(!validity.expires_within(at(2, 10), Duration::hours(1)));
+    assert!(validity.expires_within(at(2, 19), Duration::hours(1)));
+    assert!(validity.expires_within(at(3, 0), Duration::hours(1)));
+}
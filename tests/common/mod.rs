@@ -0,0 +1,170 @@
+//! Shared harness for the integration tests.
+//!
+//! Tunnels shell out to `ssh`, so the harness puts a mock `ssh` executable
+//! first on `PATH` and points `HOME` at a scratch directory, keeping the
+//! history log and config of the developer running the tests untouched.
+//!
+//! The mock picks its behaviour from the destination host (its last argument):
+//!
+//! - `refused-*` exits immediately like an unreachable server,
+//! - `unreachable-*` and `nxdomain-*` fail the reachability check,
+//! - `flaky-*` fails on the first connection and stays up afterwards,
+//! - `taken-*` finds the port of its forward taken on the first connection
+//!   and stays up afterwards,
+//! - `touch-*` waits a second for a security key touch, then stays up,
+//! - `mux-*` has a master connection answering `-O` commands, which fail
+//!   for other hosts until started with `-f`, as rtun starts its own,
+//! - anything else stays up until killed.
+//!
+//! Every invocation appends its arguments to `<bin>/<host>.args`.
+//! `ssh -V` prints a made-up OpenSSH version. A mock `ssh-keyscan` finds no
+//! host keys.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use rtun::tunnel::{TunnelEvent, TunnelStatus};
+use tokio::sync::mpsc;
+
+const MOCK_SSH: &str = r#"#!/bin/sh
+dir="$(dirname "$0")"
+if [ "$1" = "-V" ]; then
+  echo "OpenSSH_9.6p1, mock" >&2
+  exit 0
+fi
+if [ "$1" = "-G" ]; then
+  case "$2" in
+    nxdomain*) printf 'hostname %s.invalid\nport 22\n' "$2" ;;
+    unreachable*) printf 'hostname 127.0.0.1\nport 1\n' ;;
+    *) printf 'hostname 127.0.0.1\nport @SSHD_PORT@\n' ;;
+  esac
+  exit 0
+fi
+for host; do :; done
+echo "$*" >> "$dir/$host.args"
+case " $* " in
+  *" -O "*)
+    case "$host" in
+      mux*) exit 0 ;;
+    esac
+    # rtun's own master, once started.
+    case " $* " in
+      *" -S "*)
+        if [ -e "$dir/$host.master" ]; then
+          case " $* " in *" -O exit "*) rm "$dir/$host.master" ;; esac
+          exit 0
+        fi
+        ;;
+    esac
+    echo "Control socket connect($host): No such file or directory" >&2
+    exit 255
+    ;;
+  *" -f "*)
+    touch "$dir/$host.master"
+    exit 0
+    ;;
+esac
+case "$host" in
+  refused*)
+    echo "ssh: connect to host $host port 22: Connection refused" >&2
+    exit 255
+    ;;
+  taken*)
+    if [ ! -e "$dir/$host.failed" ]; then
+      touch "$dir/$host.failed"
+      echo "bind [127.0.0.1]:40000: Address already in use" >&2
+      echo "Could not request local forwarding." >&2
+      exit 255
+    fi
+    ;;
+  touch*)
+    echo "rtun prompt: Confirm user presence for key ED25519-SK SHA256:test" >&2
+    sleep 1
+    echo "rtun prompt done" >&2
+    ;;
+  flaky*)
+    if [ ! -e "$dir/$host.failed" ]; then
+      touch "$dir/$host.failed"
+      echo "ssh: connect to host $host port 22: Connection timed out" >&2
+      exit 255
+    fi
+    ;;
+esac
+exec sleep 3600
+"#;
+
+pub struct Harness {
+    pub home: PathBuf,
+    pub bin: PathBuf,
+}
+
+impl Harness {
+    /// Arguments the mock ssh received for `host`, one line per invocation.
+    pub fn ssh_invocations(&self, host: &str) -> Vec<String> {
+        fs::read_to_string(self.bin.join(format!("{}.args", host)))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Waits until the mock ssh has been started `count` times for `host`.
+    pub async fn wait_for_invocations(&self, host: &str, count: usize) -> Vec<String> {
+        for _ in 0..100 {
+            let invocations = self.ssh_invocations(host);
+            if invocations.len() >= count {
+                return invocations;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("mock ssh was not started {} times for {}", count, host);
+    }
+}
+
+/// Sets up the process-wide mock environment once per test binary.
+pub fn harness() -> &'static Harness {
+    static HARNESS: OnceLock<Harness> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let root = tempfile::tempdir().expect("create temp dir").keep();
+        let home = root.join("home");
+        let bin = root.join("bin");
+        fs::create_dir_all(&home).unwrap();
+        fs::create_dir_all(&bin).unwrap();
+
+        // Stands in for the ssh servers the reachability check connects to.
+        // The kernel completes the handshakes without anyone accepting.
+        let sshd = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sshd_port = sshd.local_addr().unwrap().port();
+        std::mem::forget(sshd);
+
+        let ssh = bin.join("ssh");
+        fs::write(
+            &ssh,
+            MOCK_SSH.replace("@SSHD_PORT@", &sshd_port.to_string()),
+        )
+        .unwrap();
+        fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+        let keyscan = bin.join("ssh-keyscan");
+        fs::write(&keyscan, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&keyscan, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = std::env::var("PATH").unwrap_or_default();
+        std::env::set_var("PATH", format!("{}:{}", bin.display(), path));
+        std::env::set_var("HOME", &home);
+        Harness { home, bin }
+    })
+}
+
+/// Waits for the next status reported by a tunnel.
+pub async fn next_status(events: &mut mpsc::UnboundedReceiver<TunnelEvent>) -> TunnelStatus {
+    tokio::time::timeout(Duration::from_secs(10), events.recv())
+        .await
+        .expect("timed out waiting for a tunnel event")
+        .expect("tunnel event channel closed")
+        .status
+}
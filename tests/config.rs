@@ -0,0 +1,347 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::Weekday;
+use rtun::config::{self, AlertEvent, Config, Profile, ReconnectConfig, SortOrder};
+use rtun::schedule::Schedule;
+use rtun::secrets::Secret;
+use rtun::tunnel::TunnelSpec;
+
+use common::harness;
+
+#[test]
+fn parses_tunnel_spec() {
+    assert_eq!(
+        TunnelSpec::parse("bastion 8080:80"),
+        Ok(TunnelSpec::new("bastion", 8080, 80))
+    );
+    assert_eq!(
+        TunnelSpec::parse("bastion 8080:80 # Jenkins for release"),
+        Ok(TunnelSpec {
+            label: Some("Jenkins for release".to_string()),
+            ..TunnelSpec::new("bastion", 8080, 80)
+        })
+    );
+    assert_eq!(
+        TunnelSpec::parse("bastion 5432:5432:db.internal"),
+        Ok(TunnelSpec {
+            remote_host: Some("db.internal".to_string()),
+            ..TunnelSpec::new("bastion", 5432, 5432)
+        })
+    );
+    assert_eq!(
+        TunnelSpec::parse(
+            "--host bastion --local-port 5432 --remote=5432 --dest db.internal # primary"
+        ),
+        Ok(TunnelSpec {
+            label: Some("primary".to_string()),
+            remote_host: Some("db.internal".to_string()),
+            ..TunnelSpec::new("bastion", 5432, 5432)
+        })
+    );
+}
+
+#[test]
+fn rejects_malformed_tunnel_spec() {
+    assert!(TunnelSpec::parse("bastion").is_err());
+    assert!(TunnelSpec::parse("bastion 8080").is_err());
+    assert!(TunnelSpec::parse("bastion 8080:http").is_err());
+    assert!(TunnelSpec::parse("bastion 70000:80").is_err());
+    assert!(TunnelSpec::parse("bastion 8080:80 extra").is_err());
+    assert!(TunnelSpec::parse("bastion 8080:80:").is_err());
+    assert!(TunnelSpec::parse("bastion 8080:80:db:x").is_err());
+    assert!(TunnelSpec::parse("--host bastion --remote 80").is_err());
+    assert!(TunnelSpec::parse("--host bastion --local-port 8080 --remote").is_err());
+    assert!(TunnelSpec::parse("--host bastion --local-port 8080 --remote 80 --user x").is_err());
+}
+
+#[test]
+fn reads_tunnels_from_a_file() {
+    let path = harness().home.join("tunnels.txt");
+    fs::write(
+        &path,
+        "# staging\nbastion 8080:80 # Jenkins\n\n  bastion 5432:5432:db  \n",
+    )
+    .unwrap();
+    let tunnels = config::tunnels_from_file(&path).unwrap();
+    assert_eq!(tunnels.len(), 2);
+    assert_eq!(tunnels[0].label.as_deref(), Some("Jenkins"));
+    assert_eq!(tunnels[1].remote_host.as_deref(), Some("db"));
+
+    fs::write(&path, "bastion 8080\nbastion 8081:81\nbastion x:1\n").unwrap();
+    let error = config::tunnels_from_file(&path).unwrap_err();
+    let message = error.to_string();
+    assert!(message.contains("tunnels.txt line 1:"), "{}", message);
+    assert!(message.contains("tunnels.txt line 3:"), "{}", message);
+    assert!(!message.contains("line 2"), "{}", message);
+    assert_eq!(rtun::errors::code(&error), rtun::errors::ErrorCode::Config);
+}
+
+#[test]
+fn loads_config_file() {
+    let harness = harness();
+    fs::create_dir_all(harness.home.join(".rtun")).unwrap();
+    fs::write(
+        harness.home.join(".rtun/config.toml"),
+        r##"
+[alerts]
+command = "notify-send {host}"
+on = ["failed"]
+
+[reconnect]
+max_attempts = 3
+
+[host_colors]
+bastion = "#ff8800"
+
+[auth.bastion]
+password = { keychain = "bastion" }
+
+[auth.db]
+password = { store = "db" }
+
+[auth.ci]
+password_cmd = "op read op://dev/ci/password"
+
+[auth.vault]
+identity = "/keys/id_ed25519"
+certificate = "/keys/id_ed25519-cert.pub"
+warn_before = 240
+
+[intervals]
+ui_tick = 250
+stats_sample = 5
+
+[profiles.dev]
+reconnect = { forever = true, backoff_cap = 10 }
+schedule = { days = ["mon", "fri"], from = "09:00", until = "18:00" }
+tags = ["env:dev"]
+tunnels = [
+    { host = "bastion", local_port = 5432, remote_port = 5432, tags = ["db"] },
+    { host = "bastion", local_port = 8080, remote_port = 80, reconnect = { forever = false }, schedule = { open = "0 8 * * *", close = "0 20 * * *" } },
+]
+"##,
+    )
+    .unwrap();
+
+    let config = config::load().unwrap();
+    let alerts = config.alerts.clone().expect("alerts section");
+    assert_eq!(alerts.command.as_deref(), Some("notify-send {host}"));
+    assert_eq!(alerts.webhook, None);
+    assert_eq!(alerts.on, [AlertEvent::Failed]);
+    assert!(alerts.message.contains("{error}"));
+
+    let tunnels = config.profile_tunnels("dev").unwrap();
+    assert_eq!(tunnels.len(), 2);
+    let db = tunnels[0].reconnect.or(config.reconnect).policy();
+    assert!(db.forever);
+    assert_eq!(db.backoff_cap, Duration::from_secs(10));
+    let web = tunnels[1].reconnect.or(config.reconnect).policy();
+    assert!(!web.forever);
+    assert_eq!(web.max_attempts, 3);
+    assert!(matches!(
+        tunnels[0].schedule,
+        Some(Schedule::Window { ref days, .. }) if days == &[Weekday::Mon, Weekday::Fri]
+    ));
+    assert!(matches!(tunnels[1].schedule, Some(Schedule::Cron { .. })));
+    assert_eq!(tunnels[0].tags, ["db", "env:dev"]);
+    assert_eq!(tunnels[1].tags, ["env:dev"]);
+    assert!(config.profile_tunnels("prod").is_err());
+    assert_eq!(config.sort, SortOrder::Opened);
+    assert_eq!(config.host_colors["bastion"], "#ff8800");
+    assert_eq!(
+        config.auth["bastion"].password,
+        Some(Secret::Keychain("bastion".to_string()))
+    );
+    assert_eq!(
+        config.auth["db"].password(),
+        Some(Secret::Store("db".to_string()))
+    );
+    assert_eq!(
+        config.auth["ci"].password(),
+        Some(Secret::Command("op read op://dev/ci/password".to_string()))
+    );
+    assert_eq!(
+        config.auth["vault"].certificate.as_deref(),
+        Some(std::path::Path::new("/keys/id_ed25519-cert.pub"))
+    );
+    assert_eq!(
+        config.auth["vault"].certificate_warning(),
+        chrono::Duration::hours(4)
+    );
+    assert_eq!(config.intervals.ui_tick(), Duration::from_millis(250));
+    assert_eq!(config.intervals.stats_sample(), Duration::from_secs(5));
+    assert_eq!(config.intervals.idle_check(), Duration::from_secs(30));
+
+    config::save_sort(SortOrder::LocalPort).unwrap();
+    let config = config::load().unwrap();
+    assert_eq!(config.sort, SortOrder::LocalPort);
+    assert_eq!(config.profile_tunnels("dev").unwrap().len(), 2);
+    let contents = fs::read_to_string(harness.home.join(".rtun/config.toml")).unwrap();
+    assert!(
+        contents.starts_with("sort = \"local_port\"\n"),
+        "{}",
+        contents
+    );
+}
+
+#[test]
+fn extends_and_composes_profiles() {
+    let profile = |extends: Option<&str>, tags: &[&str], tunnels: Vec<TunnelSpec>| Profile {
+        extends: extends.map(str::to_string),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        tunnels,
+        ..Profile::default()
+    };
+    let config = Config {
+        profiles: BTreeMap::from([
+            (
+                "base".to_string(),
+                Profile {
+                    reconnect: ReconnectConfig {
+                        forever: Some(true),
+                        ..ReconnectConfig::default()
+                    },
+                    ..profile(
+                        None,
+                        &["base"],
+                        vec![
+                            TunnelSpec::new("bastion", 5432, 5432),
+                            TunnelSpec::new("bastion", 6379, 6379),
+                        ],
+                    )
+                },
+            ),
+            (
+                "api".to_string(),
+                profile(
+                    Some("base"),
+                    &["api"],
+                    vec![
+                        TunnelSpec::new("replica", 5432, 5432),
+                        TunnelSpec::new("bastion", 8080, 80),
+                    ],
+                ),
+            ),
+            (
+                "extra".to_string(),
+                profile(None, &[], vec![TunnelSpec::new("cache", 6379, 6379)]),
+            ),
+            ("loop".to_string(), profile(Some("loop"), &[], vec![])),
+        ]),
+        ..Config::default()
+    };
+
+    let api = config.profile_tunnels("api").unwrap();
+    let hosts: Vec<_> = api
+        .iter()
+        .map(|t| (t.host.as_str(), t.local_port))
+        .collect();
+    assert_eq!(
+        hosts,
+        [("bastion", 6379), ("replica", 5432), ("bastion", 8080)]
+    );
+    assert!(api.iter().all(|t| t.tags == ["base", "api"]));
+    assert!(api.iter().all(|t| t.reconnect.policy().forever));
+
+    let composed = config
+        .compose_profiles(&["api".to_string(), "extra".to_string()])
+        .unwrap();
+    let hosts: Vec<_> = composed
+        .iter()
+        .map(|t| (t.host.as_str(), t.local_port))
+        .collect();
+    assert_eq!(
+        hosts,
+        [("replica", 5432), ("bastion", 8080), ("cache", 6379)]
+    );
+    let error = config.profile_tunnels("loop").unwrap_err().to_string();
+    assert!(error.contains("extends itself"), "{}", error);
+}
+
+#[test]
+fn explains_config_mistakes() {
+    let path = Path::new("config.toml");
+    let contents = r#"
+[profiles.db]
+tunnels = [
+  { host = "db", local_port = 5432, remot_port = 5432 },
+]
+"#;
+    let error = config::parse(contents, path).unwrap_err().to_string();
+    assert!(
+        error.starts_with(
+            "config.toml:4:37: unknown field `remot_port`; did you mean `remote_port`?"
+        ),
+        "{}",
+        error
+    );
+    assert!(
+        error.ends_with("4 |   { host = \"db\", local_port = 5432, remot_port = 5432 },\n  |                                     ^^^^^^^^^^"),
+        "{}",
+        error
+    );
+
+    let error = config::parse("bel = true\n", path).unwrap_err().to_string();
+    assert!(error.contains("did you mean `bell`?"), "{}", error);
+    let error = config::parse("[profiles.a]\nextends = \"b\"\n", path)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("'b'"), "{}", error);
+    let error = config::parse("[intervals]\nwhatever = 1\n", path)
+        .unwrap_err()
+        .to_string();
+    assert!(!error.contains("did you mean"), "{}", error);
+    let error = config::parse("locale = \"xx\"\n", path)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Unknown locale 'xx'"), "{}", error);
+    assert!(config::parse("bell = true\n", path).unwrap().bell);
+}
+
+#[test]
+fn tightens_settings_in_strict_mode() {
+    let prod = TunnelSpec {
+        tags: vec!["prod".to_string()],
+        idle_timeout: Some(5),
+        ..TunnelSpec::new("db", 5432, 5432)
+    };
+    let dev = TunnelSpec::new("db", 5433, 5432);
+    let config = Config::default();
+    assert!(!config.needs_confirmation(&prod));
+    assert_eq!(config.idle_timeout(&dev), None);
+
+    let config = Config {
+        strict: true,
+        ..Config::default()
+    };
+    assert!(config.needs_confirmation(&prod));
+    assert!(!config.needs_confirmation(&dev));
+    assert_eq!(config.idle_timeout(&prod), Some(Duration::from_secs(300)));
+    // Strict mode makes idle timeouts mandatory.
+    assert_eq!(config.idle_timeout(&dev), Some(Duration::from_secs(3600)));
+    let lan = TunnelSpec {
+        lan: true,
+        ..dev.clone()
+    };
+    assert!(config.strict_violation(&lan).is_some());
+    assert!(config.strict_violation(&dev).is_none());
+}
+
+#[test]
+fn reconnect_backoff_is_capped() {
+    let policy = ReconnectConfig {
+        backoff_base: Some(2),
+        backoff_cap: Some(10),
+        ..Default::default()
+    }
+    .policy();
+    let delays: Vec<u64> = (1..=5).map(|a| policy.delay(a).as_secs()).collect();
+    assert_eq!(delays, [2, 4, 8, 10, 10]);
+    assert!(!policy.gives_up_after(5));
+    assert!(policy.gives_up_after(6));
+}
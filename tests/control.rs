@@ -0,0 +1,277 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rtun::config::Config;
+use rtun::control::{
+    self, Closed, ControlConfig, ControlServer, Permission, Request, RpcError, TunnelList,
+    VersionInfo,
+};
+use rtun::errors::{self, ErrorCode};
+use rtun::events::Kind;
+use rtun::manager::Manager;
+use rtun::status::{Format, Health, Summary, TunnelInfo};
+use rtun::tunnel::{Stop, TunnelSpec};
+use serde_json::Value;
+use tokio::sync::{broadcast, mpsc};
+
+use common::harness;
+
+fn info(host: &str, health: Health) -> TunnelInfo {
+    TunnelInfo {
+        host: host.to_string(),
+        local_port: 8080,
+        remote_port: 80,
+        remote_host: None,
+        name: None,
+        label: None,
+        status: "up".to_string(),
+        health,
+        error: None,
+        up_since: None,
+    }
+}
+
+#[tokio::test]
+async fn answers_list_over_the_socket() {
+    harness();
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    let config = ControlConfig::default();
+    let _server = ControlServer::start(&config, requests_tx, broadcast::channel(1).0).unwrap();
+    tokio::spawn(async move {
+        while let Some((request, caller, reply)) = requests.recv().await {
+            assert_eq!(request, Request::List);
+            assert_eq!(caller.permission, Permission::ManageAll);
+            let _ = reply.send(control::reply(&TunnelList {
+                tunnels: vec![info("bastion", Health::Up)],
+            }));
+        }
+    });
+
+    let list: TunnelList =
+        tokio::task::spawn_blocking(move || control::call(&config, &Request::List))
+            .await
+            .unwrap()
+            .unwrap();
+    assert_eq!(list.tunnels, [info("bastion", Health::Up)]);
+}
+
+#[tokio::test]
+async fn speaks_json_rpc() {
+    harness();
+    let (requests_tx, mut requests) = mpsc::unbounded_channel();
+    let config = ControlConfig::default();
+    let _server = ControlServer::start(&config, requests_tx, broadcast::channel(1).0).unwrap();
+    tokio::spawn(async move {
+        while let Some((request, _, reply)) = requests.recv().await {
+            if let Request::Add(tunnel) = request {
+                assert_eq!(tunnel.tags, ["db"]);
+            }
+            let _ = reply.send(control::reply(&Closed { closed: 0 }));
+        }
+    });
+
+    let path = config.socket_path().unwrap();
+    let lines = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream
+            .write_all(
+                concat!(
+                    r#"{"jsonrpc":"2.0","id":1,"method":"version"}"#,
+                    "\n",
+                    r#"{"jsonrpc":"2.0","method":"list"}"#,
+                    "\n",
+                    r#"{"jsonrpc":"2.0","id":"a","method":"add","params":{"host":"db","local_port":5432,"remote_port":5432,"tags":["db"]}}"#,
+                    "\n",
+                    r#"{"jsonrpc":"2.0","id":3,"method":"frobnicate"}"#,
+                    "\n",
+                    r#"{"jsonrpc":"2.0","id":4,"method":"close","params":{"local_port":"x"}}"#,
+                    "\n",
+                    "not json\n",
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        (0..5)
+            .map(|_| serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap())
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap();
+
+    // The notification without an id gets no response.
+    assert_eq!(lines[0]["id"], 1);
+    assert_eq!(lines[0]["result"]["protocol"], control::PROTOCOL_VERSION);
+    assert_eq!(lines[0]["result"]["methods"][4], "subscribe");
+    assert_eq!(lines[1]["id"], "a");
+    assert_eq!(lines[1]["result"]["closed"], 0);
+    assert_eq!(lines[2]["error"]["code"], RpcError::METHOD_NOT_FOUND);
+    assert_eq!(lines[3]["error"]["code"], RpcError::INVALID_PARAMS);
+    assert_eq!(lines[4]["error"]["code"], RpcError::PARSE_ERROR);
+    assert!(lines.iter().all(|line| line["jsonrpc"] == "2.0"));
+}
+
+#[test]
+fn tells_which_instances_are_too_old() {
+    let old = VersionInfo {
+        protocol: 1,
+        rtun: "0.9.0".to_string(),
+        methods: vec!["version".to_string(), "list".to_string()],
+    };
+    assert_eq!(old.incompatibility(&Request::List), None);
+    let failure = old.incompatibility(&Request::Subscribe).unwrap();
+    assert_eq!(failure.code, ErrorCode::Incompatible);
+    assert!(failure.message.contains("too old for `subscribe`"));
+
+    let newer = VersionInfo {
+        protocol: control::PROTOCOL_VERSION + 1,
+        ..VersionInfo::current()
+    };
+    let failure = newer.incompatibility(&Request::List).unwrap();
+    assert!(failure.message.contains("is newer than this one"));
+
+    let current = VersionInfo::current();
+    assert!(Request::METHODS
+        .iter()
+        .all(|method| current.methods.contains(&method.to_string())));
+    assert_eq!(current.incompatibility(&Request::Subscribe), None);
+}
+
+#[tokio::test]
+async fn refuses_to_talk_to_an_instance_without_the_method() {
+    harness();
+    let path = ControlConfig::default().socket_path().unwrap();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    // An instance from before `methods`, knowing only `version` and `close`.
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for line in BufReader::new(stream).lines() {
+                let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let response = match request["method"].as_str() {
+                    Some("version") => {
+                        r#"{"jsonrpc":"2.0","id":1,"result":{"protocol":1,"rtun":"0.1.0"}}"#
+                    }
+                    Some("close") => {
+                        r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"No such tunnel"}}"#
+                    }
+                    _ => {
+                        r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Unknown method"}}"#
+                    }
+                };
+                writeln!(writer, "{}", response).unwrap();
+            }
+        }
+    });
+
+    let error = tokio::task::spawn_blocking(|| control::subscribe(&ControlConfig::default()))
+        .await
+        .unwrap()
+        .err()
+        .unwrap();
+    assert_eq!(errors::code(&error), ErrorCode::Incompatible);
+    assert!(error
+        .to_string()
+        .contains("rtun 0.1.0 is too old for `subscribe`"));
+    assert!(error.to_string().contains("restart it"));
+
+    // Turning a request down is not being too old for it.
+    let close = Request::Close {
+        local_port: Some(9),
+        tags: Vec::new(),
+    };
+    let reply =
+        tokio::task::spawn_blocking(move || control::send(&ControlConfig::default(), &close))
+            .await
+            .unwrap()
+            .unwrap();
+    let error = reply.unwrap_err();
+    assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    assert_eq!(error.message, "No such tunnel");
+}
+
+#[tokio::test]
+async fn streams_events_to_subscribers() {
+    harness();
+    let mut manager = Manager::new(Arc::new(Config::default()));
+    let (requests_tx, _requests) = mpsc::unbounded_channel();
+    let config = ControlConfig::default();
+    let _server = ControlServer::start(&config, requests_tx, manager.changes()).unwrap();
+    let subscription = tokio::task::spawn_blocking(move || control::subscribe(&config))
+        .await
+        .unwrap()
+        .unwrap();
+    let events = tokio::task::spawn_blocking(move || {
+        subscription
+            .take(4)
+            .map(|event| event.unwrap().event)
+            .collect::<Vec<_>>()
+    });
+
+    manager.open(TunnelSpec::new("flaky-events", 18160, 80));
+    while !events.is_finished() {
+        manager.poll_events();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    // The mock ssh of a flaky host exits once, then stays up.
+    assert_eq!(
+        events.await.unwrap(),
+        [
+            Kind::Opened,
+            Kind::Unhealthy,
+            Kind::Reconnecting,
+            Kind::Connected
+        ]
+    );
+    manager.shutdown(Stop::Close).await;
+}
+
+#[test]
+fn renders_status_formats() {
+    let summary = Summary::new(
+        true,
+        vec![
+            info("web", Health::Up),
+            info("db", Health::Down),
+            info("ci", Health::Idle),
+        ],
+    );
+    assert_eq!(summary.render(Format::Plain), "1 up, 1 down");
+    assert_eq!(summary.render(Format::Starship), "1↑ 1↓");
+    assert_eq!(
+        summary.render(Format::Tmux),
+        "#[fg=green]1↑#[default] #[fg=red]1↓#[default]"
+    );
+    let waybar: serde_json::Value = serde_json::from_str(&summary.render(Format::Waybar)).unwrap();
+    assert_eq!(waybar["class"], "degraded");
+    let json: serde_json::Value = serde_json::from_str(&summary.render(Format::Json)).unwrap();
+    assert_eq!(json["tunnels"][1]["health"], "down");
+
+    assert_eq!(Summary::new(false, Vec::new()).render(Format::Starship), "");
+}
+
+#[test]
+fn grants_permissions_by_user() {
+    let config: ControlConfig =
+        toml::from_str(r#"users = { "*" = "observe", nobody = "manage-own" }"#).unwrap();
+    let own_uid = unsafe { libc::geteuid() };
+    assert_eq!(config.permission(own_uid), Some(Permission::ManageAll));
+    assert_eq!(config.permission(0), Some(Permission::ManageAll));
+    // The "*" entry covers users without an entry of their own.
+    assert_eq!(config.permission(4_000_000), Some(Permission::Observe));
+    assert_eq!(ControlConfig::default().permission(4_000_000), None);
+
+    assert!(Permission::Observe >= Request::List.required_permission());
+    let close = Request::Close {
+        local_port: None,
+        tags: Vec::new(),
+    };
+    assert!(Permission::Observe < close.required_permission());
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use rtun::config;
+use rtun::dbclient::{self, Database};
+
+#[test]
+fn points_the_client_at_the_local_port() {
+    let config = config::parse(
+        r#"
+[profiles.staging]
+tunnels = [
+    { host = "db", local_port = 15432, remote_port = 5432 },
+    { host = "db", local_port = 13306, remote_port = 3307, label = "MariaDB replica", client = { user = "report", database = "orders" } },
+    { host = "cache", local_port = 16379, remote_port = 7000, client = { kind = "redis", database = "2" } },
+    { host = "db", local_port = 15433, remote_port = 5433, client = { kind = "postgres", user = "o'brien", command = "pgcli -p {local_port} -U {user}" } },
+    { host = "web", local_port = 8080, remote_port = 80 },
+]
+"#,
+        Path::new("config.toml"),
+    )
+    .unwrap();
+    let tunnels = &config.profiles["staging"].tunnels;
+    let commands: Vec<_> = tunnels
+        .iter()
+        .map(|spec| dbclient::command(spec).unwrap())
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            Some("psql -h 127.0.0.1 -p 15432".to_string()),
+            Some("mysql -h 127.0.0.1 -P 13306 -u 'report' 'orders'".to_string()),
+            Some("redis-cli -h 127.0.0.1 -p 16379 -n '2'".to_string()),
+            Some(r"pgcli -p '15433' -U 'o'\''brien'".to_string()),
+            None,
+        ]
+    );
+    assert_eq!(dbclient::database(&tunnels[1]), Some(Database::Mysql));
+
+    let error = config::parse(
+        r#"
+[profiles.staging]
+tunnels = [{ host = "db", local_port = 15432, remote_port = 5432, client = { command = "psql -d {database}" } }]
+"#,
+        Path::new("config.toml"),
+    )
+    .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("but the client sets no database in profile 'staging'"),
+        "{:#}",
+        error
+    );
+}
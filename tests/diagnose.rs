@@ -0,0 +1,57 @@
+mod common;
+
+use std::net::TcpListener;
+
+use rtun::config::Config;
+use rtun::diagnose;
+use rtun::doctor::Status;
+use rtun::tunnel::{RemoteTarget, TunnelSpec};
+
+use common::harness;
+
+fn target(port: u16) -> RemoteTarget {
+    RemoteTarget::try_from(format!("db.internal:{}", port)).unwrap()
+}
+
+#[test]
+fn explains_probe_results() {
+    let check = diagnose::probe_check(&target(5432), "bastion", Some(0));
+    assert_eq!(check.status, Status::Pass);
+    let check = diagnose::probe_check(&target(5432), "bastion", Some(124));
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.hint.unwrap().contains("firewall"));
+    let check = diagnose::probe_check(&target(5433), "bastion", Some(1));
+    assert_eq!(check.status, Status::Fail);
+    assert!(check.detail.contains("refused"), "{}", check.detail);
+    let check = diagnose::probe_check(&target(5432), "bastion", Some(127));
+    assert_eq!(check.status, Status::Warn);
+}
+
+#[tokio::test]
+async fn stops_at_the_local_checks() {
+    harness();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let spec = TunnelSpec::new("bastion", port, 5432);
+    let config = Config::default();
+
+    let checks = diagnose::run(
+        &config,
+        &spec,
+        &target(5432),
+        Err("Connection refused".into()),
+    )
+    .await;
+    let statuses: Vec<Status> = checks.iter().map(|c| c.status).collect();
+    assert_eq!(statuses, [Status::Pass, Status::Fail]);
+    assert!(checks[1].detail.contains("Connection refused"));
+
+    drop(listener);
+    let socks = TunnelSpec {
+        socks: true,
+        ..spec
+    };
+    let checks = diagnose::run(&config, &socks, &target(5432), Ok(())).await;
+    let statuses: Vec<Status> = checks.iter().map(|c| c.status).collect();
+    assert_eq!(statuses, [Status::Fail, Status::Pass]);
+}
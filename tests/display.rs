@@ -0,0 +1,10 @@
+use rtun::display;
+
+#[test]
+fn swaps_symbols_for_ascii() {
+    let drawn: String = ["┌", "─", "┐", "│", "●", "↑", "▁", "▇", "é", "a"]
+        .iter()
+        .map(|symbol| display::ascii(symbol).unwrap_or(symbol))
+        .collect();
+    assert_eq!(drawn, "+-+|*^_#éa");
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use rtun::config::DnsConfig;
+use rtun::dns;
+use rtun::pac::PacRules;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+
+/// A query for the A records of `name`, with recursion desired.
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = id.to_be_bytes().to_vec();
+    query.extend_from_slice(&[1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.extend_from_slice(&[0, 0, 1, 0, 1]);
+    query
+}
+
+async fn resolve(socket: &UdpSocket, query: &[u8]) -> Vec<u8> {
+    socket.send_to(query, "127.0.0.1:18172").await.unwrap();
+    let mut answer = vec![0; 512];
+    let len = tokio::time::timeout(std::time::Duration::from_secs(10), socket.recv(&mut answer))
+        .await
+        .expect("no answer")
+        .unwrap();
+    answer.truncate(len);
+    answer
+}
+
+#[tokio::test]
+async fn resolves_tunneled_domains_through_socks() {
+    // Stands in for ssh -D and the DNS server behind it.
+    let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rules = Arc::new(PacRules::default());
+    rules.insert(
+        &["corp.internal".to_string(), "*.lab".to_string()],
+        proxy.local_addr().unwrap().port(),
+    );
+    assert!(rules.route("grafana.corp.internal.").is_some());
+    assert!(rules.route("Wiki.LAB").is_some());
+    assert!(rules.route("notcorp.internal").is_none());
+    let config = DnsConfig {
+        port: 18172,
+        server: "10.0.0.2".to_string(),
+    };
+    dns::serve(&config, rules).await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let asked = query(7, "grafana.corp.internal");
+    let client = {
+        let socket = &socket;
+        let asked = asked.clone();
+        async move { resolve(socket, &asked).await }
+    };
+    let server = async {
+        let (mut stream, _) = proxy.accept().await.unwrap();
+        let mut greeting = [0; 3];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        stream.write_all(&[5, 0]).await.unwrap();
+        let mut request = [0; 10];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 2, 0, 53]);
+        stream
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let len = stream.read_u16().await.unwrap();
+        let mut forwarded = vec![0; len as usize];
+        stream.read_exact(&mut forwarded).await.unwrap();
+        assert_eq!(forwarded, asked);
+        let mut answer = forwarded.clone();
+        answer[2] |= 0x80;
+        stream.write_u16(answer.len() as u16).await.unwrap();
+        stream.write_all(&answer).await.unwrap();
+        answer
+    };
+    let (answer, sent) = tokio::join!(client, server);
+    assert_eq!(answer, sent);
+
+    // Other names are for the system resolver.
+    let asked = query(8, "example.com");
+    let answer = resolve(&socket, &asked).await;
+    assert_eq!(answer[..2], [0, 8]);
+    assert_eq!(answer[3] & 0x0f, 5);
+    assert_eq!(answer[12..], asked[12..]);
+    assert_eq!(dns::name(&asked).as_deref(), Some("example.com"));
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use rtun::config;
+use rtun::doctor::{self, Status};
+
+use common::harness;
+
+#[test]
+fn diagnoses_the_environment() {
+    harness();
+    let contents = r#"
+strict = true
+
+[profiles.db]
+tunnels = [
+  { host = "db", local_port = 5432, remote_port = 5432, backend = "wireguard" },
+]
+"#;
+    let config = config::parse(contents, "config.toml".as_ref()).unwrap();
+    let checks = doctor::run(&config);
+    let check = |name: &str| {
+        checks
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap_or_else(|| panic!("no {} check in {:?}", name, checks))
+    };
+
+    assert_eq!(check("ssh").status, Status::Pass);
+    assert_eq!(check("ssh").detail, "OpenSSH_9.6p1, mock");
+    assert_eq!(check("ssh config").status, Status::Pass);
+    // Strict mode has ssh refuse hosts it does not know yet.
+    assert_eq!(check("known_hosts").status, Status::Fail);
+    assert!(check("known_hosts").hint.is_some());
+    assert_eq!(check("control socket").detail, "no instance running");
+    assert_eq!(check("backend").status, Status::Fail);
+    assert!(check("backend").detail.contains("wireguard"));
+    assert!(checks.iter().any(|c| c.name == "lsof"));
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use rtun::config;
+use rtun::control::{self, ControlConfig, Request};
+use rtun::errors::{self, ErrorCode, ErrorInfo};
+
+#[test]
+fn classifies_failures() {
+    let cases = [
+        (
+            "ssh: connect to host db port 22: Connection refused",
+            ErrorCode::HostUnreachable,
+        ),
+        (
+            "ssh: Could not resolve hostname db: Name or service not known",
+            ErrorCode::HostUnreachable,
+        ),
+        (
+            "Host unreachable: 10.0.0.1:22: timed out",
+            ErrorCode::HostUnreachable,
+        ),
+        (
+            "deploy@db: Permission denied (publickey).",
+            ErrorCode::AuthFailure,
+        ),
+        (
+            "bind [127.0.0.1]:5432: Address already in use",
+            ErrorCode::PortConflict,
+        ),
+        ("something else entirely", ErrorCode::Other),
+    ];
+    for (line, code) in cases {
+        assert_eq!(ErrorCode::of_tunnel_error(line), code, "{}", line);
+    }
+
+    let error = config::parse("bel = true\n", "config.toml".as_ref()).unwrap_err();
+    assert_eq!(errors::code(&error), ErrorCode::Config);
+    assert_eq!(errors::code(&error).exit_status(), 3);
+    let error = config::Config::default()
+        .profile_tunnels("nope")
+        .unwrap_err();
+    assert_eq!(errors::code(&error), ErrorCode::Config);
+
+    let control = ControlConfig {
+        path: Some(PathBuf::from("/nonexistent/rtun.sock")),
+        ..Default::default()
+    };
+    let error = control::send(&control, &Request::List).unwrap_err();
+    assert_eq!(errors::code(&error), ErrorCode::NotRunning);
+    assert_eq!(error.to_string(), "rtun is not running");
+    let json = serde_json::to_value(ErrorInfo::of_report(&error)).unwrap();
+    assert_eq!(json["code"], "not_running");
+    assert_eq!(
+        errors::code(&color_eyre::eyre::eyre!("boom")),
+        ErrorCode::Other
+    );
+}
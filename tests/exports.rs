@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rtun::config;
+use rtun::exports::{self, Endpoint};
+use rtun::tunnel::TunnelSpec;
+
+#[test]
+fn renders_exports_for_named_tunnels() {
+    let tunnels = [
+        TunnelSpec {
+            name: Some("db".to_string()),
+            ..TunnelSpec::new("bastion", 15432, 5432)
+        },
+        TunnelSpec::new("bastion", 8080, 80),
+    ];
+    let mut endpoints = vec![Endpoint {
+        name: "db".to_string(),
+        port: 25432,
+    }];
+    endpoints.extend(exports::endpoints(&tunnels));
+    assert_eq!(endpoints.len(), 2);
+
+    let env = BTreeMap::from([
+        (
+            "DATABASE_URL".to_string(),
+            "postgres://localhost:{db.port}/app".to_string(),
+        ),
+        ("PGPASS_HINT".to_string(), "it's {db.port}".to_string()),
+    ]);
+    assert_eq!(
+        exports::render(&env, &endpoints).unwrap(),
+        "export DATABASE_URL='postgres://localhost:25432/app'\n\
+         export PGPASS_HINT='it'\\''s 25432'\n"
+    );
+
+    let only = |template: &str| exports::expand(template, &endpoints);
+    assert!(only("{cache.port}").unwrap_err().contains("'cache'"));
+    assert!(only("{db.host}").is_err());
+    assert!(only("{db.port").is_err());
+    let bad_key = BTreeMap::from([("DATABASE-URL".to_string(), String::new())]);
+    assert!(exports::render(&bad_key, &endpoints).is_err());
+}
+
+#[test]
+fn checks_env_templates_when_loading() {
+    let path = Path::new("config.toml");
+    let contents = r#"
+[profiles.dev]
+tunnels = [{ host = "bastion", name = "db", local_port = 15432, remote_port = 5432 }]
+env = { DATABASE_URL = "postgres://localhost:{db.port}/app" }
+
+[profiles.staging]
+extends = "dev"
+env = { REDIS_URL = "redis://localhost:{redis.port}" }
+"#;
+    let error = config::parse(contents, path).unwrap_err().to_string();
+    assert!(error.contains("profile 'staging'"), "{}", error);
+    let fixed = contents.replace("{redis.port}", "{db.port}");
+    let config = config::parse(&fixed, path).unwrap();
+    assert_eq!(config.profile("staging").unwrap().env.len(), 2);
+}
+
+#[test]
+fn writes_envrc_for_direnv() {
+    let envrc = exports::envrc("dev");
+    assert!(
+        envrc.contains("rtun up 'dev' --wait --timeout 30s --tie-to-pid \"$shell_pid\""),
+        "{}",
+        envrc
+    );
+    assert!(envrc.contains("eval \"$(rtun env 'dev')\""), "{}", envrc);
+    assert!(exports::envrc("my dev").contains("rtun env 'my dev'"));
+}
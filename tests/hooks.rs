@@ -0,0 +1,82 @@
+use rtun::events::{Event, Kind};
+use rtun::hooks::{Command, Hooks};
+use rtun::status::{Health, TunnelInfo};
+use rtun::tunnel::TunnelSpec;
+
+fn event(kind: Kind, host: &str) -> Event {
+    let tunnel = TunnelInfo {
+        host: host.to_string(),
+        local_port: 5432,
+        remote_port: 5432,
+        remote_host: None,
+        name: None,
+        label: None,
+        status: "failed".to_string(),
+        health: Health::Down,
+        error: None,
+        up_since: None,
+    };
+    Event::new(kind, tunnel, Some("Connection refused".to_string()))
+}
+
+#[test]
+fn runs_hooks_for_events() {
+    let hooks = Hooks::compile(
+        r#"
+        fn on_failed(event) {
+            if event.tunnel.host == "db" {
+                open("db-replica", event.tunnel.local_port + 1, 5432);
+            }
+        }
+        fn on_vpn_up(name) {
+            open_profile(name);
+        }
+        fn on_vpn_down(name) {
+            close_tag("vpn:" + name);
+        }
+        "#,
+    )
+    .unwrap();
+    assert!(hooks.handles("on_failed"));
+    assert!(!hooks.handles("on_connected"));
+
+    assert_eq!(
+        hooks.on_event(&event(Kind::Failed, "db")).unwrap(),
+        [Command::Open(Box::new(TunnelSpec::new(
+            "db-replica",
+            5433,
+            5432
+        )))]
+    );
+    assert_eq!(hooks.on_event(&event(Kind::Failed, "web")).unwrap(), []);
+    // Events without a hook are fine.
+    assert_eq!(hooks.on_event(&event(Kind::Connected, "db")).unwrap(), []);
+    assert_eq!(
+        hooks.on_vpn("staging", true).unwrap(),
+        [Command::OpenProfile("staging".to_string())]
+    );
+    assert_eq!(
+        hooks.on_vpn("staging", false).unwrap(),
+        [Command::CloseTag("vpn:staging".to_string())]
+    );
+}
+
+#[test]
+fn reports_broken_hooks() {
+    assert!(Hooks::compile("fn on_failed(event) {").is_err());
+
+    let hooks = Hooks::compile(
+        r#"
+        fn on_failed(event) { open("db", 70000, 5432); }
+        fn on_closed(event) { loop {} }
+        "#,
+    )
+    .unwrap();
+    let error = hooks.on_event(&event(Kind::Failed, "db")).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("70000 is not a port"),
+        "{:#}",
+        error
+    );
+    assert!(hooks.on_event(&event(Kind::Closed, "db")).is_err());
+}
@@ -0,0 +1,55 @@
+use chrono::{TimeZone, Utc};
+use rtun::hostkeys::{self, HostKey, KnownKeys};
+
+fn key(kind: &str, fingerprint: &str) -> HostKey {
+    HostKey {
+        kind: kind.to_string(),
+        fingerprint: fingerprint.to_string(),
+    }
+}
+
+#[test]
+fn parses_ssh_keygen_fingerprints() {
+    let output = "\
+256 SHA256:Ef3Jq0YbLq6pN2xTA5v9wMHl4pQ3uKkq4F8n1DkQ8bM db.example.com (ED25519)
+3072 SHA256:k1w9v2cGm3OeD0ZpR8yJ4u7hX5tL6aNq0sBfYdCe2Vg db.example.com (RSA)
+not a fingerprint
+";
+
+    assert_eq!(
+        hostkeys::parse(output),
+        [
+            key(
+                "ED25519",
+                "SHA256:Ef3Jq0YbLq6pN2xTA5v9wMHl4pQ3uKkq4F8n1DkQ8bM"
+            ),
+            key("RSA", "SHA256:k1w9v2cGm3OeD0ZpR8yJ4u7hX5tL6aNq0sBfYdCe2Vg"),
+        ]
+    );
+}
+
+#[test]
+fn flags_keys_that_changed_since_first_seen() {
+    let known = KnownKeys {
+        first_seen: Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap(),
+        keys: vec![key("ED25519", "SHA256:old")],
+    };
+
+    assert_eq!(
+        hostkeys::compare(&known, &[key("ED25519", "SHA256:old")]),
+        None
+    );
+    // A key type the server did not offer before is no change.
+    assert_eq!(
+        hostkeys::compare(
+            &known,
+            &[key("ED25519", "SHA256:old"), key("RSA", "SHA256:rsa")]
+        ),
+        None
+    );
+    let change = hostkeys::compare(&known, &[key("ED25519", "SHA256:new")]).unwrap();
+    assert_eq!(
+        change.to_string(),
+        "ED25519 key changed from SHA256:old to SHA256:new, first seen 2026-03-01"
+    );
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use rtun::config;
+use rtun::hosts;
+use rtun::tunnel::TunnelSpec;
+
+use common::harness;
+
+#[test]
+fn picks_resolved_options_in_order() {
+    let output = "\
+user deploy
+hostname db.internal
+port 2222
+identityfile ~/.ssh/id_ed25519
+identityfile ~/.ssh/id_rsa
+proxyjump bastion
+proxycommand none
+localforward [127.0.0.1]:5432 [127.0.0.1]:5432
+serveraliveinterval 0
+stricthostkeychecking accept-new
+";
+
+    assert_eq!(
+        hosts::parse_resolved(output),
+        [
+            ("HostName", "db.internal".to_string()),
+            ("User", "deploy".to_string()),
+            ("Port", "2222".to_string()),
+            ("IdentityFile", "~/.ssh/id_ed25519".to_string()),
+            ("IdentityFile", "~/.ssh/id_rsa".to_string()),
+            ("ProxyJump", "bastion".to_string()),
+            (
+                "LocalForward",
+                "[127.0.0.1]:5432 [127.0.0.1]:5432".to_string()
+            ),
+            ("StrictHostKeyChecking", "accept-new".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn resolves_hosts_with_the_ssh_client() {
+    harness();
+    let config = config::parse(
+        r#"
+[ssh.hosts.teleport]
+program = "tsh"
+args = ["ssh", "{host}"]
+"#,
+        "config.toml".as_ref(),
+    )
+    .unwrap();
+
+    let resolved = hosts::resolve(&config, "db", &[TunnelSpec::new("db", 5432, 5432)]).unwrap();
+    assert_eq!(resolved[0], ("HostName", "127.0.0.1".to_string()));
+    assert_eq!(resolved[1].0, "Port");
+    assert!(hosts::resolve(&config, "teleport", &[]).is_err());
+}
@@ -0,0 +1,48 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use rtun::config;
+use rtun::hostsfile;
+
+#[test]
+fn replaces_only_its_own_lines() {
+    let contents = "\
+127.0.0.1 localhost
+::1 localhost
+127.0.0.1 old.staging.test # rtun
+10.0.0.5 nas
+";
+    let names: BTreeSet<String> = ["grafana.staging.test", "api.staging.test"]
+        .map(str::to_string)
+        .into();
+    let updated = hostsfile::with_names(contents, &names);
+    assert_eq!(
+        updated,
+        "\
+127.0.0.1 localhost
+::1 localhost
+10.0.0.5 nas
+127.0.0.1 api.staging.test # rtun
+127.0.0.1 grafana.staging.test # rtun
+"
+    );
+    assert_eq!(hostsfile::with_names(&updated, &names), updated);
+    assert_eq!(
+        hostsfile::with_names(&updated, &BTreeSet::new()),
+        "127.0.0.1 localhost\n::1 localhost\n10.0.0.5 nas\n"
+    );
+
+    let error = config::parse(
+        r#"
+[profiles.staging]
+tunnels = [{ host = "staging", local_port = 3000, remote_port = 3000, hostnames = ["graf ana"] }]
+"#,
+        Path::new("config.toml"),
+    )
+    .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Invalid hostname 'graf ana' in profile 'staging'"),
+        "{:#}",
+        error
+    );
+}
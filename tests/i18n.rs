@@ -0,0 +1,38 @@
+use rtun::events::Kind;
+use rtun::i18n;
+use rtun::palette::{Action, Tab};
+
+#[test]
+fn translates_the_tui() {
+    assert_eq!(i18n::find("pt-BR"), Some("pt-BR"));
+    assert_eq!(i18n::find("pt_BR.UTF-8"), Some("pt-BR"));
+    assert_eq!(i18n::find("pt"), Some("pt-BR"));
+    assert_eq!(i18n::find("en_US.UTF-8"), Some("en"));
+    assert_eq!(i18n::find("de_DE"), None);
+    for locale in i18n::locales() {
+        assert_eq!(i18n::missing(locale), Vec::<&str>::new(), "{}", locale);
+    }
+
+    assert_eq!(Tab::Tunnels.title(), "Tunnels");
+    assert!(!i18n::set_locale("de_DE"));
+    assert_eq!(i18n::locale(), "en");
+    assert!(i18n::set_locale("pt_BR.UTF-8"));
+    assert_eq!(i18n::locale(), "pt-BR");
+    assert_eq!(Tab::Tunnels.title(), "Túneis");
+    assert_eq!(Action::ShowTab(Tab::Profiles).title(), "Ir para Perfis");
+    assert_eq!(
+        i18n::format("wizard-local-port", &[("host", &"db"), ("port", &5432)]),
+        "Novo túnel 3/3: porta local para db:5432"
+    );
+    assert_eq!(i18n::text("no-such-message"), "no-such-message");
+    assert!(i18n::set_locale("en"));
+}
+
+#[test]
+fn names_every_event() {
+    // `text` falls back to the key, so a missing one would show as is.
+    for kind in Kind::ALL {
+        let key = format!("event-{}", kind);
+        assert!(i18n::has(&key), "{}", key);
+    }
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+use rtun::instance::{self, PidFile};
+use rtun::tunnel::Stop;
+use tokio::sync::mpsc;
+
+#[test]
+fn writes_and_removes_the_pid_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run/rtun.pid");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    // Left behind by a run that crashed.
+    fs::write(&path, "1\n").unwrap();
+
+    let pid_file = PidFile::create(&path).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("{}\n", process::id())
+    );
+    drop(pid_file);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn stops_when_the_tied_process_exits() {
+    let mut child = process::Command::new("sleep").arg("0.2").spawn().unwrap();
+    let pid = child.id();
+    let (quit_tx, mut quit_rx) = mpsc::channel(1);
+    instance::tie_to(pid, quit_tx).unwrap();
+    std::thread::spawn(move || child.wait());
+
+    let stop = tokio::time::timeout(Duration::from_secs(5), quit_rx.recv()).await;
+    assert_eq!(stop.unwrap(), Some(Stop::Close));
+    assert!(instance::tie_to(pid, mpsc::channel(1).0).is_err());
+}
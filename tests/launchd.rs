@@ -0,0 +1,24 @@
+use std::path::Path;
+
+use rtun::launchd;
+
+#[test]
+fn writes_a_launch_agent() {
+    let plist = launchd::plist(
+        "db&cache",
+        Path::new("/opt/homebrew/bin/rtun"),
+        Path::new("/Users/me/.rtun/db&cache.log"),
+        "/opt/homebrew/bin:/usr/bin:/bin",
+    );
+
+    assert!(plist.contains("<string>com.github.andycancado.rtun.db&amp;cache</string>"));
+    assert!(plist.contains(
+        "        <string>/opt/homebrew/bin/rtun</string>\n        <string>--plain</string>\n        \
+         <string>up</string>\n        <string>db&amp;cache</string>\n"
+    ));
+    assert!(plist.contains("<string>/opt/homebrew/bin:/usr/bin:/bin</string>"));
+    assert!(plist.contains("<key>StandardInPath</key>\n    <string>/dev/null</string>"));
+    assert!(plist.contains("<string>/Users/me/.rtun/db&amp;cache.log</string>"));
+    // Stopped on purpose, rtun exits cleanly and stays stopped.
+    assert!(plist.contains("<key>SuccessfulExit</key>\n        <false/>"));
+}
@@ -0,0 +1,20 @@
+use rtun::pac;
+
+#[test]
+fn reads_the_auto_proxy_settings_of_macos() {
+    assert_eq!(
+        pac::parse_autoproxy("URL: http://wpad.corp.example/proxy.pac\nEnabled: Yes\n"),
+        (Some("http://wpad.corp.example/proxy.pac".to_string()), true)
+    );
+    assert_eq!(
+        pac::parse_autoproxy("URL: http://wpad.corp.example/proxy.pac\nEnabled: No\n"),
+        (
+            Some("http://wpad.corp.example/proxy.pac".to_string()),
+            false
+        )
+    );
+    assert_eq!(
+        pac::parse_autoproxy("URL: (null)\nEnabled: No\n"),
+        (None, false)
+    );
+}
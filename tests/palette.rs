@@ -0,0 +1,20 @@
+use rtun::palette::{self, Action};
+
+#[test]
+fn fuzzy_matches_in_order() {
+    assert!(palette::fuzzy_score("rst", "Restart all tunnels").is_some());
+    assert!(palette::fuzzy_score("RA", "Restart all tunnels").is_some());
+    assert_eq!(palette::fuzzy_score("tsr", "Restart all tunnels"), None);
+    assert_eq!(palette::fuzzy_score("", "Quit"), Some(0));
+}
+
+#[test]
+fn ranks_word_starts_first() {
+    let profiles = ["staging".to_string()];
+    let actions = Action::all(&profiles);
+    let found = palette::search(&actions, "rest all");
+    assert_eq!(found[0], &Action::RestartAll);
+    let found = palette::search(&actions, "stag");
+    assert_eq!(found[0], &Action::OpenProfile("staging".to_string()));
+    assert_eq!(palette::search(&actions, "").len(), actions.len());
+}
@@ -0,0 +1,66 @@
+use rtun::plan::{self, Change};
+use rtun::status::{Health, TunnelInfo};
+use rtun::tunnel::TunnelSpec;
+
+fn running(host: &str, local_port: u16, remote_port: u16) -> TunnelInfo {
+    TunnelInfo {
+        host: host.to_string(),
+        local_port,
+        remote_port,
+        remote_host: None,
+        name: None,
+        label: None,
+        status: "up".to_string(),
+        health: Health::Up,
+        error: None,
+        up_since: None,
+    }
+}
+
+#[test]
+fn plans_what_up_would_change() {
+    let wanted = [
+        TunnelSpec::new("bastion", 5432, 5432),
+        TunnelSpec::new("bastion", 6379, 6379),
+        TunnelSpec {
+            remote_host: Some("db-2".to_string()),
+            ..TunnelSpec::new("bastion", 8080, 80)
+        },
+    ];
+    let running = [
+        running("bastion", 5432, 5432),
+        running("bastion", 8080, 80),
+        running("old-bastion", 9000, 9000),
+    ];
+
+    let plan = plan::plan(&wanted, &running);
+    assert_eq!(plan.unchanged, 1);
+    // `up` leaves the tunnel on 9000 running, so it is not listed.
+    assert_eq!(
+        plan.changes,
+        [
+            Change::Open(wanted[1].clone()),
+            Change::Blocked {
+                running: running[1].clone(),
+                wanted: wanted[2].clone(),
+            },
+        ]
+    );
+    let lines: Vec<String> = plan.changes.iter().map(Change::to_string).collect();
+    assert_eq!(
+        lines,
+        [
+            "+ bastion 6379:6379",
+            "! bastion 8080:80\n    keeps the port from bastion -> db-2 8080:80",
+        ]
+    );
+    assert_eq!(plan.summary(), "1 to open, 1 blocked, 1 unchanged");
+}
+
+#[test]
+fn reports_no_changes() {
+    let wanted = [TunnelSpec::new("bastion", 5432, 5432)];
+    let plan = plan::plan(&wanted, &[running("bastion", 5432, 5432)]);
+    assert!(plan.changes.is_empty());
+    assert_eq!(plan.summary(), "No changes, 1 unchanged");
+}
@@ -0,0 +1,20 @@
+use std::env;
+use std::fs;
+
+use rtun::platform;
+
+#[test]
+fn finds_a_shell_without_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("usr");
+    fs::create_dir_all(prefix.join("bin")).unwrap();
+    fs::write(prefix.join("bin/sh"), "").unwrap();
+    env::set_var("PATH", dir.path().join("empty"));
+    env::set_var("PREFIX", &prefix);
+    env::remove_var("TERMUX_VERSION");
+
+    assert_eq!(platform::shell(), prefix.join("bin/sh"));
+    assert!(!platform::is_termux());
+    env::set_var("TERMUX_VERSION", "0.118.0");
+    assert!(platform::is_termux());
+}
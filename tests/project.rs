@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use rtun::project::{self, Selection};
+
+use common::harness;
+
+#[test]
+fn matches_branch_patterns() {
+    assert!(project::matches("release/*", "release/1.2"));
+    assert!(project::matches("release/*", "release/1.2/hotfix"));
+    assert!(project::matches("feature-?", "feature-a"));
+    assert!(project::matches("*", "main"));
+    assert!(!project::matches("release/*", "main"));
+    assert!(!project::matches("feature-?", "feature-ab"));
+}
+
+#[test]
+fn selects_the_most_specific_mapping() {
+    let config = project::parse(
+        r#"
+[branches]
+"*" = "dev"
+"release/*" = "staging"
+"release/1.x" = "prod"
+
+[worktrees]
+"hotfix-*" = "prod"
+"#,
+        Path::new(".rtun.toml"),
+    )
+    .unwrap();
+    let profile = |branch: Option<&str>, worktree: &str| {
+        config
+            .select(branch, worktree)
+            .map(|selection| selection.profile)
+    };
+    assert_eq!(profile(Some("main"), "app").as_deref(), Some("dev"));
+    assert_eq!(
+        profile(Some("release/2.0"), "app").as_deref(),
+        Some("staging")
+    );
+    assert_eq!(profile(Some("release/1.x"), "app").as_deref(), Some("prod"));
+    assert_eq!(profile(Some("main"), "hotfix-42").as_deref(), Some("prod"));
+    assert_eq!(profile(None, "app"), None);
+
+    let error = project::parse("[branch]\nmain = \"dev\"\n", Path::new(".rtun.toml"))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("did you mean `branches`?"), "{}", error);
+}
+
+#[test]
+fn detects_the_profile_of_a_git_checkout() {
+    let repo = harness().home.join("project");
+    fs::create_dir_all(repo.join("src")).unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?}", args);
+    };
+    git(&["init", "-q", "-b", "release/1.2"]);
+    assert_eq!(project::detect(&repo.join("src")).unwrap(), None);
+
+    fs::write(
+        repo.join(".rtun.toml"),
+        "[branches]\n\"release/*\" = \"staging\"\n",
+    )
+    .unwrap();
+    assert_eq!(
+        project::detect(&repo.join("src")).unwrap(),
+        Some(Selection {
+            profile: "staging".to_string(),
+            name: "release/1.2".to_string(),
+            pattern: "release/*".to_string(),
+        })
+    );
+    assert_eq!(project::detect(&harness().home).unwrap(), None);
+}
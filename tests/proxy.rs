@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use rtun::pac::{self, PacRules};
+use rtun::proxy::{self, Routes};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn request(port: u16, host: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let request = format!(
+        "GET /status HTTP/1.1\r\nHost: {}\r\nAccept: */*\r\n\r\n",
+        host
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    stream
+}
+
+#[tokio::test]
+async fn routes_by_service_name() {
+    let grafana = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let routes = Arc::new(Routes::default());
+    routes.insert("Grafana", grafana.local_addr().unwrap().port());
+    proxy::serve(18100, routes).await.unwrap();
+
+    let _client = request(18100, "grafana.localhost:18100").await;
+    let (mut upstream, _) = grafana.accept().await.unwrap();
+    let mut head = vec![0u8; 64];
+    let n = upstream.read(&mut head).await.unwrap();
+    assert!(head[..n].starts_with(b"GET /status HTTP/1.1\r\nHost: grafana.localhost:18100"));
+
+    let mut client = request(18100, "kibana.localhost").await;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    assert!(
+        response.ends_with("Known services: grafana\n"),
+        "{}",
+        response
+    );
+}
+
+#[tokio::test]
+async fn serves_pac_for_socks_tunnels() {
+    let rules = Arc::new(PacRules::default());
+    rules.insert(
+        &["*.internal".to_string(), "corp.example".to_string()],
+        1080,
+    );
+    rules.insert(&[], 1081);
+    pac::serve(18101, rules).await.unwrap();
+
+    let mut client = request(18101, "127.0.0.1:18101").await;
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.ends_with(
+        "function FindProxyForURL(url, host) {\n  \
+         if (shExpMatch(host, \"*.internal\") || host == \"corp.example\" || dnsDomainIs(host, \".corp.example\")) \
+         return \"SOCKS5 127.0.0.1:1080; SOCKS 127.0.0.1:1080\";\n  \
+         return \"DIRECT\";\n}\n"
+    ));
+}
@@ -0,0 +1,50 @@
+use std::net::TcpListener;
+use std::time::Duration;
+
+use rtun::ready;
+use rtun::status::{Health, TunnelInfo};
+
+fn tunnel(local_port: u16, health: Health) -> TunnelInfo {
+    TunnelInfo {
+        host: "db".to_string(),
+        local_port,
+        remote_port: 5432,
+        remote_host: None,
+        name: None,
+        label: None,
+        status: "up".to_string(),
+        health,
+        error: None,
+        up_since: None,
+    }
+}
+
+#[test]
+fn parses_timeouts() {
+    assert_eq!(ready::parse_timeout("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(ready::parse_timeout("2m"), Ok(Duration::from_secs(120)));
+    assert_eq!(ready::parse_timeout("1h"), Ok(Duration::from_secs(3600)));
+    assert_eq!(ready::parse_timeout("45"), Ok(Duration::from_secs(45)));
+    assert!(ready::parse_timeout("soon").is_err());
+    assert!(ready::parse_timeout("5d").is_err());
+}
+
+#[test]
+fn waits_for_tunnels_up_and_listening() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let tunnels = [
+        tunnel(open, Health::Up),
+        tunnel(closed, Health::Up),
+        tunnel(9, Health::Down),
+    ];
+    assert_eq!(ready::waiting_for(&tunnels, &[open]), Vec::<u16>::new());
+    assert_eq!(ready::waiting_for(&tunnels, &[open, closed]), [closed]);
+    assert_eq!(ready::waiting_for(&tunnels, &[]), [closed, 9]);
+    assert_eq!(ready::waiting_for(&tunnels, &[1234]), [1234]);
+}
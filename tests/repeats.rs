@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+use rtun::repeats::{Repeats, REPORT_INTERVAL, WINDOW};
+
+#[test]
+fn folds_repeated_errors() {
+    let error = "deploy@db: Permission denied (publickey).";
+    let start = Instant::now();
+    let mut repeats = Repeats::default();
+    assert!(repeats.see(error, start));
+    assert_eq!(repeats.summary(error, start), error);
+
+    let reported: Vec<bool> = (1..12)
+        .map(|i| repeats.see(error, start + Duration::from_secs(10 * i)))
+        .collect();
+    let later = start + Duration::from_secs(110);
+    // Once more after a minute, not on every attempt.
+    assert_eq!(reported.iter().filter(|r| **r).count(), 1);
+    assert!(reported[5]);
+    assert_eq!(repeats.count(later), 12);
+    assert_eq!(repeats.summary(error, later), "auth failed ×12 in last 5m");
+
+    // Another error starts over, and is reported right away.
+    let refused = "ssh: connect to host db port 22: Connection refused";
+    assert!(repeats.see(refused, later));
+    assert!(!repeats.see(refused, later + Duration::from_secs(1)));
+    assert_eq!(repeats.summary(error, later), error);
+    assert_eq!(
+        repeats.summary(refused, later + Duration::from_secs(1)),
+        "host unreachable ×2 in last 5m"
+    );
+
+    // Only the window counts.
+    let quiet = later + WINDOW + REPORT_INTERVAL;
+    assert!(repeats.see(refused, quiet));
+    assert_eq!(repeats.count(quiet), 1);
+    assert_eq!(repeats.summary(refused, quiet), refused);
+}
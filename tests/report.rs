@@ -0,0 +1,62 @@
+use chrono::{TimeZone, Utc};
+
+use rtun::errors::{ErrorCode, ErrorInfo};
+use rtun::history::{EventKind, HistoryEntry};
+use rtun::report::{self, ReportFormat};
+use rtun::status::{Health, TunnelInfo};
+
+fn tunnel(host: &str, status: &str, health: Health) -> TunnelInfo {
+    TunnelInfo {
+        host: host.to_string(),
+        local_port: 5432,
+        remote_port: 5432,
+        remote_host: None,
+        name: None,
+        label: None,
+        status: status.to_string(),
+        health,
+        error: None,
+        up_since: None,
+    }
+}
+
+#[test]
+fn renders_tunnels_and_events() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let mut db = tunnel("db", "up", Health::Up);
+    db.label = Some("primary | replica".to_string());
+    db.up_since = Some(now - chrono::Duration::seconds(7_260));
+    let mut web = tunnel("web", "failed", Health::Down);
+    web.error = Some(ErrorInfo {
+        code: ErrorCode::AuthFailure,
+        message: "Permission denied".to_string(),
+    });
+    let events = [HistoryEntry {
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 9, 59, 0).unwrap(),
+        event: EventKind::Open,
+        user: "ana".to_string(),
+        host: "db".to_string(),
+        local_port: 5432,
+        remote_port: 5432,
+        detail: None,
+    }];
+
+    let markdown = report::render(
+        ReportFormat::Markdown,
+        &[db.clone(), web.clone()],
+        &events,
+        now,
+    );
+    assert!(markdown.starts_with("# rtun session report\n\nAs of 2024-03-01 12:00:00 UTC."));
+    assert!(markdown.contains("| db 5432:5432 (primary \\| replica) | up | 2h01m |  |\n"));
+    assert!(markdown.contains("| web 5432:5432 | failed |  | Permission denied |\n"));
+    assert!(markdown.contains("- 2024-03-01 09:59:00 UTC  open  ana  db 5432:5432\n"));
+
+    let text = report::render(ReportFormat::Text, &[db, web], &events, now);
+    assert!(text.contains("  db 5432:5432 (primary | replica)  up for 2h01m\n"));
+    assert!(text.contains("  web 5432:5432  failed: Permission denied\n"));
+    assert_eq!(
+        report::render(ReportFormat::Text, &[], &[], now),
+        "rtun session report as of 2024-03-01 12:00:00 UTC\n\nTunnels:\n  none open\n\nRecent events:\n"
+    );
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Local, TimeZone, Weekday};
+use rtun::schedule::Schedule;
+
+fn at(day: u32, time: &str) -> DateTime<Local> {
+    // 2024-01-01 was a Monday.
+    let (hour, minute) = time.split_once(':').unwrap();
+    Local
+        .with_ymd_and_hms(
+            2024,
+            1,
+            day,
+            hour.parse().unwrap(),
+            minute.parse().unwrap(),
+            0,
+        )
+        .unwrap()
+}
+
+fn window(days: Vec<Weekday>, from: &str, until: &str) -> Schedule {
+    Schedule::Window {
+        days,
+        from: from.to_string(),
+        until: until.to_string(),
+    }
+}
+
+#[test]
+fn window_respects_days_and_times() {
+    let office = window(
+        vec![
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+        ],
+        "09:00",
+        "18:00",
+    );
+    assert!(office.validate().is_ok());
+    assert!(office.is_open(at(1, "09:00")));
+    assert!(office.is_open(at(5, "17:59")));
+    assert!(!office.is_open(at(1, "08:59")));
+    assert!(!office.is_open(at(1, "18:00")));
+    assert!(!office.is_open(at(6, "12:00")));
+}
+
+#[test]
+fn window_can_cross_midnight() {
+    // Friday night into Saturday morning, but not Saturday night.
+    let night = window(vec![Weekday::Fri], "22:00", "06:00");
+    assert!(night.is_open(at(5, "23:00")));
+    assert!(night.is_open(at(6, "05:00")));
+    assert!(!night.is_open(at(6, "23:00")));
+    assert!(!night.is_open(at(5, "05:00")));
+}
+
+#[test]
+fn cron_schedule_opens_between_expressions() {
+    let schedule = Schedule::Cron {
+        open: "0 9 * * Mon-Fri".to_string(),
+        close: "0 18 * * Mon-Fri".to_string(),
+    };
+    assert!(schedule.validate().is_ok());
+    assert!(schedule.is_open(at(2, "10:00")));
+    assert!(!schedule.is_open(at(2, "19:00")));
+    // Closed at 18:00 on Friday until Monday morning.
+    assert!(!schedule.is_open(at(6, "10:00")));
+    assert!(schedule.is_open(at(8, "09:30")));
+}
+
+#[test]
+fn rejects_invalid_schedules() {
+    assert!(window(Vec::new(), "9am", "18:00").validate().is_err());
+    let cron = Schedule::Cron {
+        open: "every morning".to_string(),
+        close: "0 18 * * *".to_string(),
+    };
+    assert!(cron.validate().is_err());
+}
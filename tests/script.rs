@@ -0,0 +1,47 @@
+use rtun::script::Command;
+use rtun::tunnel::TunnelSpec;
+
+#[test]
+fn parses_stdin_commands() {
+    assert_eq!(
+        Command::parse("add bastion 8080:80"),
+        Some(Ok(Command::Add(Box::new(TunnelSpec::new(
+            "bastion", 8080, 80
+        )))))
+    );
+    assert_eq!(
+        Command::parse("add --host bastion --local-port 8080 --remote 80"),
+        Some(Ok(Command::Add(Box::new(TunnelSpec::new(
+            "bastion", 8080, 80
+        )))))
+    );
+    assert_eq!(
+        Command::parse("  close 8080 "),
+        Some(Ok(Command::Close(8080)))
+    );
+    assert_eq!(
+        Command::parse("restart 8080"),
+        Some(Ok(Command::Restart(8080)))
+    );
+    assert_eq!(
+        Command::parse("up staging"),
+        Some(Ok(Command::Up("staging".to_string())))
+    );
+    assert_eq!(
+        Command::parse("code 8080 123 456"),
+        Some(Ok(Command::Code(8080, "123 456".to_string())))
+    );
+    assert_eq!(Command::parse("no"), Some(Ok(Command::Confirm(false))));
+    assert_eq!(Command::parse("quit"), Some(Ok(Command::Quit)));
+    assert_eq!(Command::parse(""), None);
+    assert_eq!(Command::parse("# open the database"), None);
+
+    assert!(matches!(Command::parse("add bastion 8080"), Some(Err(_))));
+    assert!(matches!(Command::parse("close http"), Some(Err(_))));
+    assert!(matches!(Command::parse("up"), Some(Err(_))));
+    assert!(matches!(Command::parse("quit now"), Some(Err(_))));
+    assert_eq!(
+        Command::parse("open db"),
+        Some(Err("Unknown command 'open db'".to_string()))
+    );
+}
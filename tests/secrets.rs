@@ -0,0 +1,56 @@
+mod common;
+
+use rtun::secrets::{self, Secret, Store};
+
+use common::harness;
+
+#[test]
+fn keeps_secrets_encrypted() {
+    let harness = harness();
+    let mut store = Store::open("correct horse").unwrap();
+    store.set("bastion", "hunter2");
+    store.save().unwrap();
+
+    let contents = std::fs::read(harness.home.join(".rtun/secrets.enc")).unwrap();
+    assert!(!String::from_utf8_lossy(&contents).contains("hunter2"));
+    assert!(Store::open("wrong horse").is_err());
+
+    let store = Store::open("correct horse").unwrap();
+    assert_eq!(store.get("bastion"), Some("hunter2"));
+    assert_eq!(store.names().collect::<Vec<_>>(), ["bastion"]);
+    store.unlock();
+    assert_eq!(
+        secrets::resolve(&Secret::Store("bastion".to_string())).unwrap(),
+        "hunter2"
+    );
+    assert!(secrets::resolve(&Secret::Store("db".to_string())).is_err());
+
+    // Only a token goes into the environment of ssh, good for one claim.
+    let env = secrets::askpass_env(Some(&Secret::Store("bastion".to_string()))).unwrap();
+    assert!(!env.iter().any(|(_, value)| value.contains("hunter2")));
+    let value = |name| env.iter().find(|(n, _)| *n == name).unwrap().1.clone();
+    let socket = std::path::PathBuf::from(value(secrets::ASKPASS_SOCKET));
+    let token = value(secrets::ASKPASS_TOKEN);
+    assert!(secrets::claim(&socket, "not-a-token").is_err());
+    assert_eq!(secrets::claim(&socket, &token).unwrap(), "hunter2");
+    assert!(secrets::claim(&socket, &token).is_err());
+}
+
+#[test]
+fn runs_password_commands_when_asked() {
+    let secret = Secret::Command("printf 'pa ss\\n'".to_string());
+    assert_eq!(secrets::resolve(&secret).unwrap(), "pa ss");
+    assert!(secrets::resolve(&Secret::Command("exit 1".to_string())).is_err());
+
+    // The command is left for the askpass program to run.
+    let env = secrets::askpass_env(Some(&secret)).unwrap();
+    assert!(env.contains(&(secrets::ASKPASS_COMMAND, "printf 'pa ss\\n'".to_string())));
+    assert!(!env.iter().any(|(name, _)| *name == secrets::ASKPASS_TOKEN));
+
+    // Without a password rtun still answers, to pass security key prompts on.
+    let env = secrets::askpass_env(None).unwrap();
+    assert!(env.iter().any(|(name, _)| *name == secrets::ASKPASS));
+    assert!(!env
+        .iter()
+        .any(|(name, _)| *name == secrets::ASKPASS_COMMAND));
+}
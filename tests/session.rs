@@ -0,0 +1,79 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use rtun::config::{Config, Profile};
+use rtun::session::{self, SavedTunnel};
+use rtun::tunnel::TunnelSpec;
+
+use common::harness;
+
+fn config() -> Config {
+    Config {
+        profiles: BTreeMap::from([(
+            "dev".to_string(),
+            Profile {
+                tunnels: vec![TunnelSpec::new("bastion", 5432, 5432)],
+                ..Profile::default()
+            },
+        )]),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn saves_tunnels_by_where_they_came_from() {
+    let config = config();
+    let profile = TunnelSpec::new("bastion", 5432, 5432);
+    assert_eq!(
+        SavedTunnel::of(&config, &profile),
+        SavedTunnel::Profile {
+            profile: "dev".to_string(),
+            local_port: 5432
+        }
+    );
+    let single = TunnelSpec {
+        label: Some("Jenkins".to_string()),
+        remote_host: Some("ci".to_string()),
+        ..TunnelSpec::new("bastion", 8080, 80)
+    };
+    let saved = SavedTunnel::of(&config, &single);
+    assert!(matches!(saved, SavedTunnel::Single { .. }));
+    assert_eq!(saved.restore(&config), Ok(single));
+
+    let chained = TunnelSpec {
+        via: vec!["gateway".to_string()],
+        ..TunnelSpec::new("db", 6543, 5432)
+    };
+    let saved = SavedTunnel::of(&config, &chained);
+    assert!(matches!(saved, SavedTunnel::Unsaved { .. }));
+    assert!(saved.restore(&config).is_err());
+
+    let gone = SavedTunnel::Profile {
+        profile: "dev".to_string(),
+        local_port: 6379,
+    };
+    let error = gone.restore(&config).unwrap_err();
+    assert!(error.contains("no tunnel on port 6379"), "{}", error);
+}
+
+#[test]
+fn restores_only_sessions_left_behind() {
+    let path = harness().home.join(".rtun/session.json");
+    session::save(vec![SavedTunnel::Profile {
+        profile: "dev".to_string(),
+        local_port: 5432,
+    }])
+    .unwrap();
+    // Still this instance's own.
+    assert_eq!(session::left_behind(), None);
+
+    let contents = fs::read_to_string(&path).unwrap();
+    let owner = format!("\"owner\": {}", std::process::id());
+    fs::write(&path, contents.replace(&owner, "\"owner\": 999999999")).unwrap();
+    let session = session::left_behind().expect("a session left behind");
+    assert_eq!(session.tunnels.len(), 1);
+    session::clear();
+    assert_eq!(session::left_behind(), None);
+}
@@ -0,0 +1,65 @@
+use rtun::sniff::{self, Protocol};
+
+// A ClientHello for example.com as sent by curl, cut after the server name.
+const CLIENT_HELLO: &[u8] = &[
+    0x16, 0x03, 0x01, 0x00, 0x4a, // record
+    0x01, 0x00, 0x00, 0x46, // handshake
+    0x03, 0x03, // version
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+    0x1f, // random
+    0x00, // session id
+    0x00, 0x02, 0x13, 0x01, // cipher suites
+    0x01, 0x00, // compression methods
+    0x00, 0x1b, // extensions
+    0x00, 0x17, 0x00, 0x00, // extended master secret
+    0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, b'e', b'x', b'a', b'm', b'p', b'l', b'e',
+    b'.', b'c', b'o', b'm', // server name
+    0x00, 0x0b, 0x00, 0x02, 0x01, 0x00, // point formats
+];
+
+#[test]
+fn recognizes_protocols_from_the_first_bytes() {
+    assert_eq!(
+        sniff::client(b"GET / HTTP/1.1\r\nUser-Agent: curl\r\nHost: grafana.internal\r\n\r\n"),
+        Some(Protocol::Http {
+            host: Some("grafana.internal".to_string())
+        })
+    );
+    assert_eq!(
+        sniff::client(CLIENT_HELLO),
+        Some(Protocol::Tls {
+            server_name: Some("example.com".to_string())
+        })
+    );
+    // Too short to hold the server name.
+    assert_eq!(
+        sniff::client(&CLIENT_HELLO[..20]),
+        Some(Protocol::Tls { server_name: None })
+    );
+    assert_eq!(
+        sniff::client(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]),
+        Some(Protocol::Postgres)
+    );
+    assert_eq!(
+        sniff::client(b"*1\r\n$4\r\nPING\r\n"),
+        Some(Protocol::Redis)
+    );
+    assert_eq!(
+        sniff::client(b"SSH-2.0-OpenSSH_9.6\r\n"),
+        Some(Protocol::Ssh)
+    );
+    assert_eq!(
+        sniff::server(b"SSH-2.0-OpenSSH_9.6\r\n"),
+        Some(Protocol::Ssh)
+    );
+    assert_eq!(sniff::client(b"\x0a5.7.44"), None);
+    assert_eq!(sniff::client(b""), None);
+}
+
+#[test]
+fn knows_the_usual_protocol_of_well_known_ports() {
+    assert_eq!(sniff::usual(5432), Some("Postgres"));
+    assert_eq!(sniff::usual(3000), None);
+    assert_eq!(Protocol::Redis.name(), "Redis");
+}
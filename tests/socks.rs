@@ -0,0 +1,41 @@
+use rtun::socks;
+
+#[test]
+fn reads_the_destination_of_socks_requests() {
+    // Greeting offering no authentication, then a connect to 10.0.0.7:5432.
+    let by_address = [5, 1, 0, 5, 1, 0, 1, 10, 0, 0, 7, 0x15, 0x38];
+    assert_eq!(
+        socks::request(&by_address),
+        Some(("10.0.0.7".to_string(), 5432))
+    );
+    // Not all there yet.
+    assert_eq!(socks::request(&by_address[..9]), None);
+
+    let mut by_name = vec![5, 2, 0, 2, 5, 1, 0, 3, 12];
+    by_name.extend_from_slice(b"grafana.corp");
+    by_name.extend_from_slice(&[1, 0xbb]);
+    assert_eq!(
+        socks::request(&by_name),
+        Some(("grafana.corp".to_string(), 443))
+    );
+
+    let mut ipv6 = vec![5, 1, 0, 5, 1, 0, 4];
+    ipv6.extend_from_slice(&[0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    ipv6.extend_from_slice(&[0, 80]);
+    assert_eq!(socks::request(&ipv6), Some(("fd00::1".to_string(), 80)));
+
+    // SOCKS 4, then 4a with the name after the user id.
+    let socks4 = [4, 1, 0, 80, 192, 168, 1, 5, b'm', b'e', 0];
+    assert_eq!(
+        socks::request(&socks4),
+        Some(("192.168.1.5".to_string(), 80))
+    );
+    let mut socks4a = vec![4, 1, 0, 80, 0, 0, 0, 1, 0];
+    socks4a.extend_from_slice(b"wiki.corp\0");
+    assert_eq!(
+        socks::request(&socks4a),
+        Some(("wiki.corp".to_string(), 80))
+    );
+
+    assert_eq!(socks::request(b"GET / HTTP/1.1\r\n\r\n"), None);
+}
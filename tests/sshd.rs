@@ -0,0 +1,132 @@
+//! End-to-end tests against a real sshd running in docker.
+//!
+//! Run with `cargo test --features sshd-tests`; requires `docker` and `ssh`.
+
+#![cfg(feature = "sshd-tests")]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rtun::config::Config;
+use rtun::tunnel::{self, Control, Stop, TunnelSpec, TunnelStatus};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+const IMAGE: &str = "rtun-test-sshd";
+const SSH_PORT: u16 = 22222;
+
+struct Container(String);
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        let _ = Command::new("docker").args(["rm", "-f", &self.0]).output();
+    }
+}
+
+fn run(command: &mut Command) -> String {
+    let output = command.output().expect("failed to run command");
+    assert!(
+        output.status.success(),
+        "{:?} failed: {:?}",
+        command,
+        output
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Starts the sshd container and puts an `ssh` wrapper on `PATH` that points
+/// the real client at it through a dedicated config file.
+fn start_sshd(dir: &Path) -> Container {
+    let key = dir.join("id_ed25519");
+    run(Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key));
+    let public_key = fs::read_to_string(dir.join("id_ed25519.pub")).unwrap();
+
+    let context = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sshd");
+    run(Command::new("docker")
+        .args(["build", "-q", "-t", IMAGE])
+        .arg(context));
+    let id = run(Command::new("docker")
+        .args(["run", "-d", "--rm", "-p"])
+        .arg(format!("127.0.0.1:{}:22", SSH_PORT))
+        .arg("-e")
+        .arg(format!("AUTHORIZED_KEY={}", public_key.trim()))
+        .arg(IMAGE));
+
+    let config = dir.join("ssh_config");
+    fs::write(
+        &config,
+        format!(
+            "Host rtun-test\n  HostName 127.0.0.1\n  Port {}\n  User root\n  IdentityFile {}\n  \
+             StrictHostKeyChecking no\n  UserKnownHostsFile /dev/null\n",
+            SSH_PORT,
+            key.display()
+        ),
+    )
+    .unwrap();
+
+    let real_ssh = run(Command::new("sh").args(["-c", "command -v ssh"]));
+    let bin = dir.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    let wrapper = bin.join("ssh");
+    fs::write(
+        &wrapper,
+        format!(
+            "#!/bin/sh\nexec {} -F {} \"$@\"\n",
+            real_ssh,
+            config.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{}", bin.display(), path));
+    std::env::set_var("HOME", dir);
+
+    Container(id)
+}
+
+async fn echo_through(port: u16) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    stream.write_all(b"ping\n").await?;
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await?;
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+#[tokio::test]
+async fn forwards_traffic_through_real_sshd() {
+    let dir = tempfile::tempdir().unwrap();
+    let _container = start_sshd(dir.path());
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        TunnelSpec::new("rtun-test", 27777, 7777),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(events.recv().await.unwrap().status, TunnelStatus::Up);
+
+    // sshd needs a moment to boot and the forward to come up.
+    let mut echoed = None;
+    for _ in 0..30 {
+        if let Ok(reply) = echo_through(27777).await {
+            echoed = Some(reply);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(echoed.as_deref(), Some("ping\n"));
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    handle.await.unwrap();
+    assert!(echo_through(27777).await.is_err());
+}
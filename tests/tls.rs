@@ -0,0 +1,32 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use rtun::tls;
+
+use common::harness;
+
+#[test]
+fn creates_one_ca_when_asked_at_once() {
+    let _harness = harness();
+    let loaded: Vec<String> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| scope.spawn(|| tls::load_or_create_ca().unwrap().1.serialize_pem()))
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    assert!(loaded.iter().all(|key| *key == loaded[0]));
+
+    let cert_path = tls::ca_cert_path().unwrap();
+    let key_path = cert_path.with_file_name("ca-key.pem");
+    assert_eq!(fs::read_to_string(&key_path).unwrap(), loaded[0]);
+    let mode = fs::metadata(&key_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let staged: Vec<_> = fs::read_dir(cert_path.parent().unwrap())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(staged.is_empty());
+}
@@ -0,0 +1,1588 @@
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use rtun::backend::{self, Forward, TunnelBackend};
+use rtun::capture::{Capture, CaptureConfig};
+use rtun::config::{self, Config, HealthCheckConfig, ReconnectConfig, SshClient, VpnConfig};
+use rtun::history::{self, EventKind};
+use rtun::manager::{Manager, Progress};
+use rtun::otp;
+use rtun::proxy;
+use rtun::relay::{self, Cidr, Relay};
+use rtun::tunnel::{
+    self, Control, LoadBalance, Prompt, RemoteTarget, Stop, TunnelSpec, TunnelStatus,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+use common::{harness, next_status};
+
+fn spec(host: &str, local_port: u16) -> TunnelSpec {
+    TunnelSpec::new(host, local_port, 80)
+}
+
+fn events_for(host: &str) -> Vec<EventKind> {
+    history::load(usize::MAX, Some(host))
+        .unwrap()
+        .into_iter()
+        .map(|e| e.event)
+        .collect()
+}
+
+#[tokio::test]
+async fn open_and_close() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("steady", 18080),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("steady", 1).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    assert_eq!(events_for("steady"), [EventKind::Open, EventKind::Close]);
+    assert_eq!(args.len(), 1);
+    assert!(args[0].contains("-L 18080:127.0.0.1:80"), "{}", args[0]);
+}
+
+#[tokio::test]
+async fn reconnects_after_failure() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("flaky-1", 18081),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    assert_eq!(
+        next_status(&mut events).await,
+        TunnelStatus::Reconnecting(1)
+    );
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("flaky-1", 2).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    assert_eq!(
+        events_for("flaky-1"),
+        [
+            EventKind::Open,
+            EventKind::Failure,
+            EventKind::Reconnect,
+            EventKind::Close
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failure_records_ssh_error() {
+    harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("refused-1", 18082),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    assert_eq!(
+        next_status(&mut events).await,
+        TunnelStatus::Reconnecting(1)
+    );
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    let failure = history::load(usize::MAX, Some("refused-1"))
+        .unwrap()
+        .into_iter()
+        .find(|e| e.event == EventKind::Failure)
+        .expect("failure was recorded");
+    assert_eq!(
+        failure.detail.as_deref(),
+        Some("ssh: connect to host refused-1 port 22: Connection refused")
+    );
+}
+
+#[tokio::test]
+async fn restart_replaces_ssh_process() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("restarted", 18083),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("restarted", 1).await;
+    control
+        .send(Control::Restart("network changed".to_string()))
+        .unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("restarted", 2).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    let entries = history::load(usize::MAX, Some("restarted")).unwrap();
+    assert_eq!(entries[1].event, EventKind::Reconnect);
+    assert_eq!(entries[1].detail.as_deref(), Some("network changed"));
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    harness();
+    let mut spec = spec("refused-2", 18084);
+    spec.reconnect = ReconnectConfig {
+        max_attempts: Some(1),
+        backoff_base: Some(0),
+        ..Default::default()
+    };
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (_control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    assert_eq!(
+        next_status(&mut events).await,
+        TunnelStatus::Reconnecting(1)
+    );
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Failed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn fails_over_when_health_check_fails() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    // The mock ssh does not listen on the local port, so every probe fails.
+    let spec = TunnelSpec {
+        fallbacks: vec![RemoteTarget::try_from("db-2:5432".to_string()).unwrap()],
+        health_check: Some(HealthCheckConfig {
+            interval: 1,
+            failures: 1,
+        }),
+        ..spec("replicated", 18085)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let first = events.recv().await.unwrap();
+    assert_eq!(first.target, "127.0.0.1:80");
+    let args = harness.wait_for_invocations("replicated", 2).await;
+    assert!(args[1].contains("-L 18085:db-2:5432"), "{}", args[1]);
+    let second = events.recv().await.unwrap();
+    assert_eq!(second.status, TunnelStatus::Up);
+    assert_eq!(second.target, "db-2:5432");
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    let reconnect = history::load(usize::MAX, Some("replicated"))
+        .unwrap()
+        .into_iter()
+        .find(|e| e.event == EventKind::Reconnect)
+        .expect("reconnect entry");
+    assert_eq!(
+        reconnect.detail.as_deref(),
+        Some("health check failed on 127.0.0.1:80, switching to db-2:5432")
+    );
+}
+
+#[tokio::test]
+async fn balances_connections_across_targets() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        fallbacks: vec![RemoteTarget::try_from("db-2:80".to_string()).unwrap()],
+        load_balance: Some(LoadBalance::RoundRobin),
+        ..spec("balanced", 18086)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let up = events.recv().await.unwrap();
+    assert_eq!(up.status, TunnelStatus::Up);
+    let stats = up.connections.expect("relay stats");
+    // The mock ssh forwards nothing, so stand in for its listeners.
+    let args = harness.wait_for_invocations("balanced", 1).await;
+    let backends: Vec<TcpListener> = {
+        let mut listeners = Vec::new();
+        for forward in args[0].split(' ').filter(|a| a.starts_with("127.0.0.1:")) {
+            let port: u16 = forward.split(':').nth(1).unwrap().parse().unwrap();
+            listeners.push(TcpListener::bind(("127.0.0.1", port)).await.unwrap());
+        }
+        listeners
+    };
+    assert_eq!(backends.len(), 2, "{}", args[0]);
+    assert!(args[0].contains(":127.0.0.1:80 "), "{}", args[0]);
+    assert!(args[0].contains(":db-2:80 "), "{}", args[0]);
+
+    let _first = TcpStream::connect("127.0.0.1:18086").await.unwrap();
+    let _first_backend = backends[0].accept().await.unwrap();
+    let _second = TcpStream::connect("127.0.0.1:18086").await.unwrap();
+    let _second_backend = backends[1].accept().await.unwrap();
+    let counts = || -> Vec<(usize, u64)> {
+        stats
+            .targets
+            .iter()
+            .map(|t| (t.active(), t.total()))
+            .collect()
+    };
+    // The relay counts a connection right after its backend accepted it.
+    for _ in 0..50 {
+        if counts() == [(1, 1), (1, 1)] {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(counts(), [(1, 1), (1, 1)]);
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[test]
+fn matches_clients_against_the_allow_list() {
+    let allow: Vec<Cidr> = ["192.168.1.0/24", "fd00::/8", "10.0.0.7"]
+        .iter()
+        .map(|cidr| Cidr::try_from(cidr.to_string()).unwrap())
+        .collect();
+    let allowed = |ip: &str| relay::is_allowed(&allow, ip.parse().unwrap());
+    assert!(allowed("192.168.1.42"));
+    assert!(allowed("::ffff:192.168.1.42"));
+    assert!(allowed("fd12::1"));
+    assert!(allowed("10.0.0.7"));
+    assert!(!allowed("10.0.0.8"));
+    assert!(!allowed("192.168.2.1"));
+    // This machine always gets in, for health checks.
+    assert!(allowed("127.0.0.1"));
+    assert!(relay::is_allowed(&[], "203.0.113.9".parse().unwrap()));
+    assert!(Cidr::try_from("10.0.0.0/33".to_string()).is_err());
+    assert!(Cidr::try_from("lan".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn relays_tunnels_with_an_allow_list() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        lan: true,
+        allow: vec![Cidr::try_from("192.168.1.0/24".to_string()).unwrap()],
+        ..spec("guarded", 18099)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let up = events.recv().await.unwrap();
+    assert_eq!(up.status, TunnelStatus::Up);
+    assert!(up.connections.is_some());
+    let args = harness.wait_for_invocations("guarded", 1).await;
+    // ssh forwards a private port; rtun itself listens on 18099.
+    let forward = args[0]
+        .split(' ')
+        .find(|a| a.starts_with("127.0.0.1:"))
+        .unwrap_or_else(|| panic!("{}", args[0]));
+    assert!(forward.ends_with(":127.0.0.1:80"), "{}", forward);
+    let port: u16 = forward.split(':').nth(1).unwrap().parse().unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    let _client = TcpStream::connect("127.0.0.1:18099").await.unwrap();
+    backend.accept().await.unwrap();
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn drains_relayed_tunnels() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        max_connections: Some(10),
+        ..spec("drained", 18107)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("drained", 1).await;
+    let backend = TcpListener::bind(("127.0.0.1", backend_port(&args, 0)))
+        .await
+        .unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18107").await.unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+
+    control.send(Control::Drain).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Draining);
+    assert!(TcpStream::connect("127.0.0.1:18107").await.is_err());
+    // The open connection carries on meanwhile.
+    client.write_all(b"ping").await.unwrap();
+    let mut ping = [0; 4];
+    remote.read_exact(&mut ping).await.unwrap();
+    assert_eq!(&ping, b"ping");
+
+    drop(client);
+    drop(remote);
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    let history = history::load(usize::MAX, Some("drained")).unwrap();
+    assert_eq!(history.last().unwrap().event, EventKind::Close);
+    assert_eq!(history.last().unwrap().detail.as_deref(), Some("drained"));
+}
+
+/// The port rtun relays to in the `n`th ssh invocation of `args`.
+fn backend_port(args: &[String], n: usize) -> u16 {
+    let forward = args[n]
+        .split(' ')
+        .find(|a| a.starts_with("127.0.0.1:"))
+        .unwrap_or_else(|| panic!("{}", args[n]));
+    forward.split(':').nth(1).unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn moves_to_another_backend_port_when_it_was_taken() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        max_connections: Some(10),
+        ..spec("taken-1", 18111)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    assert_eq!(
+        next_status(&mut events).await,
+        TunnelStatus::Reconnecting(1)
+    );
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("taken-1", 2).await;
+    assert_ne!(backend_port(&args, 0), backend_port(&args, 1));
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn retargets_without_dropping_connections() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        max_connections: Some(10),
+        ..spec("retargeted", 18108)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("retargeted", 1).await;
+    let old = TcpListener::bind(("127.0.0.1", backend_port(&args, 0)))
+        .await
+        .unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18108").await.unwrap();
+    let (mut remote, _) = old.accept().await.unwrap();
+
+    let target = RemoteTarget::try_from("replica-2:5432".to_string()).unwrap();
+    control.send(Control::Retarget(target)).unwrap();
+    let up = events.recv().await.unwrap();
+    assert_eq!(up.target, "replica-2:5432");
+    let args = harness.wait_for_invocations("retargeted", 2).await;
+    assert!(args[1].contains(":replica-2:5432"), "{}", args[1]);
+    let new = TcpListener::bind(("127.0.0.1", backend_port(&args, 1)))
+        .await
+        .unwrap();
+
+    // The open connection stays on the old forward, new ones go to the new.
+    client.write_all(b"ping").await.unwrap();
+    let mut ping = [0; 4];
+    remote.read_exact(&mut ping).await.unwrap();
+    let _second = TcpStream::connect("127.0.0.1:18108").await.unwrap();
+    new.accept().await.unwrap();
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    let history = history::load(usize::MAX, Some("retargeted")).unwrap();
+    assert!(history
+        .iter()
+        .any(|e| e.detail.as_deref() == Some("retargeted from 127.0.0.1:80 to replica-2:5432")));
+}
+
+#[tokio::test]
+async fn connects_lazy_tunnels_on_the_first_client() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        lazy: true,
+        ..spec("lazy", 18109)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Parked);
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert!(harness.ssh_invocations("lazy").is_empty());
+
+    // The client waits while ssh comes up and binds the forward.
+    let mut client = TcpStream::connect("127.0.0.1:18109").await.unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("lazy", 1).await;
+    let backend = TcpListener::bind(("127.0.0.1", backend_port(&args, 0)))
+        .await
+        .unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut ping = [0; 4];
+    remote.read_exact(&mut ping).await.unwrap();
+    assert_eq!(&ping, b"ping");
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn parks_tunnels_turned_lazy() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("turned-lazy", 18110),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("turned-lazy", 1).await;
+    assert!(args[0].contains("-L 18110:"), "{}", args[0]);
+
+    // Without a relay, it gets one, listening where ssh did.
+    control.send(Control::Lazy(true)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Parked);
+    let _client = TcpStream::connect("127.0.0.1:18110").await.unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("turned-lazy", 2).await;
+    backend_port(&args, 1);
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    let history = history::load(usize::MAX, Some("turned-lazy")).unwrap();
+    assert!(history
+        .iter()
+        .any(|e| e.detail.as_deref() == Some("connecting on demand")));
+}
+
+#[tokio::test]
+async fn lists_and_closes_relayed_connections() {
+    let spec = spec("listed", 18102);
+    let relay = Relay::start(&spec, &spec.targets(), std::time::Duration::from_secs(1))
+        .await
+        .unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", relay.backends()[0]))
+        .await
+        .unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18102").await.unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut greeting = [0; 5];
+    remote.read_exact(&mut greeting).await.unwrap();
+    remote.write_all(b"hi").await.unwrap();
+    client.read_exact(&mut greeting[..2]).await.unwrap();
+
+    let clients = relay.stats.clients();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].peer, client.local_addr().unwrap());
+    assert_eq!((clients[0].sent(), clients[0].received()), (5, 2));
+
+    assert!(relay.stats.kill(clients[0].id));
+    assert_eq!(client.read(&mut greeting).await.unwrap(), 0);
+    assert!(relay.stats.clients().is_empty());
+    for _ in 0..50 {
+        if relay.stats.targets[0].active() == 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(relay.stats.targets[0].active(), 0);
+}
+
+#[tokio::test]
+async fn limits_relayed_connections() {
+    let spec = TunnelSpec {
+        max_connections: Some(1),
+        ..spec("limited", 18103)
+    };
+    assert!(spec.relayed());
+    let relay = Relay::start(&spec, &spec.targets(), std::time::Duration::from_secs(1))
+        .await
+        .unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", relay.backends()[0]))
+        .await
+        .unwrap();
+    let _first = TcpStream::connect("127.0.0.1:18103").await.unwrap();
+    let _remote = backend.accept().await.unwrap();
+    let mut second = TcpStream::connect("127.0.0.1:18103").await.unwrap();
+    assert_eq!(second.read(&mut [0; 1]).await.unwrap_or(0), 0);
+    assert_eq!(relay.stats.open(), 1);
+    let rejected = history::load(usize::MAX, Some("limited")).unwrap();
+    assert_eq!(rejected.last().unwrap().event, EventKind::Rejected);
+}
+
+#[tokio::test]
+async fn logs_http_requests_through_relays() {
+    let spec = TunnelSpec {
+        log_requests: true,
+        ..spec("logged", 18104)
+    };
+    let relay = Relay::start(&spec, &spec.targets(), std::time::Duration::from_secs(1))
+        .await
+        .unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", relay.backends()[0]))
+        .await
+        .unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18104").await.unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+    let mut buffer = [0; 256];
+    client
+        .write_all(b"GET /health HTTP/1.1\r\nHost: app\r\n\r\nPOST /jobs HTTP/1.1\r\n")
+        .await
+        .unwrap();
+    client
+        .write_all(b"Content-Length: 2\r\n\r\n{}")
+        .await
+        .unwrap();
+    let mut read = 0;
+    while !buffer[..read].ends_with(b"{}") {
+        read += remote.read(&mut buffer[read..]).await.unwrap();
+    }
+    remote
+        .write_all(b"HTTP/1.1 204 No Content\r\n\r\nHTTP/1.1 500 Internal Server Error\r\n")
+        .await
+        .unwrap();
+    remote
+        .write_all(b"Content-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut read = 0;
+    while !buffer[..read].ends_with(b"Content-Length: 0\r\n\r\n") {
+        read += client.read(&mut buffer[read..]).await.unwrap();
+    }
+
+    let requests = relay.stats.requests().unwrap();
+    let logged: Vec<_> = requests
+        .iter()
+        .map(|r| (r.method.as_str(), r.path.as_str(), r.status))
+        .collect();
+    assert_eq!(
+        logged,
+        [("GET", "/health", Some(204)), ("POST", "/jobs", Some(500))]
+    );
+}
+
+#[tokio::test]
+async fn captures_relayed_traffic_to_pcap() {
+    let spec = spec("captured", 18105);
+    let relay = Relay::start(&spec, &spec.targets(), std::time::Duration::from_secs(1))
+        .await
+        .unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", relay.backends()[0]))
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let config = CaptureConfig {
+        dir: Some(dir.path().to_path_buf()),
+        ..CaptureConfig::default()
+    };
+    relay
+        .stats
+        .start_capture(Capture::create(&config, &spec).unwrap());
+    let mut client = TcpStream::connect("127.0.0.1:18105").await.unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    remote.read_exact(&mut [0; 4]).await.unwrap();
+    remote.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut [0; 4]).await.unwrap();
+    assert!(relay.stats.capturing().is_some());
+
+    let path = relay.stats.stop_capture().unwrap().unwrap();
+    assert!(relay.stats.capturing().is_none());
+    assert_eq!(relay.stats.captured(), Some(path.clone()));
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let pcap = std::fs::read(path).unwrap();
+    assert_eq!(pcap[..4], 0xa1b2c3d4u32.to_le_bytes());
+    // Header, then two records of 16 bytes, 40 bytes of IPv4 and TCP
+    // headers and the payload.
+    assert_eq!(pcap.len(), 24 + 2 * (16 + 40 + 4));
+    assert_eq!(&pcap[24 + 16 + 40..24 + 16 + 44], b"ping");
+    assert_eq!(&pcap[pcap.len() - 4..], b"pong");
+}
+
+#[tokio::test]
+async fn samples_relay_throughput() {
+    let spec = spec("sampled", 18106);
+    let interval = std::time::Duration::from_secs(60);
+    let relay = Relay::start(&spec, &spec.targets(), interval)
+        .await
+        .unwrap();
+    let backend = TcpListener::bind(("127.0.0.1", relay.backends()[0]))
+        .await
+        .unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18106").await.unwrap();
+    let (mut remote, _) = backend.accept().await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    remote.read_exact(&mut [0; 4]).await.unwrap();
+    remote.write_all(b"pong").await.unwrap();
+    client.read_exact(&mut [0; 4]).await.unwrap();
+
+    // With the clock paused, sleeping runs the sampler for exactly the
+    // intervals slept through, however long the exchange took.
+    tokio::time::pause();
+    tokio::time::sleep(interval * 2 + interval / 2).await;
+    assert_eq!(relay.stats.throughput(), [8, 0]);
+}
+
+#[tokio::test]
+async fn chains_through_jump_hosts() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        via: vec!["bastion".to_string(), "staging-gw".to_string()],
+        remote_host: Some("db".to_string()),
+        ..TunnelSpec::new("db-host", 18087, 5432)
+    };
+    assert_eq!(spec.route(), "bastion -> staging-gw -> db-host -> db");
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("db-host", 1).await;
+    assert!(args[0].contains("-J bastion,staging-gw"), "{}", args[0]);
+    assert!(args[0].contains("-L 18087:db:5432"), "{}", args[0]);
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn enforces_strict_mode() {
+    let harness = harness();
+    let config = Arc::new(Config {
+        strict: true,
+        ..Config::default()
+    });
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("strict", 18096),
+        config.clone(),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("strict", 1).await;
+    assert!(
+        args[0].contains("-o StrictHostKeyChecking=yes"),
+        "{}",
+        args[0]
+    );
+    assert!(args[0].contains("-o ForwardAgent=no"), "{}", args[0]);
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (_control, control_rx) = mpsc::unbounded_channel();
+    let lan = TunnelSpec {
+        lan: true,
+        ..spec("strict-lan", 18097)
+    };
+    tunnel::supervise(lan, config.clone(), events_tx, control_rx).await;
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Failed);
+    assert!(harness.ssh_invocations("strict-lan").is_empty());
+
+    // Clients without {options} would go without the host key checks.
+    let mut plink = (*config).clone();
+    plink.ssh.hosts.insert(
+        "strict-plink".to_string(),
+        SshClient {
+            args: Some(vec!["{forwards}".to_string(), "{host}".to_string()]),
+            ..SshClient::default()
+        },
+    );
+    assert!(plink
+        .strict_violation(&spec("strict-plink", 18099))
+        .is_some_and(|violation| violation.contains("{options}")));
+    let insecure = TunnelSpec {
+        tls_port: Some(18100),
+        tls_backend: Some(config::TlsBackendConfig {
+            insecure: true,
+            ..Default::default()
+        }),
+        ..spec("strict-tls", 18099)
+    };
+    assert!(config
+        .strict_violation(&insecure)
+        .is_some_and(|violation| violation.contains("certificate checks")));
+
+    let mut manager = Manager::new(config);
+    manager.open(TunnelSpec {
+        tags: vec!["env:prod".to_string()],
+        ..spec("strict-prod", 18098)
+    });
+    assert!(manager.tunnels.is_empty());
+    assert_eq!(manager.pending.len(), 1);
+    manager.confirm(false);
+    assert!(manager.pending.is_empty() && manager.tunnels.is_empty());
+}
+
+#[tokio::test]
+async fn knocks_before_connecting() {
+    let harness = harness();
+    let udp = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut config = Config::default();
+    config.knock.insert(
+        "knocked".to_string(),
+        toml::from_str(&format!(
+            r#"
+address = "127.0.0.1"
+sequence = ["{}/udp", "{}"]
+delay = 10
+"#,
+            udp.local_addr().unwrap().port(),
+            tcp.local_addr().unwrap().port()
+        ))
+        .unwrap(),
+    );
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("knocked", 18088),
+        Arc::new(config),
+        events_tx,
+        control_rx,
+    ));
+
+    let mut buf = [0u8; 1];
+    udp.recv_from(&mut buf).await.unwrap();
+    tcp.accept().await.unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("knocked", 1).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn reports_unreachable_host_without_running_ssh() {
+    let harness = harness();
+    for (host, expected) in [
+        ("unreachable-1", "Host unreachable: 127.0.0.1:1"),
+        ("nxdomain-1", "DNS lookup failed for nxdomain-1.invalid"),
+    ] {
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let (control, control_rx) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(tunnel::supervise(
+            spec(host, 18089),
+            Arc::new(Config::default()),
+            events_tx,
+            control_rx,
+        ));
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.status, TunnelStatus::Reconnecting(1));
+        let detail = event.detail.unwrap_or_default();
+        assert!(detail.starts_with(expected), "{}", detail);
+        control.send(Control::Stop(Stop::Close)).unwrap();
+        assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+        handle.await.unwrap();
+        assert!(harness.ssh_invocations(host).is_empty());
+    }
+}
+
+#[tokio::test]
+async fn waits_for_vpn_before_connecting() {
+    let harness = harness();
+    let marker = harness.home.join("vpn-up");
+    let mut config = Config::default();
+    config.vpn.insert(
+        "corp".to_string(),
+        VpnConfig {
+            command: Some(format!("test -e '{}'", marker.display())),
+            ..Default::default()
+        },
+    );
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        requires_vpn: Some("corp".to_string()),
+        ..spec("behind-vpn", 18090)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(config),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::WaitingForVpn);
+    assert!(harness.ssh_invocations("behind-vpn").is_empty());
+    std::fs::write(&marker, "").unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("behind-vpn", 1).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn terminates_tls_in_front_of_the_forward() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::{self, pki_types::ServerName, ClientConfig, RootCertStore};
+
+    let harness = harness();
+    // The mock ssh forwards nothing, so stand in for the forwarded service.
+    let service = TcpListener::bind("127.0.0.1:18091").await.unwrap();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        tls_port: Some(18092),
+        ..spec("secure", 18091)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("secure", 1).await;
+
+    let (ca, _) = rtun::tls::load_or_create_ca().unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let client =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    let stream = TcpStream::connect("127.0.0.1:18092").await.unwrap();
+    let mut tls = tokio_rustls::TlsConnector::from(Arc::new(client))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    tls.write_all(b"ping").await.unwrap();
+    let (mut upstream, _) = service.accept().await.unwrap();
+    let mut buf = [0u8; 4];
+    upstream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn encrypts_again_towards_tls_forwards() {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::{
+        self, pki_types::ServerName, ClientConfig, RootCertStore, ServerConfig,
+    };
+
+    let harness = harness();
+    // A service with a certificate from a CA only the company trusts.
+    let internal_key = KeyPair::generate().unwrap();
+    let mut internal = CertificateParams::new(Vec::new()).unwrap();
+    internal.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let internal = internal.self_signed(&internal_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["grafana.corp".to_string()])
+        .unwrap()
+        .signed_by(&key, &internal, &internal_key)
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let ca_path = dir.path().join("corp-ca.pem");
+    std::fs::write(&ca_path, internal.pem()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+    let service = TcpListener::bind("127.0.0.1:18173").await.unwrap();
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        tls_port: Some(18174),
+        tls_backend: Some(config::TlsBackendConfig {
+            ca: Some(ca_path),
+            server_name: Some("grafana.corp".to_string()),
+            insecure: false,
+        }),
+        ..spec("secure-backend", 18173)
+    };
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    harness.wait_for_invocations("secure-backend", 1).await;
+
+    let (ca, _) = rtun::tls::load_or_create_ca().unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let stream = TcpStream::connect("127.0.0.1:18174").await.unwrap();
+    let mut tls = tokio_rustls::TlsConnector::from(Arc::new(client))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    tls.write_all(b"ping").await.unwrap();
+    let (upstream, _) = service.accept().await.unwrap();
+    let mut upstream = tokio_rustls::TlsAcceptor::from(Arc::new(server))
+        .accept(upstream)
+        .await
+        .unwrap();
+    let mut buf = [0u8; 4];
+    upstream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn opens_socks_proxy() {
+    let harness = harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let spec = TunnelSpec {
+        socks: true,
+        ..spec("socks-gw", 18093)
+    };
+    assert_eq!(spec.to_string(), "socks-gw socks:18093");
+    let handle = tokio::spawn(tunnel::supervise(
+        spec,
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let up = events.recv().await.unwrap();
+    assert_eq!(up.status, TunnelStatus::Up);
+    let stats = up.connections.expect("relay stats");
+    assert_eq!(stats.destinations(), Some(Vec::new()));
+    let args = harness.wait_for_invocations("socks-gw", 1).await;
+    let backend = backend_port(&args, 0);
+    assert!(
+        args[0].contains(&format!("-D 127.0.0.1:{} socks-gw", backend)),
+        "{}",
+        args[0]
+    );
+    assert!(!args[0].contains("-L"), "{}", args[0]);
+
+    // Stands in for the proxy of ssh, which the mock does not open.
+    let proxy = TcpListener::bind(("127.0.0.1", backend)).await.unwrap();
+    let mut client = TcpStream::connect("127.0.0.1:18093").await.unwrap();
+    // Greeting, then a connect by name.
+    let mut handshake = vec![5, 1, 0, 5, 1, 0, 3, 12];
+    handshake.extend_from_slice(b"grafana.corp");
+    handshake.extend_from_slice(&443u16.to_be_bytes());
+    client.write_all(&handshake).await.unwrap();
+    let (mut remote, _) = proxy.accept().await.unwrap();
+    let mut sent = vec![0; handshake.len()];
+    remote.read_exact(&mut sent).await.unwrap();
+    let destinations = stats.destinations().unwrap();
+    assert_eq!(destinations.len(), 1);
+    assert_eq!(
+        (destinations[0].host.as_str(), destinations[0].port),
+        ("grafana.corp", 443)
+    );
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn closes_tunnels_by_tag() {
+    let harness = harness();
+    let mut manager = Manager::new(Arc::new(Config::default()));
+    for (host, port, tags) in [
+        ("tagged-staging", 18150, vec!["env:staging", "db"]),
+        ("tagged-prod", 18151, vec!["env:prod", "db"]),
+    ] {
+        manager.open(TunnelSpec {
+            tags: tags.into_iter().map(str::to_string).collect(),
+            ..spec(host, port)
+        });
+    }
+    harness.wait_for_invocations("tagged-staging", 1).await;
+    harness.wait_for_invocations("tagged-prod", 1).await;
+
+    assert_eq!(manager.tags(), ["db", "env:prod", "env:staging"]);
+    let closed = manager.close_tagged(&["env:staging".to_string(), "db".to_string()], None);
+    assert_eq!(closed, 1);
+    assert_eq!(manager.tunnels[0].status, TunnelStatus::Closed);
+    assert_eq!(manager.tunnels[1].status, TunnelStatus::Up);
+    manager.shutdown(Stop::Close).await;
+}
+
+#[tokio::test]
+async fn closes_tunnels_tied_to_a_process_once_it_exits() {
+    let harness = harness();
+    let mut manager = Manager::new(Arc::new(Config::default()));
+    manager.open(spec("tied-direnv", 18175));
+    manager.open(spec("tied-other", 18176));
+    harness.wait_for_invocations("tied-direnv", 1).await;
+    harness.wait_for_invocations("tied-other", 1).await;
+
+    let mut child = std::process::Command::new("sleep")
+        .arg("0.2")
+        .spawn()
+        .unwrap();
+    manager.tie(child.id(), vec![18175], None);
+    std::thread::spawn(move || child.wait());
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+    while manager.tunnels[0].status != TunnelStatus::Closed {
+        assert!(tokio::time::Instant::now() < deadline);
+        manager.poll_events();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(manager.tunnels[1].status, TunnelStatus::Up);
+    manager.shutdown(Stop::Close).await;
+}
+
+#[tokio::test]
+async fn keeps_lazy_tunnels_parked_while_taking_turns() {
+    let harness = harness();
+    let config = Config {
+        parallel_connects: 1,
+        ..Config::default()
+    };
+    let mut manager = Manager::new(Arc::new(config));
+    manager.open(TunnelSpec {
+        lazy: true,
+        ..spec("turns-lazy", 18177)
+    });
+    manager.open(spec("turns-after-lazy", 18178));
+    for _ in 0..100 {
+        manager.poll_events();
+        if manager.tunnels[1].status != TunnelStatus::Queued {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    // Its turn ended once it listened, without a client showing up.
+    assert_eq!(manager.tunnels[0].status, TunnelStatus::Parked);
+    assert_eq!(manager.tunnels[1].status, TunnelStatus::Up);
+    harness.wait_for_invocations("turns-after-lazy", 1).await;
+    assert!(harness.ssh_invocations("turns-lazy").is_empty());
+    manager.shutdown(Stop::Close).await;
+}
+
+#[tokio::test]
+async fn unroutes_closed_tunnels() {
+    let _harness = harness();
+    let mut manager = Manager::new(Arc::new(Config::default()));
+    manager.open(TunnelSpec {
+        lazy: true,
+        socks: true,
+        pac_domains: vec!["corp.example".to_string()],
+        ..spec("unroute-socks", 18112)
+    });
+    manager.open(TunnelSpec {
+        lazy: true,
+        name: Some("grafana".to_string()),
+        ..spec("unroute-web", 18113)
+    });
+    assert_eq!(manager.pac_rules().route("wiki.corp.example"), Some(18112));
+
+    manager.close(0);
+    assert_eq!(manager.pac_rules().route("wiki.corp.example"), None);
+    assert!(!manager.pac_rules().render().contains("corp.example"));
+    manager.close_matching(|spec| spec.local_port == 18113, None);
+    proxy::serve(18114, manager.routes()).await.unwrap();
+    let mut client = TcpStream::connect(("127.0.0.1", 18114)).await.unwrap();
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: grafana.localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+    manager.shutdown(Stop::Close).await;
+}
+
+#[tokio::test]
+async fn takes_turns_connecting() {
+    let harness = harness();
+    let config = Config {
+        parallel_connects: 1,
+        ..Config::default()
+    };
+    let mut manager = Manager::new(Arc::new(config));
+    manager.open(spec("turns-first", 18153));
+    manager.open(spec("turns-second", 18154));
+    assert_eq!(manager.tunnels[1].status, TunnelStatus::Queued);
+    let progress = Progress {
+        connected: 0,
+        pending: 2,
+        failed: 0,
+    };
+    assert_eq!(manager.progress(), Some(progress));
+    harness.wait_for_invocations("turns-first", 1).await;
+    assert!(harness.ssh_invocations("turns-second").is_empty());
+
+    // Listening as ssh does once connected.
+    let _forward = TcpListener::bind("127.0.0.1:18153").await.unwrap();
+    for _ in 0..100 {
+        manager.poll_events();
+        if manager.tunnels[1].status != TunnelStatus::Queued {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(manager.tunnels[1].status, TunnelStatus::Up);
+    harness.wait_for_invocations("turns-second", 1).await;
+    manager.shutdown(Stop::Close).await;
+}
+
+#[tokio::test]
+async fn records_when_a_tunnel_drops() {
+    harness();
+    let mut manager = Manager::new(Arc::new(Config::default()));
+    manager.open(spec("flaky-drop", 18152));
+    for _ in 0..100 {
+        manager.poll_events();
+        if manager.tunnels[0].dropped_at.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert!(manager.tunnels[0].dropped_at.is_some());
+    manager.shutdown(Stop::Close).await;
+}
+
+/// Runs `sleep` in place of a transport, remembering what it was asked for.
+#[derive(Default)]
+struct RecordingBackend {
+    forwards: Mutex<Vec<Forward>>,
+    teardowns: Mutex<usize>,
+}
+
+impl TunnelBackend for RecordingBackend {
+    fn connect(
+        &self,
+        _spec: &TunnelSpec,
+        forwards: &[Forward],
+        _config: &Config,
+    ) -> std::io::Result<tokio::process::Child> {
+        self.forwards.lock().unwrap().extend_from_slice(forwards);
+        tokio::process::Command::new("sleep")
+            .arg("30")
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+    }
+
+    fn teardown(&self, _spec: &TunnelSpec) {
+        *self.teardowns.lock().unwrap() += 1;
+    }
+}
+
+#[tokio::test]
+async fn passes_security_key_prompts_on() {
+    harness();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("touch-1", 18094),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let mut prompts = Vec::new();
+    while prompts.len() < 3 {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.status, TunnelStatus::Up);
+        prompts.push(event.prompt);
+    }
+    assert_eq!(
+        prompts,
+        [
+            None,
+            Some(Prompt::Touch(
+                "Confirm user presence for key ED25519-SK SHA256:test".to_string()
+            )),
+            None
+        ]
+    );
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn asks_for_codes_before_connecting_to_hosts_needing_them() {
+    let harness = harness();
+    assert!(otp::is_code_prompt("Verification code: "));
+    assert!(!otp::is_code_prompt("me@bastion's password: "));
+    otp::remember("otp-1").unwrap();
+    assert!(otp::needs_code("otp-1"));
+    assert!(!otp::needs_code("otp-2"));
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("otp-1", 18095),
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    let event = events.recv().await.unwrap();
+    assert_eq!(
+        event.prompt,
+        Some(Prompt::Code("Verification code for otp-1".to_string()))
+    );
+    assert!(harness.ssh_invocations("otp-1").is_empty());
+    control.send(Control::Answer("123456".to_string())).unwrap();
+    harness.wait_for_invocations("otp-1", 1).await;
+    let event = events.recv().await.unwrap();
+    assert_eq!((event.status, event.prompt), (TunnelStatus::Up, None));
+
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn uses_registered_backends() {
+    harness();
+    let recording = Arc::new(RecordingBackend::default());
+    backend::register("recording", recording.clone());
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        TunnelSpec {
+            backend: Some("recording".to_string()),
+            ..spec("backend", 18170)
+        },
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    assert_eq!(
+        *recording.forwards.lock().unwrap(),
+        [Forward::Local {
+            bind: "18170".to_string(),
+            target: "127.0.0.1:80".to_string(),
+        }]
+    );
+    assert_eq!(*recording.teardowns.lock().unwrap(), 1);
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (_control, control_rx) = mpsc::unbounded_channel();
+    tokio::spawn(tunnel::supervise(
+        TunnelSpec {
+            backend: Some("carrier-pigeon".to_string()),
+            ..spec("backend", 18171)
+        },
+        Arc::new(Config::default()),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Failed);
+}
+
+#[test]
+fn builds_ssh_arguments_from_templates() {
+    let config = config::parse(
+        r#"
+[ssh.hosts.prod]
+program = "tsh"
+args = ["ssh", "-N", "{forwards}", "root@{host}"]
+"#,
+        "config.toml".as_ref(),
+    )
+    .unwrap();
+    let forwards = [Forward::Local {
+        bind: "5432".to_string(),
+        target: "127.0.0.1:5432".to_string(),
+    }];
+    let args = |host: &str| -> Vec<String> {
+        backend::ssh_args(&spec(host, 5432), &forwards, &config)
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    };
+
+    assert_eq!(
+        args("db"),
+        [
+            "-N",
+            "-T",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-L",
+            "5432:127.0.0.1:5432",
+            "db"
+        ]
+    );
+    assert_eq!(
+        args("prod"),
+        ["ssh", "-N", "-L", "5432:127.0.0.1:5432", "root@prod"]
+    );
+    assert_eq!(config.ssh.client("prod").program(), Path::new("tsh"));
+    assert_eq!(config.ssh.client("db").program(), Path::new("ssh"));
+}
+
+#[tokio::test]
+async fn adds_forwards_to_master_connections() {
+    let harness = harness();
+    let config =
+        Arc::new(config::parse("[ssh]\nmultiplex = true\n", "config.toml".as_ref()).unwrap());
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    let (control, control_rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(tunnel::supervise(
+        spec("mux-db", 18090),
+        config.clone(),
+        events_tx,
+        control_rx,
+    ));
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+    let args = harness.wait_for_invocations("mux-db", 3).await;
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+
+    assert_eq!(args[0], "-O check mux-db");
+    assert_eq!(args[1], "-O forward -L 18090:127.0.0.1:80 mux-db");
+    let args = harness.wait_for_invocations("mux-db", 5).await;
+    assert!(args.contains(&"-O cancel -L 18090:127.0.0.1:80 mux-db".to_string()));
+
+    // Without one, rtun starts a master of its own, carrying no forwards,
+    // for the tunnels to add theirs to.
+    let mut tunnels = Vec::new();
+    for port in [18091, 18179] {
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let (control, control_rx) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(tunnel::supervise(
+            spec("first-db", port),
+            config.clone(),
+            events_tx,
+            control_rx,
+        ));
+        assert_eq!(next_status(&mut events).await, TunnelStatus::Up);
+        tunnels.push((events, control, handle));
+    }
+    let args = harness.wait_for_invocations("first-db", 8).await;
+    let masters: Vec<_> = args.iter().filter(|a| a.contains(" -f")).collect();
+    assert_eq!(masters.len(), 1, "{:?}", args);
+    assert!(
+        masters[0].contains("-o ControlMaster=yes"),
+        "{}",
+        masters[0]
+    );
+    assert!(!masters[0].contains("-L "), "{}", masters[0]);
+    for port in [18091, 18179] {
+        let forward = format!("-O forward -L {}:127.0.0.1:80 first-db", port);
+        assert!(
+            args.iter()
+                .any(|a| a.starts_with("-S ") && a.ends_with(&forward)),
+            "{:?}",
+            args
+        );
+    }
+
+    // Closing one tunnel only takes its forwards off the master.
+    let (mut events, control, handle) = tunnels.remove(0);
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    let cancel = "-O cancel -L 18091:127.0.0.1:80 first-db";
+    for _ in 0..100 {
+        let args = harness.ssh_invocations("first-db");
+        if args
+            .iter()
+            .any(|a| a.starts_with("-S ") && a.ends_with(cancel))
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    let args = harness.ssh_invocations("first-db");
+    assert!(args
+        .iter()
+        .any(|a| a.starts_with("-S ") && a.ends_with(cancel)));
+    assert!(!args.iter().any(|a| a.contains("-O exit")));
+    assert!(harness.bin.join("first-db.master").exists());
+
+    // The last one has it exit.
+    let (mut events, control, handle) = tunnels.remove(0);
+    control.send(Control::Stop(Stop::Close)).unwrap();
+    assert_eq!(next_status(&mut events).await, TunnelStatus::Closed);
+    handle.await.unwrap();
+    for _ in 0..100 {
+        if !harness.bin.join("first-db.master").exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert!(!harness.bin.join("first-db.master").exists());
+}
+
+#[test]
+fn applies_host_key_policies() {
+    let mut config = config::parse(
+        r#"
+[host_keys.lab]
+policy = "off"
+
+[host_keys.db]
+policy = "accept-new"
+
+[profiles.project.host_keys.db]
+policy = "strict"
+known_hosts = "deploy/known_hosts"
+
+[[profiles.project.tunnels]]
+host = "db"
+local_port = 5432
+remote_port = 5432
+"#,
+        "config.toml".as_ref(),
+    )
+    .unwrap();
+    let args = |spec: &TunnelSpec, config: &config::Config| -> Vec<String> {
+        backend::ssh_args(spec, &[], config)
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .filter(|arg| arg.contains("HostKey") || arg.contains("KnownHosts"))
+            .collect()
+    };
+    let project = config.profile_tunnels("project").unwrap();
+
+    assert_eq!(
+        args(&project[0], &config),
+        [
+            "StrictHostKeyChecking=yes",
+            "UserKnownHostsFile=deploy/known_hosts"
+        ]
+    );
+    assert_eq!(
+        args(&spec("db", 5432), &config),
+        ["StrictHostKeyChecking=accept-new"]
+    );
+    assert_eq!(
+        args(&spec("lab", 8080), &config),
+        ["StrictHostKeyChecking=no", "UserKnownHostsFile=/dev/null"]
+    );
+    assert_eq!(config.strict_violation(&spec("lab", 8080)), None);
+
+    config.strict = true;
+    assert!(config.strict_violation(&spec("lab", 8080)).is_some());
+    assert_eq!(
+        args(&spec("db", 5432), &config),
+        [
+            "StrictHostKeyChecking=yes",
+            "StrictHostKeyChecking=accept-new"
+        ]
+    );
+}
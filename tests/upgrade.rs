@@ -0,0 +1,41 @@
+#![cfg(feature = "self-update")]
+
+use std::fs;
+
+use rtun::upgrade;
+
+#[test]
+fn compares_release_versions() {
+    assert_eq!(upgrade::parse_version("v1.2.3"), Some((1, 2, 3)));
+    assert_eq!(upgrade::parse_version("0.2"), Some((0, 2, 0)));
+    assert_eq!(upgrade::parse_version("1.2.3-rc.1"), Some((1, 2, 3)));
+    assert_eq!(upgrade::parse_version("nightly"), None);
+    assert!(upgrade::is_newer("v0.10.0", "0.9.9"));
+    assert!(!upgrade::is_newer("v0.1.1", "0.1.1"));
+    assert!(!upgrade::is_newer("nightly", "0.1.1"));
+}
+
+#[test]
+fn reads_release_checksums_and_notes() {
+    let sums = "aa11  rtun-aarch64-macos\nbb22 *rtun-x86_64-linux\n";
+    assert_eq!(
+        upgrade::checksum_for(sums, "rtun-x86_64-linux"),
+        Some("bb22")
+    );
+    assert_eq!(upgrade::checksum_for(sums, "rtun-x86_64-windows"), None);
+    let notes = "## Changes\n\n- Faster reconnects\n- Fix the Stats tab\n";
+    assert_eq!(
+        upgrade::changelog(notes, 2),
+        ["## Changes", "- Faster reconnects"]
+    );
+}
+
+#[test]
+fn replaces_the_binary_in_one_step() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rtun");
+    fs::write(&path, "old").unwrap();
+    upgrade::replace(&path, b"new").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"new");
+    assert!(!dir.path().join("rtun.new").exists());
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use std::time::Duration;
+
+use rtun::tunnel::TunnelSpec;
+use rtun::usage;
+
+use common::harness;
+
+#[test]
+fn adds_up_usage_across_sessions() {
+    harness();
+    let db = TunnelSpec::new("db", 5432, 5432);
+    let web = TunnelSpec::new("web", 8080, 80);
+    usage::opened(&db);
+    usage::add(&db, Duration::from_secs(90), 1_000);
+    usage::opened(&db);
+    usage::add(&db, Duration::from_secs(30), 500);
+    usage::opened(&web);
+
+    let entries = usage::load();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        (
+            &entries[0].host,
+            entries[0].opens,
+            entries[0].uptime,
+            entries[0].bytes
+        ),
+        (&"db".to_string(), 2, 120, 1_500)
+    );
+    assert_eq!((entries[1].opens, entries[1].uptime), (1, 0));
+
+    let profile = [web, TunnelSpec::new("legacy", 9000, 9000)];
+    assert_eq!(
+        usage::unused(&entries, &profile),
+        [&TunnelSpec::new("legacy", 9000, 9000)]
+    );
+}
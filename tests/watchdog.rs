@@ -0,0 +1,66 @@
+use std::net::TcpListener as StdListener;
+
+use rtun::backend::{self, Forward};
+use rtun::config::WatchdogConfig;
+use rtun::tunnel::{RemoteTarget, TunnelSpec};
+use rtun::watchdog;
+use tokio::net::{TcpListener, TcpStream};
+
+/// A port whose next port is free too.
+fn free_port_pair() -> u16 {
+    loop {
+        let port = StdListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        if port < u16::MAX && StdListener::bind(("127.0.0.1", port + 1)).is_ok() {
+            return port;
+        }
+    }
+}
+
+#[tokio::test]
+async fn monitors_the_loop_of_forwards() {
+    let port = free_port_pair();
+    let spec = TunnelSpec {
+        watchdog: Some(WatchdogConfig {
+            monitor_port: Some(port),
+            ..Default::default()
+        }),
+        ..TunnelSpec::new("db", 5432, 5432)
+    };
+    let target = RemoteTarget {
+        host: "127.0.0.1".to_string(),
+        port: 5432,
+    };
+    assert_eq!(
+        backend::forwards(&spec, &target, None)[1..],
+        [
+            Forward::Local {
+                bind: port.to_string(),
+                target: format!("127.0.0.1:{}", port),
+            },
+            Forward::Remote {
+                bind: port.to_string(),
+                target: format!("127.0.0.1:{}", port + 1),
+            },
+        ]
+    );
+
+    // Nothing carries the data.
+    assert!(!watchdog::monitor(port).await);
+
+    // What ssh does with both forwards: the port leads back to the next one.
+    let forward = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = forward.accept().await {
+            tokio::spawn(async move {
+                if let Ok(mut back) = TcpStream::connect(("127.0.0.1", port + 1)).await {
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut back).await;
+                }
+            });
+        }
+    });
+    assert!(watchdog::monitor(port).await);
+}
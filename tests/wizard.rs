@@ -0,0 +1,170 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use rtun::recent::{self, Recent};
+use rtun::remoteport;
+use rtun::tunnel::TunnelSpec;
+use rtun::wizard::{self, Outcome, Step, Wizard};
+
+use common::harness;
+
+#[test]
+fn parses_listening_ports() {
+    let ss = "LISTEN 0 4096 127.0.0.1:5432 0.0.0.0:*\nLISTEN 0 511 [::]:80 [::]:*\n";
+    assert_eq!(wizard::parse_listening_ports(ss), [80, 5432]);
+    let netstat = "Active Internet connections (only servers)\n\
+        Proto Recv-Q Send-Q Local Address Foreign Address State\n\
+        tcp 0 0 0.0.0.0:22 0.0.0.0:* LISTEN\n\
+        tcp6 0 0 :::22 :::* LISTEN\n";
+    assert_eq!(wizard::parse_listening_ports(netstat), [22]);
+    let bsd = "tcp4 0 0 *.8080 *.* LISTEN\ntcp4 0 0 10.0.0.2.51000 10.0.0.1.22 ESTABLISHED\n";
+    assert_eq!(wizard::parse_listening_ports(bsd), [8080]);
+}
+
+#[test]
+fn warns_about_remote_ports_nothing_listens_on() {
+    let listening = [22, 5432, 6379];
+    assert_eq!(remoteport::warning(5432, &listening), None);
+    assert_eq!(
+        remoteport::warning(5433, &listening).as_deref(),
+        Some("remote 5433 is not listening, did you mean 5432?")
+    );
+    assert_eq!(
+        remoteport::warning(8080, &listening).as_deref(),
+        Some("remote 8080 is not listening")
+    );
+    assert_eq!(remoteport::suggest(6380, &[6379, 6381]), Some(6379));
+}
+
+#[test]
+fn one_line_spec_skips_the_steps() {
+    let mut wizard = Wizard::new(Vec::new());
+    assert_eq!(
+        wizard.submit("bastion 8080:80"),
+        Outcome::Done(Box::new(TunnelSpec::new("bastion", 8080, 80)))
+    );
+    assert!(matches!(wizard.submit("bastion 8080"), Outcome::Invalid(_)));
+}
+
+#[test]
+fn opens_a_list_of_tunnels_at_once() {
+    harness();
+    let mut wizard = Wizard::new(vec!["web".to_string()]);
+    let typed = "web 8080:80\n# staging\n\ndb 5432\ndb 5433:5432 # replica";
+    assert!(wizard.candidates(typed).is_empty());
+    assert_eq!(
+        wizard.submit(typed),
+        Outcome::Batch(vec![
+            (1, Ok(TunnelSpec::new("web", 8080, 80))),
+            (
+                4,
+                Err("Ports part does not match expected format '12234:45321'".to_string())
+            ),
+            (
+                5,
+                Ok(TunnelSpec {
+                    label: Some("replica".to_string()),
+                    ..TunnelSpec::new("db", 5433, 5432)
+                })
+            ),
+        ])
+    );
+
+    std::fs::write(
+        std::env::home_dir().unwrap().join("runbook.txt"),
+        "web 8080:80\n",
+    )
+    .unwrap();
+    assert_eq!(
+        wizard.submit("@~/runbook.txt"),
+        Outcome::Batch(vec![(1, Ok(TunnelSpec::new("web", 8080, 80)))])
+    );
+    assert!(matches!(
+        wizard.submit("@~/missing.txt"),
+        Outcome::Invalid(_)
+    ));
+    assert_eq!(wizard.step, Step::Host);
+}
+
+#[tokio::test]
+async fn walks_through_host_and_ports() {
+    harness();
+    let mut wizard = Wizard::new(vec!["web".to_string(), "db-bastion".to_string()]);
+    assert_eq!(wizard.candidates("db"), ["db-bastion"]);
+    wizard.move_selection("db", true);
+    assert!(matches!(wizard.submit("db"), Outcome::Next { .. }));
+    assert_eq!(wizard.host, "db-bastion");
+    assert_eq!(wizard.step, Step::RemotePort);
+
+    assert!(matches!(wizard.submit("postgres"), Outcome::Invalid(_)));
+    let Outcome::Next { prefill } = wizard.submit("5432") else {
+        panic!("remote port not accepted");
+    };
+    assert_eq!(wizard.step, Step::LocalPort);
+    let local_port: u16 = prefill.parse().unwrap();
+    assert_eq!(
+        wizard.submit(&prefill),
+        Outcome::Done(Box::new(TunnelSpec::new("db-bastion", local_port, 5432)))
+    );
+}
+
+fn opened(host: &str, ports: (u16, u16), opens: u32, day: u32, pinned: bool) -> Recent {
+    Recent {
+        host: host.to_string(),
+        local_port: ports.0,
+        remote_port: ports.1,
+        opens,
+        last_opened: Utc.with_ymd_and_hms(2026, 5, day, 9, 0, 0).unwrap(),
+        pinned,
+    }
+}
+
+#[test]
+fn offers_pinned_then_most_opened_tunnels() {
+    let entries: Vec<Recent> = (1..=8)
+        .map(|day| opened("web", (8000 + day as u16, 80), day, day, false))
+        .chain([
+            opened("db", (5432, 5432), 1, 1, true),
+            opened("cache", (6379, 6379), 8, 2, false),
+        ])
+        .collect();
+
+    let quick: Vec<String> = recent::quick(&entries)
+        .iter()
+        .map(Recent::to_string)
+        .collect();
+    assert_eq!(
+        quick,
+        [
+            "db 5432:5432",
+            "web 8008:80",
+            "cache 6379:6379",
+            "web 8007:80",
+            "web 8006:80"
+        ]
+    );
+}
+
+#[test]
+fn picks_recent_tunnels_in_the_first_step() {
+    let mut wizard = Wizard::new(vec!["bastion".to_string()]).with_recent(vec![
+        opened("db", (5432, 5432), 3, 1, true),
+        opened("bastion", (8080, 80), 2, 1, false),
+    ]);
+    assert_eq!(
+        wizard.labels(""),
+        ["1 db 5432:5432 ★", "2 bastion 8080:80", "bastion"]
+    );
+    assert_eq!(
+        wizard.quick_pick("", 2).map(Recent::spec),
+        Some(TunnelSpec::new("bastion", 8080, 80))
+    );
+    assert_eq!(wizard.quick_pick("b", 1), None);
+    assert_eq!(wizard.labels("bas"), ["bastion 8080:80", "bastion"]);
+
+    wizard.move_selection("bas", true);
+    assert_eq!(
+        wizard.submit("bas"),
+        Outcome::Done(Box::new(TunnelSpec::new("bastion", 8080, 80)))
+    );
+}